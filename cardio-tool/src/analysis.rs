@@ -0,0 +1,192 @@
+use std::{iter, num::NonZeroU8};
+
+use anyhow::{bail, Result as AnyResult};
+use signal_processing::{
+    ecg::{ecg_filter, heart_rate_noise_filter, BaselineFilter, MedianBaseline, SampleRate},
+    filter::{
+        filtfilt::filtfilt,
+        iir::{BandStop, BiquadCascade, HighPass},
+        Filter,
+    },
+    heart_rate::HeartRateCalculator,
+    mains::MainsFrequency,
+    respiration::RespirationRateCalculator,
+};
+
+use crate::recording::Recording;
+
+/// The output of the firmware's processing chain.
+pub struct Analysis {
+    /// Filtered signal in volts. `None` where the filters didn't produce output yet.
+    pub filtered: Vec<Option<f32>>,
+    /// The heart rate displayed at the end of each second.
+    pub heart_rate: Vec<Option<NonZeroU8>>,
+    /// The respiration rate displayed at the end of each second. Empty if the recording has no
+    /// respiration channel.
+    pub respiration_rate: Vec<Option<NonZeroU8>>,
+}
+
+/// Length of the signal extension used by zero-phase filtering. Long enough for the baseline
+/// filter to settle.
+const ZERO_PHASE_PADDING: usize = 4000;
+
+/// Quality factor of the power line notch filters.
+const NOTCH_Q: f32 = 30.0;
+
+/// Runs the ECG channel of the recording through the same filters and heart rate calculator the device used.
+pub fn analyze(recording: &Recording) -> AnyResult<Analysis> {
+    let Some(rate) = SampleRate::from_hz(recording.header.sample_rate) else {
+        bail!(
+            "Unsupported sample rate: {} sps",
+            recording.header.sample_rate
+        );
+    };
+
+    let baseline = if recording.header.median_baseline {
+        Some(BaselineFilter::Median)
+    } else {
+        BaselineFilter::from_cutoff(recording.header.high_pass_cutoff)
+    };
+    let Some(baseline) = baseline else {
+        bail!(
+            "Unknown high-pass filter: {} Hz",
+            recording.header.high_pass_cutoff
+        );
+    };
+
+    let Some(mains) = MainsFrequency::from_hz(recording.header.power_line_frequency) else {
+        bail!(
+            "Unsupported power line frequency: {} Hz",
+            recording.header.power_line_frequency
+        );
+    };
+
+    let Some(mut filter) = ecg_filter(baseline, mains, rate) else {
+        bail!("Out of memory");
+    };
+    let mut hr_noise_filter = heart_rate_noise_filter(rate);
+    let mut heart_rate_calculator = HeartRateCalculator::new_alloc(rate.fs());
+
+    let samples_per_second = recording.header.sample_rate as usize;
+    let mut filtered = Vec::with_capacity(recording.frames());
+    let mut heart_rate = Vec::new();
+
+    for (idx, sample) in recording.volts(recording.ecg()).enumerate() {
+        let output = filter.update(sample);
+        if let Some(output) = output {
+            if let Some(output) = hr_noise_filter.update(output) {
+                heart_rate_calculator.update(output);
+            }
+        }
+        filtered.push(output);
+
+        if (idx + 1) % samples_per_second == 0 {
+            heart_rate.push(heart_rate_calculator.current_hr());
+        }
+    }
+
+    let mut respiration_rate = Vec::new();
+    if let Some(channel) = recording.respiration() {
+        let mut calculator = RespirationRateCalculator::new(recording.sample_rate() as f32);
+        for (idx, sample) in recording.volts(channel).enumerate() {
+            calculator.update(sample);
+
+            if (idx + 1) % samples_per_second == 0 {
+                respiration_rate.push(calculator.current_rate());
+            }
+        }
+    }
+
+    Ok(Analysis {
+        filtered,
+        heart_rate,
+        respiration_rate,
+    })
+}
+
+/// Filters the ECG channel of the recording forward and backward, which removes the baseline
+/// wander and power line interference like the device does, but without distorting the waveform.
+/// Recordings made with the median baseline filter use that filter, which doesn't distort the
+/// waveform on its own. Returns the signal in volts.
+pub fn zero_phase(recording: &Recording) -> AnyResult<Vec<f32>> {
+    let fs = recording.sample_rate() as f32;
+    let cutoff = recording.header.high_pass_cutoff;
+
+    let Some(mains) = MainsFrequency::from_hz(recording.header.power_line_frequency) else {
+        bail!(
+            "Unsupported power line frequency: {} Hz",
+            recording.header.power_line_frequency
+        );
+    };
+
+    let mut signal = recording.volts(recording.ecg()).collect::<Vec<_>>();
+
+    if !recording.header.median_baseline && cutoff > 0.0 {
+        let Ok(high_pass) = BiquadCascade::<HighPass, 1>::butterworth(fs, cutoff, 1) else {
+            bail!("Invalid high-pass filter: {cutoff} Hz");
+        };
+        filtfilt::<_, ZERO_PHASE_PADDING>(&high_pass, &mut signal);
+    }
+
+    // Harmonics above the Nyquist frequency are not present in the signal.
+    for frequency in mains.harmonics() {
+        if let Ok(notch) = BiquadCascade::<BandStop, 1>::notch(fs, frequency, NOTCH_Q) {
+            filtfilt::<_, ZERO_PHASE_PADDING>(&notch, &mut signal);
+        }
+    }
+
+    if recording.header.median_baseline {
+        let Some(rate) = SampleRate::from_hz(recording.header.sample_rate) else {
+            bail!(
+                "Unsupported sample rate: {} sps",
+                recording.header.sample_rate
+            );
+        };
+        signal = remove_median_baseline(rate, &signal)?;
+    }
+
+    Ok(signal)
+}
+
+/// Runs the median baseline filter over the signal and removes its delay. The signal is extended
+/// by repeating its first and last samples, so that there is an output for every input sample.
+fn remove_median_baseline(rate: SampleRate, signal: &[f32]) -> AnyResult<Vec<f32>> {
+    let (Some(&first), Some(&last)) = (signal.first(), signal.last()) else {
+        return Ok(Vec::new());
+    };
+
+    // The filter produces its first output after twice its delay, which belongs to the input
+    // sample one delay before.
+    let delay = MedianBaseline::delay(rate);
+    let Some(mut filter) = MedianBaseline::new(rate) else {
+        bail!("Out of memory");
+    };
+
+    Ok(iter::repeat_n(first, delay)
+        .chain(signal.iter().copied())
+        .chain(iter::repeat_n(last, delay))
+        .filter_map(|sample| filter.update(sample))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn median_baseline_is_aligned_with_the_input() {
+        let rate = SampleRate::Sps250;
+
+        // Narrow pulses on a constant offset.
+        let signal = (0..1000)
+            .map(|n| if n % 100 == 50 { 1.5 } else { 0.5 })
+            .collect::<Vec<_>>();
+
+        let filtered = remove_median_baseline(rate, &signal).unwrap();
+
+        assert_eq!(filtered.len(), signal.len());
+        for (n, (input, output)) in signal.iter().zip(filtered.iter()).enumerate() {
+            assert!((output - (input - 0.5)).abs() < 1e-6, "{n}: {output}");
+        }
+    }
+}
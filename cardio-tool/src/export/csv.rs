@@ -0,0 +1,47 @@
+use std::io::{self, Write};
+
+use crate::recording::Recording;
+
+/// Writes one row per sample: time in seconds, every recorded channel and optionally the filtered
+/// ECG signal in millivolts, whether the electrodes had contact and the signal quality score.
+/// Filtered values are left empty until the filters produce output, quality scores are left empty
+/// for samples that weren't scored.
+pub fn write(
+    mut writer: impl Write,
+    recording: &Recording,
+    filtered: Option<&[Option<f32>]>,
+) -> io::Result<()> {
+    write!(writer, "time_s")?;
+    for channel in recording.channels.iter() {
+        write!(writer, ",{}_mv", channel.label().to_lowercase())?;
+    }
+    if filtered.is_some() {
+        write!(writer, ",filtered_mv")?;
+    }
+    writeln!(writer, ",lead_off,quality")?;
+
+    let lead_off = recording.lead_off_mask();
+    let quality = recording.frame_quality();
+
+    for idx in 0..recording.frames() {
+        let time = idx as f64 / recording.sample_rate();
+        write!(writer, "{time:.3}")?;
+        for channel in recording.channels.iter() {
+            write!(writer, ",{:.6}", recording.millivolts(channel.samples[idx]))?;
+        }
+
+        if let Some(filtered) = filtered {
+            write!(writer, ",")?;
+            if let Some(value) = filtered[idx] {
+                write!(writer, "{:.6}", value * 1000.0)?;
+            }
+        }
+        write!(writer, ",{}", u8::from(lead_off[idx]))?;
+        match quality[idx] {
+            Some(score) => writeln!(writer, ",{score}")?,
+            None => writeln!(writer, ",")?,
+        }
+    }
+
+    writer.flush()
+}
@@ -0,0 +1,268 @@
+//! EDF+ export.
+//!
+//! The recording is written as a continuous (EDF+C) file with one signal per recorded channel and
+//! the mandatory annotation signal, using one second long data records. The last record is padded
+//! by repeating the last sample. Lead-off intervals are written as "Lead off" annotations.
+
+use std::{
+    io::{self, Write},
+    iter::repeat_n,
+};
+
+use crate::{
+    export::{DateTime, Signal},
+    recording::Recording,
+};
+
+const MONTHS: [&str; 12] = [
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+
+/// Minimum number of bytes reserved for annotations in each data record.
+const MIN_ANNOTATION_BYTES: usize = 64;
+
+const DIGITAL_MIN: i32 = i16::MIN as i32;
+const DIGITAL_MAX: i32 = i16::MAX as i32;
+
+/// Writes a left-aligned, space padded header field. Non-ASCII characters are not allowed.
+fn write_field(writer: &mut impl Write, value: &str, width: usize) -> io::Result<()> {
+    let value: String = value
+        .chars()
+        .map(|c| if c.is_ascii_graphic() { c } else { ' ' })
+        .take(width)
+        .collect();
+    write!(writer, "{value:<width$}")
+}
+
+/// Returns the annotations of a data record: the time-keeping annotation, followed by the
+/// lead-off intervals that start in the record.
+fn record_annotations(recording: &Recording, record: usize) -> Vec<u8> {
+    // Onset of the data record, followed by an empty annotation.
+    let mut annotations = format!("+{record}\x14\x14\0").into_bytes();
+
+    let sample_rate = recording.sample_rate();
+    for range in recording.header.lead_off.iter() {
+        let onset = range.start as f64 / sample_rate;
+        if onset.floor() as usize == record {
+            let duration = range.length as f64 / sample_rate;
+            annotations.extend_from_slice(
+                format!("+{onset:.3}\x15{duration:.3}\x14Lead off\x14\0").as_bytes(),
+            );
+        }
+    }
+
+    annotations
+}
+
+/// Writes one header field for every signal, followed by the annotation signal's field.
+fn write_fields<'s>(
+    writer: &mut impl Write,
+    values: impl Iterator<Item = &'s str>,
+    annotation: &str,
+    width: usize,
+) -> io::Result<()> {
+    for value in values {
+        write_field(writer, value, width)?;
+    }
+    write_field(writer, annotation, width)
+}
+
+/// Formats a number so that it fits into an 8 character header field.
+fn number(value: f64) -> String {
+    for precision in (0..=6).rev() {
+        let formatted = format!("{value:.precision$}");
+        if formatted.len() <= 8 {
+            return formatted;
+        }
+    }
+    format!("{value:.0}")
+}
+
+/// Returns the physical range of the signal, as it will be written into the header.
+fn physical_range(signal: &[f64]) -> (f64, f64) {
+    let min = signal.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = signal.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+
+    let (min, max) = if min < max {
+        (min, max)
+    } else {
+        // Empty or constant signal. The range must not be empty.
+        let value = if min.is_finite() { min } else { 0.0 };
+        (value - 1.0, value + 1.0)
+    };
+
+    // Use the exact values that readers see. Samples that fall out of the range because of
+    // rounding are clamped.
+    (number(min).parse().unwrap(), number(max).parse().unwrap())
+}
+
+/// Writes `signals` as an EDF+ file. All signals must have the same length.
+pub fn write(mut writer: impl Write, recording: &Recording, signals: &[Signal]) -> io::Result<()> {
+    let samples_per_record = recording.header.sample_rate as usize;
+    let signal_len = signals.first().map_or(0, |signal| signal.values.len());
+    let record_count = signal_len.div_ceil(samples_per_record);
+
+    let annotations = (0..record_count)
+        .map(|record| record_annotations(recording, record))
+        .collect::<Vec<_>>();
+    // Every record has the same size, annotation signal samples are 2 bytes long.
+    let annotation_bytes = annotations
+        .iter()
+        .map(|annotation| annotation.len().next_multiple_of(2))
+        .fold(MIN_ANNOTATION_BYTES, usize::max);
+
+    let ranges = signals
+        .iter()
+        .map(|signal| physical_range(&signal.values))
+        .collect::<Vec<_>>();
+
+    let start = recording.header.start_time.map(DateTime::from_unix);
+    let (start_date, start_time) = match start {
+        Some(dt) => (
+            format!("{:02}.{:02}.{:02}", dt.day, dt.month, dt.year % 100),
+            format!("{:02}.{:02}.{:02}", dt.hour, dt.minute, dt.second),
+        ),
+        // EDF+ requires a date, 01.01.85 is the conventional placeholder.
+        None => ("01.01.85".to_string(), "00.00.00".to_string()),
+    };
+    let equipment = match recording.header.firmware_version {
+        "" => "Card/IO".to_string(),
+        version => format!("Card/IO_{version}"),
+    };
+    let recording_id = match start {
+        Some(dt) => format!(
+            "Startdate {:02}-{}-{} X X {equipment}",
+            dt.day,
+            MONTHS[dt.month as usize - 1],
+            dt.year,
+        ),
+        None => format!("Startdate X X X {equipment}"),
+    };
+
+    let signal_count = signals.len() + 1;
+    let header_bytes = 256 * (signal_count + 1);
+
+    // Fixed part of the header
+    for (value, width) in [
+        ("0", 8),
+        ("X X X X", 80),
+        (recording_id.as_str(), 80),
+        (start_date.as_str(), 8),
+        (start_time.as_str(), 8),
+        (header_bytes.to_string().as_str(), 8),
+        ("EDF+C", 44),
+        (record_count.to_string().as_str(), 8),
+        ("1", 8),
+        (signal_count.to_string().as_str(), 4),
+    ] {
+        write_field(&mut writer, value, width)?;
+    }
+
+    // Per-signal part of the header, field by field. The annotation signal comes last.
+    let annotation_samples = (annotation_bytes / 2).to_string();
+    let samples_per_record_str = samples_per_record.to_string();
+    let physical_mins = ranges
+        .iter()
+        .map(|(min, _)| number(*min))
+        .collect::<Vec<_>>();
+    let physical_maxs = ranges
+        .iter()
+        .map(|(_, max)| number(*max))
+        .collect::<Vec<_>>();
+    let digital_min_str = DIGITAL_MIN.to_string();
+    let digital_max_str = DIGITAL_MAX.to_string();
+
+    let n = signals.len();
+    let labels = signals.iter().map(|signal| signal.label.as_str());
+    write_fields(&mut writer, labels, "EDF Annotations", 16)?;
+    write_fields(&mut writer, repeat_n("AgAgCl electrode", n), "", 80)?;
+    write_fields(&mut writer, repeat_n("mV", n), "", 8)?;
+    write_fields(
+        &mut writer,
+        physical_mins.iter().map(String::as_str),
+        "-1",
+        8,
+    )?;
+    write_fields(
+        &mut writer,
+        physical_maxs.iter().map(String::as_str),
+        "1",
+        8,
+    )?;
+    write_fields(
+        &mut writer,
+        repeat_n(digital_min_str.as_str(), n),
+        "-32768",
+        8,
+    )?;
+    write_fields(
+        &mut writer,
+        repeat_n(digital_max_str.as_str(), n),
+        "32767",
+        8,
+    )?;
+    let prefiltering = signals.iter().map(|signal| signal.prefiltering.as_str());
+    write_fields(&mut writer, prefiltering, "", 80)?;
+    let samples = repeat_n(samples_per_record_str.as_str(), n);
+    write_fields(&mut writer, samples, &annotation_samples, 8)?;
+    write_fields(&mut writer, repeat_n("", n), "", 32)?;
+
+    // Data records
+    for (record, mut annotation) in annotations.into_iter().enumerate() {
+        let start = record * samples_per_record;
+        for (signal, &(physical_min, physical_max)) in signals.iter().zip(ranges.iter()) {
+            let scale = (DIGITAL_MAX - DIGITAL_MIN) as f64 / (physical_max - physical_min);
+            let last = signal.values.last().copied().unwrap_or(0.0);
+            for idx in start..start + samples_per_record {
+                let value = signal.values.get(idx).copied().unwrap_or(last);
+                let digital = ((value - physical_min) * scale + DIGITAL_MIN as f64).round();
+                let digital = digital.clamp(DIGITAL_MIN as f64, DIGITAL_MAX as f64) as i16;
+                writer.write_all(&digital.to_le_bytes())?;
+            }
+        }
+
+        annotation.resize(annotation_bytes, 0);
+        writer.write_all(&annotation)?;
+    }
+
+    writer.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use signal_processing::measurement::{FrameRange, FrameRanges, MeasurementHeader};
+
+    use super::*;
+
+    #[test]
+    fn lead_off_is_annotated_in_the_record_it_starts_in() {
+        let range = FrameRange {
+            start: 1500,
+            length: 250,
+        };
+        let bytes = range.to_le_bytes();
+        let recording = Recording {
+            version: 2,
+            header: MeasurementHeader {
+                lead_off: FrameRanges::from_bytes(&bytes).unwrap(),
+                ..MeasurementHeader::LEGACY
+            },
+            first_frame: 0,
+            channels: Vec::new(),
+            encoded_len: 0,
+        };
+
+        assert_eq!(record_annotations(&recording, 0), b"+0\x14\x14\0");
+        assert_eq!(
+            record_annotations(&recording, 1),
+            b"+1\x14\x14\0+1.500\x150.250\x14Lead off\x14\0"
+        );
+    }
+
+    #[test]
+    fn numbers_fit_into_header_fields() {
+        assert_eq!(number(-1.25), "-1.25000");
+        assert_eq!(number(0.5), "0.500000");
+        assert_eq!(number(-2420.123456), "-2420.12");
+    }
+}
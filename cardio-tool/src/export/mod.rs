@@ -0,0 +1,82 @@
+pub mod csv;
+pub mod edf;
+pub mod wfdb;
+
+/// A signal to be written into a single-rate, multi-signal file.
+pub struct Signal {
+    pub label: String,
+    /// Values in millivolts.
+    pub values: Vec<f64>,
+    /// Description of the filters applied to the signal. Empty if unfiltered.
+    pub prefiltering: String,
+}
+
+/// A UTC calendar date and time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    pub fn from_unix(timestamp: u64) -> Self {
+        let days = (timestamp / 86_400) as i64;
+        let seconds = timestamp % 86_400;
+
+        // Howard Hinnant's `civil_from_days` algorithm.
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+        let year = yoe + era * 400 + i64::from(month <= 2);
+
+        Self {
+            year,
+            month,
+            day,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unix_to_date_time() {
+        assert_eq!(
+            DateTime::from_unix(0),
+            DateTime {
+                year: 1970,
+                month: 1,
+                day: 1,
+                hour: 0,
+                minute: 0,
+                second: 0
+            }
+        );
+        // 2024-02-29 13:45:30 UTC
+        assert_eq!(
+            DateTime::from_unix(1_709_214_330),
+            DateTime {
+                year: 2024,
+                month: 2,
+                day: 29,
+                hour: 13,
+                minute: 45,
+                second: 30
+            }
+        );
+    }
+}
@@ -0,0 +1,100 @@
+//! WFDB export.
+//!
+//! Writes a record with one signal per recorded channel: a `.hea` header and a `.dat` signal file
+//! in format 24 (24-bit little endian samples, interleaved), which stores the ADC's samples without
+//! loss.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    export::{DateTime, Signal},
+    recording::Recording,
+};
+
+/// Writes `signals` as `<path>.hea` and `<path>.dat`. All signals must have the same length.
+pub fn write(path: &Path, recording: &Recording, signals: &[Signal]) -> io::Result<()> {
+    let record_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid record name"))?;
+
+    // ADC units per millivolt
+    let gain = 1.0 / recording.millivolts_per_lsb();
+
+    let samples = signals
+        .iter()
+        .map(|signal| {
+            signal
+                .values
+                .iter()
+                .map(|value| (value * gain).round().clamp(-8_388_608.0, 8_388_607.0) as i32)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let sample_count = samples.first().map_or(0, Vec::len);
+
+    let mut dat = BufWriter::new(File::create(path.with_extension("dat"))?);
+    for idx in 0..sample_count {
+        for signal in samples.iter() {
+            dat.write_all(&signal[idx].to_le_bytes()[..3])?;
+        }
+    }
+    dat.flush()?;
+
+    let mut hea = BufWriter::new(File::create(path.with_extension("hea"))?);
+    write_header(&mut hea, record_name, recording, gain, signals, &samples)?;
+    hea.flush()
+}
+
+fn write_header(
+    writer: &mut impl Write,
+    record_name: &str,
+    recording: &Recording,
+    gain: f64,
+    signals: &[Signal],
+    samples: &[Vec<i32>],
+) -> io::Result<()> {
+    write!(
+        writer,
+        "{record_name} {} {} {}",
+        signals.len(),
+        recording.header.sample_rate,
+        samples.first().map_or(0, Vec::len)
+    )?;
+    if let Some(start_time) = recording.header.start_time {
+        let dt = DateTime::from_unix(start_time);
+        write!(
+            writer,
+            " {:02}:{:02}:{:02} {:02}/{:02}/{:04}",
+            dt.hour, dt.minute, dt.second, dt.day, dt.month, dt.year
+        )?;
+    }
+    writeln!(writer)?;
+
+    for (signal, samples) in signals.iter().zip(samples) {
+        let initial_value = samples.first().copied().unwrap_or(0);
+        let checksum = samples
+            .iter()
+            .fold(0i16, |sum, &s| sum.wrapping_add(s as i16));
+
+        writeln!(
+            writer,
+            "{record_name}.dat 24 {gain:.4}(0)/mV 24 0 {initial_value} {checksum} 0 {}",
+            signal.label
+        )?;
+    }
+
+    if !recording.header.firmware_version.is_empty() {
+        writeln!(
+            writer,
+            "# Recorded by Card/IO firmware {}",
+            recording.header.firmware_version
+        )?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,657 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, ErrorKind},
+    net::{IpAddr, Ipv4Addr, TcpListener, UdpSocket},
+    num::{NonZeroU32, NonZeroU8},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context as _, Result as AnyResult};
+use clap::{Parser, Subcommand, ValueEnum};
+use protocol::stream::{
+    is_subscription, subscription, Packet, SampleKind, MAX_PACKET_LEN, STREAM_PORT,
+    SUBSCRIPTION_INTERVAL_S,
+};
+use signal_processing::hrv::HrvCalculator;
+
+use crate::{
+    analysis::{analyze, zero_phase},
+    export::Signal,
+    receiver::{PacketOutcome, StreamWriter},
+    recording::{decode_samples, encode_samples, Recording, Source, SAMPLE_ENCODINGS},
+    upload_server::UploadServer,
+};
+
+mod analysis;
+mod export;
+mod receiver;
+mod recording;
+mod upload_server;
+
+/// Signal quality scores below this mark a segment as unusable.
+const POOR_QUALITY: u8 = 50;
+
+#[derive(Debug, Subcommand)]
+pub enum Subcommands {
+    /// Prints the measurement's metadata and statistics.
+    Info {
+        #[clap(flatten)]
+        input: Input,
+    },
+
+    /// Converts the measurement to a different file format.
+    Export {
+        #[clap(flatten)]
+        input: Input,
+
+        /// Output file format.
+        #[clap(long, short)]
+        format: ExportFormat,
+
+        /// Output file. For WFDB, the record path without extension.
+        #[clap(long, short)]
+        output: PathBuf,
+
+        /// Export the signal as filtered by the device.
+        #[clap(long)]
+        filter: bool,
+
+        /// Export the signal filtered forward and backward, which doesn't distort the waveform
+        /// like the device's filters do.
+        #[clap(long, conflicts_with = "filter")]
+        zero_phase: bool,
+    },
+
+    /// Runs the device's filters and heart rate calculator on the measurement.
+    Analyze {
+        #[clap(flatten)]
+        input: Input,
+    },
+
+    /// Prints the samples of a part of the measurement, in millivolts.
+    Segment {
+        #[clap(flatten)]
+        input: Input,
+
+        /// Start of the segment, in seconds from the start of the measurement.
+        #[clap(long)]
+        start: f64,
+
+        /// Length of the segment, in seconds.
+        #[clap(long, default_value_t = 1.0)]
+        duration: f64,
+    },
+
+    /// Encodes the measurement's samples with every sample encoding and compares the results.
+    Compression {
+        #[clap(flatten)]
+        input: Input,
+    },
+
+    /// Records the live stream of a measurement into a CSV file, until the device starts a new
+    /// stream.
+    Stream {
+        /// Output file.
+        #[clap(long, short)]
+        output: PathBuf,
+
+        /// UDP port to listen on.
+        #[clap(long, default_value_t = STREAM_PORT)]
+        port: u16,
+
+        /// Address of the device. Subscriptions are broadcast if not set.
+        #[clap(long)]
+        device: Option<IpAddr>,
+
+        /// Stop after this many seconds.
+        #[clap(long)]
+        duration: Option<f64>,
+    },
+
+    /// Runs a stand-in for the backend's measurement upload endpoints. Point the device's
+    /// backend URL to `http://<address>:<port>`.
+    Serve {
+        /// Directory to write the uploaded measurements to.
+        #[clap(long, short)]
+        output: PathBuf,
+
+        /// Address to listen on.
+        #[clap(long, default_value = "0.0.0.0:8080")]
+        listen: String,
+
+        /// Store every Nth chunk without responding, like a lost acknowledgement.
+        #[clap(long)]
+        drop_every: Option<NonZeroU32>,
+    },
+}
+
+#[derive(Debug, clap::Args)]
+pub struct Input {
+    /// Measurement file.
+    file: PathBuf,
+
+    /// The file is a request body received by the backend, not a file read from the device.
+    #[clap(long)]
+    uploaded: bool,
+}
+
+impl Input {
+    fn source(&self) -> Source {
+        if self.uploaded {
+            Source::Uploaded
+        } else {
+            Source::Stored
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+    Edf,
+    Wfdb,
+}
+
+#[derive(Debug, Parser)]
+#[clap(about, version, propagate_version = true)]
+pub struct Cli {
+    #[clap(subcommand)]
+    pub subcommand: Subcommands,
+}
+
+fn read_file(path: &Path) -> AnyResult<Vec<u8>> {
+    fs::read(path).with_context(|| format!("Failed to read {}", path.display()))
+}
+
+fn info(recording: &Recording) {
+    let header = &recording.header;
+
+    println!("Format version:       {}", recording.version);
+    if !header.firmware_version.is_empty() {
+        println!("Firmware version:     {}", header.firmware_version);
+    }
+    match header.start_time {
+        Some(start_time) => {
+            let dt = export::DateTime::from_unix(start_time);
+            println!(
+                "Start time:           {:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+                dt.year, dt.month, dt.day, dt.hour, dt.minute, dt.second
+            );
+        }
+        None => println!("Start time:           unknown"),
+    }
+    println!("Sample rate:          {} sps", header.sample_rate);
+    println!("Gain:                 {}", header.gain);
+    println!("Reference voltage:    {} mV", header.reference_mv);
+    if let Some(id) = header.adc_device_id {
+        println!("ADC device ID:        {id:#04x}");
+    }
+    if header.median_baseline {
+        println!("Baseline filter:      median");
+    } else {
+        println!("High-pass cutoff:     {} Hz", header.high_pass_cutoff);
+    }
+    println!("Power line frequency: {} Hz", header.power_line_frequency);
+    println!();
+
+    println!("Samples:              {}", recording.frames());
+    println!("Duration:             {:.3} s", recording.duration());
+    if !header.keyframes.is_empty() {
+        println!("Keyframes:            {}", header.keyframes.len());
+    }
+
+    let lead_off = &header.lead_off;
+    if !lead_off.is_empty() {
+        let frames = lead_off
+            .iter()
+            .map(|range| range.length as f64)
+            .sum::<f64>();
+        println!(
+            "Lead off:             {} intervals, {:.3} s",
+            lead_off.len(),
+            frames / recording.sample_rate()
+        );
+    }
+
+    let quality = header.quality.scores();
+    if !quality.is_empty() {
+        let average = quality.iter().map(|&score| score as f64).sum::<f64>() / quality.len() as f64;
+        let poor = quality
+            .iter()
+            .filter(|&&score| score < POOR_QUALITY)
+            .count();
+        println!(
+            "Signal quality:       {average:.0} average, {poor} of {} windows poor",
+            quality.len()
+        );
+    }
+
+    let r_peaks = &header.r_peaks;
+    if !r_peaks.is_empty() {
+        print!("R peaks:              {}", r_peaks.len());
+        let (count, sum) = r_peaks
+            .rr_intervals()
+            .fold((0, 0.0), |(count, sum), rr| (count + 1, sum + rr as f64));
+        if count > 0 {
+            let mean_rr_ms = sum / count as f64 / recording.sample_rate() * 1000.0;
+            print!(", mean RR interval {mean_rr_ms:.0} ms");
+        }
+        println!();
+    }
+
+    if let Some(rhythm) = header.rhythm {
+        let result = if rhythm.is_irregular() {
+            "irregular"
+        } else {
+            "regular"
+        };
+        println!(
+            "Rhythm screening:     {result} ({} of {} windows irregular)",
+            rhythm.irregular_windows, rhythm.windows
+        );
+    }
+
+    if let Some(intervals) = header.intervals {
+        let ms = |interval: Option<u16>| match interval {
+            Some(ms) => format!("{ms} ms"),
+            None => String::from("-"),
+        };
+        println!(
+            "Intervals:            PR {}, QRS {} ms, QT {}, QTc {} ({} beats averaged)",
+            ms(intervals.pr_ms),
+            intervals.qrs_ms,
+            ms(intervals.qt_ms),
+            ms(intervals.qtc_ms()),
+            intervals.beats
+        );
+        if let Some(st_uv) = intervals.st_uv {
+            println!("ST deviation:         {:.2} mV", st_uv as f64 / 1000.0);
+        }
+    }
+
+    let mut hrv = HrvCalculator::new(header.sample_rate as f32);
+    for rr_interval in r_peaks.rr_intervals() {
+        hrv.update(rr_interval);
+    }
+    if let Some(metrics) = hrv.metrics() {
+        println!(
+            "HRV:                  SDNN {:.1} ms, RMSSD {:.1} ms, pNN50 {:.1} %",
+            metrics.sdnn_ms, metrics.rmssd_ms, metrics.pnn50
+        );
+        println!(
+            "NN intervals:         {} used, {} rejected",
+            metrics.normal_intervals, metrics.rejected_intervals
+        );
+    }
+
+    for channel in recording.channels.iter() {
+        let samples = &channel.samples;
+        if let (Some(&min), Some(&max)) = (samples.iter().min(), samples.iter().max()) {
+            println!();
+            println!("Channel {} ({})", channel.number, channel.label());
+            println!(
+                "Minimum:              {min} ({:.3} mV)",
+                recording.millivolts(min)
+            );
+            println!(
+                "Maximum:              {max} ({:.3} mV)",
+                recording.millivolts(max)
+            );
+        }
+    }
+    println!();
+
+    // The ADC produces 24-bit samples.
+    let raw_size = recording.frames() * recording.channels.len() * 3;
+    println!("Encoded size:         {} bytes", recording.encoded_len);
+    if recording.encoded_len > 0 {
+        println!(
+            "Compression ratio:    {:.2}",
+            raw_size as f64 / recording.encoded_len as f64
+        );
+    }
+}
+
+fn analyze_recording(recording: &Recording) -> AnyResult<()> {
+    let analysis = analyze(recording)?;
+
+    let has_respiration = !analysis.respiration_rate.is_empty();
+    let format_rate = |rate: Option<&Option<NonZeroU8>>| match rate {
+        Some(Some(rate)) => rate.to_string(),
+        _ => "-".to_string(),
+    };
+
+    if has_respiration {
+        println!("Time [s]  Heart rate [bpm]  Respiration rate [1/min]");
+    } else {
+        println!("Time [s]  Heart rate [bpm]");
+    }
+    for (second, hr) in analysis.heart_rate.iter().enumerate() {
+        let hr = format_rate(Some(hr));
+        if has_respiration {
+            let rate = format_rate(analysis.respiration_rate.get(second));
+            println!("{:>8}  {hr:<16}  {rate}", second + 1);
+        } else {
+            println!("{:>8}  {hr}", second + 1);
+        }
+    }
+
+    let detected = analysis
+        .heart_rate
+        .iter()
+        .flatten()
+        .map(|hr| hr.get() as u32)
+        .collect::<Vec<_>>();
+    if let (Some(min), Some(max)) = (detected.iter().min(), detected.iter().max()) {
+        let average = detected.iter().sum::<u32>() as f64 / detected.len() as f64;
+        println!();
+        println!("Heart rate: min {min}, max {max}, average {average:.1} bpm");
+    } else {
+        println!("No heart rate detected");
+    }
+
+    if has_respiration {
+        let detected = analysis
+            .respiration_rate
+            .iter()
+            .flatten()
+            .map(|rate| rate.get() as u32)
+            .collect::<Vec<_>>();
+        if detected.is_empty() {
+            println!("No breathing detected");
+        } else {
+            let average = detected.iter().sum::<u32>() as f64 / detected.len() as f64;
+            println!("Respiration rate: average {average:.1} per minute");
+        }
+    }
+
+    Ok(())
+}
+
+fn print_segment(data: &[u8], source: Source, start: f64, duration: f64) -> AnyResult<()> {
+    if start < 0.0 || duration < 0.0 {
+        bail!("The segment must not start or end before the measurement");
+    }
+
+    let sample_rate = Recording::decode_segment(data, source, 0, 0)?.sample_rate();
+    let first_frame = (start * sample_rate) as u32;
+    let frames = (duration * sample_rate) as usize;
+    let recording = Recording::decode_segment(data, source, first_frame, frames)?;
+
+    print!("Time [s]");
+    for channel in recording.channels.iter() {
+        print!("  {} [mV]", channel.label());
+    }
+    println!();
+
+    for idx in 0..recording.frames() {
+        let frame = recording.first_frame as usize + idx;
+        print!("{:.3}", frame as f64 / sample_rate);
+        for channel in recording.channels.iter() {
+            print!("  {:.4}", recording.millivolts(channel.samples[idx]));
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+fn compare_encodings(recording: &Recording) -> AnyResult<()> {
+    let channels = recording.channels.len();
+    let frames = (0..recording.frames())
+        .flat_map(|frame| {
+            recording
+                .channels
+                .iter()
+                .map(move |channel| channel.samples[frame])
+        })
+        .collect::<Vec<_>>();
+    if frames.is_empty() {
+        bail!("The recording contains no samples");
+    }
+
+    println!("Encoding  Size [bytes]  Bytes/sample  Encoding [ms]  Decoding [ms]");
+    for encoding in SAMPLE_ENCODINGS {
+        let start = Instant::now();
+        let stream = encode_samples(&frames, encoding, channels)?;
+        let encoding_time = start.elapsed();
+
+        let start = Instant::now();
+        let decoded = decode_samples(&stream, encoding, channels, usize::MAX)?;
+        let decoding_time = start.elapsed();
+
+        let lossless = decoded
+            .iter()
+            .zip(recording.channels.iter())
+            .all(|(samples, channel)| *samples == channel.samples);
+        if !lossless {
+            bail!("Sample encoding {encoding} did not reproduce the samples");
+        }
+
+        println!(
+            "{encoding:>8}  {:>12}  {:>12.3}  {:>13.1}  {:>13.1}",
+            stream.len(),
+            stream.len() as f64 / frames.len() as f64,
+            encoding_time.as_secs_f64() * 1000.0,
+            decoding_time.as_secs_f64() * 1000.0,
+        );
+    }
+
+    Ok(())
+}
+
+fn record_stream(
+    output: &Path,
+    port: u16,
+    device: Option<IpAddr>,
+    duration: Option<f64>,
+) -> AnyResult<()> {
+    let socket = UdpSocket::bind(("0.0.0.0", port))
+        .with_context(|| format!("Failed to listen on port {port}"))?;
+    // Wake up regularly to report progress and to renew the subscription, even if nothing
+    // arrives.
+    socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+    socket.set_broadcast(true)?;
+
+    // The device only sends the stream to subscribers, unless it runs its own access point.
+    let device = device.unwrap_or(IpAddr::V4(Ipv4Addr::BROADCAST));
+    let subscription_interval = Duration::from_secs(SUBSCRIPTION_INTERVAL_S);
+    let mut last_subscription = None::<Instant>;
+
+    let file = File::create(output)
+        .map(BufWriter::new)
+        .with_context(|| format!("Failed to create {}", output.display()))?;
+    let mut writer = StreamWriter::new(file);
+
+    println!("Waiting for a stream on port {port}");
+
+    let mut buffer = [0; MAX_PACKET_LEN];
+    let mut last_report = Instant::now();
+    loop {
+        if last_subscription.is_none_or(|sent| sent.elapsed() >= subscription_interval) {
+            if let Err(e) = socket.send_to(&subscription(), (device, STREAM_PORT)) {
+                println!("Failed to subscribe to the stream: {e}");
+            }
+            last_subscription = Some(Instant::now());
+        }
+
+        match socket.recv_from(&mut buffer) {
+            // Our own, or another receiver's subscription.
+            Ok((len, _)) if is_subscription(&buffer[..len]) => {}
+            Ok((len, sender)) => match Packet::parse(&buffer[..len]) {
+                Ok(packet) => {
+                    if writer.header().is_none() {
+                        let kind = match packet.header.kind {
+                            SampleKind::Raw => "raw",
+                            SampleKind::Filtered => "filtered",
+                        };
+                        println!(
+                            "Receiving {kind} samples from {sender}, {} sps",
+                            packet.header.sample_rate
+                        );
+                    }
+                    if writer.write_packet(&packet)? == PacketOutcome::NewStream {
+                        println!("A new stream started");
+                        break;
+                    }
+                }
+                Err(e) => println!("Ignoring packet from {sender}: {e:?}"),
+            },
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(e) => return Err(e).context("Failed to receive"),
+        }
+
+        if last_report.elapsed() >= Duration::from_secs(1) {
+            last_report = Instant::now();
+            writer.flush()?;
+
+            let stats = writer.stats();
+            if let Some(header) = writer.header() {
+                println!(
+                    "{:.1} s received, {} frames lost, {} packets late",
+                    stats.frames as f64 / header.sample_rate as f64,
+                    stats.lost_frames,
+                    stats.late_packets
+                );
+            }
+        }
+
+        let recorded = writer.header().map_or(0.0, |header| {
+            writer.stats().frames as f64 / header.sample_rate as f64
+        });
+        if duration.is_some_and(|duration| recorded >= duration) {
+            break;
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+fn export_recording(
+    recording: &Recording,
+    format: ExportFormat,
+    output: &Path,
+    filter: bool,
+    zero_phase_filter: bool,
+) -> AnyResult<()> {
+    let filtered = if filter {
+        Some(analyze(recording)?.filtered)
+    } else if zero_phase_filter {
+        Some(zero_phase(recording)?.into_iter().map(Some).collect())
+    } else {
+        None
+    };
+
+    let create = |path: &Path| {
+        File::create(path)
+            .map(BufWriter::new)
+            .with_context(|| format!("Failed to create {}", path.display()))
+    };
+
+    // Both filtering modes remove the baseline the same way the device did.
+    let baseline = if recording.header.median_baseline {
+        "BL:median".to_string()
+    } else {
+        format!("HP:{}Hz", recording.header.high_pass_cutoff)
+    };
+
+    // EDF and WFDB store one signal per channel. When filtering, the ECG channel is replaced by
+    // its filtered version, samples the filters haven't produced output for are exported as 0.
+    let signals = recording
+        .channels
+        .iter()
+        .map(|channel| match filtered.as_deref() {
+            Some(filtered) if channel.number == recording.ecg().number => Signal {
+                label: channel.label(),
+                values: filtered
+                    .iter()
+                    .map(|value| value.map_or(0.0, |v| v as f64 * 1000.0))
+                    .collect(),
+                prefiltering: format!("{baseline} N:{}Hz", recording.header.power_line_frequency),
+            },
+            _ => Signal {
+                label: channel.label(),
+                values: channel
+                    .samples
+                    .iter()
+                    .map(|&sample| recording.millivolts(sample))
+                    .collect(),
+                prefiltering: String::new(),
+            },
+        })
+        .collect::<Vec<_>>();
+
+    match format {
+        ExportFormat::Csv => {
+            export::csv::write(create(output)?, recording, filtered.as_deref())?;
+        }
+        ExportFormat::Edf => {
+            export::edf::write(create(output)?, recording, &signals)?;
+        }
+        ExportFormat::Wfdb => {
+            export::wfdb::write(output, recording, &signals)
+                .with_context(|| format!("Failed to write {}", output.display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn main() -> AnyResult<()> {
+    let cli = Cli::parse();
+
+    match cli.subcommand {
+        Subcommands::Info { input } => {
+            let data = read_file(&input.file)?;
+            info(&Recording::decode(&data, input.source())?);
+        }
+        Subcommands::Export {
+            input,
+            format,
+            output,
+            filter,
+            zero_phase,
+        } => {
+            let data = read_file(&input.file)?;
+            let recording = Recording::decode(&data, input.source())?;
+            export_recording(&recording, format, &output, filter, zero_phase)?;
+        }
+        Subcommands::Analyze { input } => {
+            let data = read_file(&input.file)?;
+            analyze_recording(&Recording::decode(&data, input.source())?)?;
+        }
+        Subcommands::Segment {
+            input,
+            start,
+            duration,
+        } => {
+            let data = read_file(&input.file)?;
+            print_segment(&data, input.source(), start, duration)?;
+        }
+        Subcommands::Compression { input } => {
+            let data = read_file(&input.file)?;
+            compare_encodings(&Recording::decode(&data, input.source())?)?;
+        }
+        Subcommands::Stream {
+            output,
+            port,
+            device,
+            duration,
+        } => record_stream(&output, port, device, duration)?,
+        Subcommands::Serve {
+            output,
+            listen,
+            drop_every,
+        } => {
+            let listener = TcpListener::bind(&listen)
+                .with_context(|| format!("Failed to listen on {listen}"))?;
+            println!("Listening on {}", listener.local_addr()?);
+
+            upload_server::serve(listener, UploadServer::new(output), drop_every)?;
+        }
+    }
+
+    Ok(())
+}
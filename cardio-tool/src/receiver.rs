@@ -0,0 +1,203 @@
+use std::io::{self, Write};
+
+use protocol::stream::{Packet, PacketHeader};
+
+/// Counts what happened to the frames of a stream.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StreamStats {
+    pub packets: u64,
+    pub frames: u64,
+    /// Frames that were never received.
+    pub lost_frames: u64,
+    /// Packets that arrived after a later one, and were dropped.
+    pub late_packets: u64,
+}
+
+/// What [`StreamWriter::write_packet`] did with a packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PacketOutcome {
+    Written,
+    /// The packet arrived after a later one and was dropped.
+    Late,
+    /// The packet belongs to a different stream, nothing was written.
+    NewStream,
+}
+
+/// Writes the frames of a live stream as CSV, one row per frame: time in seconds and every
+/// channel in millivolts. Lost frames leave a gap in the time column.
+pub struct StreamWriter<W: Write> {
+    writer: W,
+    header: Option<PacketHeader>,
+    next_frame: u32,
+    stats: StreamStats,
+}
+
+impl<W: Write> StreamWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            header: None,
+            next_frame: 0,
+            stats: StreamStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> StreamStats {
+        self.stats
+    }
+
+    /// The format of the stream being written, once the first packet arrived.
+    pub fn header(&self) -> Option<PacketHeader> {
+        self.header
+    }
+
+    pub fn write_packet(&mut self, packet: &Packet) -> io::Result<PacketOutcome> {
+        let header = packet.header;
+        match self.header {
+            None => {
+                write!(self.writer, "time_s")?;
+                for channel in header.channels.channels() {
+                    write!(self.writer, ",ch{channel}_mv")?;
+                }
+                writeln!(self.writer)?;
+
+                self.header = Some(header);
+                self.next_frame = header.first_frame;
+            }
+            Some(stream) => {
+                let same_format = stream.kind == header.kind
+                    && stream.channels == header.channels
+                    && stream.sample_rate == header.sample_rate;
+                if !same_format {
+                    return Ok(PacketOutcome::NewStream);
+                }
+
+                // Frame indices only go back by much if the device started a new stream.
+                if header.first_frame < self.next_frame {
+                    let behind = self.next_frame - header.first_frame;
+                    if behind > header.sample_rate as u32 {
+                        return Ok(PacketOutcome::NewStream);
+                    }
+
+                    self.stats.late_packets += 1;
+                    return Ok(PacketOutcome::Late);
+                }
+            }
+        }
+
+        self.stats.lost_frames += (header.first_frame - self.next_frame) as u64;
+
+        let channels = header.channels.count();
+        let volts = packet.volts().collect::<Vec<_>>();
+        for (idx, frame) in volts.chunks_exact(channels).enumerate() {
+            let time = (header.first_frame as usize + idx) as f64 / header.sample_rate as f64;
+            write!(self.writer, "{time:.4}")?;
+            for sample in frame {
+                write!(self.writer, ",{:.4}", *sample as f64 * 1000.0)?;
+            }
+            writeln!(self.writer)?;
+        }
+
+        self.next_frame = header.first_frame + header.frames as u32;
+        self.stats.packets += 1;
+        self.stats.frames += header.frames as u64;
+
+        Ok(PacketOutcome::Written)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use protocol::stream::{Packetizer, SampleKind};
+    use signal_processing::measurement::ChannelMask;
+
+    use super::*;
+
+    fn packets(first: i32, frames: i32) -> Vec<Vec<u8>> {
+        let header = PacketHeader {
+            kind: SampleKind::Raw,
+            channels: ChannelMask::ALL,
+            gain: 1,
+            sample_rate: 100,
+            reference_mv: 2420,
+            first_frame: 0,
+            frames: 0,
+        };
+        let mut packetizer = Packetizer::new(header, 2);
+
+        (0..first + frames)
+            .filter_map(|frame| {
+                let packet = packetizer.push_raw(&[frame << 16, -(frame << 16)])?;
+                (frame >= first).then(|| packet.to_vec())
+            })
+            .collect()
+    }
+
+    fn write(writer: &mut StreamWriter<Vec<u8>>, packets: &[Vec<u8>]) -> Vec<PacketOutcome> {
+        packets
+            .iter()
+            .map(|packet| {
+                writer
+                    .write_packet(&Packet::parse(packet).unwrap())
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn frames_are_written_in_millivolts() {
+        let mut writer = StreamWriter::new(Vec::new());
+
+        write(&mut writer, &packets(0, 2));
+
+        let csv = String::from_utf8(writer.writer).unwrap();
+        assert_eq!(
+            csv,
+            "time_s,ch1_mv,ch2_mv\n0.0000,0.0000,0.0000\n0.0100,18.9063,-18.9063\n"
+        );
+    }
+
+    #[test]
+    fn lost_and_late_packets_are_counted() {
+        let mut writer = StreamWriter::new(Vec::new());
+        let packets = packets(0, 8);
+
+        let outcomes = write(
+            &mut writer,
+            &[&packets[..1], &packets[2..], &packets[1..2]].concat(),
+        );
+
+        assert_eq!(
+            outcomes,
+            [
+                PacketOutcome::Written,
+                PacketOutcome::Written,
+                PacketOutcome::Written,
+                PacketOutcome::Late,
+            ]
+        );
+        assert_eq!(
+            writer.stats(),
+            StreamStats {
+                packets: 3,
+                frames: 6,
+                lost_frames: 2,
+                late_packets: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn restarted_stream_is_detected() {
+        let mut writer = StreamWriter::new(Vec::new());
+        write(&mut writer, &packets(300, 2));
+
+        let outcomes = write(&mut writer, &packets(0, 2));
+
+        assert_eq!(outcomes, [PacketOutcome::NewStream]);
+    }
+}
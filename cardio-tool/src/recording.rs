@@ -0,0 +1,468 @@
+use anyhow::{anyhow, bail, Result as AnyResult};
+use signal_processing::{
+    compressing_buffer::{EkgFormat, RiceFormat},
+    measurement::{Keyframe, MeasurementHeader, FORMAT_VERSION},
+};
+
+/// How the measurement data was obtained.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// A `meas.N` file read from the device's storage. Starts with an 8-bit format version.
+    Stored,
+    /// A request body received by the backend. Starts with a 32-bit format version.
+    Uploaded,
+}
+
+/// The sample encodings the tool can read and write.
+pub const SAMPLE_ENCODINGS: [u8; 2] = [EkgFormat::VERSION, RiceFormat::VERSION];
+
+/// Codes the samples of one channel.
+enum Codec {
+    Delta(EkgFormat),
+    Rice(Box<RiceFormat>),
+}
+
+impl Codec {
+    fn new(sample_encoding: u8) -> AnyResult<Self> {
+        match sample_encoding {
+            EkgFormat::VERSION => Ok(Self::Delta(EkgFormat::new())),
+            RiceFormat::VERSION => Ok(Self::Rice(Box::new(RiceFormat::new()))),
+            _ => bail!("Unsupported sample encoding: {sample_encoding}"),
+        }
+    }
+
+    fn read(&mut self, stream: &mut &[u8]) -> AnyResult<Option<i32>> {
+        Ok(match self {
+            Self::Delta(format) => format.read(stream)?,
+            Self::Rice(format) => format.read(stream)?,
+        })
+    }
+
+    fn write(&mut self, sample: i32, stream: &mut Vec<u8>) -> AnyResult<()> {
+        match self {
+            Self::Delta(format) => format.write(sample, stream)?,
+            Self::Rice(format) => format.write(sample, stream)?,
+        };
+        Ok(())
+    }
+
+    fn flush(&mut self, stream: &mut Vec<u8>) -> AnyResult<()> {
+        if let Self::Rice(format) = self {
+            format.flush(stream)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes up to `max_frames` frames of a sample stream of `channels` interleaved channels.
+/// Returns the samples of every channel. A truncated last frame is dropped.
+pub fn decode_samples(
+    mut stream: &[u8],
+    sample_encoding: u8,
+    channels: usize,
+    max_frames: usize,
+) -> AnyResult<Vec<Vec<i32>>> {
+    let mut decoders = (0..channels)
+        .map(|_| Codec::new(sample_encoding))
+        .collect::<AnyResult<Vec<_>>>()?;
+    let mut samples = vec![Vec::new(); channels];
+    let mut frame = vec![0; channels];
+    'frames: for _ in 0..max_frames {
+        for (decoder, sample) in decoders.iter_mut().zip(frame.iter_mut()) {
+            match decoder.read(&mut stream)? {
+                Some(value) => *sample = value,
+                None => break 'frames,
+            }
+        }
+        for (channel, &sample) in samples.iter_mut().zip(frame.iter()) {
+            channel.push(sample);
+        }
+    }
+
+    Ok(samples)
+}
+
+/// Encodes interleaved frames of `channels` channels into a sample stream.
+pub fn encode_samples(frames: &[i32], sample_encoding: u8, channels: usize) -> AnyResult<Vec<u8>> {
+    let mut encoders = (0..channels)
+        .map(|_| Codec::new(sample_encoding))
+        .collect::<AnyResult<Vec<_>>>()?;
+    let mut stream = Vec::new();
+    for (idx, &sample) in frames.iter().enumerate() {
+        encoders[idx % channels].write(sample, &mut stream)?;
+    }
+    for encoder in encoders.iter_mut() {
+        encoder.flush(&mut stream)?;
+    }
+
+    Ok(stream)
+}
+
+/// What a channel measures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelKind {
+    /// The lead the device processes.
+    Ecg,
+    /// Demodulated thoracic impedance.
+    Respiration,
+    /// Any other recorded channel.
+    Auxiliary,
+}
+
+/// The samples of one ADC channel.
+pub struct Channel {
+    /// 1-based ADC channel number.
+    pub number: u8,
+    pub kind: ChannelKind,
+    /// Raw ADC samples.
+    pub samples: Vec<i32>,
+}
+
+impl Channel {
+    /// Signal label used in exported files.
+    pub fn label(&self) -> String {
+        match self.kind {
+            ChannelKind::Ecg => "ECG".to_string(),
+            ChannelKind::Respiration => "RESP".to_string(),
+            ChannelKind::Auxiliary => format!("CH{}", self.number),
+        }
+    }
+}
+
+/// A decoded measurement.
+pub struct Recording<'a> {
+    pub version: u8,
+    pub header: MeasurementHeader<'a>,
+    /// The index of the first decoded frame. Frame indices in the header are relative to the
+    /// start of the recording, not to this frame.
+    pub first_frame: u32,
+    /// Recorded channels, in ascending channel order. All channels have the same length.
+    pub channels: Vec<Channel>,
+    /// Size of the encoded sample stream, in bytes.
+    pub encoded_len: usize,
+}
+
+impl<'a> Recording<'a> {
+    pub fn decode(data: &'a [u8], source: Source) -> AnyResult<Self> {
+        Self::decode_segment(data, source, 0, usize::MAX)
+    }
+
+    /// Decodes up to `frames` frames, starting at `first_frame`. If the recording has keyframes,
+    /// decoding starts at the closest one, and the frames before it are skipped without decoding.
+    pub fn decode_segment(
+        data: &'a [u8],
+        source: Source,
+        first_frame: u32,
+        frames: usize,
+    ) -> AnyResult<Self> {
+        let (version, data) = match source {
+            Source::Stored => match data.split_first() {
+                Some((&version, data)) => (version, data),
+                None => bail!("File is empty"),
+            },
+            Source::Uploaded => match data.split_first_chunk::<4>() {
+                Some((&version, data)) => {
+                    let version = u32::from_le_bytes(version);
+                    if version > FORMAT_VERSION as u32 {
+                        bail!("Unsupported format version: {version}");
+                    }
+                    (version as u8, data)
+                }
+                None => bail!("File is too short"),
+            },
+        };
+
+        let (header, header_len) = MeasurementHeader::parse(version, data)
+            .map_err(|e| anyhow!("Invalid measurement: {e:?}"))?;
+
+        let stream = &data[header_len..];
+        let encoded_len = stream.len();
+
+        // The device processes the lowest numbered channel that isn't used for respiration.
+        let ecg_channel = header
+            .channels
+            .channels()
+            .find(|&number| Some(number) != header.respiration_channel)
+            .ok_or_else(|| anyhow!("The recording contains no ECG channel"))?;
+
+        let keyframe = header.keyframes.seek(first_frame).unwrap_or(Keyframe {
+            frame: 0,
+            offset: 0,
+        });
+        let Some(stream) = stream.get(keyframe.offset as usize..) else {
+            bail!("Keyframe at frame {} is outside the data", keyframe.frame);
+        };
+        let skipped = (first_frame - keyframe.frame) as usize;
+
+        // Frames hold one sample of every channel, each channel is coded independently.
+        let samples = decode_samples(
+            stream,
+            header.sample_encoding,
+            header.channels.count(),
+            skipped.saturating_add(frames),
+        )?;
+        let channels = header
+            .channels
+            .channels()
+            .zip(samples)
+            .map(|(number, samples)| Channel {
+                number,
+                kind: if number == ecg_channel {
+                    ChannelKind::Ecg
+                } else if Some(number) == header.respiration_channel {
+                    ChannelKind::Respiration
+                } else {
+                    ChannelKind::Auxiliary
+                },
+                samples: samples.get(skipped..).unwrap_or_default().to_vec(),
+            })
+            .collect::<Vec<_>>();
+
+        Ok(Self {
+            version,
+            header,
+            first_frame,
+            channels,
+            encoded_len,
+        })
+    }
+
+    /// The channel the device processes.
+    pub fn ecg(&self) -> &Channel {
+        self.channel(ChannelKind::Ecg)
+            .expect("decoded recordings have an ECG channel")
+    }
+
+    pub fn respiration(&self) -> Option<&Channel> {
+        self.channel(ChannelKind::Respiration)
+    }
+
+    fn channel(&self, kind: ChannelKind) -> Option<&Channel> {
+        self.channels.iter().find(|channel| channel.kind == kind)
+    }
+
+    /// Number of samples per channel.
+    pub fn frames(&self) -> usize {
+        self.ecg().samples.len()
+    }
+
+    /// Returns, for every frame, whether it was recorded while an electrode had no contact.
+    pub fn lead_off_mask(&self) -> Vec<bool> {
+        let mut mask = vec![false; self.frames()];
+        for range in self.header.lead_off.iter() {
+            let start = (range.start.saturating_sub(self.first_frame) as usize).min(mask.len());
+            let end = (range.end().saturating_sub(self.first_frame) as usize).min(mask.len());
+            mask[start..end].fill(true);
+        }
+        mask
+    }
+
+    /// Returns the signal quality score of every frame, if it was scored.
+    pub fn frame_quality(&self) -> Vec<Option<u8>> {
+        let mut quality = vec![None; self.frames()];
+        for (range, score) in self.header.quality.iter() {
+            let start = (range.start.saturating_sub(self.first_frame) as usize).min(quality.len());
+            let end = (range.end().saturating_sub(self.first_frame) as usize).min(quality.len());
+            quality[start..end].fill(Some(score));
+        }
+        quality
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.header.sample_rate as f64
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.frames() as f64 / self.sample_rate()
+    }
+
+    /// The voltage represented by one LSB of a raw sample.
+    pub fn millivolts_per_lsb(&self) -> f64 {
+        self.header.reference_mv as f64 / self.header.gain as f64 / (1 << 23) as f64
+    }
+
+    pub fn millivolts(&self, sample: i32) -> f64 {
+        sample as f64 * self.millivolts_per_lsb()
+    }
+
+    /// Samples of `channel` converted to volts, the unit the device's filters work with.
+    pub fn volts<'c>(&'c self, channel: &'c Channel) -> impl Iterator<Item = f32> + 'c {
+        channel
+            .samples
+            .iter()
+            .map(|&sample| (self.millivolts(sample) / 1000.0) as f32)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use signal_processing::measurement::{ChannelMask, Keyframes};
+
+    use super::*;
+
+    fn encode(header: &MeasurementHeader, samples: &[i32]) -> Vec<u8> {
+        let mut header_bytes = vec![0; header.encoded_len()];
+        header.write(&mut header_bytes.as_mut_slice()).unwrap();
+
+        let stream =
+            encode_samples(samples, header.sample_encoding, header.channels.count()).unwrap();
+
+        [header_bytes, stream].concat()
+    }
+
+    const SAMPLES: &[i32] = &[0, 100, -100, 8_000_000, -8_000_000, 5];
+
+    #[test]
+    fn decodes_stored_file() {
+        let header = MeasurementHeader {
+            start_time: Some(1_700_000_000),
+            firmware_version: "1.2.3",
+            ..MeasurementHeader::LEGACY
+        };
+        let data = [&[FORMAT_VERSION][..], &encode(&header, SAMPLES)].concat();
+
+        let recording = Recording::decode(&data, Source::Stored).unwrap();
+
+        assert_eq!(recording.header, header);
+        assert_eq!(recording.ecg().samples, SAMPLES);
+    }
+
+    #[test]
+    fn decodes_uploaded_body() {
+        let header = MeasurementHeader::LEGACY;
+        let data = [
+            &(FORMAT_VERSION as u32).to_le_bytes()[..],
+            &encode(&header, SAMPLES),
+        ]
+        .concat();
+
+        let recording = Recording::decode(&data, Source::Uploaded).unwrap();
+
+        assert_eq!(recording.ecg().samples, SAMPLES);
+    }
+
+    #[test]
+    fn decodes_legacy_file() {
+        let mut data = encode(&MeasurementHeader::LEGACY, SAMPLES);
+        // Version 0 files have no header.
+        data.drain(..MeasurementHeader::LEGACY.encoded_len());
+        data.insert(0, 0);
+
+        let recording = Recording::decode(&data, Source::Stored).unwrap();
+
+        assert_eq!(recording.header, MeasurementHeader::LEGACY);
+        assert_eq!(recording.ecg().samples, SAMPLES);
+        assert_eq!(recording.duration(), SAMPLES.len() as f64 / 1000.0);
+    }
+
+    #[test]
+    fn deinterleaves_channels() {
+        let header = MeasurementHeader {
+            channels: ChannelMask::ALL,
+            ..MeasurementHeader::LEGACY
+        };
+        // The last, incomplete frame is dropped.
+        let data = [&[FORMAT_VERSION][..], &encode(&header, &SAMPLES[..5])].concat();
+
+        let recording = Recording::decode(&data, Source::Stored).unwrap();
+
+        assert_eq!(recording.frames(), 2);
+        assert_eq!(recording.channels[0].number, 1);
+        assert_eq!(recording.channels[0].samples, [0, -100]);
+        assert_eq!(recording.channels[1].number, 2);
+        assert_eq!(recording.channels[1].samples, [100, 8_000_000]);
+        assert_eq!(recording.channels[1].label(), "CH2");
+    }
+
+    #[test]
+    fn decodes_rice_coded_channels() {
+        let header = MeasurementHeader {
+            sample_encoding: RiceFormat::VERSION,
+            channels: ChannelMask::ALL,
+            ..MeasurementHeader::LEGACY
+        };
+        let frames = (0..1000).map(|n| n * n % 1013 - 500).collect::<Vec<_>>();
+        let data = [&[FORMAT_VERSION][..], &encode(&header, &frames)].concat();
+
+        let recording = Recording::decode(&data, Source::Stored).unwrap();
+
+        assert_eq!(recording.frames(), 500);
+        let first = frames.iter().step_by(2).copied().collect::<Vec<_>>();
+        let second = frames
+            .iter()
+            .skip(1)
+            .step_by(2)
+            .copied()
+            .collect::<Vec<_>>();
+        assert_eq!(recording.channels[0].samples, first);
+        assert_eq!(recording.channels[1].samples, second);
+    }
+
+    #[test]
+    fn segment_is_decoded_from_keyframe() {
+        let samples = (0..300).map(|n| n * n % 1013).collect::<Vec<_>>();
+        let block_len = RiceFormat::BLOCK_LEN;
+        let first_block = encode_samples(&samples[..block_len], RiceFormat::VERSION, 1).unwrap();
+
+        let mut keyframes = Vec::new();
+        keyframes.extend_from_slice(
+            &Keyframe {
+                frame: 0,
+                offset: 0,
+            }
+            .to_le_bytes(),
+        );
+        let keyframe = Keyframe {
+            frame: block_len as u32,
+            offset: first_block.len() as u32,
+        };
+        keyframes.extend_from_slice(&keyframe.to_le_bytes());
+        let header = MeasurementHeader {
+            sample_encoding: RiceFormat::VERSION,
+            keyframes: Keyframes::from_bytes(&keyframes).unwrap(),
+            ..MeasurementHeader::LEGACY
+        };
+        let mut data = [&[FORMAT_VERSION][..], &encode(&header, &samples)].concat();
+
+        // The first block is not decoded.
+        let stream_start = data.len()
+            - encode_samples(&samples, RiceFormat::VERSION, 1)
+                .unwrap()
+                .len();
+        data[stream_start..stream_start + first_block.len()].fill(0xFF);
+
+        let recording = Recording::decode_segment(&data, Source::Stored, 200, 50).unwrap();
+
+        assert_eq!(recording.first_frame, 200);
+        assert_eq!(recording.ecg().samples, samples[200..250]);
+    }
+
+    #[test]
+    fn rejects_unknown_sample_encoding() {
+        let header = MeasurementHeader {
+            sample_encoding: 0xFF,
+            ..MeasurementHeader::LEGACY
+        };
+        let mut header_bytes = vec![0; header.encoded_len()];
+        header.write(&mut header_bytes.as_mut_slice()).unwrap();
+        let data = [&[FORMAT_VERSION][..], &header_bytes].concat();
+
+        assert!(Recording::decode(&data, Source::Stored).is_err());
+    }
+
+    #[test]
+    fn ecg_is_not_the_respiration_channel() {
+        let header = MeasurementHeader {
+            channels: ChannelMask::ALL,
+            respiration_channel: Some(1),
+            ..MeasurementHeader::LEGACY
+        };
+        let data = [&[FORMAT_VERSION][..], &encode(&header, &SAMPLES[..4])].concat();
+
+        let recording = Recording::decode(&data, Source::Stored).unwrap();
+
+        assert_eq!(recording.ecg().number, 2);
+        assert_eq!(recording.ecg().samples, [100, 8_000_000]);
+        assert_eq!(recording.respiration().unwrap().label(), "RESP");
+    }
+}
@@ -0,0 +1,453 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    net::TcpListener,
+    num::NonZeroU32,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A request received by the stand-in backend.
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Reads a request with a `Content-Length` delimited body. Returns `None` if the connection
+    /// was closed before a request arrived.
+    pub fn read(reader: &mut impl BufRead) -> io::Result<Option<Self>> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let mut parts = line.split_whitespace();
+        let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+            return Err(invalid("Invalid request line"));
+        };
+        let method = method.to_string();
+        let path = path.to_string();
+
+        let mut headers = Vec::new();
+        loop {
+            line.clear();
+            reader.read_line(&mut line)?;
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+
+            let (name, value) = line.split_once(':').ok_or(invalid("Invalid header"))?;
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+
+        let mut request = Self {
+            method,
+            path,
+            headers,
+            body: Vec::new(),
+        };
+
+        if request.header("Transfer-Encoding").is_some() {
+            return Err(invalid(
+                "Only Content-Length delimited bodies are supported",
+            ));
+        }
+        if let Some(length) = request.header("Content-Length") {
+            let length = length
+                .parse()
+                .map_err(|_| invalid("Invalid Content-Length"))?;
+            request.body = vec![0; length];
+            reader.read_exact(&mut request.body)?;
+        }
+
+        Ok(Some(request))
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    fn new(status: u16, body: impl ToString) -> Self {
+        Self {
+            status,
+            body: body.to_string(),
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            201 => "Created",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            409 => "Conflict",
+            _ => "Internal Server Error",
+        }
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        write!(
+            writer,
+            "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            self.reason(),
+            self.body.len(),
+            self.body
+        )?;
+        writer.flush()
+    }
+}
+
+struct Session {
+    serial: String,
+    timestamp: u64,
+    total_len: usize,
+    data: Vec<u8>,
+}
+
+/// A stand-in for the backend's chunked upload endpoints, as described in
+/// `signal_processing::upload`. Sessions are kept in memory, completed uploads are written to
+/// the output directory as `<serial>-<timestamp>-<session>.bin` request bodies.
+pub struct UploadServer {
+    output: PathBuf,
+    sessions: HashMap<String, Session>,
+    /// Makes session ids unique across restarts of the server.
+    id_prefix: u64,
+    next_id: u64,
+}
+
+impl UploadServer {
+    pub fn new(output: PathBuf) -> Self {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+
+        Self {
+            output,
+            sessions: HashMap::new(),
+            id_prefix: started,
+            next_id: 0,
+        }
+    }
+
+    pub fn handle(&mut self, request: &Request) -> io::Result<Response> {
+        let path = request.path.trim_end_matches('/');
+        let Some((_, resource)) = path.split_once("/upload_session/") else {
+            return Ok(Response::new(404, "Unknown endpoint"));
+        };
+
+        match (request.method.as_str(), resource.split_once('/')) {
+            ("POST", None) => Ok(self.open_session(resource, request)),
+            ("GET", Some((_, id))) => Ok(self.query(id)),
+            ("PUT", Some((_, id))) => self.store_chunk(id, request),
+            _ => Ok(Response::new(405, "Unsupported method")),
+        }
+    }
+
+    fn open_session(&mut self, serial: &str, request: &Request) -> Response {
+        let Some(total_len) = request
+            .header("X-Upload-Length")
+            .and_then(|length| length.parse().ok())
+        else {
+            return Response::new(400, "Missing X-Upload-Length");
+        };
+        let timestamp = request
+            .header("X-Timestamp")
+            .and_then(|timestamp| timestamp.parse().ok())
+            .unwrap_or(0);
+
+        let id = format!("{:x}-{}", self.id_prefix, self.next_id);
+        self.next_id += 1;
+
+        self.sessions.insert(
+            id.clone(),
+            Session {
+                serial: serial.to_string(),
+                timestamp,
+                total_len,
+                data: Vec::new(),
+            },
+        );
+
+        Response::new(201, id)
+    }
+
+    fn query(&self, id: &str) -> Response {
+        match self.sessions.get(id) {
+            Some(session) => Response::new(200, session.data.len()),
+            None => Response::new(404, "Unknown session"),
+        }
+    }
+
+    fn store_chunk(&mut self, id: &str, request: &Request) -> io::Result<Response> {
+        let Some(session) = self.sessions.get_mut(id) else {
+            return Ok(Response::new(404, "Unknown session"));
+        };
+        let Some(offset) = request
+            .header("X-Upload-Offset")
+            .and_then(|offset| offset.parse::<usize>().ok())
+        else {
+            return Ok(Response::new(400, "Missing X-Upload-Offset"));
+        };
+
+        let stored = session.data.len();
+        if offset != stored {
+            return Ok(Response::new(409, stored));
+        }
+        if stored + request.body.len() > session.total_len {
+            return Ok(Response::new(400, "Chunk exceeds the upload length"));
+        }
+        if request.body.is_empty() {
+            return Ok(Response::new(400, "Empty chunk"));
+        }
+
+        session.data.extend_from_slice(&request.body);
+        let stored = session.data.len();
+        if stored < session.total_len {
+            return Ok(Response::new(200, stored));
+        }
+
+        fs::create_dir_all(&self.output)?;
+        let path = self
+            .output
+            .join(format!("{}-{}-{id}.bin", session.serial, session.timestamp));
+        fs::write(&path, &session.data)?;
+        println!("Upload complete: {}", path.display());
+
+        Ok(Response::new(201, stored))
+    }
+}
+
+/// Serves requests one connection at a time. If `drop_every` is set, the response to every
+/// `drop_every`th chunk is dropped after the chunk has been stored, like a lost acknowledgement.
+pub fn serve(
+    listener: TcpListener,
+    mut server: UploadServer,
+    drop_every: Option<NonZeroU32>,
+) -> io::Result<()> {
+    let mut chunks = 0;
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let mut reader = BufReader::new(&stream);
+
+        let request = match Request::read(&mut reader) {
+            Ok(Some(request)) => request,
+            Ok(None) => continue,
+            Err(e) => {
+                println!("Invalid request: {e}");
+                Response::new(400, e).write(&mut &stream)?;
+                continue;
+            }
+        };
+
+        let response = server.handle(&request)?;
+        println!("{} {} -> {}", request.method, request.path, response.status);
+
+        if request.method == "PUT" {
+            chunks += 1;
+            if drop_every.is_some_and(|n| chunks % n.get() == 0) {
+                println!("Dropping the response");
+                continue;
+            }
+        }
+
+        response.write(&mut &stream)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Read, net::TcpStream, path::Path, thread};
+
+    use protocol::upload::{parse_offset, SessionId, UploadSession};
+
+    use super::*;
+
+    fn output_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cardio-upload-{name}-{}", std::process::id()));
+        _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn request(method: &str, path: &str, headers: &[(&str, String)], body: &[u8]) -> Request {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.clone()))
+                .collect(),
+            body: body.to_vec(),
+        }
+    }
+
+    fn open(server: &mut UploadServer, data: &[u8]) -> UploadSession {
+        let headers = [
+            ("X-Upload-Length", data.len().to_string()),
+            ("X-Timestamp", "1700000000".to_string()),
+        ];
+        let response = server
+            .handle(&request("POST", "/api/upload_session/42", &headers, &[]))
+            .unwrap();
+        assert_eq!(response.status, 201);
+
+        let id = SessionId::parse(response.body.as_bytes()).unwrap();
+        UploadSession::new(id, data.len() as u32)
+    }
+
+    fn query(server: &mut UploadServer, session: &mut UploadSession) -> Response {
+        let path = format!("/api/upload_session/42/{}", session.id().as_str());
+        let response = server.handle(&request("GET", &path, &[], &[])).unwrap();
+        if response.status == 200 {
+            session
+                .acknowledge(parse_offset(response.body.as_bytes()).unwrap())
+                .unwrap();
+        }
+        response
+    }
+
+    fn send_chunk(
+        server: &mut UploadServer,
+        session: &mut UploadSession,
+        data: &[u8],
+        offset: usize,
+    ) -> Response {
+        let range = offset..(offset + 8).min(data.len());
+        let path = format!("/api/upload_session/42/{}", session.id().as_str());
+        let headers = [("X-Upload-Offset", offset.to_string())];
+
+        let response = server
+            .handle(&request("PUT", &path, &headers, &data[range]))
+            .unwrap();
+        if [200, 201, 409].contains(&response.status) {
+            session
+                .acknowledge(parse_offset(response.body.as_bytes()).unwrap())
+                .unwrap();
+        }
+        response
+    }
+
+    fn uploaded(dir: &Path, session: &UploadSession) -> Vec<u8> {
+        let name = format!("42-1700000000-{}.bin", session.id().as_str());
+        fs::read(dir.join(name)).unwrap()
+    }
+
+    #[test]
+    fn chunks_are_assembled() {
+        let dir = output_dir("assembled");
+        let mut server = UploadServer::new(dir.clone());
+        let data = (0..20).collect::<Vec<u8>>();
+
+        let mut session = open(&mut server, &data);
+        let mut statuses = Vec::new();
+        while let Some(range) = session.next_chunk(8) {
+            statuses.push(send_chunk(&mut server, &mut session, &data, range.start).status);
+        }
+
+        assert_eq!(statuses, [200, 200, 201]);
+        assert_eq!(uploaded(&dir, &session), data);
+    }
+
+    #[test]
+    fn lost_acknowledgement_is_recovered() {
+        let dir = output_dir("lost");
+        let mut server = UploadServer::new(dir.clone());
+        let data = (0..20).collect::<Vec<u8>>();
+
+        let mut session = open(&mut server, &data);
+        send_chunk(&mut server, &mut session, &data, 0);
+
+        // The backend stores the second chunk, but the device doesn't learn about it.
+        let mut lost = session;
+        send_chunk(&mut server, &mut lost, &data, 8);
+
+        // Sending the chunk again is rejected with the stored offset.
+        let response = send_chunk(&mut server, &mut session, &data, 8);
+        assert_eq!(response, Response::new(409, 16));
+        assert_eq!(session.next_chunk(8), Some(16..20));
+
+        send_chunk(&mut server, &mut session, &data, 16);
+        assert_eq!(uploaded(&dir, &session), data);
+    }
+
+    #[test]
+    fn upload_resumes_after_restart() {
+        let dir = output_dir("resume");
+        let mut server = UploadServer::new(dir.clone());
+        let data = (0..20).collect::<Vec<u8>>();
+
+        let mut session = open(&mut server, &data);
+        send_chunk(&mut server, &mut session, &data, 0);
+
+        // After a reboot, only the session id and the length are known.
+        let mut resumed = UploadSession::new(*session.id(), data.len() as u32);
+        assert_eq!(query(&mut server, &mut resumed).status, 200);
+        assert_eq!(resumed.next_chunk(8), Some(8..16));
+
+        while let Some(range) = resumed.next_chunk(8) {
+            send_chunk(&mut server, &mut resumed, &data, range.start);
+        }
+        assert_eq!(uploaded(&dir, &resumed), data);
+
+        // A session that was completed before the device could delete its file.
+        assert_eq!(query(&mut server, &mut session).status, 200);
+        assert!(session.is_complete());
+    }
+
+    #[test]
+    fn unknown_sessions_are_reported() {
+        let mut server = UploadServer::new(output_dir("unknown"));
+        let data = [0; 4];
+
+        let mut session = open(&mut server, &data);
+        let mut server = UploadServer::new(output_dir("unknown"));
+
+        assert_eq!(query(&mut server, &mut session).status, 404);
+        assert_eq!(send_chunk(&mut server, &mut session, &data, 0).status, 404);
+    }
+
+    #[test]
+    fn requests_are_served_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = UploadServer::new(output_dir("tcp"));
+        thread::spawn(move || serve(listener, server, None));
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .write_all(
+                b"POST /upload_session/42 HTTP/1.1\r\nHost: test\r\nX-Upload-Length: 10\r\n\
+                  Content-Length: 0\r\n\r\n",
+            )
+            .unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        let (head, id) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 201 Created\r\n"));
+        assert!(SessionId::parse(id.as_bytes()).is_ok());
+    }
+}
@@ -2,10 +2,11 @@ use core::{cell::RefCell, num::NonZeroU8};
 
 use embedded_graphics::{
     image::{Image, ImageRaw},
+    mono_font::{ascii::FONT_6X10, MonoTextStyle, MonoTextStyleBuilder},
     pixelcolor::BinaryColor,
-    prelude::{DrawTarget, OriginDimensions, Point},
-    primitives::{Line, Primitive, PrimitiveStyle},
-    text::{Baseline, Text},
+    prelude::{DrawTarget, OriginDimensions, Point, Size},
+    primitives::{Line, Primitive, PrimitiveStyle, Rectangle},
+    text::{Alignment, Baseline, Text, TextStyle, TextStyleBuilder},
     Drawable,
 };
 use itertools::Itertools;
@@ -118,6 +119,9 @@ impl Camera {
 pub struct EcgScreen {
     buffer: SlidingWindow<128>,
     pub heart_rate: Option<NonZeroU8>,
+    pub respiration_rate: Option<NonZeroU8>,
+    pub poor_contact: bool,
+    pub quality: Option<u8>,
     pub elapsed_secs: usize,
     camera: RefCell<Camera>,
 }
@@ -127,6 +131,9 @@ impl EcgScreen {
         Self {
             buffer: SlidingWindow::new(),
             heart_rate: None,
+            respiration_rate: None,
+            poor_contact: false,
+            quality: None,
             elapsed_secs: 0,
             camera: RefCell::new(Camera {
                 min_limit: Limit::new(LimitKind::Min),
@@ -174,6 +181,19 @@ impl EcgScreen {
     pub fn update_heart_rate(&mut self, hr: Option<NonZeroU8>) {
         self.heart_rate = hr;
     }
+
+    pub fn update_respiration_rate(&mut self, rate: Option<NonZeroU8>) {
+        self.respiration_rate = rate;
+    }
+
+    pub fn update_contact(&mut self, electrodes_connected: bool) {
+        self.poor_contact = !electrodes_connected;
+    }
+
+    /// Sets the signal quality score, from 0 to 100.
+    pub fn update_quality(&mut self, quality: Option<u8>) {
+        self.quality = quality;
+    }
 }
 
 impl Drawable for EcgScreen {
@@ -219,10 +239,56 @@ impl Drawable for EcgScreen {
             str_buffer.clear();
             unwrap!(uwrite!(&mut str_buffer, "{}", hr));
 
-            Text::with_baseline(&str_buffer, status_loc, NORMAL_TEXT, Baseline::Top)
+            status_loc = Text::with_baseline(&str_buffer, status_loc, NORMAL_TEXT, Baseline::Top)
+                .draw(display)?;
+        }
+
+        if let Some(rate) = self.respiration_rate {
+            const LUNGS: ImageRaw<'_, BinaryColor> = ImageRaw::new(
+                &[
+                    0b00010000, //
+                    0b00010000, //
+                    0b01101100, //
+                    0b11101110, //
+                    0b11101110, //
+                    0b11101110, //
+                    0b11000110, //
+                    0b00000000, //
+                ],
+                8,
+            );
+
+            status_loc += Point::new(2, 0);
+            Image::new(&LUNGS, status_loc).draw(display)?;
+            status_loc += Point::new(LUNGS.size().width as i32, 0);
+
+            str_buffer.clear();
+            unwrap!(uwrite!(&mut str_buffer, "{}", rate));
+
+            status_loc = Text::with_baseline(&str_buffer, status_loc, NORMAL_TEXT, Baseline::Top)
                 .draw(display)?;
         }
 
+        if let Some(quality) = self.quality {
+            const BAR_SIZE: Size = Size::new(12, 6);
+            const OUTLINE: PrimitiveStyle<BinaryColor> =
+                PrimitiveStyle::with_stroke(BinaryColor::On, 1);
+            const FILL: PrimitiveStyle<BinaryColor> = PrimitiveStyle::with_fill(BinaryColor::On);
+
+            let bar_loc = status_loc + Point::new(3, 1);
+            Rectangle::new(bar_loc, BAR_SIZE)
+                .into_styled(OUTLINE)
+                .draw(display)?;
+
+            let filled = (BAR_SIZE.width - 2) * quality.min(100) as u32 / 100;
+            Rectangle::new(
+                bar_loc + Point::new(1, 1),
+                Size::new(filled, BAR_SIZE.height - 2),
+            )
+            .into_styled(FILL)
+            .draw(display)?;
+        }
+
         let (min, max) = self.limits();
 
         let scaler = unwrap!(self.camera.try_borrow_mut()).update(min, max, display);
@@ -242,6 +308,28 @@ impl Drawable for EcgScreen {
             line.draw(display)?;
         }
 
+        if self.poor_contact {
+            // Drawn over the signal, with a background to keep it readable.
+            const WARNING_TEXT: MonoTextStyle<'static, BinaryColor> = MonoTextStyleBuilder::new()
+                .font(&FONT_6X10)
+                .text_color(BinaryColor::On)
+                .background_color(BinaryColor::Off)
+                .build();
+            const BOTTOM_RIGHT: TextStyle = TextStyleBuilder::new()
+                .alignment(Alignment::Right)
+                .baseline(Baseline::Bottom)
+                .build();
+
+            let size = display.bounding_box().size;
+            Text::with_text_style(
+                "Poor contact",
+                Point::new(size.width as i32 - 1, size.height as i32 - 1),
+                WARNING_TEXT,
+                BOTTOM_RIGHT,
+            )
+            .draw(display)?;
+        }
+
         Ok(())
     }
 }
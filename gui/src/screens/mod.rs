@@ -22,6 +22,7 @@ pub mod init;
 pub mod measure;
 pub mod message;
 pub mod qr;
+pub mod summary;
 pub mod wifi_ap;
 
 pub const fn menu_style<R>(
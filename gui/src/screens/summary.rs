@@ -0,0 +1,112 @@
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::DrawTarget, Drawable};
+use embedded_text::TextBox;
+use ufmt::uwrite;
+
+use crate::screens::{CENTERED_TEXTBOX, NORMAL_TEXT};
+
+/// Shown after a measurement. Values that could not be computed are displayed as dashes.
+pub struct MeasurementSummaryScreen {
+    pub duration_secs: u32,
+    /// Average heart rate in beats per minute.
+    pub heart_rate: Option<u8>,
+    /// RMSSD heart rate variability, in milliseconds.
+    pub rmssd_ms: Option<u16>,
+    /// Whether irregular rhythm screening flagged the measurement. This is only an advisory.
+    pub irregular_rhythm: bool,
+}
+
+impl Drawable for MeasurementSummaryScreen {
+    type Color = BinaryColor;
+    type Output = ();
+
+    #[inline]
+    fn draw<D>(&self, display: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        let mut text = heapless::String::<96>::new();
+
+        unwrap!(uwrite!(
+            &mut text,
+            "Duration: {}s\nAvg HR: ",
+            self.duration_secs
+        ));
+        match self.heart_rate {
+            Some(hr) => unwrap!(uwrite!(&mut text, "{} BPM", hr)),
+            None => unwrap!(uwrite!(&mut text, "--")),
+        }
+        unwrap!(uwrite!(&mut text, "\nRMSSD: "));
+        match self.rmssd_ms {
+            Some(rmssd) => unwrap!(uwrite!(&mut text, "{} ms", rmssd)),
+            None => unwrap!(uwrite!(&mut text, "--")),
+        }
+        if self.irregular_rhythm {
+            unwrap!(uwrite!(&mut text, "\nIrregular rhythm"));
+        }
+
+        TextBox::with_textbox_style(&text, display.bounding_box(), NORMAL_TEXT, CENTERED_TEXTBOX)
+            .draw(display)?;
+
+        Ok(())
+    }
+}
+
+/// Shown after [`MeasurementSummaryScreen`] if intervals could be measured on the median beat.
+pub struct IntervalSummaryScreen {
+    /// PR interval in milliseconds.
+    pub pr_ms: Option<u16>,
+    /// QRS duration in milliseconds.
+    pub qrs_ms: u16,
+    /// QT interval in milliseconds.
+    pub qt_ms: Option<u16>,
+    /// Heart rate corrected QT interval in milliseconds.
+    pub qtc_ms: Option<u16>,
+    /// ST deviation in microvolts.
+    pub st_uv: Option<i16>,
+}
+
+impl Drawable for IntervalSummaryScreen {
+    type Color = BinaryColor;
+    type Output = ();
+
+    #[inline]
+    fn draw<D>(&self, display: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        let mut text = heapless::String::<96>::new();
+
+        unwrap!(uwrite!(&mut text, "PR: "));
+        match self.pr_ms {
+            Some(pr) => unwrap!(uwrite!(&mut text, "{} ms", pr)),
+            None => unwrap!(uwrite!(&mut text, "--")),
+        }
+        unwrap!(uwrite!(&mut text, "\nQRS: {} ms\nQT/QTc: ", self.qrs_ms));
+        match (self.qt_ms, self.qtc_ms) {
+            (Some(qt), Some(qtc)) => unwrap!(uwrite!(&mut text, "{}/{} ms", qt, qtc)),
+            _ => unwrap!(uwrite!(&mut text, "--")),
+        }
+        unwrap!(uwrite!(&mut text, "\nST: "));
+        match self.st_uv {
+            Some(st) => {
+                // Displayed in millivolts, with two decimals.
+                let sign = if st < 0 { "-" } else { "" };
+                let hundredths = (st.unsigned_abs() + 5) / 10;
+                unwrap!(uwrite!(
+                    &mut text,
+                    "{}{}.{}{} mV",
+                    sign,
+                    hundredths / 100,
+                    hundredths / 10 % 10,
+                    hundredths % 10
+                ));
+            }
+            None => unwrap!(uwrite!(&mut text, "--")),
+        }
+
+        TextBox::with_textbox_style(&text, display.bounding_box(), NORMAL_TEXT, CENTERED_TEXTBOX)
+            .draw(display)?;
+
+        Ok(())
+    }
+}
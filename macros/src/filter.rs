@@ -1,9 +1,13 @@
+use num_complex::Complex64;
 use proc_macro2::{Span, TokenStream};
 use quote::quote;
 use sci_rs::signal::filter::design::{
     iirfilter_dyn, BaFormatFilter, DigitalFilter, FilterBandType, FilterOutputType, FilterType,
 };
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    f64::consts::{PI, TAU},
+};
 use syn::{
     parse::{Parse, ParseBuffer},
     Lit, LitStr, Token,
@@ -13,6 +17,11 @@ use syn::{
 enum FilterKind {
     HighPassIir,
     LowPassIir,
+    BandPassIir,
+    BandStopIir,
+    NotchIir,
+    LowPassFir,
+    HighPassFir,
 }
 
 impl Parse for FilterKind {
@@ -23,6 +32,11 @@ impl Parse for FilterKind {
         let filter_kind = match filter_kind.as_str() {
             "highpassiir" => FilterKind::HighPassIir,
             "lowpassiir" => FilterKind::LowPassIir,
+            "bandpassiir" => FilterKind::BandPassIir,
+            "bandstopiir" => FilterKind::BandStopIir,
+            "notchiir" => FilterKind::NotchIir,
+            "lowpassfir" => FilterKind::LowPassFir,
+            "highpassfir" => FilterKind::HighPassFir,
 
             _ => {
                 return Err(syn::Error::new(
@@ -36,10 +50,15 @@ impl Parse for FilterKind {
     }
 }
 
+enum OptionValue {
+    Number(f32),
+    Text(LitStr),
+}
+
 pub struct FilterSpec {
     filter_kind: FilterKind,
     span: Span,
-    options: HashMap<String, f32>,
+    options: HashMap<String, OptionValue>,
 }
 
 impl Parse for FilterSpec {
@@ -57,9 +76,15 @@ impl Parse for FilterSpec {
             let value = input.parse::<Lit>()?;
 
             let value = match value {
-                Lit::Int(lit) => lit.base10_parse().unwrap(),
-                Lit::Float(lit) => lit.base10_parse().unwrap(),
-                _ => return Err(syn::Error::new_spanned(value, "expected a number")),
+                Lit::Int(lit) => OptionValue::Number(lit.base10_parse().unwrap()),
+                Lit::Float(lit) => OptionValue::Number(lit.base10_parse().unwrap()),
+                Lit::Str(lit) => OptionValue::Text(lit),
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        value,
+                        "expected a number or a string",
+                    ))
+                }
             };
 
             if options.insert(keystr, value).is_some() {
@@ -78,20 +103,92 @@ impl Parse for FilterSpec {
     }
 }
 
+impl FilterSpec {
+    fn number(&self, name: &str) -> syn::Result<Option<f32>> {
+        match self.options.get(name) {
+            None => Ok(None),
+            Some(OptionValue::Number(value)) => Ok(Some(*value)),
+            Some(OptionValue::Text(lit)) => Err(syn::Error::new(
+                lit.span(),
+                format!("'{name}' must be a number"),
+            )),
+        }
+    }
+
+    fn required_number(&self, name: &str, display_name: &str) -> syn::Result<f32> {
+        self.number(name)?.ok_or_else(|| {
+            syn::Error::new(
+                self.span,
+                format!("missing required option '{display_name}'"),
+            )
+        })
+    }
+
+    fn text(&self, name: &str) -> syn::Result<Option<LitStr>> {
+        match self.options.get(name) {
+            None => Ok(None),
+            Some(OptionValue::Text(lit)) => Ok(Some(lit.clone())),
+            Some(OptionValue::Number(_)) => Err(syn::Error::new(
+                self.span,
+                format!("'{name}' must be a string"),
+            )),
+        }
+    }
+
+    fn order(&self) -> syn::Result<usize> {
+        let order = self.required_number("filterorder", "FilterOrder")?;
+        if order < 1.0 || order.fract() != 0.0 {
+            return Err(syn::Error::new(
+                self.span,
+                "'FilterOrder' must be a positive integer",
+            ));
+        }
+        Ok(order as usize)
+    }
+
+    /// Returns the sample rate. Without one, frequencies are normalized to the Nyquist frequency.
+    fn sample_rate(&self) -> syn::Result<f64> {
+        match self.number("samplerate")? {
+            Some(fs) if fs > 0.0 => Ok(fs as f64),
+            Some(_) => Err(syn::Error::new(self.span, "'SampleRate' must be positive")),
+            None => Ok(2.0),
+        }
+    }
+
+    fn frequency(&self, name: &str, display_name: &str) -> syn::Result<f64> {
+        let fs = self.sample_rate()?;
+        let frequency = self.required_number(name, display_name)? as f64;
+        if frequency <= 0.0 || frequency >= fs / 2.0 {
+            return Err(syn::Error::new(
+                self.span,
+                format!("'{display_name}' must be between 0 and half the sample rate"),
+            ));
+        }
+        Ok(frequency)
+    }
+}
+
 fn validate_filter_option(filter_kind: FilterKind, key: &LitStr) -> syn::Result<String> {
-    let expected = match filter_kind {
-        FilterKind::HighPassIir => &[
+    let expected: &[&str] = match filter_kind {
+        FilterKind::HighPassIir | FilterKind::LowPassIir => &[
             "filterorder",
             "passbandfrequency",
             "halfpowerfrequency",
             "passbandripple",
             "samplerate",
         ],
-        FilterKind::LowPassIir => &[
+        FilterKind::BandPassIir | FilterKind::BandStopIir => &[
             "filterorder",
-            "passbandfrequency",
-            "halfpowerfrequency",
-            "passbandripple",
+            "halfpowerfrequency1",
+            "halfpowerfrequency2",
+            "samplerate",
+        ],
+        FilterKind::NotchIir => &["centerfrequency", "bandwidth", "samplerate"],
+        FilterKind::LowPassFir | FilterKind::HighPassFir => &[
+            "filterorder",
+            "cutofffrequency",
+            "designmethod",
+            "window",
             "samplerate",
         ],
     };
@@ -107,35 +204,26 @@ fn validate_filter_option(filter_kind: FilterKind, key: &LitStr) -> syn::Result<
     Ok(value)
 }
 
-fn iir(args: FilterSpec, ty: FilterBandType) -> TokenStream {
-    let Some(&order) = args.options.get("filterorder") else {
-        return syn::Error::new(args.span, "missing required option 'FilterOrder'")
-            .to_compile_error();
-    };
+fn iir(args: FilterSpec, ty: FilterBandType) -> syn::Result<TokenStream> {
+    let order = args.order()?;
+    let half_power = args.required_number("halfpowerfrequency", "HalfPowerFrequency")?;
 
     let kind = match ty {
         FilterBandType::Lowpass => quote! { LowPass },
         FilterBandType::Highpass => quote! { HighPass },
-        FilterBandType::Bandpass => {
-            return syn::Error::new(args.span, "bandpass filters are not supported")
-                .to_compile_error();
-        }
-        FilterBandType::Bandstop => {
-            return syn::Error::new(args.span, "bandstop filters are not supported")
-                .to_compile_error();
-        }
+        FilterBandType::Bandpass | FilterBandType::Bandstop => unreachable!(),
     };
 
     let filter = iirfilter_dyn(
-        order as usize,
-        vec![args.options.get("halfpowerfrequency").copied().unwrap()],
+        order,
+        vec![half_power],
         None,
         None,
         Some(ty),
         Some(FilterType::Butterworth),
         Some(false),
         Some(FilterOutputType::Ba),
-        args.options.get("samplerate").copied(),
+        args.number("samplerate")?,
     );
 
     let DigitalFilter::Ba(BaFormatFilter { mut b, mut a }) = filter else {
@@ -162,14 +250,261 @@ fn iir(args: FilterSpec, ty: FilterBandType) -> TokenStream {
 
     let n = a.len();
 
+    Ok(quote! {
+        Iir::<#kind, #n>::new(&[#(#b,)*], &[#(#a,)*])
+    })
+}
+
+/// Expands the polynomial with the given roots, highest power first.
+fn poly(roots: &[Complex64]) -> Vec<f64> {
+    let mut coeffs = vec![Complex64::new(1.0, 0.0)];
+    for root in roots {
+        coeffs.push(Complex64::new(0.0, 0.0));
+        for i in (1..coeffs.len()).rev() {
+            let previous = coeffs[i - 1];
+            coeffs[i] -= *root * previous;
+        }
+    }
+
+    // Roots come in conjugate pairs, so the imaginary parts cancel out.
+    coeffs.iter().map(|c| c.re).collect()
+}
+
+/// Maps an analog filter, given as zeros, poles and gain, to a digital one with the bilinear
+/// transform. Returns the numerator and denominator coefficients, highest power first.
+fn bilinear(zeros: &[Complex64], poles: &[Complex64], gain: f64, fs: f64) -> (Vec<f64>, Vec<f64>) {
+    let fs2 = 2.0 * fs;
+    let map = |s: &Complex64| (fs2 + *s) / (fs2 - *s);
+
+    let mut digital_zeros = zeros.iter().map(map).collect::<Vec<_>>();
+    let digital_poles = poles.iter().map(map).collect::<Vec<_>>();
+
+    // Zeros at infinity are mapped to the Nyquist frequency.
+    digital_zeros.resize(poles.len(), Complex64::new(-1.0, 0.0));
+
+    let gain = gain
+        * (zeros.iter().map(|z| fs2 - *z).product::<Complex64>()
+            / poles.iter().map(|p| fs2 - *p).product::<Complex64>())
+        .re;
+
+    let b = poly(&digital_zeros).into_iter().map(|b| b * gain).collect();
+    let a = poly(&digital_poles);
+
+    (b, a)
+}
+
+/// Butterworth band-pass or band-stop filter, designed the same way as MATLAB's `butter`.
+fn band_iir(args: FilterSpec, stop: bool) -> syn::Result<TokenStream> {
+    let order = args.order()?;
+    if order % 2 != 0 {
+        return Err(syn::Error::new(
+            args.span,
+            "'FilterOrder' must be even for band filters",
+        ));
+    }
+
+    let fs = args.sample_rate()?;
+    let f1 = args.frequency("halfpowerfrequency1", "HalfPowerFrequency1")?;
+    let f2 = args.frequency("halfpowerfrequency2", "HalfPowerFrequency2")?;
+    if f1 >= f2 {
+        return Err(syn::Error::new(
+            args.span,
+            "'HalfPowerFrequency1' must be lower than 'HalfPowerFrequency2'",
+        ));
+    }
+
+    // Prewarped band edges
+    let w1 = 2.0 * fs * (PI * f1 / fs).tan();
+    let w2 = 2.0 * fs * (PI * f2 / fs).tan();
+    let bandwidth = w2 - w1;
+    let center = (w1 * w2).sqrt();
+
+    // Analog low-pass prototype of half the order, with unity gain and no zeros
+    let prototype_order = order / 2;
+    let prototype = (0..prototype_order).map(|k| {
+        let angle = PI * (2 * k + prototype_order + 1) as f64 / (2 * prototype_order) as f64;
+        Complex64::from_polar(1.0, angle)
+    });
+
+    let mut zeros = Vec::new();
+    let mut poles = Vec::new();
+    let gain = if stop {
+        for p in prototype {
+            let half = bandwidth / 2.0 / p;
+            let root = (half * half - center * center).sqrt();
+            poles.extend([half + root, half - root]);
+            zeros.extend([Complex64::new(0.0, center), Complex64::new(0.0, -center)]);
+        }
+        1.0
+    } else {
+        for p in prototype {
+            let half = p * bandwidth / 2.0;
+            let root = (half * half - center * center).sqrt();
+            poles.extend([half + root, half - root]);
+            zeros.push(Complex64::new(0.0, 0.0));
+        }
+        bandwidth.powi(prototype_order as i32)
+    };
+
+    let (b, a) = bilinear(&zeros, &poles, gain, fs);
+
+    Ok(iir_tokens(
+        if stop {
+            quote! { BandStop }
+        } else {
+            quote! { BandPass }
+        },
+        b,
+        a,
+    ))
+}
+
+/// Second order notch filter, designed the same way as MATLAB's `iirnotch`.
+fn notch_iir(args: FilterSpec) -> syn::Result<TokenStream> {
+    let fs = args.sample_rate()?;
+    let center = args.frequency("centerfrequency", "CenterFrequency")?;
+    let bandwidth = args.frequency("bandwidth", "Bandwidth")?;
+
+    let w0 = TAU * center / fs;
+    let bw = TAU * bandwidth / fs;
+
+    let gain = 1.0 / (1.0 + (bw / 2.0).tan());
+    let b = vec![gain, -2.0 * gain * w0.cos(), gain];
+    let a = vec![1.0, -2.0 * gain * w0.cos(), 2.0 * gain - 1.0];
+
+    Ok(iir_tokens(quote! { BandStop }, b, a))
+}
+
+/// Generates an `Iir` from coefficients in their usual order, highest power first.
+fn iir_tokens(kind: TokenStream, b: Vec<f64>, mut a: Vec<f64>) -> TokenStream {
+    let b = b.into_iter().map(|b| b as f32).collect::<Vec<_>>();
+
+    // Strip off always-1 coefficient and reverse the rest to avoid having to reverse it during
+    // filtering
+    a.remove(0);
+    let a = a.into_iter().rev().map(|a| a as f32).collect::<Vec<_>>();
+
+    let n = a.len();
+
     quote! {
         Iir::<#kind, #n>::new(&[#(#b,)*], &[#(#a,)*])
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Window {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+}
+
+impl Window {
+    fn parse(lit: Option<LitStr>) -> syn::Result<Self> {
+        let Some(lit) = lit else {
+            return Ok(Self::Hamming);
+        };
+
+        match lit.value().to_ascii_lowercase().as_str() {
+            "rectangular" | "rectwin" => Ok(Self::Rectangular),
+            "hann" => Ok(Self::Hann),
+            "hamming" => Ok(Self::Hamming),
+            "blackman" => Ok(Self::Blackman),
+            other => Err(syn::Error::new(
+                lit.span(),
+                format!("unknown window: {other}"),
+            )),
+        }
+    }
+
+    /// The value of the window at `n`, for a filter of the given order.
+    fn at(self, n: usize, order: usize) -> f64 {
+        let x = TAU * n as f64 / order as f64;
+        match self {
+            Window::Rectangular => 1.0,
+            Window::Hann => 0.5 - 0.5 * x.cos(),
+            Window::Hamming => 0.54 - 0.46 * x.cos(),
+            Window::Blackman => 0.42 - 0.5 * x.cos() + 0.08 * (2.0 * x).cos(),
+        }
+    }
+}
+
+/// Windowed-sinc FIR filter, designed the same way as MATLAB's `fir1`.
+fn fir(args: FilterSpec, high_pass: bool) -> syn::Result<TokenStream> {
+    if let Some(method) = args.text("designmethod")? {
+        if !method.value().eq_ignore_ascii_case("window") {
+            return Err(syn::Error::new(
+                method.span(),
+                "only the 'window' design method is supported",
+            ));
+        }
+    }
+
+    let order = args.order()?;
+    if high_pass && order % 2 != 0 {
+        return Err(syn::Error::new(
+            args.span,
+            "'FilterOrder' must be even for high-pass FIR filters",
+        ));
+    }
+
+    let fs = args.sample_rate()?;
+    let cutoff = args.frequency("cutofffrequency", "CutoffFrequency")? / fs;
+    let window = Window::parse(args.text("window")?)?;
+
+    let mut coeffs = (0..=order)
+        .map(|n| {
+            let t = n as f64 - order as f64 / 2.0;
+            let sinc = if t == 0.0 {
+                2.0 * cutoff
+            } else {
+                (TAU * cutoff * t).sin() / (PI * t)
+            };
+            let ideal = if high_pass {
+                if t == 0.0 {
+                    1.0 - sinc
+                } else {
+                    -sinc
+                }
+            } else {
+                sinc
+            };
+            ideal * window.at(n, order)
+        })
+        .collect::<Vec<_>>();
+
+    // Scale for unity gain in the middle of the passband: at DC for low-pass, and at the
+    // Nyquist frequency for high-pass filters.
+    let gain = coeffs
+        .iter()
+        .enumerate()
+        .map(|(n, c)| if high_pass && n % 2 == 1 { -c } else { *c })
+        .sum::<f64>();
+    let gain = if high_pass && (order / 2) % 2 == 1 {
+        -gain
+    } else {
+        gain
+    };
+    coeffs.iter_mut().for_each(|c| *c /= gain);
+
+    let coeffs = coeffs.into_iter().map(|c| c as f32);
+    let taps = order + 1;
+
+    Ok(quote! {
+        Fir::<#taps>::from_coeffs(&[#(#coeffs,)*])
+    })
+}
+
 pub fn run(args: FilterSpec) -> TokenStream {
-    match args.filter_kind {
+    let result = match args.filter_kind {
         FilterKind::HighPassIir => iir(args, FilterBandType::Highpass),
         FilterKind::LowPassIir => iir(args, FilterBandType::Lowpass),
-    }
+        FilterKind::BandPassIir => band_iir(args, false),
+        FilterKind::BandStopIir => band_iir(args, true),
+        FilterKind::NotchIir => notch_iir(args),
+        FilterKind::LowPassFir => fir(args, false),
+        FilterKind::HighPassFir => fir(args, true),
+    };
+
+    result.unwrap_or_else(syn::Error::into_compile_error)
 }
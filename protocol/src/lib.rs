@@ -0,0 +1,9 @@
+//! Network protocols shared by the firmware and the host tools.
+
+#![cfg_attr(not(test), no_std)]
+
+#[macro_use]
+extern crate logger;
+
+pub mod stream;
+pub mod upload;
@@ -0,0 +1,385 @@
+//! Live sample stream format
+//!
+//! During a measurement, the device can send the samples to the local network as they arrive.
+//! Every UDP datagram holds one packet: a header followed by a number of frames. Each frame holds
+//! one sample of every channel in the header's channel mask, in ascending channel order, as 4
+//! little endian bytes. Raw samples are `i32` ADC codes, filtered samples are `f32` volts.
+//!
+//! The header is, in little endian byte order:
+//!
+//! | Offset | Size | Field                                           |
+//! |--------|------|-------------------------------------------------|
+//! | 0      | 4    | Magic, `CIOS`                                   |
+//! | 4      | 1    | Format version                                  |
+//! | 5      | 1    | Sample kind, 0 for raw and 1 for filtered       |
+//! | 6      | 1    | Channel mask                                    |
+//! | 7      | 1    | PGA gain                                        |
+//! | 8      | 2    | Sample rate                                     |
+//! | 10     | 2    | ADC reference voltage in millivolts             |
+//! | 12     | 4    | Index of the first frame since the stream began |
+//! | 16     | 2    | Number of frames                                |
+//!
+//! Datagrams may be lost or reordered, receivers can tell from the frame indices. A frame index
+//! that is lower than expected means a new stream has started.
+//!
+//! On the device's own access point, the packets are broadcast to [`STREAM_PORT`]. On other
+//! networks, they are only sent to a receiver that asked for them: receivers broadcast a
+//! [`subscription`] to [`STREAM_PORT`] every [`SUBSCRIPTION_INTERVAL_S`] seconds, and the device
+//! sends the packets to the address and port of the latest one. The device stops sending if no
+//! subscription arrives for [`SUBSCRIPTION_TIMEOUT_S`] seconds.
+
+use signal_processing::measurement::ChannelMask;
+
+/// The UDP port the stream is sent to.
+pub const STREAM_PORT: u16 = 5551;
+
+/// The current stream format version.
+pub const STREAM_VERSION: u8 = 1;
+
+/// The largest packet the device sends, header included.
+pub const MAX_PACKET_LEN: usize = 1024;
+
+/// How often receivers send a subscription, in seconds.
+pub const SUBSCRIPTION_INTERVAL_S: u64 = 2;
+
+/// How long a subscription lasts, in seconds.
+pub const SUBSCRIPTION_TIMEOUT_S: u64 = 10;
+
+/// The length of a subscription.
+pub const SUBSCRIPTION_LEN: usize = 5;
+
+const MAGIC: [u8; 4] = *b"CIOS";
+
+const SUBSCRIPTION_MAGIC: [u8; 4] = *b"CIOR";
+
+/// Every sample takes this many bytes.
+const SAMPLE_LEN: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StreamError {
+    /// The packet is shorter than its header says.
+    Truncated,
+    /// The packet does not start with the stream magic.
+    NotAStreamPacket,
+    /// The packet was produced by a newer firmware.
+    UnsupportedVersion(u8),
+    /// A header field has an invalid value.
+    InvalidHeader,
+}
+
+/// Returns the datagram a receiver sends to ask for the stream: `CIOR` followed by the format
+/// version.
+pub fn subscription() -> [u8; SUBSCRIPTION_LEN] {
+    let mut bytes = [0; SUBSCRIPTION_LEN];
+    bytes[0..4].copy_from_slice(&SUBSCRIPTION_MAGIC);
+    bytes[4] = STREAM_VERSION;
+    bytes
+}
+
+/// Returns whether a datagram is a subscription to the current stream format.
+pub fn is_subscription(bytes: &[u8]) -> bool {
+    bytes == subscription()
+}
+
+/// The kind of samples in a stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SampleKind {
+    /// ADC codes, as stored in recordings.
+    Raw = 0,
+    /// The output of the device's ECG filter, in volts.
+    Filtered = 1,
+}
+
+/// Describes the frames of a packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PacketHeader {
+    pub kind: SampleKind,
+    /// The channels in a frame.
+    pub channels: ChannelMask,
+    /// PGA gain of the channels.
+    pub gain: u8,
+    /// Frames per second.
+    pub sample_rate: u16,
+    /// ADC reference voltage in millivolts.
+    pub reference_mv: u16,
+    /// The index of the packet's first frame, counted from the start of the stream.
+    pub first_frame: u32,
+    /// The number of frames in the packet.
+    pub frames: u16,
+}
+
+impl PacketHeader {
+    pub const ENCODED_LEN: usize = 18;
+
+    pub fn to_le_bytes(self) -> [u8; Self::ENCODED_LEN] {
+        let mut bytes = [0; Self::ENCODED_LEN];
+        bytes[0..4].copy_from_slice(&MAGIC);
+        bytes[4] = STREAM_VERSION;
+        bytes[5] = self.kind as u8;
+        bytes[6] = self.channels.bits();
+        bytes[7] = self.gain;
+        bytes[8..10].copy_from_slice(&self.sample_rate.to_le_bytes());
+        bytes[10..12].copy_from_slice(&self.reference_mv.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.first_frame.to_le_bytes());
+        bytes[16..18].copy_from_slice(&self.frames.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_le_bytes(bytes: [u8; Self::ENCODED_LEN]) -> Result<Self, StreamError> {
+        if bytes[0..4] != MAGIC {
+            return Err(StreamError::NotAStreamPacket);
+        }
+        if bytes[4] != STREAM_VERSION {
+            return Err(StreamError::UnsupportedVersion(bytes[4]));
+        }
+
+        let kind = match bytes[5] {
+            0 => SampleKind::Raw,
+            1 => SampleKind::Filtered,
+            _ => return Err(StreamError::InvalidHeader),
+        };
+        let channels = ChannelMask::from_bits(bytes[6]).ok_or(StreamError::InvalidHeader)?;
+        let header = Self {
+            kind,
+            channels,
+            gain: bytes[7],
+            sample_rate: u16::from_le_bytes([bytes[8], bytes[9]]),
+            reference_mv: u16::from_le_bytes([bytes[10], bytes[11]]),
+            first_frame: u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]),
+            frames: u16::from_le_bytes([bytes[16], bytes[17]]),
+        };
+        if header.gain == 0 || header.sample_rate == 0 {
+            return Err(StreamError::InvalidHeader);
+        }
+
+        Ok(header)
+    }
+
+    /// Returns the number of bytes the packet's frames take.
+    pub fn data_len(&self) -> usize {
+        self.frames as usize * self.channels.count() * SAMPLE_LEN
+    }
+
+    /// Converts an encoded sample to volts.
+    fn volts(&self, sample: [u8; SAMPLE_LEN]) -> f32 {
+        match self.kind {
+            SampleKind::Raw => {
+                let volts_per_lsb =
+                    self.reference_mv as f32 / 1000.0 / self.gain as f32 / (1 << 23) as f32;
+                i32::from_le_bytes(sample) as f32 * volts_per_lsb
+            }
+            SampleKind::Filtered => f32::from_le_bytes(sample),
+        }
+    }
+}
+
+/// A received packet.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Packet<'a> {
+    pub header: PacketHeader,
+    data: &'a [u8],
+}
+
+impl<'a> Packet<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, StreamError> {
+        let Some((header, data)) = bytes.split_first_chunk::<{ PacketHeader::ENCODED_LEN }>()
+        else {
+            return Err(StreamError::Truncated);
+        };
+
+        let header = PacketHeader::from_le_bytes(*header)?;
+        let data = data
+            .get(..header.data_len())
+            .ok_or(StreamError::Truncated)?;
+
+        Ok(Self { header, data })
+    }
+
+    /// Iterates over the samples in volts, frame by frame.
+    pub fn volts(&self) -> impl Iterator<Item = f32> + 'a {
+        let header = self.header;
+        self.data
+            .chunks_exact(SAMPLE_LEN)
+            .map(move |sample| header.volts([sample[0], sample[1], sample[2], sample[3]]))
+    }
+}
+
+/// Collects frames into packets.
+pub struct Packetizer {
+    buffer: [u8; MAX_PACKET_LEN],
+    header: PacketHeader,
+    frames_per_packet: u16,
+}
+
+impl Packetizer {
+    /// Creates a packetizer that sends `frames_per_packet` frames at once, or as many as fit into a
+    /// packet. The header's frame fields are ignored.
+    pub fn new(header: PacketHeader, frames_per_packet: u16) -> Self {
+        let frame_len = header.channels.count() * SAMPLE_LEN;
+        let max_frames = (MAX_PACKET_LEN - PacketHeader::ENCODED_LEN) / frame_len;
+
+        Self {
+            buffer: [0; MAX_PACKET_LEN],
+            header: PacketHeader {
+                first_frame: 0,
+                frames: 0,
+                ..header
+            },
+            frames_per_packet: frames_per_packet.clamp(1, max_frames as u16),
+        }
+    }
+
+    /// Adds a frame of ADC codes. Returns the packet when it is complete.
+    pub fn push_raw(&mut self, frame: &[i32]) -> Option<&[u8]> {
+        debug_assert_eq!(self.header.kind, SampleKind::Raw);
+        self.push_frame(frame.iter().map(|sample| sample.to_le_bytes()))
+    }
+
+    /// Adds a frame of filtered samples. Returns the packet when it is complete.
+    pub fn push_filtered(&mut self, frame: &[f32]) -> Option<&[u8]> {
+        debug_assert_eq!(self.header.kind, SampleKind::Filtered);
+        self.push_frame(frame.iter().map(|sample| sample.to_le_bytes()))
+    }
+
+    fn push_frame(
+        &mut self,
+        samples: impl ExactSizeIterator<Item = [u8; SAMPLE_LEN]>,
+    ) -> Option<&[u8]> {
+        debug_assert_eq!(samples.len(), self.header.channels.count());
+
+        // The previous call returned a complete packet, start a new one.
+        if self.header.frames == self.frames_per_packet {
+            self.header.first_frame = self
+                .header
+                .first_frame
+                .wrapping_add(self.header.frames as u32);
+            self.header.frames = 0;
+        }
+
+        let start = PacketHeader::ENCODED_LEN + self.header.data_len();
+        for (sample, bytes) in samples.zip(self.buffer[start..].chunks_exact_mut(SAMPLE_LEN)) {
+            bytes.copy_from_slice(&sample);
+        }
+        self.header.frames += 1;
+
+        if self.header.frames < self.frames_per_packet {
+            return None;
+        }
+
+        self.buffer[..PacketHeader::ENCODED_LEN].copy_from_slice(&self.header.to_le_bytes());
+        Some(&self.buffer[..PacketHeader::ENCODED_LEN + self.header.data_len()])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn header(kind: SampleKind, channels: ChannelMask) -> PacketHeader {
+        PacketHeader {
+            kind,
+            channels,
+            gain: 1,
+            sample_rate: 1000,
+            reference_mv: 2420,
+            first_frame: 0,
+            frames: 0,
+        }
+    }
+
+    #[test]
+    fn raw_frames_roundtrip() {
+        let mut packetizer = Packetizer::new(header(SampleKind::Raw, ChannelMask::ALL), 2);
+
+        assert_eq!(packetizer.push_raw(&[1 << 23, -(1 << 22)]), None);
+        let packet = packetizer.push_raw(&[0, 1 << 20]).unwrap().to_vec();
+
+        let packet = Packet::parse(&packet).unwrap();
+        assert_eq!(packet.header.first_frame, 0);
+        assert_eq!(packet.header.frames, 2);
+        assert_eq!(
+            packet.volts().collect::<Vec<_>>(),
+            [2.42, -1.21, 0.0, 2.42 / 8.0]
+        );
+    }
+
+    #[test]
+    fn filtered_frames_roundtrip() {
+        let mut packetizer = Packetizer::new(header(SampleKind::Filtered, ChannelMask::CH2), 1);
+
+        let packet = packetizer.push_filtered(&[1.5e-3]).unwrap();
+
+        let packet = Packet::parse(packet).unwrap();
+        assert_eq!(packet.header.channels, ChannelMask::CH2);
+        assert_eq!(packet.volts().collect::<Vec<_>>(), [1.5e-3]);
+    }
+
+    #[test]
+    fn packets_continue_frame_count() {
+        let mut packetizer = Packetizer::new(header(SampleKind::Raw, ChannelMask::CH1), 3);
+
+        let first_frames = (0..9)
+            .filter_map(|sample| {
+                let packet = packetizer.push_raw(&[sample])?;
+                Some(Packet::parse(packet).unwrap().header.first_frame)
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(first_frames, [0, 3, 6]);
+    }
+
+    #[test]
+    fn packets_are_limited_in_size() {
+        let mut packetizer = Packetizer::new(header(SampleKind::Raw, ChannelMask::ALL), 1000);
+
+        let packet = (0..1000).find_map(|_| packetizer.push_raw(&[0, 0]).map(<[u8]>::len));
+
+        let max_frames = (MAX_PACKET_LEN - PacketHeader::ENCODED_LEN) / 8;
+        assert_eq!(packet, Some(PacketHeader::ENCODED_LEN + max_frames * 8));
+    }
+
+    #[test]
+    fn invalid_packets_are_rejected() {
+        let mut packetizer = Packetizer::new(header(SampleKind::Raw, ChannelMask::CH1), 2);
+        packetizer.push_raw(&[0]);
+        let packet = packetizer.push_raw(&[0]).unwrap().to_vec();
+
+        assert_eq!(
+            Packet::parse(&packet[..packet.len() - 1]),
+            Err(StreamError::Truncated)
+        );
+
+        let mut newer = packet.clone();
+        newer[4] = STREAM_VERSION + 1;
+        assert_eq!(
+            Packet::parse(&newer),
+            Err(StreamError::UnsupportedVersion(STREAM_VERSION + 1))
+        );
+
+        let mut other = packet.clone();
+        other[0] = b'X';
+        assert_eq!(Packet::parse(&other), Err(StreamError::NotAStreamPacket));
+
+        let mut no_channels = packet;
+        no_channels[6] = 0;
+        assert_eq!(Packet::parse(&no_channels), Err(StreamError::InvalidHeader));
+    }
+
+    #[test]
+    fn subscriptions_are_recognized() {
+        assert!(is_subscription(&subscription()));
+
+        let mut older = subscription();
+        older[4] = STREAM_VERSION - 1;
+        assert!(!is_subscription(&older));
+        assert!(!is_subscription(&subscription()[..SUBSCRIPTION_LEN - 1]));
+
+        let mut packetizer = Packetizer::new(header(SampleKind::Raw, ChannelMask::CH1), 1);
+        let packet = packetizer.push_raw(&[0]).unwrap();
+        assert!(!is_subscription(packet));
+        assert_eq!(Packet::parse(&subscription()), Err(StreamError::Truncated));
+    }
+}
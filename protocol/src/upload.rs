@@ -0,0 +1,243 @@
+//! Chunked measurement upload
+//!
+//! Measurements are uploaded in chunks, so that a weak connection only has to retry the chunk it
+//! lost, and an interrupted upload can continue where it stopped, even after a reboot. The
+//! uploaded data is the same as the body of the single request upload: a 32-bit little endian
+//! format version followed by the encoded measurement.
+//!
+//! The backend provides the following endpoints, relative to the backend URL:
+//!
+//! - `POST /upload_session/<serial>` opens a session. The `X-Upload-Length` header holds the
+//!   total length of the data, `X-Timestamp` holds the start of the measurement in Unix seconds
+//!   (0 if unknown). The response body is the session id.
+//! - `PUT /upload_session/<serial>/<id>` sends a chunk. The `X-Upload-Offset` header holds the
+//!   position of the chunk in the data. The response body is the number of bytes the backend has
+//!   stored. If the offset is not what the backend expects, it responds with `409 Conflict` and
+//!   the number of stored bytes. The response to the last chunk is `201 Created`.
+//! - `GET /upload_session/<serial>/<id>` responds with the number of stored bytes, or with
+//!   `404 Not Found` if the session is unknown.
+//!
+//! Offsets and the number of stored bytes are decimal numbers.
+//!
+//! If opening a session responds with `404 Not Found`, the backend predates upload sessions and
+//! the device sends the whole data in a single `POST /upload_data/<serial>` request instead.
+
+use core::ops::Range;
+
+/// The size of the chunks the device sends.
+pub const CHUNK_SIZE: usize = 8 * 1024;
+
+/// The longest session id the device accepts.
+pub const MAX_SESSION_ID_LEN: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UploadError {
+    /// The session id is empty, too long or contains characters that can't be used in a URL.
+    InvalidSessionId,
+    /// The response is not a decimal number.
+    InvalidOffset,
+    /// The backend acknowledged more data than the upload has.
+    OffsetOutOfRange,
+}
+
+/// Identifies an upload session, as assigned by the backend.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SessionId {
+    bytes: [u8; MAX_SESSION_ID_LEN],
+    len: u8,
+}
+
+impl SessionId {
+    /// Parses a session id. Only ASCII letters, digits, `-` and `_` are accepted.
+    pub fn parse(id: &[u8]) -> Result<Self, UploadError> {
+        let id = id.trim_ascii();
+        let valid = |c: &u8| c.is_ascii_alphanumeric() || *c == b'-' || *c == b'_';
+        if id.is_empty() || id.len() > MAX_SESSION_ID_LEN || !id.iter().all(valid) {
+            return Err(UploadError::InvalidSessionId);
+        }
+
+        let mut bytes = [0; MAX_SESSION_ID_LEN];
+        bytes[..id.len()].copy_from_slice(id);
+
+        Ok(Self {
+            bytes,
+            len: id.len() as u8,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only ASCII characters are accepted by `parse`.
+        unwrap!(core::str::from_utf8(&self.bytes[..self.len as usize]).ok())
+    }
+}
+
+impl core::fmt::Debug for SessionId {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for SessionId {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{=str}", self.as_str())
+    }
+}
+
+/// Parses the number of stored bytes from a response body.
+pub fn parse_offset(body: &[u8]) -> Result<u32, UploadError> {
+    let body = body.trim_ascii();
+    if body.is_empty() || !body.iter().all(u8::is_ascii_digit) {
+        return Err(UploadError::InvalidOffset);
+    }
+
+    body.iter().try_fold(0u32, |offset, digit| {
+        offset
+            .checked_mul(10)
+            .and_then(|offset| offset.checked_add((digit - b'0') as u32))
+            .ok_or(UploadError::InvalidOffset)
+    })
+}
+
+/// The client side state of an upload session.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct UploadSession {
+    id: SessionId,
+    total_len: u32,
+    acknowledged: u32,
+}
+
+impl UploadSession {
+    /// Creates the state of a session. Nothing is assumed to be stored by the backend, resumed
+    /// sessions should ask the backend for the stored length first.
+    pub fn new(id: SessionId, total_len: u32) -> Self {
+        Self {
+            id,
+            total_len,
+            acknowledged: 0,
+        }
+    }
+
+    pub fn id(&self) -> &SessionId {
+        &self.id
+    }
+
+    pub fn total_len(&self) -> u32 {
+        self.total_len
+    }
+
+    /// The number of bytes the backend has stored.
+    pub fn acknowledged(&self) -> u32 {
+        self.acknowledged
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.acknowledged == self.total_len
+    }
+
+    /// The range of the data to send next, or `None` if the upload is complete.
+    pub fn next_chunk(&self, chunk_size: usize) -> Option<Range<usize>> {
+        if self.is_complete() {
+            return None;
+        }
+
+        let start = self.acknowledged as usize;
+        let end = (start + chunk_size).min(self.total_len as usize);
+        Some(start..end)
+    }
+
+    /// Records the number of stored bytes the backend reported. The number may be lower than
+    /// before, in which case the lost data is sent again.
+    pub fn acknowledge(&mut self, offset: u32) -> Result<(), UploadError> {
+        if offset > self.total_len {
+            return Err(UploadError::OffsetOutOfRange);
+        }
+
+        self.acknowledged = offset;
+        Ok(())
+    }
+}
+
+/// Returns the parts of `range` in the concatenation of `parts`, without copying.
+pub fn slice_parts<'a: 'b, 'b>(
+    parts: &'b [&'a [u8]],
+    range: Range<usize>,
+) -> impl Iterator<Item = &'a [u8]> + 'b {
+    let mut part_start = 0;
+    parts.iter().filter_map(move |part| {
+        let start = range.start.saturating_sub(part_start).min(part.len());
+        let end = range.end.saturating_sub(part_start).min(part.len());
+        part_start += part.len();
+
+        (start < end).then(|| &part[start..end])
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn session(total_len: u32) -> UploadSession {
+        UploadSession::new(SessionId::parse(b"abc-123").unwrap(), total_len)
+    }
+
+    #[test]
+    fn session_ids_are_validated() {
+        assert_eq!(SessionId::parse(b" a_B-9\r\n").unwrap().as_str(), "a_B-9");
+
+        for id in [
+            &b""[..],
+            b"a/b",
+            b"a b",
+            b"a?b",
+            &[b'a'; MAX_SESSION_ID_LEN + 1],
+        ] {
+            assert_eq!(SessionId::parse(id), Err(UploadError::InvalidSessionId));
+        }
+    }
+
+    #[test]
+    fn offsets_are_parsed() {
+        assert_eq!(parse_offset(b"0"), Ok(0));
+        assert_eq!(parse_offset(b"8192\n"), Ok(8192));
+        assert_eq!(parse_offset(b"4294967295"), Ok(u32::MAX));
+
+        for body in [&b""[..], b"-1", b"1.5", b"0x10", b"4294967296"] {
+            assert_eq!(parse_offset(body), Err(UploadError::InvalidOffset));
+        }
+    }
+
+    #[test]
+    fn chunks_follow_acknowledgements() {
+        let mut session = session(20);
+
+        assert_eq!(session.next_chunk(8), Some(0..8));
+        session.acknowledge(8).unwrap();
+        assert_eq!(session.next_chunk(8), Some(8..16));
+
+        // The backend lost part of the data.
+        session.acknowledge(4).unwrap();
+        assert_eq!(session.next_chunk(8), Some(4..12));
+
+        session.acknowledge(16).unwrap();
+        assert_eq!(session.next_chunk(8), Some(16..20));
+
+        assert_eq!(session.acknowledge(21), Err(UploadError::OffsetOutOfRange));
+        session.acknowledge(20).unwrap();
+        assert!(session.is_complete());
+        assert_eq!(session.next_chunk(8), None);
+    }
+
+    #[test]
+    fn ranges_span_parts() {
+        let parts: [&[u8]; 3] = [&[0, 1, 2, 3], &[], &[4, 5, 6]];
+        let slices = |range| slice_parts(&parts, range).collect::<Vec<_>>();
+
+        assert_eq!(slices(0..7), [&[0, 1, 2, 3][..], &[4, 5, 6]]);
+        assert_eq!(slices(2..5), [&[2, 3][..], &[4]]);
+        assert_eq!(slices(4..6), [&[4, 5][..]]);
+        assert_eq!(slices(7..7), Vec::<&[u8]>::new());
+    }
+}
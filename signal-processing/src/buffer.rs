@@ -1,6 +1,6 @@
 use core::{convert::Infallible, mem::MaybeUninit, ops::Range};
 
-use embedded_io::{ErrorType, Read};
+use embedded_io::{ErrorType, Read, Write};
 
 pub struct Buffer<T: Copy, const N: usize, const POP: bool> {
     write_idx: usize,
@@ -222,6 +222,21 @@ impl<const N: usize> Read for Buffer<u8, N, true> {
     }
 }
 
+impl<const N: usize> Write for Buffer<u8, N, true> {
+    /// Overwrites the oldest bytes if the buffer is full.
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        for &byte in buf {
+            self.push(byte);
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
     #[test]
@@ -1,15 +1,39 @@
 //! Compressing i32 buffer
 //!
-//! This buffer tries to compress a sequence of i32 values by storing the varint-encoded
-//! difference from the last. This is useful for storing a sequence of values that are
-//! close to each other, such as a sequence of samples from a sensor.
+//! This buffer compresses a sequence of i32 values that are close to each other, such as a
+//! sequence of samples from a sensor, using [`RiceFormat`].
+//!
+//! The buffer can store multiple channels. In that case, samples are stored in frames that contain
+//! one sample of every channel, and each channel is encoded independently.
+//!
+//! Older firmware stored the varint-encoded difference from the last value, see [`EkgFormat`].
 
 use core::{fmt::Debug, slice};
 
 use embedded_io::{Read, ReadExactError, Write};
 
-use crate::buffer::Buffer;
+use crate::{buffer::Buffer, measurement::Keyframe};
+
+const fn zigzag_encode(val: i32) -> u32 {
+    ((val << 1) ^ (val >> 31)) as u32
+}
+
+const fn zigzag_decode(val: u32) -> i32 {
+    (val >> 1) as i32 ^ -((val & 1) as i32)
+}
+
+/// Reads a single byte. Returns `None` at the end of the data.
+fn read_byte<R: Read>(reader: &mut R) -> Result<Option<u8>, R::Error> {
+    let mut byte = 0;
+    match reader.read_exact(slice::from_mut(&mut byte)) {
+        Ok(()) => Ok(Some(byte)),
+        Err(ReadExactError::UnexpectedEof) => Ok(None),
+        Err(ReadExactError::Other(e)) => Err(e),
+    }
+}
 
+/// Stores the varint-encoded difference from the previous sample. Every sample takes at least one
+/// byte.
 #[derive(Clone, Copy, Default)]
 pub struct EkgFormat {
     previous: i32,
@@ -26,10 +50,6 @@ impl EkgFormat {
         let diff = sample - self.previous;
         self.previous = sample;
 
-        const fn zigzag_encode(val: i32) -> u32 {
-            ((val << 1) ^ (val >> 31)) as u32
-        }
-
         let mut diff = zigzag_encode(diff);
 
         let mut buffer = [0; 8];
@@ -48,20 +68,12 @@ impl EkgFormat {
     }
 
     pub fn read<R: Read>(&mut self, reader: &mut R) -> Result<Option<i32>, R::Error> {
-        const fn zigzag_decode(val: u32) -> i32 {
-            (val >> 1) as i32 ^ -((val & 1) as i32)
-        }
-
         let mut diff = 0;
         let mut idx = 0;
-        let mut byte = 0;
         loop {
-            if let Err(e) = reader.read_exact(slice::from_mut(&mut byte)) {
-                match e {
-                    ReadExactError::UnexpectedEof => return Ok(None),
-                    ReadExactError::Other(e) => return Err(e),
-                }
-            }
+            let Some(byte) = read_byte(reader)? else {
+                return Ok(None);
+            };
             diff |= ((byte & 0x7F) as u32) << (idx * 7);
             idx += 1;
             if byte & 0x80 == 0 {
@@ -77,10 +89,353 @@ impl EkgFormat {
     }
 }
 
+/// Predicts a sample by extending the line through the previous two.
+#[derive(Default)]
+struct Predictor {
+    history: [i32; 2],
+    count: usize,
+}
+
+impl Predictor {
+    fn predict(&self) -> i32 {
+        let [last, before] = self.history;
+        match self.count {
+            0 => 0,
+            1 => last,
+            _ => last.wrapping_mul(2).wrapping_sub(before),
+        }
+    }
+
+    fn update(&mut self, sample: i32) {
+        self.history = [sample, self.history[0]];
+        self.count += 1;
+    }
+}
+
+struct BitWriter<'a, W: Write> {
+    writer: &'a mut W,
+    bits: u64,
+    bit_count: u32,
+    written: usize,
+}
+
+impl<'a, W: Write> BitWriter<'a, W> {
+    fn new(writer: &'a mut W) -> Self {
+        Self {
+            writer,
+            bits: 0,
+            bit_count: 0,
+            written: 0,
+        }
+    }
+
+    /// Writes the lowest `count` bits of `value`, most significant first.
+    fn write(&mut self, value: u32, count: u32) -> Result<(), W::Error> {
+        let mask = (1 << count) - 1;
+        self.bits = (self.bits << count) | (value as u64 & mask);
+        self.bit_count += count;
+
+        while self.bit_count >= 8 {
+            self.bit_count -= 8;
+            self.writer
+                .write_all(&[(self.bits >> self.bit_count) as u8])?;
+            self.written += 1;
+        }
+        self.bits &= (1 << self.bit_count) - 1;
+
+        Ok(())
+    }
+
+    /// Pads the last byte with zeros. Returns the number of bytes written.
+    fn finish(mut self) -> Result<usize, W::Error> {
+        if self.bit_count > 0 {
+            self.write(0, 8 - self.bit_count)?;
+        }
+
+        Ok(self.written)
+    }
+}
+
+struct BitReader<'a, R: Read> {
+    reader: &'a mut R,
+    bits: u64,
+    bit_count: u32,
+}
+
+impl<'a, R: Read> BitReader<'a, R> {
+    fn new(reader: &'a mut R) -> Self {
+        Self {
+            reader,
+            bits: 0,
+            bit_count: 0,
+        }
+    }
+
+    /// Reads `count` bits, most significant first. Returns `None` at the end of the data.
+    ///
+    /// Bytes are only read when needed, so the reader stops at the end of the last byte that
+    /// contained the requested bits.
+    fn read(&mut self, count: u32) -> Result<Option<u32>, R::Error> {
+        while self.bit_count < count {
+            let Some(byte) = read_byte(self.reader)? else {
+                return Ok(None);
+            };
+            self.bits = (self.bits << 8) | byte as u64;
+            self.bit_count += 8;
+        }
+
+        self.bit_count -= count;
+        let value = (self.bits >> self.bit_count) as u32 & ((1u64 << count) - 1) as u32;
+        self.bits &= (1 << self.bit_count) - 1;
+
+        Ok(Some(value))
+    }
+}
+
+/// Stores samples in blocks. Every sample is predicted from the previous two, and the prediction
+/// error is Rice coded, with the Rice parameter chosen for each block. Samples of a quiet signal
+/// take less than a byte.
+///
+/// A block starts with the number of samples and the Rice parameter, one byte each, followed by the
+/// bit-packed residuals. Blocks end on a byte boundary. Prediction restarts in every block, so
+/// blocks can be decoded independently.
+///
+/// The same value is used to write or to read a stream, but not both.
+#[derive(Clone, Copy)]
+pub struct RiceFormat {
+    /// Samples waiting to be written, or decoded samples waiting to be read.
+    block: [i32; Self::BLOCK_LEN],
+    len: usize,
+    pos: usize,
+    /// The Rice parameter of the current block and the size of its residuals in bits, once the
+    /// parameter has been picked.
+    parameter: Option<(u32, u32)>,
+}
+
+impl Default for RiceFormat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RiceFormat {
+    pub const VERSION: u8 = 1;
+
+    /// The maximum number of samples in a block.
+    pub const BLOCK_LEN: usize = 128;
+
+    /// Residuals with a quotient at least this large are stored verbatim, after this many 1 bits.
+    const ESCAPE: u32 = 16;
+
+    pub const fn new() -> Self {
+        Self {
+            block: [0; Self::BLOCK_LEN],
+            len: 0,
+            pos: 0,
+            parameter: None,
+        }
+    }
+
+    /// Adds a sample to the current block, and writes the block if it is full. Returns the number
+    /// of bytes written.
+    pub fn write<W: Write>(&mut self, sample: i32, writer: &mut W) -> Result<usize, W::Error> {
+        if self.push(sample) {
+            self.flush(writer)
+        } else {
+            Ok(0)
+        }
+    }
+
+    /// Writes the current block, even if it isn't full. Returns the number of bytes written.
+    pub fn flush<W: Write>(&mut self, writer: &mut W) -> Result<usize, W::Error> {
+        if self.len == 0 {
+            return Ok(0);
+        }
+
+        let (k, _) = self.rice_parameter();
+
+        let mut bits = BitWriter::new(writer);
+        bits.write(self.len as u32, 8)?;
+        bits.write(k, 8)?;
+        for residual in self.residuals() {
+            let quotient = residual >> k;
+            if quotient < Self::ESCAPE {
+                // `quotient` 1 bits and a 0 bit
+                bits.write((1 << (quotient + 1)) - 2, quotient + 1)?;
+                bits.write(residual, k)?;
+            } else {
+                bits.write((1 << Self::ESCAPE) - 1, Self::ESCAPE)?;
+                bits.write(residual, 32)?;
+            }
+        }
+        let written = bits.finish()?;
+
+        self.clear();
+
+        Ok(written)
+    }
+
+    pub fn read<R: Read>(&mut self, reader: &mut R) -> Result<Option<i32>, R::Error> {
+        if self.pos == self.len && !self.read_block(reader)? {
+            return Ok(None);
+        }
+
+        let sample = self.block[self.pos];
+        self.pos += 1;
+
+        Ok(Some(sample))
+    }
+
+    /// Adds a sample to the current block. Returns `true` if the block is full.
+    fn push(&mut self, sample: i32) -> bool {
+        self.block[self.len] = sample;
+        self.len += 1;
+        self.parameter = None;
+
+        self.len == Self::BLOCK_LEN
+    }
+
+    /// The number of samples waiting to be written or read.
+    fn buffered(&self) -> usize {
+        self.len - self.pos
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+        self.pos = 0;
+        self.parameter = None;
+    }
+
+    /// The size of the current block when written, in bytes.
+    fn encoded_len(&mut self) -> usize {
+        if self.len == 0 {
+            return 0;
+        }
+
+        let (_, bits) = self.rice_parameter();
+        2 + bits.div_ceil(8) as usize
+    }
+
+    /// Picks the Rice parameter for the current block. The best parameter is close to the average
+    /// length of the residuals in bits, so only that estimate and its neighbours are tried. Unlike
+    /// the mean residual, the average length isn't thrown off by a few large residuals, which are
+    /// escaped anyway. Returns the parameter and the size of the residuals in bits.
+    fn rice_parameter(&mut self) -> (u32, u32) {
+        if let Some(parameter) = self.parameter {
+            return parameter;
+        }
+
+        let bits = self
+            .residuals()
+            .map(|residual| u32::BITS - residual.leading_zeros())
+            .sum::<u32>();
+        let estimate = bits / self.len as u32;
+
+        let candidates = estimate.saturating_sub(1)..=(estimate + 1).min(31);
+        let parameter = unwrap!(candidates
+            .map(|k| (k, self.encoded_bits(k)))
+            .min_by_key(|&(_, bits)| bits));
+        self.parameter = Some(parameter);
+
+        parameter
+    }
+
+    /// The size of the current block's residuals when Rice coded with parameter `k`, in bits.
+    fn encoded_bits(&self, k: u32) -> u32 {
+        self.residuals()
+            .map(|residual| {
+                let quotient = residual >> k;
+                if quotient < Self::ESCAPE {
+                    quotient + 1 + k
+                } else {
+                    Self::ESCAPE + 32
+                }
+            })
+            .sum()
+    }
+
+    /// The zigzag-encoded prediction errors of the current block.
+    fn residuals(&self) -> impl Iterator<Item = u32> + '_ {
+        let mut predictor = Predictor::default();
+        self.block[..self.len].iter().map(move |&sample| {
+            let residual = sample.wrapping_sub(predictor.predict());
+            predictor.update(sample);
+            zigzag_encode(residual)
+        })
+    }
+
+    /// Decodes the next block. Returns `false` if there is no more data. A truncated block is
+    /// decoded up to its last complete sample.
+    fn read_block<R: Read>(&mut self, reader: &mut R) -> Result<bool, R::Error> {
+        self.clear();
+
+        let mut bits = BitReader::new(reader);
+        let (Some(count), Some(k)) = (bits.read(8)?, bits.read(8)?) else {
+            return Ok(false);
+        };
+        // Invalid headers are treated as the end of the data.
+        if count == 0 || count as usize > Self::BLOCK_LEN || k >= 32 {
+            return Ok(false);
+        }
+
+        let mut predictor = Predictor::default();
+        while self.len < count as usize {
+            let Some(residual) = Self::read_residual(&mut bits, k)? else {
+                break;
+            };
+            let sample = zigzag_decode(residual).wrapping_add(predictor.predict());
+            predictor.update(sample);
+
+            self.block[self.len] = sample;
+            self.len += 1;
+        }
+
+        Ok(self.len > 0)
+    }
+
+    fn read_residual<R: Read>(
+        bits: &mut BitReader<'_, R>,
+        k: u32,
+    ) -> Result<Option<u32>, R::Error> {
+        let mut quotient = 0;
+        while quotient < Self::ESCAPE {
+            match bits.read(1)? {
+                Some(1) => quotient += 1,
+                Some(_) => break,
+                None => return Ok(None),
+            }
+        }
+
+        if quotient == Self::ESCAPE {
+            bits.read(32)
+        } else {
+            Ok(bits.read(k)?.map(|remainder| (quotient << k) | remainder))
+        }
+    }
+}
+
+/// The maximum number of channels a [`CompressingBuffer`] can interleave.
+pub const MAX_CHANNELS: usize = 2;
+
+/// The maximum number of block groups a [`CompressingBuffer`] holds. The position of every block
+/// is tracked, so the buffer drops its oldest block when the index is full, even if there would
+/// be space for more bytes. This only limits signals that compress unusually well.
+const MAX_TRACKED_BLOCKS: usize = 1024;
+
+/// The size of the blocks that were written together, one for every channel.
+#[derive(Clone, Copy)]
+struct BlockGroup {
+    bytes: u16,
+    frames: u8,
+}
+
 pub struct CompressingBuffer<const N: usize> {
-    reader: EkgFormat,
-    writer: EkgFormat,
+    reader: [RiceFormat; MAX_CHANNELS],
+    writer: [RiceFormat; MAX_CHANNELS],
+    channels: usize,
     element_count: usize,
+    /// The block groups in `buffer`.
+    blocks: Buffer<BlockGroup, MAX_TRACKED_BLOCKS, true>,
 
     buffer: Buffer<u8, N, true>,
 }
@@ -112,44 +467,146 @@ impl<const N: usize> CompressingBuffer<N> {
 
     pub const fn new() -> Self {
         Self {
-            reader: EkgFormat::new(),
-            writer: EkgFormat::new(),
+            reader: [RiceFormat::new(); MAX_CHANNELS],
+            writer: [RiceFormat::new(); MAX_CHANNELS],
+            channels: 1,
             element_count: 0,
+            blocks: Buffer::EMPTY,
             buffer: Buffer::EMPTY,
         }
     }
 
+    /// Sets the number of interleaved channels. Clears the buffer.
+    pub fn set_channels(&mut self, channels: usize) {
+        assert!(channels > 0 && channels <= MAX_CHANNELS);
+
+        self.clear();
+        self.channels = channels;
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Stores a single sample. The buffer must be set up for one channel.
     pub fn push(&mut self, item: i32) {
-        let mut buffer = [0u8; 8];
-        let bytes = unwrap!(self.writer.write(item, &mut &mut buffer[..]));
+        self.push_frame(&[item]);
+    }
+
+    /// Removes a single sample. The buffer must be set up for one channel.
+    pub fn pop(&mut self) -> Option<i32> {
+        let mut frame = [0];
+        self.pop_frame(&mut frame).then_some(frame[0])
+    }
+
+    /// Stores one sample of every channel. Frames are encoded once they fill a block, which drops
+    /// the oldest blocks if the buffer is full.
+    pub fn push_frame(&mut self, frame: &[i32]) {
+        assert_eq!(frame.len(), self.channels);
+
+        let mut block_full = false;
+        for (writer, &item) in self.writer.iter_mut().zip(frame) {
+            block_full = writer.push(item);
+        }
+        self.element_count += 1;
+
+        if block_full {
+            self.write_blocks();
+        }
+    }
+
+    /// Removes the oldest frame into `frame`. Returns `false` if the buffer is empty.
+    pub fn pop_frame(&mut self, frame: &mut [i32]) -> bool {
+        assert_eq!(frame.len(), self.channels);
+
+        if self.reader[0].buffered() == 0 && self.buffer.is_empty() {
+            // The remaining frames haven't filled a block yet.
+            self.write_blocks();
+        }
+
+        if self.element_count == 0 {
+            return false;
+        }
+
+        if self.reader[0].buffered() == 0 {
+            self.forget_oldest_block();
+        }
+        for (reader, item) in self.reader.iter_mut().zip(frame.iter_mut()) {
+            *item = unwrap!(unwrap!(reader.read(&mut self.buffer)));
+        }
+        self.element_count -= 1;
+
+        true
+    }
+
+    /// Encodes the frames that are not yet stored in the buffer. Drops the oldest blocks to make
+    /// space.
+    fn write_blocks(&mut self) {
+        let frames = self.writer[0].buffered();
+        if frames == 0 {
+            return;
+        }
+
+        let bytes = self.writer[..self.channels]
+            .iter_mut()
+            .map(RiceFormat::encoded_len)
+            .sum::<usize>();
+
+        // Every block stays indexed.
+        if self.blocks.is_full() {
+            self.drop_block();
+        }
 
         while self.space() < bytes {
-            if self.pop().is_none() {
+            if !self.drop_block() {
+                // The blocks don't fit even into an empty buffer.
+                self.element_count -= self.writer[0].buffered();
+                self.writer.iter_mut().for_each(RiceFormat::clear);
                 return;
             }
         }
 
-        for byte in buffer.iter().take(bytes).copied() {
-            self.buffer.push(byte);
+        let mut written = 0;
+        for writer in self.writer[..self.channels].iter_mut() {
+            written += unwrap!(writer.flush(&mut self.buffer));
         }
 
-        self.element_count += 1;
+        self.blocks.push(BlockGroup {
+            bytes: written as u16,
+            frames: frames as u8,
+        });
     }
 
-    pub fn pop(&mut self) -> Option<i32> {
-        let sample = unwrap!(self.reader.read(&mut self.buffer));
+    /// Drops the oldest block of every channel. Returns `false` if the buffer is empty.
+    fn drop_block(&mut self) -> bool {
+        if self.buffer.is_empty() {
+            return false;
+        }
 
-        if sample.is_some() {
-            self.element_count -= 1;
+        // Frames that were already decoded are older than the ones in the buffer.
+        let mut dropped = self.reader[0].buffered();
+        for reader in self.reader[..self.channels].iter_mut() {
+            unwrap!(reader.read_block(&mut self.buffer));
         }
+        dropped += self.reader[0].buffered();
+
+        self.reader.iter_mut().for_each(RiceFormat::clear);
+        self.element_count -= dropped;
+        self.forget_oldest_block();
 
-        sample
+        true
+    }
+
+    /// Called when the oldest block group is removed from the buffer.
+    fn forget_oldest_block(&mut self) {
+        self.blocks.pop();
     }
 
     pub fn capacity(&self) -> usize {
         N
     }
 
+    /// Returns the number of stored frames.
     pub fn len(&self) -> usize {
         self.element_count
     }
@@ -158,6 +615,7 @@ impl<const N: usize> CompressingBuffer<N> {
         self.len() == 0
     }
 
+    /// Returns the number of encoded bytes. Frames that don't fill a block yet are not included.
     pub fn byte_count(&self) -> usize {
         self.buffer.len()
     }
@@ -168,18 +626,43 @@ impl<const N: usize> CompressingBuffer<N> {
 
     pub fn clear(&mut self) {
         self.element_count = 0;
-        self.reader.previous = 0;
-        self.writer.previous = 0;
+        self.blocks.clear();
+        self.reader.iter_mut().for_each(RiceFormat::clear);
+        self.writer.iter_mut().for_each(RiceFormat::clear);
         self.buffer.clear();
     }
 
+    /// Returns the encoded bytes. Frames that don't fill a block yet are not included.
     pub fn as_slices(&self) -> (&[u8], &[u8]) {
         self.buffer.as_slices()
     }
 
+    /// Encodes the frames that don't fill a block yet. This may drop the oldest blocks.
+    pub fn flush(&mut self) {
+        self.write_blocks();
+    }
+
+    /// Encodes every frame and returns the encoded bytes. Frames that were decoded together with
+    /// a popped frame, but not popped yet, are not included.
     pub fn make_contiguous(&mut self) -> &[u8] {
+        self.flush();
         self.buffer.make_contiguous()
     }
+
+    /// Returns where every block of the encoded bytes starts, relative to the first encoded frame
+    /// and byte.
+    pub fn keyframes(&self) -> impl Iterator<Item = Keyframe> + '_ {
+        let mut next = Keyframe {
+            frame: 0,
+            offset: 0,
+        };
+        self.blocks.iter().map(move |block| {
+            let keyframe = next;
+            next.frame += block.frames as u32;
+            next.offset += block.bytes as u32;
+            keyframe
+        })
+    }
 }
 
 #[cfg(test)]
@@ -202,10 +685,15 @@ mod test {
     }
 
     #[test]
-    fn first_element_is_stored() {
+    fn elements_are_stored_in_blocks() {
         let mut buffer = CompressingBuffer::<100>::new();
         buffer.push(1);
-        assert_eq!(buffer.byte_count(), 1);
+        assert_eq!(buffer.byte_count(), 0);
+
+        // Header and a single, 3-bit residual
+        buffer.make_contiguous();
+        assert_eq!(buffer.byte_count(), 3);
+        assert_eq!(buffer.len(), 1);
     }
 
     #[test]
@@ -260,8 +748,9 @@ mod test {
         buffer.push(0);
         buffer.push(-6);
         buffer.push(32);
+        buffer.make_contiguous();
 
-        // -1 because we explicitly don't store the first element
+        // Less than storing all but one of the samples verbatim, even with the block header.
         assert!(buffer.byte_count() < (buffer.len() - 1) * 4);
     }
 
@@ -285,4 +774,286 @@ mod test {
 
         assert_eq!(&output[output.len() - 4..], [32, 0, -6, 32]);
     }
+
+    #[test]
+    fn frames_are_interleaved() {
+        let mut buffer = CompressingBuffer::<100>::new();
+        buffer.set_channels(2);
+
+        buffer.push_frame(&[100, -100]);
+        buffer.push_frame(&[101, -102]);
+
+        assert_eq!(buffer.len(), 2);
+
+        // Channels are coded separately
+        let mut reader = [RiceFormat::new(); 2];
+        let mut bytes = buffer.make_contiguous();
+        let mut decoded = Vec::new();
+        while let Some(sample) = reader[decoded.len() % 2].read(&mut bytes).unwrap() {
+            decoded.push(sample);
+        }
+        assert_eq!(decoded, [100, -100, 101, -102]);
+
+        let mut frame = [0; 2];
+        assert!(buffer.pop_frame(&mut frame));
+        assert_eq!(frame, [100, -100]);
+        assert!(buffer.pop_frame(&mut frame));
+        assert_eq!(frame, [101, -102]);
+        assert!(!buffer.pop_frame(&mut frame));
+    }
+
+    #[test]
+    fn overwriting_keeps_frames_aligned() {
+        let mut buffer = CompressingBuffer::<100>::new();
+        buffer.set_channels(2);
+
+        for input in 0..500 {
+            buffer.push_frame(&[6 * input, -1000 * input]);
+        }
+
+        let mut frame = [0; 2];
+        let mut frames = 0;
+        while buffer.pop_frame(&mut frame) {
+            assert_eq!(frame[1], -1000 * (frame[0] / 6));
+            frames += 1;
+        }
+
+        assert!(frames > 0);
+        assert_eq!(frame[0], 6 * 499);
+    }
+
+    fn encode(samples: &[i32]) -> Vec<u8> {
+        let mut encoder = RiceFormat::new();
+        let mut bytes = vec![0; samples.len() * 8 + 16];
+        let mut len = 0;
+        for &sample in samples {
+            len += encoder.write(sample, &mut &mut bytes[len..]).unwrap();
+        }
+        len += encoder.flush(&mut &mut bytes[len..]).unwrap();
+        bytes.truncate(len);
+        bytes
+    }
+
+    fn decode(mut bytes: &[u8]) -> Vec<i32> {
+        let mut decoder = RiceFormat::new();
+        let mut samples = Vec::new();
+        while let Some(sample) = decoder.read(&mut bytes).unwrap() {
+            samples.push(sample);
+        }
+        samples
+    }
+
+    #[test]
+    fn rice_format_round_trips() {
+        let samples = (0..1000)
+            .map(|n| match n % 97 {
+                0 => i32::MIN,
+                1 => i32::MAX,
+                2 => 0,
+                _ => ((n as f32 * 0.02).sin() * 100_000.0) as i32 + (n * 7919 % 31),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(decode(&encode(&samples)), samples);
+    }
+
+    #[test]
+    fn rice_format_blocks_decode_independently() {
+        let samples = (0..2 * RiceFormat::BLOCK_LEN as i32)
+            .map(|n| 1000 * n - n * n)
+            .collect::<Vec<_>>();
+
+        let bytes = encode(&samples);
+        let mut encoder = RiceFormat::new();
+        let mut first_block = vec![0; bytes.len()];
+        let mut first_block_len = 0;
+        for &sample in &samples[..RiceFormat::BLOCK_LEN] {
+            first_block_len += encoder
+                .write(sample, &mut first_block.as_mut_slice())
+                .unwrap();
+        }
+
+        assert_eq!(bytes[..first_block_len], first_block[..first_block_len]);
+        assert_eq!(
+            decode(&bytes[first_block_len..]),
+            samples[RiceFormat::BLOCK_LEN..]
+        );
+    }
+
+    #[test]
+    fn rice_format_decodes_truncated_block() {
+        let samples = [100, 200, 300, 400, 500];
+        let bytes = encode(&samples);
+
+        // The padding of the last byte doesn't decode as a sample.
+        assert_eq!(decode(&bytes), samples);
+        assert_eq!(decode(&bytes[..bytes.len() - 1]), samples[..4]);
+        assert_eq!(decode(&bytes[..2]), []);
+    }
+
+    #[test]
+    fn rice_format_is_smaller_than_delta_coding() {
+        // 1 mV sine with a few LSBs of noise, sampled at 1 kHz
+        let samples = (0..10_000)
+            .map(|n| {
+                let signal = (n as f32 * 0.0126).sin() * 3500.0;
+                let noise = (n * 7919 % 13) as f32 - 6.0;
+                (signal + noise) as i32
+            })
+            .collect::<Vec<_>>();
+
+        let rice_coded = encode(&samples);
+
+        assert!(rice_coded.len() < samples.len());
+        assert!(rice_coded.len() < delta_coded_len(&samples));
+    }
+
+    fn delta_coded_len(samples: &[i32]) -> usize {
+        let mut delta_coded = vec![0; samples.len() * 5];
+        let mut len = 0;
+        let mut encoder = EkgFormat::new();
+        for &sample in samples {
+            len += encoder.write(sample, &mut &mut delta_coded[len..]).unwrap();
+        }
+        len
+    }
+
+    /// 5 seconds of raw samples at 1000 sps, as little endian `i32`s. Generated by
+    /// [`SyntheticEcg`](crate::synthetic::SyntheticEcg) with the noise, baseline wander and power
+    /// line interference of a resting and of an ambulatory measurement.
+    const SAMPLE_RECORDINGS: [(&str, &[u8]); 2] = [
+        ("resting", include_bytes!("../test-data/resting.bin")),
+        ("ambulatory", include_bytes!("../test-data/ambulatory.bin")),
+    ];
+
+    fn sample_recording(bytes: &[u8]) -> Vec<i32> {
+        bytes
+            .chunks_exact(4)
+            .map(|sample| i32::from_le_bytes(sample.try_into().unwrap()))
+            .collect()
+    }
+
+    /// Compares the formats on the sample recordings. Run with `--nocapture` to see the sizes.
+    #[test]
+    fn sample_recordings_bytes_per_sample() {
+        for (name, bytes) in SAMPLE_RECORDINGS {
+            let samples = sample_recording(bytes);
+            let bytes_per_sample = |len: usize| len as f32 / samples.len() as f32;
+
+            let v0 = bytes_per_sample(delta_coded_len(&samples));
+            let v1 = bytes_per_sample(encode(&samples).len());
+            std::println!("{name}: v0 {v0:.3} bytes/sample, v1 {v1:.3} bytes/sample");
+
+            assert_eq!(decode(&encode(&samples)), samples);
+            assert!(v1 < v0, "{name}: {v1} >= {v0}");
+        }
+    }
+
+    #[test]
+    fn rice_parameter_estimate_is_close_to_the_best() {
+        for (name, bytes) in SAMPLE_RECORDINGS {
+            let (mut estimated, mut best) = (0, 0);
+            for block in sample_recording(bytes).chunks(RiceFormat::BLOCK_LEN) {
+                let mut format = RiceFormat::new();
+                for &sample in block {
+                    format.push(sample);
+                }
+
+                estimated += format.rice_parameter().1;
+                best += (0..32).map(|k| format.encoded_bits(k)).min().unwrap();
+            }
+
+            assert!(
+                estimated * 1000 <= best * 1001,
+                "{name}: {estimated} > {best}"
+            );
+        }
+    }
+
+    /// Decodes interleaved frames, starting at the beginning of `bytes`.
+    fn decode_frames(mut bytes: &[u8], channels: usize) -> Vec<i32> {
+        let mut readers = vec![RiceFormat::new(); channels];
+        let mut samples = Vec::new();
+        while let Some(sample) = readers[samples.len() % channels].read(&mut bytes).unwrap() {
+            samples.push(sample);
+        }
+        samples
+    }
+
+    #[test]
+    fn keyframes_point_to_block_starts() {
+        let mut buffer = CompressingBuffer::<10_000>::new();
+        buffer.set_channels(2);
+
+        let frames = (0..1000).map(|n| [n * n % 977, -3 * n]).collect::<Vec<_>>();
+        for frame in &frames {
+            buffer.push_frame(frame);
+        }
+        buffer.flush();
+
+        let keyframes = buffer.keyframes().collect::<Vec<_>>();
+        assert_eq!(keyframes.len(), 1000usize.div_ceil(RiceFormat::BLOCK_LEN));
+        assert_eq!(
+            keyframes[0],
+            Keyframe {
+                frame: 0,
+                offset: 0
+            }
+        );
+
+        let bytes = buffer.make_contiguous();
+        for keyframe in keyframes {
+            let decoded = decode_frames(&bytes[keyframe.offset as usize..], 2);
+            assert_eq!(decoded[..2], frames[keyframe.frame as usize]);
+        }
+    }
+
+    #[test]
+    fn every_block_stays_indexed() {
+        let mut buffer = CompressingBuffer::<60_000>::new();
+
+        // A flat signal fits more blocks into the buffer than can be indexed.
+        let blocks = MAX_TRACKED_BLOCKS + 100;
+        for input in 0..blocks * RiceFormat::BLOCK_LEN {
+            buffer.push((input / 1000) as i32);
+        }
+        buffer.flush();
+        assert_eq!(buffer.len(), MAX_TRACKED_BLOCKS * RiceFormat::BLOCK_LEN);
+
+        let keyframes = buffer.keyframes().collect::<Vec<_>>();
+        assert_eq!(keyframes.len(), MAX_TRACKED_BLOCKS);
+
+        let bytes = buffer.make_contiguous();
+        let decoded = decode_frames(bytes, 1);
+        let block_ends = keyframes.iter().skip(1).map(|k| k.offset as usize);
+        for (idx, (keyframe, end)) in keyframes.iter().zip(block_ends).enumerate() {
+            assert_eq!(keyframe.frame as usize, idx * RiceFormat::BLOCK_LEN);
+
+            let block = decode_frames(&bytes[keyframe.offset as usize..end], 1);
+            let start = keyframe.frame as usize;
+            assert_eq!(block, decoded[start..start + RiceFormat::BLOCK_LEN]);
+        }
+    }
+
+    #[test]
+    fn keyframes_follow_overwriting() {
+        let mut buffer = CompressingBuffer::<300>::new();
+
+        for input in 0..2000 {
+            buffer.push(((input as f32 * 0.05).sin() * 1000.0) as i32);
+        }
+        buffer.flush();
+
+        let keyframes = buffer.keyframes().collect::<Vec<_>>();
+        assert!(keyframes.len() > 2);
+        let frames = buffer.len();
+        let bytes = buffer.make_contiguous();
+        let decoded = decode_frames(bytes, 1);
+        assert_eq!(decoded.len(), frames);
+
+        for keyframe in keyframes {
+            let from_keyframe = decode_frames(&bytes[keyframe.offset as usize..], 1);
+            assert_eq!(from_keyframe, decoded[keyframe.frame as usize..]);
+        }
+    }
 }
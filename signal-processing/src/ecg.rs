@@ -0,0 +1,431 @@
+//! The ECG processing chain used during measurement.
+//!
+//! This lives here instead of the firmware so that host tools can process recordings the same
+//! way the device did.
+//!
+//! Filter lengths are fixed at compile time, so the chain supports a fixed set of sample rates,
+//! see [`SampleRate`].
+
+use alloc::boxed::Box;
+use object_chain::{chain, Chain, ChainElement, Link};
+
+use crate::{
+    filter::{
+        baseline::MedianBaselineFilter,
+        iir::{BiquadCascade, HighPass, LowPass},
+        Filter,
+    },
+    heap::try_box,
+    mains::MainsFrequency,
+};
+
+#[cfg(not(feature = "fixed-point"))]
+use crate::{
+    filter::pli::{adaptation_blocking::AdaptationBlocking, PowerLineFilter},
+    moving::sum::EstimatedSum,
+};
+
+#[cfg(feature = "fixed-point")]
+use crate::filter::{
+    fixed::FixedPoint,
+    iir::IirQ31,
+    pli::{adaptation_blocking::AdaptationBlockingQ31, PowerLineFilterQ31},
+};
+
+/// The number of samples the output of [`EcgFilter`] lags behind its input, not counting the
+/// baseline filter (see [`BaselineFilter::delay`]). The power line filter delays the signal to be
+/// able to stop adapting before a QRS complex reaches it.
+pub const ECG_FILTER_DELAY: usize = 4;
+
+/// The sample rates the ECG processing chain supports.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SampleRate {
+    Sps250,
+    Sps500,
+    #[default]
+    Sps1000,
+    Sps2000,
+}
+
+impl SampleRate {
+    /// The sample rate in samples per second.
+    pub fn hz(self) -> u16 {
+        match self {
+            SampleRate::Sps250 => 250,
+            SampleRate::Sps500 => 500,
+            SampleRate::Sps1000 => 1000,
+            SampleRate::Sps2000 => 2000,
+        }
+    }
+
+    pub fn from_hz(hz: u16) -> Option<Self> {
+        [Self::Sps250, Self::Sps500, Self::Sps1000, Self::Sps2000]
+            .into_iter()
+            .find(|rate| rate.hz() == hz)
+    }
+
+    #[inline]
+    pub fn fs(self) -> f32 {
+        self.hz() as f32
+    }
+
+    /// The number of times the signal is halved to show about one second on the display.
+    pub fn display_decimation_stages(self) -> usize {
+        match self {
+            SampleRate::Sps250 => 1,
+            SampleRate::Sps500 => 2,
+            SampleRate::Sps1000 => 3,
+            SampleRate::Sps2000 => 4,
+        }
+    }
+}
+
+// Two-stage median baseline estimators with 200 ms and 600 ms windows.
+type MedianBaseline250 = MedianBaselineFilter<51, 151, 100>;
+type MedianBaseline500 = MedianBaselineFilter<101, 301, 200>;
+type MedianBaseline1000 = MedianBaselineFilter<201, 601, 400>;
+type MedianBaseline2000 = MedianBaselineFilter<401, 1201, 800>;
+
+/// Two-stage median baseline removal for the sample rate selected at runtime. The filters hold
+/// several kilobytes of samples, so they are kept on the heap.
+#[derive(Clone)]
+pub enum MedianBaseline {
+    Sps250(Box<MedianBaseline250>),
+    Sps500(Box<MedianBaseline500>),
+    Sps1000(Box<MedianBaseline1000>),
+    Sps2000(Box<MedianBaseline2000>),
+}
+
+impl MedianBaseline {
+    /// Returns `None` if there is not enough memory for the filter.
+    #[inline(always)]
+    pub fn new(rate: SampleRate) -> Option<Self> {
+        let filter = match rate {
+            SampleRate::Sps250 => Self::Sps250(try_box(MedianBaselineFilter::new)?),
+            SampleRate::Sps500 => Self::Sps500(try_box(MedianBaselineFilter::new)?),
+            SampleRate::Sps1000 => Self::Sps1000(try_box(MedianBaselineFilter::new)?),
+            SampleRate::Sps2000 => Self::Sps2000(try_box(MedianBaselineFilter::new)?),
+        };
+
+        Some(filter)
+    }
+
+    /// The number of samples the filter delays the signal.
+    pub fn delay(rate: SampleRate) -> usize {
+        match rate {
+            SampleRate::Sps250 => MedianBaseline250::DELAY,
+            SampleRate::Sps500 => MedianBaseline500::DELAY,
+            SampleRate::Sps1000 => MedianBaseline1000::DELAY,
+            SampleRate::Sps2000 => MedianBaseline2000::DELAY,
+        }
+    }
+}
+
+/// Implements [`Filter`] for an enum whose variants all hold a filter.
+macro_rules! forward_filter {
+    ($ty:ty { $($variant:ident),+ }) => {
+        impl Filter for $ty {
+            fn update(&mut self, sample: f32) -> Option<f32> {
+                match self {
+                    $(Self::$variant(filter) => filter.update(sample),)+
+                }
+            }
+
+            fn clear(&mut self) {
+                match self {
+                    $(Self::$variant(filter) => filter.clear(),)+
+                }
+            }
+
+            fn process_in_place(&mut self, samples: &mut [f32]) -> usize {
+                match self {
+                    $(Self::$variant(filter) => filter.process_in_place(samples),)+
+                }
+            }
+        }
+    };
+}
+
+forward_filter!(MedianBaseline {
+    Sps250,
+    Sps500,
+    Sps1000,
+    Sps2000
+});
+
+/// Removes the power line fundamental and the `H` harmonics below the Nyquist frequency. The
+/// signal's variance, used to detect QRS complexes, is estimated over `W` samples. `C` is the
+/// length of the comb filter that suppresses the interference before QRS complexes are looked
+/// for, about one mains period.
+#[cfg(not(feature = "fixed-point"))]
+type HarmonicsFilter<const W: usize, const C: usize, const H: usize> = PowerLineFilter<
+    AdaptationBlocking<EstimatedSum<W>, ECG_FILTER_DELAY, C>,
+    BiquadCascade<HighPass, 1>,
+    H,
+>;
+
+#[cfg(feature = "fixed-point")]
+type HarmonicsFilter<const W: usize, const C: usize, const H: usize> =
+    FixedPoint<PowerLineFilterQ31<AdaptationBlockingQ31<W, ECG_FILTER_DELAY, C>, H>>;
+
+fn harmonics_filter<const W: usize, const C: usize, const H: usize>(
+    rate: SampleRate,
+    mains: MainsFrequency,
+) -> HarmonicsFilter<W, C, H> {
+    let harmonics = mains.harmonics();
+    let frequencies = core::array::from_fn(|idx| harmonics[idx]);
+
+    #[cfg(not(feature = "fixed-point"))]
+    let filter = unwrap!(PowerLineFilter::new(rate.fs(), frequencies));
+
+    #[cfg(feature = "fixed-point")]
+    let filter = FixedPoint::new(unwrap!(PowerLineFilterQ31::new(rate.fs(), frequencies)));
+
+    filter
+}
+
+/// Power line filter for the sample rate and mains frequency selected at runtime. The variance
+/// is estimated over 1.2 seconds. At 250 sps, the 3rd harmonic is above the Nyquist frequency.
+#[derive(Clone)]
+pub enum MainsFilter {
+    Sps250Hz50(HarmonicsFilter<300, 5, 2>),
+    Sps250Hz60(HarmonicsFilter<300, 4, 2>),
+    Sps500Hz50(HarmonicsFilter<600, 10, 3>),
+    Sps500Hz60(HarmonicsFilter<600, 8, 3>),
+    Sps1000Hz50(HarmonicsFilter<1200, 19, 3>),
+    Sps1000Hz60(HarmonicsFilter<1200, 17, 3>),
+    Sps2000Hz50(HarmonicsFilter<2400, 38, 3>),
+    Sps2000Hz60(HarmonicsFilter<2400, 34, 3>),
+}
+
+impl MainsFilter {
+    pub fn new(rate: SampleRate, mains: MainsFrequency) -> Self {
+        match (rate, mains) {
+            (SampleRate::Sps250, MainsFrequency::Hz50) => {
+                Self::Sps250Hz50(harmonics_filter(rate, mains))
+            }
+            (SampleRate::Sps250, MainsFrequency::Hz60) => {
+                Self::Sps250Hz60(harmonics_filter(rate, mains))
+            }
+            (SampleRate::Sps500, MainsFrequency::Hz50) => {
+                Self::Sps500Hz50(harmonics_filter(rate, mains))
+            }
+            (SampleRate::Sps500, MainsFrequency::Hz60) => {
+                Self::Sps500Hz60(harmonics_filter(rate, mains))
+            }
+            (SampleRate::Sps1000, MainsFrequency::Hz50) => {
+                Self::Sps1000Hz50(harmonics_filter(rate, mains))
+            }
+            (SampleRate::Sps1000, MainsFrequency::Hz60) => {
+                Self::Sps1000Hz60(harmonics_filter(rate, mains))
+            }
+            (SampleRate::Sps2000, MainsFrequency::Hz50) => {
+                Self::Sps2000Hz50(harmonics_filter(rate, mains))
+            }
+            (SampleRate::Sps2000, MainsFrequency::Hz60) => {
+                Self::Sps2000Hz60(harmonics_filter(rate, mains))
+            }
+        }
+    }
+}
+
+forward_filter!(MainsFilter {
+    Sps250Hz50,
+    Sps250Hz60,
+    Sps500Hz50,
+    Sps500Hz60,
+    Sps1000Hz50,
+    Sps1000Hz60,
+    Sps2000Hz50,
+    Sps2000Hz60
+});
+
+/// The high-pass variant of [`BaselineRemoval`].
+#[cfg(not(feature = "fixed-point"))]
+pub type HighPassFilter = BiquadCascade<HighPass, 1>;
+
+#[cfg(feature = "fixed-point")]
+pub type HighPassFilter = FixedPoint<IirQ31<HighPass, 2>>;
+
+/// Baseline wander removal selected at runtime.
+#[derive(Clone)]
+pub enum BaselineRemoval {
+    None,
+    HighPass(HighPassFilter),
+    Median(MedianBaseline),
+}
+
+impl Filter for BaselineRemoval {
+    fn update(&mut self, sample: f32) -> Option<f32> {
+        match self {
+            Self::None => Some(sample),
+            Self::HighPass(filter) => filter.update(sample),
+            Self::Median(filter) => filter.update(sample),
+        }
+    }
+
+    fn clear(&mut self) {
+        match self {
+            Self::None => {}
+            Self::HighPass(filter) => filter.clear(),
+            Self::Median(filter) => filter.clear(),
+        }
+    }
+
+    fn process_in_place(&mut self, samples: &mut [f32]) -> usize {
+        match self {
+            Self::None => samples.len(),
+            Self::HighPass(filter) => filter.process_in_place(samples),
+            Self::Median(filter) => filter.process_in_place(samples),
+        }
+    }
+}
+
+// PLI filtering algo is probably overkill for displaying, but it's fancy
+pub type EcgFilter = chain! {
+    MainsFilter,
+    BaselineRemoval
+};
+
+/// Filter used to remove baseline wander.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BaselineFilter {
+    None,
+    Weak,
+    Strong,
+    /// Two-stage median filter, see [`MedianBaseline`]. Keeps the ST level intact.
+    Median,
+}
+
+impl BaselineFilter {
+    /// The -3dB cutoff frequency of the high-pass filter in Hz, or 0 if no high-pass filter is
+    /// used.
+    pub fn cutoff(self) -> f32 {
+        match self {
+            BaselineFilter::None | BaselineFilter::Median => 0.0,
+            BaselineFilter::Weak => 0.75,
+            BaselineFilter::Strong => 1.5,
+        }
+    }
+
+    /// Returns the high-pass filter that was used for a recording, based on its cutoff
+    /// frequency.
+    pub fn from_cutoff(cutoff: f32) -> Option<Self> {
+        [Self::None, Self::Weak, Self::Strong]
+            .into_iter()
+            .find(|filter| filter.cutoff() == cutoff)
+    }
+
+    /// The number of samples the filter delays the signal.
+    pub fn delay(self, rate: SampleRate) -> usize {
+        match self {
+            BaselineFilter::Median => MedianBaseline::delay(rate),
+            _ => 0,
+        }
+    }
+
+    /// Returns `None` if there is not enough memory for the filter.
+    #[inline(always)]
+    pub fn filter(self, rate: SampleRate) -> Option<BaselineRemoval> {
+        let filter = match self {
+            BaselineFilter::None => BaselineRemoval::None,
+            BaselineFilter::Median => BaselineRemoval::Median(MedianBaseline::new(rate)?),
+            high_pass => BaselineRemoval::HighPass(high_pass.high_pass(rate)),
+        };
+
+        Some(filter)
+    }
+
+    fn high_pass(self, rate: SampleRate) -> HighPassFilter {
+        let filter = unwrap!(BiquadCascade::<HighPass, 1>::butterworth(
+            rate.fs(),
+            self.cutoff(),
+            2
+        ));
+
+        #[cfg(feature = "fixed-point")]
+        let filter = FixedPoint::new(IirQ31::from_biquad(&filter.sections()[0]));
+
+        filter
+    }
+}
+
+/// Returns `None` if there is not enough memory for the baseline filter.
+#[inline(always)]
+pub fn ecg_filter(
+    baseline: BaselineFilter,
+    mains: MainsFrequency,
+    rate: SampleRate,
+) -> Option<EcgFilter> {
+    Some(Chain::new(MainsFilter::new(rate, mains)).append(baseline.filter(rate)?))
+}
+
+/// Low-pass filter applied to the output of [`EcgFilter`] before heart rate detection.
+pub fn heart_rate_noise_filter(rate: SampleRate) -> BiquadCascade<LowPass, 1> {
+    unwrap!(BiquadCascade::<LowPass, 1>::butterworth(rate.fs(), 20.0, 2))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A crude ECG: 1 mV, 40 ms wide triangular QRS complexes at 72 BPM.
+    fn ecg(t: f32) -> f32 {
+        let phase = (t * 1.2).fract();
+        1.0e-3 * (1.0 - (phase - 0.5).abs() / 0.02).max(0.0)
+    }
+
+    #[test]
+    fn sample_rate_conversion() {
+        assert_eq!(SampleRate::from_hz(500), Some(SampleRate::Sps500));
+        assert_eq!(SampleRate::from_hz(300), None);
+        assert_eq!(SampleRate::default().hz(), 1000);
+    }
+
+    #[test]
+    fn removes_interference_at_every_sample_rate() {
+        for rate in [
+            SampleRate::Sps250,
+            SampleRate::Sps500,
+            SampleRate::Sps1000,
+            SampleRate::Sps2000,
+        ] {
+            for mains in [MainsFrequency::Hz50, MainsFrequency::Hz60] {
+                let mut filter = ecg_filter(BaselineFilter::None, mains, rate).unwrap();
+                let fs = rate.fs();
+
+                let mut error = 0.0f32;
+                for n in 0..10 * rate.hz() as usize {
+                    let t = n as f32 / fs;
+                    let interference =
+                        0.2e-3 * (core::f32::consts::TAU * mains.hz() as f32 * t).sin();
+                    let Some(output) = filter.update(0.1 + ecg(t) + interference) else {
+                        continue;
+                    };
+
+                    if t > 8.0 {
+                        let delayed = (n - ECG_FILTER_DELAY) as f32 / fs;
+                        error = error.max((output - 0.1 - ecg(delayed)).abs());
+                    }
+                }
+
+                assert!(error < 20.0e-6, "{rate:?} {mains:?}: {error}");
+            }
+        }
+    }
+
+    #[test]
+    fn median_baseline_delay_matches_filter() {
+        for rate in [SampleRate::Sps250, SampleRate::Sps2000] {
+            let mut filter = BaselineFilter::Median.filter(rate).unwrap();
+            let delay = BaselineFilter::Median.delay(rate);
+
+            let warmup = (0..4 * delay)
+                .filter(|_| filter.update(0.0).is_none())
+                .count();
+            assert_eq!(warmup, 2 * delay);
+        }
+    }
+}
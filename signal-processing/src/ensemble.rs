@@ -0,0 +1,875 @@
+//! Ensemble averaging and interval measurement
+//!
+//! [`EnsembleAverager`] cuts the recent beats out of the filtered ECG and aligns them on their R
+//! peaks. The sample by sample median of the aligned beats is a [`MedianBeat`]: noise and beats
+//! disturbed by motion are left out, while the shape every beat shares is kept. Only beats that
+//! follow a normal RR interval are averaged, because premature beats are usually shaped
+//! differently.
+//!
+//! The median beat is delineated to find the onset of the P wave, the onset and the end of the QRS
+//! complex and the end of the T wave:
+//!
+//! - The QRS complex is where the slope, averaged over a short window, is steeper than a fraction
+//!   of its maximum. Averaging keeps the turning points of the Q and S waves from ending the
+//!   complex early.
+//! - The isoelectric level is the level of the flattest point shortly before the QRS complex.
+//! - The P wave onset and the T wave end are found with the tangent method: the tangent at the
+//!   steepest slope of the wave is extended until it crosses the isoelectric level.
+//!
+//! The QT interval is corrected for the heart rate with Bazett's formula. The ST deviation is
+//! measured 60 ms after the end of the QRS complex, relative to the isoelectric level. It is only
+//! meaningful if the baseline was removed without a high-pass filter.
+
+use core::ops::Range;
+
+#[cfg(feature = "alloc")]
+use alloc::{collections::VecDeque, vec::Vec};
+
+#[cfg(feature = "alloc")]
+use crate::{
+    heap::{try_queue, try_vec},
+    heart_rate::Beat,
+};
+
+#[allow(unused_imports)]
+use crate::compat::*;
+
+/// Beats are averaged at this sample rate, or at the input's sample rate if that is lower.
+const AVERAGING_RATE: u32 = 500;
+/// The part of the beat before the R peak that is kept, in milliseconds.
+const BEFORE_R_MS: u32 = 400;
+/// The part of the beat after the R peak that is kept, in milliseconds.
+const AFTER_R_MS: u32 = 600;
+/// The number of samples in a beat at the highest averaging rate.
+pub const MAX_BEAT_LEN: usize = ((BEFORE_R_MS + AFTER_R_MS) * AVERAGING_RATE / 1000) as usize;
+
+/// The R peak is searched this far from the detected position, in milliseconds.
+const ALIGN_MS: u32 = 50;
+/// The QRS detector reports beats at most about this late, in milliseconds.
+const MAX_DETECTION_DELAY_MS: u32 = 400;
+/// The number of samples kept to cut beats from.
+#[cfg(feature = "alloc")]
+const HISTORY_LEN: usize =
+    MAX_BEAT_LEN + ((2 * ALIGN_MS + MAX_DETECTION_DELAY_MS) * AVERAGING_RATE / 1000) as usize;
+
+/// At most this many beats wait for their samples at a time.
+#[cfg(feature = "alloc")]
+const MAX_PENDING: usize = 4;
+
+/// Beats are averaged if their RR interval differs from the previous one by less than this
+/// fraction.
+const MAX_RR_DEVIATION: f32 = 0.2;
+
+/// The steepest slope of the QRS complex is searched this far from the R peak, in seconds.
+const QRS_SEARCH: f32 = 0.06;
+/// The slope is averaged over this long to find the QRS complex, in seconds.
+const SLOPE_WINDOW: f32 = 0.02;
+/// The QRS complex is where the average slope is steeper than this fraction of its maximum.
+const QRS_SLOPE_THRESHOLD: f32 = 0.06;
+/// The median beat is smoothed with a moving average this long before the QRS complex is
+/// delineated, in seconds.
+const QRS_SMOOTHING: f32 = 0.01;
+/// The P and T waves are slower, they are delineated after smoothing this long, in seconds.
+const WAVE_SMOOTHING: f32 = 0.04;
+/// The isoelectric level is searched this far before the QRS onset, in seconds.
+const ISOELECTRIC_SEARCH: f32 = 0.04;
+/// The ST deviation is measured this long after the J point, in seconds.
+const ST_OFFSET: f32 = 0.06;
+/// The P wave is searched this far before the PR segment, in seconds.
+const P_SEARCH: f32 = 0.3;
+/// The T wave is searched after this much of the ST segment, in seconds.
+const T_SEARCH_START: f32 = 0.04;
+/// The T wave is searched for this fraction of the RR interval after the R peak.
+const T_SEARCH_RR: f32 = 0.7;
+/// Smaller P waves are not measured, in volts.
+const MIN_P_AMPLITUDE: f32 = 30.0e-6;
+/// Smaller T waves are not measured, in volts.
+const MIN_T_AMPLITUDE: f32 = 50.0e-6;
+
+/// Intervals and levels measured on a [`MedianBeat`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BeatMeasurements {
+    /// From the onset of the P wave to the onset of the QRS complex, in milliseconds. `None` if
+    /// no P wave was found.
+    pub pr_ms: Option<f32>,
+    /// From the onset to the end of the QRS complex, in milliseconds.
+    pub qrs_ms: f32,
+    /// From the onset of the QRS complex to the end of the T wave, in milliseconds. `None` if no
+    /// T wave was found.
+    pub qt_ms: Option<f32>,
+    /// The QT interval corrected for the heart rate with Bazett's formula, in milliseconds.
+    pub qtc_ms: Option<f32>,
+    /// The ST level 60 ms after the J point, relative to the isoelectric level, in millivolts.
+    pub st_deviation_mv: f32,
+    /// The median RR interval of the averaged beats, in milliseconds.
+    pub rr_ms: f32,
+    /// The number of beats averaged.
+    pub beats: usize,
+}
+
+/// A representative beat, with the R peak at a known sample.
+#[derive(Clone)]
+pub struct MedianBeat {
+    samples: [f32; MAX_BEAT_LEN],
+    len: usize,
+    fs: f32,
+    r_peak: usize,
+    rr_interval: f32,
+    beats: usize,
+}
+
+impl MedianBeat {
+    /// Creates a median beat from `samples` in volts, taken at `fs`. `rr_interval` is the RR
+    /// interval of the averaged beats, in seconds.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `samples` is longer than [`MAX_BEAT_LEN`], or `r_peak` is out of bounds.
+    pub fn new(samples: &[f32], fs: f32, r_peak: usize, rr_interval: f32, beats: usize) -> Self {
+        assert!(r_peak < samples.len());
+
+        let mut beat = Self {
+            samples: [0.0; MAX_BEAT_LEN],
+            len: samples.len(),
+            fs,
+            r_peak,
+            rr_interval,
+            beats,
+        };
+        beat.samples[..samples.len()].copy_from_slice(samples);
+
+        beat
+    }
+
+    /// The samples of the beat, in volts.
+    pub fn samples(&self) -> &[f32] {
+        &self.samples[..self.len]
+    }
+
+    /// The sample rate of the beat.
+    pub fn fs(&self) -> f32 {
+        self.fs
+    }
+
+    /// The index of the R peak.
+    pub fn r_peak(&self) -> usize {
+        self.r_peak
+    }
+
+    fn samples_in(&self, seconds: f32) -> usize {
+        (seconds * self.fs) as usize
+    }
+
+    fn to_ms(&self, samples: f32) -> f32 {
+        samples * 1000.0 / self.fs
+    }
+
+    /// The slope at `idx`, in volts per sample.
+    fn slope(&self, idx: usize) -> f32 {
+        (self.samples[idx + 1] - self.samples[idx - 1]) / 2.0
+    }
+
+    /// The average absolute slope in a window centered at `idx`, which must be at least
+    /// `half_window + 1` samples from both ends.
+    fn average_slope(&self, idx: usize, half_window: usize) -> f32 {
+        let window = idx - half_window..idx + half_window + 1;
+        let len = window.len();
+        window.map(|idx| self.slope(idx).abs()).sum::<f32>() / len as f32
+    }
+
+    /// The index in `range` where the signal is the farthest from `level`.
+    fn extremum(&self, range: Range<usize>, level: f32) -> Option<usize> {
+        range.max_by(|&a, &b| {
+            let a = (self.samples[a] - level).abs();
+            let b = (self.samples[b] - level).abs();
+            a.total_cmp(&b)
+        })
+    }
+
+    /// Extends the tangent at the steepest slope in `range` with the given sign until it crosses
+    /// `level`. Returns the fractional index of the crossing.
+    fn tangent_crossing(&self, range: Range<usize>, sign: f32, level: f32) -> Option<f32> {
+        let steepest =
+            range.max_by(|&a, &b| (sign * self.slope(a)).total_cmp(&(sign * self.slope(b))))?;
+        let slope = self.slope(steepest);
+        if sign * slope <= 0.0 {
+            return None;
+        }
+
+        Some(steepest as f32 + (level - self.samples[steepest]) / slope)
+    }
+
+    /// Returns a copy of the beat, smoothed with a centered moving average of `window` seconds.
+    fn smoothed(&self, window: f32) -> Self {
+        let half_window = self.samples_in(window / 2.0);
+        let mut smoothed = self.clone();
+        for (idx, sample) in smoothed.samples[..self.len].iter_mut().enumerate() {
+            let window = &self.samples()
+                [idx.saturating_sub(half_window)..(idx + half_window + 1).min(self.len)];
+            *sample = window.iter().sum::<f32>() / window.len() as f32;
+        }
+
+        smoothed
+    }
+
+    /// Delineates the beat. Returns `None` if no QRS complex is found.
+    pub fn measure(&self) -> Option<BeatMeasurements> {
+        self.smoothed(QRS_SMOOTHING)
+            .delineate(&self.smoothed(WAVE_SMOOTHING))
+    }
+
+    /// Delineates the QRS complex of `self` and the P and T waves of `waves`.
+    fn delineate(&self, waves: &Self) -> Option<BeatMeasurements> {
+        let len = self.len;
+        let r = self.r_peak;
+        let half_window = self.samples_in(SLOPE_WINDOW / 2.0).max(1);
+        let first = half_window + 1;
+        let last = len - half_window - 2;
+        if r < first || r > last {
+            return None;
+        }
+
+        let search = self.samples_in(QRS_SEARCH);
+        let max_slope = (r.saturating_sub(search).max(first)..(r + search).min(last))
+            .map(|idx| self.average_slope(idx, half_window))
+            .fold(0.0, f32::max);
+        if max_slope <= 0.0 {
+            return None;
+        }
+
+        let threshold = QRS_SLOPE_THRESHOLD * max_slope;
+        let is_flat = |idx: usize| self.average_slope(idx, half_window) < threshold;
+        let onset = (first..r).rev().find(|&idx| is_flat(idx))?;
+        let end = (r + 1..=last).find(|&idx| is_flat(idx))?;
+
+        // The flattest point of the PR segment.
+        let segment = onset
+            .saturating_sub(self.samples_in(ISOELECTRIC_SEARCH))
+            .max(1)..onset;
+        let pr_segment = segment
+            .min_by(|&a, &b| waves.slope(a).abs().total_cmp(&waves.slope(b).abs()))
+            .unwrap_or(onset);
+        let isoelectric = waves.samples[pr_segment];
+        let st_idx = (end + self.samples_in(ST_OFFSET)).min(len - 1);
+        let st_deviation = self.samples[st_idx] - isoelectric;
+
+        let pr = waves
+            .p_onset(pr_segment, isoelectric)
+            .map(|p_onset| onset as f32 - p_onset);
+        let qt = waves
+            .t_end(end, isoelectric)
+            .map(|t_end| t_end - onset as f32);
+        let qt_ms = qt.map(|qt| self.to_ms(qt));
+
+        Some(BeatMeasurements {
+            pr_ms: pr.map(|pr| self.to_ms(pr)),
+            qrs_ms: self.to_ms((end - onset) as f32),
+            qt_ms,
+            qtc_ms: qt_ms.map(|qt| qt / self.rr_interval.sqrt()),
+            st_deviation_mv: st_deviation * 1000.0,
+            rr_ms: self.rr_interval * 1000.0,
+            beats: self.beats,
+        })
+    }
+
+    /// Returns the fractional index of the P wave's onset. The P wave ends before `pr_segment`.
+    fn p_onset(&self, pr_segment: usize, isoelectric: f32) -> Option<f32> {
+        let search_end = pr_segment;
+        let search_start = pr_segment.saturating_sub(self.samples_in(P_SEARCH)).max(1);
+        if search_start >= search_end {
+            return None;
+        }
+
+        let peak = self.extremum(search_start..search_end, isoelectric)?;
+        let amplitude = self.samples[peak] - isoelectric;
+        // A maximum at the edge of the window is the slope of some other wave.
+        if amplitude.abs() < MIN_P_AMPLITUDE || peak == search_start || peak == search_end - 1 {
+            return None;
+        }
+
+        let onset = self.tangent_crossing(search_start..peak, amplitude.signum(), isoelectric)?;
+        (onset >= 0.0 && onset < peak as f32).then_some(onset)
+    }
+
+    /// Returns the fractional index of the T wave's end.
+    fn t_end(&self, qrs_end: usize, isoelectric: f32) -> Option<f32> {
+        let search_start = qrs_end + self.samples_in(T_SEARCH_START);
+        let search_end =
+            (self.r_peak + self.samples_in(T_SEARCH_RR * self.rr_interval)).min(self.len - 1);
+        if search_start >= search_end {
+            return None;
+        }
+
+        let peak = self.extremum(search_start..search_end, isoelectric)?;
+        let amplitude = self.samples[peak] - isoelectric;
+        if amplitude.abs() < MIN_T_AMPLITUDE || peak == search_end - 1 {
+            return None;
+        }
+
+        // The T wave ends where it descends back to the isoelectric level.
+        let end = self.tangent_crossing(peak + 1..search_end, -amplitude.signum(), isoelectric)?;
+        (end > peak as f32 && end < self.len as f32).then_some(end)
+    }
+}
+
+/// An R peak waiting for the rest of its beat to arrive.
+#[cfg(feature = "alloc")]
+#[derive(Clone, Copy)]
+struct PendingBeat {
+    /// The detected position of the R peak, in averaged samples.
+    position: u32,
+    /// The RR interval before the beat, in seconds.
+    rr_interval: f32,
+}
+
+/// Collects the most recent normal beats and computes their [`MedianBeat`].
+#[cfg(feature = "alloc")]
+pub struct EnsembleAverager {
+    /// The number of input samples averaged into one sample.
+    decimation: u32,
+    /// The input sample rate.
+    input_fs: f32,
+    /// The averaged samples of a beat before the R peak, and in total.
+    before: usize,
+    len: usize,
+    align: usize,
+
+    inputs: u32,
+    sum: f32,
+    summed: u32,
+
+    /// A ring buffer of the recent averaged samples.
+    history: Vec<f32>,
+    /// The number of averaged samples written into `history`.
+    written: u32,
+    pending: VecDeque<PendingBeat>,
+    prev_rr: Option<u32>,
+
+    /// Each beat is stored as its RR interval in milliseconds, followed by its samples in
+    /// microvolts. The beats are in a ring buffer.
+    beats: Vec<i16>,
+    max_beats: usize,
+    count: usize,
+    next: usize,
+}
+
+#[cfg(feature = "alloc")]
+impl EnsembleAverager {
+    /// Averages the last `max_beats` beats of a signal sampled at `fs`. Returns `None` if there
+    /// is not enough memory for the beats.
+    pub fn new(fs: f32, max_beats: usize) -> Option<Self> {
+        let decimation = (fs as u32 / AVERAGING_RATE).max(1);
+        let averaging_fs = fs as u32 / decimation;
+        let samples = |ms: u32| (ms * averaging_fs / 1000) as usize;
+
+        let len = samples(BEFORE_R_MS + AFTER_R_MS);
+
+        Some(Self {
+            decimation,
+            input_fs: fs,
+            before: samples(BEFORE_R_MS),
+            len,
+            align: samples(ALIGN_MS),
+
+            inputs: 0,
+            sum: 0.0,
+            summed: 0,
+
+            history: try_vec(0.0, HISTORY_LEN)?,
+            written: 0,
+            pending: try_queue(MAX_PENDING)?,
+            prev_rr: None,
+
+            beats: try_vec(0, max_beats * (len + 1))?,
+            max_beats,
+            count: 0,
+            next: 0,
+        })
+    }
+
+    pub fn clear(&mut self) {
+        self.inputs = 0;
+        self.sum = 0.0;
+        self.summed = 0;
+        self.written = 0;
+        self.pending.clear();
+        self.prev_rr = None;
+        self.count = 0;
+        self.next = 0;
+    }
+
+    /// The sample rate beats are averaged at.
+    pub fn fs(&self) -> f32 {
+        self.input_fs / self.decimation as f32
+    }
+
+    /// The number of beats collected.
+    pub fn beats(&self) -> usize {
+        self.count
+    }
+
+    /// Processes a filtered sample.
+    pub fn update(&mut self, sample: f32) {
+        self.inputs += 1;
+        self.sum += sample;
+        self.summed += 1;
+        if self.summed < self.decimation {
+            return;
+        }
+
+        let averaged = self.sum / self.summed as f32;
+        self.sum = 0.0;
+        self.summed = 0;
+
+        self.history[self.written as usize % HISTORY_LEN] = averaged;
+        self.written += 1;
+
+        let after = (self.len - self.before + self.align) as u32;
+        while let Some(beat) = self.pending.front().copied() {
+            if self.written <= beat.position + after {
+                break;
+            }
+            self.pending.pop_front();
+            self.cut_beat(beat);
+        }
+    }
+
+    /// Adds a beat detected in the samples passed to [`Self::update`]. The beat must be reported
+    /// right after the sample that completed its detection.
+    pub fn add_beat(&mut self, beat: &Beat) {
+        let Some(rr_interval) = beat.rr_interval else {
+            self.prev_rr = None;
+            return;
+        };
+
+        let prev_rr = self.prev_rr.replace(rr_interval);
+        let is_normal = prev_rr.is_some_and(|prev| {
+            (rr_interval as f32 - prev as f32).abs() < MAX_RR_DEVIATION * prev as f32
+        });
+
+        // Beats found by searching back are not located precisely enough.
+        if !is_normal || beat.searchback {
+            return;
+        }
+
+        let Some(index) = (self.inputs - 1).checked_sub(beat.delay) else {
+            return;
+        };
+
+        let beat = PendingBeat {
+            position: index / self.decimation,
+            rr_interval: rr_interval as f32 / self.input_fs,
+        };
+        // Too many beats are in progress if the detections are too frequent, drop the newest.
+        if self.pending.len() < MAX_PENDING {
+            self.pending.push_back(beat);
+        }
+    }
+
+    fn sample(&self, position: u32) -> f32 {
+        self.history[position as usize % HISTORY_LEN]
+    }
+
+    /// Aligns a complete beat on its R peak and stores it.
+    fn cut_beat(&mut self, beat: PendingBeat) {
+        let oldest = self.written.saturating_sub(HISTORY_LEN as u32);
+        let align = self.align as u32;
+        let before = self.before as u32;
+        if beat.position < oldest + before + align {
+            // The beat is older than the history.
+            return;
+        }
+
+        // The R peak is the sample farthest from the average around the detected position.
+        let search = beat.position - align..beat.position + align + 1;
+        let mean = search.clone().map(|idx| self.sample(idx)).sum::<f32>() / search.len() as f32;
+        let r_peak = unwrap!(search.max_by(|&a, &b| {
+            let a = (self.sample(a) - mean).abs();
+            let b = (self.sample(b) - mean).abs();
+            a.total_cmp(&b)
+        }));
+
+        let window = r_peak - before..r_peak - before + self.len as u32;
+        let offset = window.clone().map(|idx| self.sample(idx)).sum::<f32>() / self.len as f32;
+
+        let history = &self.history;
+        let record_len = self.len + 1;
+        let record = &mut self.beats[self.next * record_len..(self.next + 1) * record_len];
+        record[0] = (beat.rr_interval * 1000.0).min(i16::MAX as f32) as i16;
+        for (stored, idx) in record[1..].iter_mut().zip(window) {
+            let microvolts = (history[idx as usize % HISTORY_LEN] - offset) * 1.0e6;
+            *stored = microvolts.clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+        }
+
+        self.next = (self.next + 1) % self.max_beats;
+        self.count = (self.count + 1).min(self.max_beats);
+    }
+
+    /// Computes the median of the collected beats, if there are at least `min_beats` of them and
+    /// there is enough memory to sort them.
+    pub fn median_beat(&self, min_beats: usize) -> Option<MedianBeat> {
+        if self.count == 0 || self.count < min_beats {
+            return None;
+        }
+
+        let record_len = self.len + 1;
+        let records = &self.beats[..self.count * record_len];
+        let mut values = try_vec(0, self.count)?;
+        let mut median_of = |offset: usize| {
+            for (value, record) in values.iter_mut().zip(records.chunks_exact(record_len)) {
+                *value = record[offset];
+            }
+            median(&mut values)
+        };
+
+        let mut samples = [0.0; MAX_BEAT_LEN];
+        for (idx, sample) in samples[..self.len].iter_mut().enumerate() {
+            *sample = median_of(idx + 1) * 1.0e-6;
+        }
+        let rr_interval = median_of(0) / 1000.0;
+
+        Some(MedianBeat::new(
+            &samples[..self.len],
+            self.fs(),
+            self.before,
+            rr_interval,
+            self.count,
+        ))
+    }
+}
+
+/// Returns the median of `values`, reordering them.
+#[cfg(feature = "alloc")]
+fn median(values: &mut [i16]) -> f32 {
+    values.sort_unstable();
+
+    let middle = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[middle - 1] as f32 + values[middle] as f32) / 2.0
+    } else {
+        values[middle] as f32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::synthetic::{SignalConfig, SyntheticEcg, PQRST};
+
+    const FS: f32 = 500.0;
+
+    /// Returns a clean beat of a synthetic signal, with the R peak at index 200.
+    fn synthetic_beat(config: SignalConfig) -> MedianBeat {
+        let rr_interval = 60.0 / config.heart_rate;
+        let mut ecg = SyntheticEcg::new(FS, config);
+
+        let mut samples = Vec::new();
+        let mut peaks = 0;
+        loop {
+            samples.push(ecg.sample());
+            if ecg.is_r_peak() {
+                peaks += 1;
+                if peaks == 3 {
+                    break;
+                }
+            }
+        }
+        let peak = samples.len() - 1;
+        samples.extend((0..299).map(|_| ecg.sample()));
+
+        MedianBeat::new(&samples[peak - 200..], FS, 200, rr_interval, 1)
+    }
+
+    /// The model's P wave is longer than most, it runs into the QRS complex. A shorter one
+    /// leaves an isoelectric PR segment, like in a real ECG.
+    fn beat_config(heart_rate: f32) -> SignalConfig {
+        let mut waves = PQRST;
+        waves[0].width = 0.12;
+
+        SignalConfig {
+            heart_rate,
+            waves,
+            ..SignalConfig::default()
+        }
+    }
+
+    /// Checks a measurement against an expected value, in the measurement's unit.
+    #[track_caller]
+    fn assert_close(measured: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (measured - expected).abs() <= tolerance,
+            "{measured} != {expected}"
+        );
+    }
+
+    #[test]
+    fn measures_synthetic_beat() {
+        let measurements = synthetic_beat(beat_config(60.0)).measure().unwrap();
+
+        // At 60 BPM, the model's waves are centered at -167 ms (P), -42 ms (Q), 42 ms (S) and
+        // 250 ms (T), with standard deviations of 19 ms (P), 16 ms (QRS) and 64 ms (T). Smoothing
+        // widens the P and T waves to 22 ms and 65 ms. The tangent at the steepest slope of a
+        // Gaussian crosses its baseline 2σ from its center.
+        // The QRS complex ends where the tails of the Q and S waves flatten out, about 2.5σ from
+        // their centers.
+        let qrs_onset = -42.0 - 2.5 * 16.0;
+        assert_close(measurements.qrs_ms, -2.0 * qrs_onset, 12.0);
+        assert_close(
+            measurements.pr_ms.unwrap(),
+            qrs_onset - (-167.0 - 2.0 * 22.0),
+            10.0,
+        );
+        assert_close(
+            measurements.qt_ms.unwrap(),
+            250.0 + 2.0 * 65.0 - qrs_onset,
+            10.0,
+        );
+        assert_eq!(measurements.qtc_ms, measurements.qt_ms);
+        assert_eq!(measurements.rr_ms, 1000.0);
+    }
+
+    #[test]
+    fn qt_is_corrected_for_heart_rate() {
+        // The model's waves are placed according to Bazett's formula.
+        for heart_rate in [50.0, 90.0, 120.0] {
+            let measurements = synthetic_beat(beat_config(heart_rate)).measure().unwrap();
+
+            let qt = measurements.qt_ms.unwrap();
+            let qtc = measurements.qtc_ms.unwrap();
+            assert_eq!(
+                qt < qtc,
+                heart_rate > 60.0,
+                "{heart_rate}: {measurements:?}"
+            );
+            assert_close(qtc, 452.0, 15.0);
+        }
+    }
+
+    #[test]
+    fn measures_st_deviation() {
+        let normal = synthetic_beat(beat_config(60.0)).measure().unwrap();
+
+        for deviation in [0.2e-3, -0.1e-3] {
+            let config = SignalConfig {
+                st_deviation: deviation,
+                ..beat_config(60.0)
+            };
+            let measurements = synthetic_beat(config).measure().unwrap();
+
+            // The T wave already rises at the measurement point.
+            let st_deviation = measurements.st_deviation_mv - normal.st_deviation_mv;
+            assert_close(st_deviation, deviation * 1000.0, 0.02);
+            assert_close(measurements.qrs_ms, normal.qrs_ms, 10.0);
+        }
+    }
+
+    #[test]
+    fn measures_wide_qrs() {
+        let normal = synthetic_beat(beat_config(60.0)).measure().unwrap();
+
+        // A conduction delay stretches the QRS complex and delays repolarization by as much.
+        let mut waves = beat_config(60.0).waves;
+        waves[4].angle += 0.3 * waves[3].angle;
+        for wave in &mut waves[1..4] {
+            wave.angle *= 1.3;
+            wave.width *= 1.3;
+        }
+        let config = SignalConfig {
+            waves,
+            ..beat_config(60.0)
+        };
+        let wide = synthetic_beat(config).measure().unwrap();
+
+        assert_close(wide.qrs_ms / normal.qrs_ms, 1.3, 0.1);
+    }
+
+    #[test]
+    fn missing_waves_are_not_measured() {
+        let mut waves = beat_config(60.0).waves;
+        // No P wave, like in atrial fibrillation, and a flat T wave.
+        waves[0].height = 0.0;
+        waves[4].height = 0.02;
+        let config = SignalConfig {
+            waves,
+            ..beat_config(60.0)
+        };
+        let measurements = synthetic_beat(config).measure().unwrap();
+
+        assert_eq!(measurements.pr_ms, None);
+        assert_eq!(measurements.qt_ms, None);
+        assert_eq!(measurements.qtc_ms, None);
+
+        let flat = MedianBeat::new(&[0.0; 500], FS, 200, 1.0, 1);
+        assert_eq!(flat.measure(), None);
+    }
+
+    /// Averages a synthetic signal sampled at 1 kHz. The beats are reported with a varying delay,
+    /// like the QRS detector does.
+    #[cfg(feature = "alloc")]
+    fn average(config: SignalConfig, seconds: u32) -> EnsembleAverager {
+        let mut ecg = SyntheticEcg::new(1000.0, config);
+        let mut averager = EnsembleAverager::new(1000.0, 8).unwrap();
+
+        let mut prev_peak = None;
+        let mut reports = VecDeque::new();
+        for n in 0..seconds * 1000 {
+            averager.update(ecg.sample());
+
+            if ecg.is_r_peak() {
+                let delay = 100 + n % 7 * 10;
+                let rr_interval = prev_peak.map(|prev| n - prev);
+                reports.push_back((n + delay, delay, rr_interval));
+                prev_peak = Some(n);
+            }
+
+            if let Some(&(report, delay, rr_interval)) = reports.front() {
+                if report == n {
+                    reports.pop_front();
+                    averager.add_beat(&Beat {
+                        index: n - delay,
+                        delay,
+                        rr_interval,
+                        searchback: false,
+                    });
+                }
+            }
+        }
+
+        averager
+    }
+
+    /// Compares measurements of the same rhythm. The wave ends are located less precisely in a
+    /// noisy recording, about as much as automated QT measurements differ between recordings.
+    #[cfg(feature = "alloc")]
+    fn assert_similar(measured: BeatMeasurements, reference: BeatMeasurements) {
+        assert_close(measured.qrs_ms, reference.qrs_ms, 10.0);
+        assert_close(measured.pr_ms.unwrap(), reference.pr_ms.unwrap(), 15.0);
+        assert_close(measured.qt_ms.unwrap(), reference.qt_ms.unwrap(), 15.0);
+        assert_close(measured.st_deviation_mv, reference.st_deviation_mv, 0.03);
+        assert_close(measured.rr_ms, reference.rr_ms, 20.0);
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn median_beat_ignores_noise_and_artifacts() {
+        let config = SignalConfig {
+            st_deviation: 0.1e-3,
+            rr_variability: 0.02,
+            ..beat_config(72.0)
+        };
+        let clean = average(config.clone(), 30);
+        let reference = clean.median_beat(5).unwrap().measure().unwrap();
+
+        let noisy = SignalConfig {
+            noise: 50.0e-6,
+            artifact_rate: 0.3,
+            artifact_amplitude: 2.0e-3,
+            offset: 0.1,
+            ..config
+        };
+        let averager = average(noisy, 30);
+        assert_eq!(averager.fs(), 500.0);
+
+        let measurements = averager.median_beat(5).unwrap().measure().unwrap();
+        assert_eq!(measurements.beats, 8);
+        assert_similar(measurements, reference);
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn premature_beats_are_left_out() {
+        let mut averager = EnsembleAverager::new(500.0, 8).unwrap();
+        let add_beat = |averager: &mut EnsembleAverager, rr_interval| {
+            for _ in 0..rr_interval {
+                averager.update(0.0);
+            }
+            averager.add_beat(&Beat {
+                index: 0,
+                delay: 50,
+                rr_interval: Some(rr_interval),
+                searchback: false,
+            });
+        };
+
+        for _ in 0..4 {
+            add_beat(&mut averager, 400);
+        }
+        // The first beat can't be judged.
+        assert_eq!(averager.beats() + averager.pending.len(), 3);
+
+        // A premature beat, a compensatory pause and the first normal beat after it.
+        for rr_interval in [250, 550, 400] {
+            add_beat(&mut averager, rr_interval);
+        }
+        assert_eq!(averager.beats() + averager.pending.len(), 3);
+
+        add_beat(&mut averager, 400);
+        assert_eq!(averager.beats() + averager.pending.len(), 4);
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn needs_enough_beats() {
+        let averager = average(SignalConfig::default(), 4);
+
+        assert!(averager.beats() < 5);
+        assert!(averager.median_beat(5).is_none());
+        assert!(averager.median_beat(1).is_some());
+    }
+
+    /// Averages a synthetic signal, with the beats detected by the measurement's processing
+    /// chain and a baseline filter that keeps the ST level.
+    #[cfg(feature = "alloc")]
+    fn detect_and_average(config: SignalConfig, seconds: usize) -> EnsembleAverager {
+        use crate::{
+            ecg::{ecg_filter, heart_rate_noise_filter, BaselineFilter, SampleRate},
+            filter::Filter,
+            heart_rate::HeartRateCalculator,
+        };
+
+        let rate = SampleRate::Sps1000;
+        let mut filter = ecg_filter(BaselineFilter::Median, Default::default(), rate).unwrap();
+        let mut noise_filter = heart_rate_noise_filter(rate);
+        let mut calculator = HeartRateCalculator::new::<300, 50>(rate.fs());
+        let mut averager = EnsembleAverager::new(rate.fs(), 8).unwrap();
+
+        let signal = SyntheticEcg::new(rate.fs(), config).take(seconds * rate.hz() as usize);
+        for sample in signal {
+            let Some(filtered) = filter.update(sample) else {
+                continue;
+            };
+            averager.update(filtered);
+            if let Some(hr_input) = noise_filter.update(filtered) {
+                calculator.update(hr_input);
+                if let Some(beat) = calculator.beat() {
+                    averager.add_beat(&beat);
+                }
+            }
+        }
+
+        averager
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn averages_detected_beats() {
+        let config = SignalConfig {
+            rr_variability: 0.02,
+            ..beat_config(72.0)
+        };
+        let clean = detect_and_average(config.clone(), 30);
+        let reference = clean.median_beat(5).unwrap().measure().unwrap();
+
+        let noisy = SignalConfig {
+            noise: 20.0e-6,
+            baseline_wander: 0.3e-3,
+            mains_amplitude: 0.2e-3,
+            ..config
+        };
+        let averager = detect_and_average(noisy, 30);
+
+        let measurements = averager.median_beat(5).unwrap().measure().unwrap();
+        assert_similar(measurements, reference);
+    }
+}
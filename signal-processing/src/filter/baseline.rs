@@ -0,0 +1,126 @@
+//! Baseline wander removal by median filtering
+//!
+//! The baseline is estimated by two median filters in series. The first one, about 200 ms long,
+//! removes the QRS complexes, the second one, about 600 ms long, removes the P and T waves. The
+//! estimate is subtracted from the signal, delayed to line up with the estimate. Unlike a
+//! high-pass filter, this doesn't change the level of the ST segment relative to the baseline.
+
+use crate::{filter::median::MedianFilter, sliding::SlidingWindow};
+
+use super::Filter;
+
+/// Removes the baseline estimated by a `SHORT` and a `LONG` sample median filter. Window lengths
+/// should be odd. The output lags `DELAY` samples behind the input, which must be the sum of the
+/// delays of the median filters: `SHORT / 2 + LONG / 2`.
+#[derive(Clone)]
+pub struct MedianBaselineFilter<const SHORT: usize, const LONG: usize, const DELAY: usize> {
+    short: MedianFilter<SHORT>,
+    long: MedianFilter<LONG>,
+    delay: SlidingWindow<DELAY>,
+}
+
+impl<const SHORT: usize, const LONG: usize, const DELAY: usize> Default
+    for MedianBaselineFilter<SHORT, LONG, DELAY>
+{
+    #[inline(always)]
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl<const SHORT: usize, const LONG: usize, const DELAY: usize>
+    MedianBaselineFilter<SHORT, LONG, DELAY>
+{
+    /// The number of samples the output lags behind the input.
+    pub const DELAY: usize = DELAY;
+
+    pub const DEFAULT: Self = {
+        assert!(DELAY == SHORT / 2 + LONG / 2);
+
+        Self {
+            short: MedianFilter::new(),
+            long: MedianFilter::new(),
+            delay: SlidingWindow::new(),
+        }
+    };
+
+    #[inline(always)]
+    pub const fn new() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl<const SHORT: usize, const LONG: usize, const DELAY: usize> Filter
+    for MedianBaselineFilter<SHORT, LONG, DELAY>
+{
+    fn update(&mut self, sample: f32) -> Option<f32> {
+        let delayed = self.delay.push(sample);
+
+        let baseline = self.short.update(sample)?;
+        let baseline = self.long.update(baseline)?;
+
+        Some(unwrap!(delayed) - baseline)
+    }
+
+    fn clear(&mut self) {
+        self.short.clear();
+        self.long.clear();
+        self.delay.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    type Baseline = MedianBaselineFilter<21, 61, 40>;
+
+    /// Narrow pulses every 100 samples on a slowly changing baseline.
+    fn signal(n: usize) -> (f32, f32) {
+        let baseline = 0.5 + 1.0e-5 * n as f32;
+        let pulse = if n % 100 < 5 { 1.0 } else { 0.0 };
+        (baseline, pulse)
+    }
+
+    #[test]
+    fn removes_baseline_and_keeps_pulses() {
+        let mut filter = Baseline::new();
+
+        for n in 0..1000 {
+            let (baseline, pulse) = signal(n);
+            let output = filter.update(baseline + pulse);
+
+            if n < 80 {
+                assert_eq!(output, None);
+            } else {
+                let (_, expected) = signal(n - 40);
+                let output = unwrap!(output);
+                assert!((output - expected).abs() < 1e-3, "{n}: {output}");
+            }
+        }
+    }
+
+    #[test]
+    fn step_is_preserved() {
+        // A median filter follows a step without ringing or overshoot, so a step in the
+        // baseline doesn't leave a tail behind.
+        let mut filter = Baseline::new();
+
+        let outputs = (0..400)
+            .filter_map(|n| filter.update(if n < 200 { 0.0 } else { 1.0 }))
+            .collect::<Vec<_>>();
+
+        assert!(outputs.iter().all(|output| output.abs() < 1e-6));
+    }
+
+    #[test]
+    fn clear_restarts_filter() {
+        let mut filter = Baseline::new();
+        for _ in 0..100 {
+            filter.update(1.0);
+        }
+
+        filter.clear();
+        assert_eq!(filter.update(1.0), None);
+    }
+}
@@ -1,4 +1,8 @@
-use super::{fir::Fir, Filter};
+use super::{
+    fir::{Fir, FirQ15},
+    fixed::{to_q15, FixedFilter},
+    Filter,
+};
 
 const COEFFS: &[f32; 43] = &[
     0.001_896_49,
@@ -46,6 +50,9 @@ const COEFFS: &[f32; 43] = &[
     0.001_896_49,
 ];
 
+const COEFFS_Q15: &[i16; 43] = &to_q15(COEFFS);
+
+#[derive(Clone)]
 pub struct DownSampler {
     filter: Fir<'static, 43>,
     output_next: bool,
@@ -90,4 +97,207 @@ impl Filter for DownSampler {
             None
         }
     }
+
+    fn process_in_place(&mut self, samples: &mut [f32]) -> usize {
+        // Only the outputs that are kept are computed.
+        let mut count = 0;
+        for idx in 0..samples.len() {
+            if !self.filter.push(samples[idx]) {
+                continue;
+            }
+
+            let output = self.output_next;
+            self.output_next = !output;
+
+            if output {
+                samples[count] = self.filter.output();
+                count += 1;
+            }
+        }
+        count
+    }
+}
+
+/// Fixed-point version of [`DownSampler`].
+#[derive(Clone)]
+pub struct DownSamplerQ15 {
+    filter: FirQ15<'static, 43>,
+    output_next: bool,
+}
+
+impl DownSamplerQ15 {
+    pub const DEFAULT: Self = Self {
+        filter: FirQ15::from_coeffs(COEFFS_Q15),
+        output_next: false,
+    };
+
+    #[inline(always)]
+    pub const fn new() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl Default for DownSamplerQ15 {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl FixedFilter for DownSamplerQ15 {
+    #[inline]
+    fn clear(&mut self) {
+        self.filter.clear();
+        self.output_next = false;
+    }
+
+    #[inline]
+    fn update(&mut self, sample: i32) -> Option<i32> {
+        let filtered = self.filter.update(sample)?;
+
+        let output = self.output_next;
+        self.output_next = !output;
+
+        output.then_some(filtered)
+    }
+}
+
+/// Halves the sample rate a number of times selected at runtime, using up to `N` stages of `F`.
+#[derive(Clone)]
+pub struct DownSamplerCascade<F, const N: usize> {
+    stages: [F; N],
+    active: usize,
+}
+
+impl<F, const N: usize> DownSamplerCascade<F, N>
+where
+    F: Clone,
+{
+    /// Creates a cascade that halves the sample rate `active` times.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `active` is larger than `N`.
+    pub fn new(stage: F, active: usize) -> Self {
+        assert!(active <= N);
+
+        Self {
+            stages: core::array::from_fn(|_| stage.clone()),
+            active,
+        }
+    }
+}
+
+impl<const N: usize> Filter for DownSamplerCascade<DownSampler, N> {
+    fn clear(&mut self) {
+        self.stages.iter_mut().for_each(DownSampler::clear);
+    }
+
+    fn update(&mut self, sample: f32) -> Option<f32> {
+        self.stages[..self.active]
+            .iter_mut()
+            .try_fold(sample, |sample, stage| stage.update(sample))
+    }
+
+    fn process_in_place(&mut self, samples: &mut [f32]) -> usize {
+        self.stages[..self.active]
+            .iter_mut()
+            .fold(samples.len(), |count, stage| {
+                stage.process_in_place(&mut samples[..count])
+            })
+    }
+}
+
+impl<const N: usize> FixedFilter for DownSamplerCascade<DownSamplerQ15, N> {
+    fn clear(&mut self) {
+        self.stages.iter_mut().for_each(DownSamplerQ15::clear);
+    }
+
+    fn update(&mut self, sample: i32) -> Option<i32> {
+        self.stages[..self.active]
+            .iter_mut()
+            .try_fold(sample, |sample, stage| stage.update(sample))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        filter::fixed::FixedPoint,
+        synthetic::{test::assert_golden, SignalConfig, SyntheticEcg},
+    };
+
+    #[test]
+    fn fixed_point_matches_float() {
+        let mut filter = DownSampler::new();
+        let mut fixed = FixedPoint::new(DownSamplerQ15::new());
+
+        let mut outputs = 0;
+        for n in 0..2000 {
+            let t = n as f32 / 1000.0;
+            let sample = 2.0e-3 * (core::f32::consts::TAU * 7.0 * t).sin();
+
+            let expected = filter.update(sample);
+            let output = fixed.update(sample);
+            assert_eq!(expected.is_some(), output.is_some());
+
+            if let (Some(expected), Some(output)) = (expected, output) {
+                assert!((expected - output).abs() < 2.0e-7, "{expected} != {output}");
+                outputs += 1;
+            }
+        }
+
+        assert_eq!(outputs, (2000 - 42) / 2);
+    }
+
+    #[test]
+    fn cascade_decimates() {
+        for stages in 0..=4 {
+            let mut cascade = DownSamplerCascade::<_, 4>::new(DownSampler::new(), stages);
+            let outputs = (0..4000).filter_map(|_| cascade.update(1.0)).count();
+
+            // Each stage drops half of the samples and needs 42 to warm up
+            let expected = (0..stages).fold(4000, |count, _| (count - 42) / 2);
+            assert_eq!(outputs, expected, "{stages} stages");
+        }
+
+        let signal = (0..2000)
+            .map(|n| (n as f32 * 0.01).sin())
+            .collect::<Vec<_>>();
+        let cascade = DownSamplerCascade::<_, 4>::new(DownSampler::new(), 3);
+        crate::filter::test::assert_block_matches_update(cascade, &signal);
+    }
+
+    #[test]
+    fn synthetic_ecg_is_preserved() {
+        let config = SignalConfig {
+            noise: 5.0e-6,
+            ..SignalConfig::default()
+        };
+        let mut ecg = SyntheticEcg::new(1000.0, config);
+        let mut filter = DownSampler::new();
+
+        let mut clean = Vec::new();
+        let mut outputs = Vec::new();
+        for n in 0..10_000 {
+            let sample = ecg.sample();
+            clean.push(ecg.clean());
+
+            if let Some(output) = filter.update(sample) {
+                // The filter's delay is half of its length. The QRS complexes lose a few percent
+                // of their height.
+                let error = output - clean[n - 21];
+                assert!(error.abs() < 40.0e-6, "{n}: {error}");
+                outputs.push(output);
+            }
+        }
+
+        #[rustfmt::skip]
+        let golden = [
+            233.07564, 24.258617, -0.72963756, 136.85266, 237.12602, 222.1448,
+            30.785639, -3.8131971, 144.90836, 395.0477, 211.24603, 26.508059,
+        ];
+        assert_golden(outputs[4000..].iter().copied().step_by(83), &golden);
+    }
 }
@@ -0,0 +1,155 @@
+//! Zero-phase filtering
+//!
+//! Running a filter over a signal forward, then backward cancels the filter's phase response, so
+//! the waveform (e.g. the ST segment of an ECG) is not distorted and not delayed. The magnitude
+//! response is applied twice. Because the whole signal is needed, this is only usable on stored
+//! recordings.
+
+use super::Filter;
+
+/// Filters `data` in place with zero phase distortion, running a fresh copy of `filter` over it
+/// in both directions.
+///
+/// The signal is extended at both ends by its odd reflection around the end samples, so the
+/// filter's transient dies out before it reaches the data. `PAD` is the length of the extension,
+/// it should be longer than the filter's settling time and is limited to the length of `data`.
+///
+/// The filter must produce one output for every input sample once it has warmed up. Samples
+/// the filter produces no output for keep their input value.
+pub fn filtfilt<F, const PAD: usize>(filter: &F, data: &mut [f32])
+where
+    F: Filter + Clone,
+{
+    let Some((&first, &last)) = data.first().zip(data.last()) else {
+        return;
+    };
+    let pad = PAD.min(data.len() - 1);
+
+    // The forward pass overwrites the data, so the end padding is generated in advance. The
+    // buffer then holds the filtered padding that starts the backward pass.
+    let mut tail = [0.0; PAD];
+    let tail = &mut tail[..pad];
+    for (k, sample) in tail.iter_mut().enumerate() {
+        *sample = 2.0 * last - data[data.len() - 2 - k];
+    }
+
+    let mut forward = filter.clone();
+    for k in (0..pad).rev() {
+        forward.update(2.0 * first - data[1 + k]);
+    }
+    for sample in data.iter_mut().chain(tail.iter_mut()) {
+        if let Some(output) = forward.update(*sample) {
+            *sample = output;
+        }
+    }
+
+    let mut backward = filter.clone();
+    for &sample in tail.iter().rev() {
+        backward.update(sample);
+    }
+    for sample in data.iter_mut().rev() {
+        if let Some(output) = backward.update(*sample) {
+            *sample = output;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::filter::{
+        iir::{BiquadCascade, HighPass, LowPass},
+        median::MedianFilter,
+    };
+
+    const FS: f32 = 1000.0;
+
+    fn gaussian(center: usize, width: f32) -> Vec<f32> {
+        (0..1000)
+            .map(|n| {
+                let x = (n as f32 - center as f32) / width;
+                (-x * x / 2.0).exp()
+            })
+            .collect()
+    }
+
+    fn peak(data: &[f32]) -> usize {
+        data.iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn peak_is_not_delayed() {
+        let filter = BiquadCascade::<LowPass, 2>::butterworth(FS, 20.0, 4).unwrap();
+
+        let mut delayed = gaussian(500, 10.0);
+        let mut causal = filter.clone();
+        for sample in delayed.iter_mut() {
+            *sample = causal.update(*sample).unwrap();
+        }
+        assert!(peak(&delayed) > 505);
+
+        let mut zero_phase = gaussian(500, 10.0);
+        filtfilt::<_, 300>(&filter, &mut zero_phase);
+        assert_eq!(peak(&zero_phase), 500);
+
+        // The pulse stays symmetric
+        for k in 1..50 {
+            assert!((zero_phase[500 - k] - zero_phase[500 + k]).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn edges_are_preserved() {
+        let filter = BiquadCascade::<LowPass, 2>::butterworth(FS, 40.0, 4).unwrap();
+
+        let signal = (0..1000)
+            .map(|n| 0.5 + (core::f32::consts::TAU * 2.0 * n as f32 / FS).sin())
+            .collect::<Vec<_>>();
+
+        let mut filtered = signal.clone();
+        filtfilt::<_, 300>(&filter, &mut filtered);
+
+        for (input, output) in signal.iter().zip(filtered.iter()) {
+            assert!((input - output).abs() < 1e-2, "{input} != {output}");
+        }
+    }
+
+    #[test]
+    fn removes_offset() {
+        let filter = BiquadCascade::<HighPass, 1>::butterworth(FS, 1.0, 2).unwrap();
+
+        let mut data = vec![0.3; 500];
+        filtfilt::<_, 300>(&filter, &mut data);
+
+        assert!(data.iter().all(|sample| sample.abs() < 1e-6));
+    }
+
+    #[test]
+    fn delay_of_warming_up_filter_is_compensated() {
+        let ramp = (0..50).map(|n| n as f32).collect::<Vec<_>>();
+
+        let mut data = ramp.clone();
+        filtfilt::<_, 8>(&MedianFilter::<5>::new(), &mut data);
+
+        assert_eq!(data, ramp);
+    }
+
+    #[test]
+    fn short_signals() {
+        let filter = BiquadCascade::<LowPass, 1>::butterworth(FS, 40.0, 2).unwrap();
+
+        filtfilt::<_, 300>(&filter, &mut []);
+
+        let mut data = [1.0];
+        filtfilt::<_, 300>(&filter, &mut data);
+        assert!(data[0].is_finite());
+
+        let mut data = [1.0; 10];
+        filtfilt::<_, 300>(&filter, &mut data);
+        assert!(data.iter().all(|sample| sample.is_finite()));
+    }
+}
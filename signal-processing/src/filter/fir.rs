@@ -1,6 +1,6 @@
-use crate::sliding::SlidingWindow;
+use crate::{buffer::Buffer, sliding::SlidingWindow};
 
-use super::Filter;
+use super::{fixed::FixedFilter, Filter};
 
 #[derive(Clone)]
 pub struct Fir<'a, const N: usize> {
@@ -18,20 +18,166 @@ impl<'a, const N: usize> Fir<'a, N> {
     }
 }
 
+impl<'a, const N: usize> Fir<'a, N> {
+    /// Adds a sample without computing an output. Returns whether the filter has warmed up.
+    #[inline(always)]
+    pub(crate) fn push(&mut self, sample: f32) -> bool {
+        self.buffer.push(sample);
+        self.buffer.is_full()
+    }
+
+    /// Computes the output for the samples pushed so far.
+    #[inline(always)]
+    pub(crate) fn output(&self) -> f32 {
+        self.buffer
+            .iter()
+            .zip(self.coeffs.iter())
+            .map(|(a, b)| a * b)
+            .sum()
+    }
+}
+
 impl<'a, const N: usize> Filter for Fir<'a, N> {
     fn clear(&mut self) {
         self.buffer.clear()
     }
 
     fn update(&mut self, sample: f32) -> Option<f32> {
+        self.push(sample).then(|| self.output())
+    }
+
+    fn process_in_place(&mut self, samples: &mut [f32]) -> usize {
+        let mut count = 0;
+        for idx in 0..samples.len() {
+            if self.push(samples[idx]) {
+                samples[count] = self.output();
+                count += 1;
+            }
+        }
+        count
+    }
+}
+
+/// Fixed-point version of [`Fir`], with Q15 coefficients. See [`to_q15`](super::fixed::to_q15).
+#[derive(Clone)]
+pub struct FirQ15<'a, const N: usize> {
+    coeffs: &'a [i16; N],
+    buffer: Buffer<i32, N, false>,
+}
+
+impl<'a, const N: usize> FirQ15<'a, N> {
+    #[inline(always)]
+    pub const fn from_coeffs(coeffs: &'a [i16; N]) -> Self {
+        Self {
+            coeffs,
+            buffer: Buffer::new(),
+        }
+    }
+}
+
+impl<const N: usize> FixedFilter for FirQ15<'_, N> {
+    fn clear(&mut self) {
+        self.buffer.clear()
+    }
+
+    fn update(&mut self, sample: i32) -> Option<i32> {
         self.buffer.push(sample);
 
         self.buffer.is_full().then(|| {
-            self.buffer
+            let acc = self
+                .buffer
                 .iter()
                 .zip(self.coeffs.iter())
-                .map(|(a, b)| a * b)
-                .sum()
+                .map(|(a, &b)| a as i64 * b as i64)
+                .sum::<i64>();
+
+            ((acc + (1 << 14)) >> 15).clamp(i32::MIN as i64, i32::MAX as i64) as i32
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Filter, Fir, FirQ15};
+    use crate::filter::fixed::{to_q15, FixedPoint};
+
+    /// Amplitude of a sine wave after the filter settled.
+    fn gain<const N: usize>(mut filter: Fir<'_, N>, frequency: f32) -> f32 {
+        let output = (0..2000)
+            .filter_map(|n| {
+                let t = n as f32 / 1000.0;
+                filter.update((core::f32::consts::TAU * frequency * t).sin())
+            })
+            .skip(500)
+            .collect::<Vec<_>>();
+
+        let power = output.iter().map(|y| y * y).sum::<f32>() / output.len() as f32;
+        (2.0 * power).sqrt()
+    }
+
+    #[test]
+    fn low_pass() {
+        #[rustfmt::skip]
+        let filter = macros::designfilt!(
+            "lowpassfir",
+            "FilterOrder", 30,
+            "CutoffFrequency", 50,
+            "SampleRate", 1000
+        );
+
+        let mut step = filter.clone();
+        let settled = (0..100).filter_map(|_| step.update(1.0)).last();
+        assert!((settled.unwrap() - 1.0).abs() < 1.0e-5);
+
+        assert!(gain(filter.clone(), 10.0) > 0.95);
+        assert!((gain(filter.clone(), 50.0) - 0.5).abs() < 0.05);
+        assert!(gain(filter, 150.0) < 0.01);
+    }
+
+    #[test]
+    fn high_pass() {
+        #[rustfmt::skip]
+        let filter = macros::designfilt!(
+            "highpassfir",
+            "FilterOrder", 40,
+            "CutoffFrequency", 100,
+            "DesignMethod", "window",
+            "Window", "blackman",
+            "SampleRate", 1000
+        );
+
+        let mut step = filter.clone();
+        let settled = (0..100).filter_map(|_| step.update(1.0)).last();
+        assert!(settled.unwrap().abs() < 1.0e-3);
+
+        assert!(gain(filter.clone(), 10.0) < 0.01);
+        assert!(gain(filter, 300.0) > 0.99);
+    }
+
+    #[test]
+    fn fixed_point_matches_float() {
+        #[rustfmt::skip]
+        let filter = macros::designfilt!(
+            "lowpassfir",
+            "FilterOrder", 40,
+            "CutoffFrequency", 40,
+            "SampleRate", 1000
+        );
+        let coeffs = to_q15(filter.coeffs);
+        let mut fixed = FixedPoint::new(FirQ15::from_coeffs(&coeffs));
+        let mut filter = filter;
+
+        // 2 mV peak signal, the typical input after baseline removal
+        let mut error = 0.0f32;
+        for n in 0..2000 {
+            let t = n as f32 / 1000.0;
+            let sample = 2.0e-3 * (core::f32::consts::TAU * 7.0 * t).sin();
+            if let (Some(a), Some(b)) = (filter.update(sample), fixed.update(sample)) {
+                error = error.max((a - b).abs());
+            }
+        }
+
+        // Q15 coefficients are accurate to about 1e-4 of the signal
+        assert!(error < 2.0e-7, "{error}");
+    }
+}
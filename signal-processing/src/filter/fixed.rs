@@ -0,0 +1,152 @@
+//! Fixed-point filtering
+//!
+//! Targets without a floating point unit (like the ESP32-C6) emulate every `f32` operation in
+//! software, which makes the per-sample filter chain expensive. The filters in this module's
+//! siblings that implement [`FixedFilter`] compute the same results as their floating point
+//! counterparts using integer arithmetic only. The `fixed-point` feature selects them for the
+//! measurement's filter chain.
+//!
+//! Samples are Q31 numbers that represent [`FULL_SCALE`] volts at full scale, so one LSB is about
+//! 3.7 nV. IIR coefficients are stored as Q2.30 and FIR coefficients as Q15 numbers. Products are
+//! accumulated in 64 bits.
+
+use object_chain::{Chain, ChainElement, Link};
+
+use super::Filter;
+
+#[allow(unused_imports)]
+use crate::compat::*;
+
+/// The voltage that corresponds to the largest Q31 sample.
+pub const FULL_SCALE: f32 = 8.0;
+
+/// The number of LSBs in one volt.
+const LSB_PER_VOLT: f32 = (1u64 << 31) as f32 / FULL_SCALE;
+
+/// Converts a voltage to a Q31 sample, saturating at full scale.
+#[inline]
+pub fn to_q31(volts: f32) -> i32 {
+    (volts * LSB_PER_VOLT) as i32
+}
+
+/// Converts a Q31 sample to volts.
+#[inline]
+pub fn from_q31(sample: i32) -> f32 {
+    sample as f32 / LSB_PER_VOLT
+}
+
+/// Converts a coefficient to Q2.30, the format of fixed-point IIR coefficients.
+#[inline]
+pub(crate) fn to_q2_30(coeff: f32) -> i32 {
+    (coeff * (1u32 << 30) as f32).round() as i32
+}
+
+/// Converts FIR coefficients to Q15. Coefficients must be in the `-1.0..1.0` range.
+pub const fn to_q15<const N: usize>(coeffs: &[f32; N]) -> [i16; N] {
+    let mut result = [0; N];
+    let mut i = 0;
+    while i < N {
+        let scaled = coeffs[i] * 32768.0;
+        result[i] = if scaled < 0.0 {
+            scaled - 0.5
+        } else {
+            scaled + 0.5
+        } as i16;
+        i += 1;
+    }
+    result
+}
+
+/// A filter that processes Q31 samples.
+pub trait FixedFilter {
+    fn update(&mut self, sample: i32) -> Option<i32>;
+    fn clear(&mut self);
+}
+
+impl<F> FixedFilter for Chain<F>
+where
+    F: FixedFilter,
+{
+    fn update(&mut self, sample: i32) -> Option<i32> {
+        self.object.update(sample)
+    }
+
+    fn clear(&mut self) {
+        self.object.clear()
+    }
+}
+
+impl<F, P> FixedFilter for Link<F, P>
+where
+    F: FixedFilter,
+    P: ChainElement + FixedFilter,
+{
+    fn update(&mut self, sample: i32) -> Option<i32> {
+        let sample = self.parent.update(sample)?;
+        self.object.update(sample)
+    }
+
+    fn clear(&mut self) {
+        self.parent.clear();
+        self.object.clear();
+    }
+}
+
+/// Makes a fixed-point filter, or a chain of them, usable as a [`Filter`]. Samples are converted
+/// only on the way in and out.
+#[derive(Clone)]
+pub struct FixedPoint<F> {
+    filter: F,
+}
+
+impl<F> FixedPoint<F>
+where
+    F: FixedFilter,
+{
+    #[inline(always)]
+    pub const fn new(filter: F) -> Self {
+        Self { filter }
+    }
+
+    pub fn inner(&self) -> &F {
+        &self.filter
+    }
+}
+
+impl<F> Filter for FixedPoint<F>
+where
+    F: FixedFilter,
+{
+    fn update(&mut self, sample: f32) -> Option<f32> {
+        self.filter.update(to_q31(sample)).map(from_q31)
+    }
+
+    fn clear(&mut self) {
+        self.filter.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sample_conversion() {
+        assert_eq!(to_q31(1.0), 1 << 28);
+        assert_eq!(to_q31(-0.5), -(1 << 27));
+        assert!((from_q31(to_q31(1.5e-3)) - 1.5e-3).abs() < 4e-9);
+
+        // Saturates instead of wrapping around
+        assert_eq!(to_q31(10.0), i32::MAX);
+        assert_eq!(to_q31(-10.0), i32::MIN);
+    }
+
+    #[test]
+    fn coefficient_conversion() {
+        assert_eq!(to_q2_30(-1.5), -(3 << 29));
+        assert_eq!(
+            to_q15(&[0.5, -0.25, 0.999_99]),
+            [1 << 14, -(1 << 13), 32767]
+        );
+    }
+}
@@ -1,8 +1,14 @@
-use core::f32::consts::TAU;
+use core::{f32::consts::TAU, marker::PhantomData};
 
 use num_complex::Complex;
 
-use crate::{filter::Filter, sliding::SlidingWindow};
+use crate::{
+    filter::{
+        fixed::{to_q2_30, FixedFilter},
+        Filter,
+    },
+    sliding::SlidingWindow,
+};
 
 #[allow(unused_imports)]
 use crate::compat::*;
@@ -25,8 +31,19 @@ pub struct HighPass {
 #[derive(Clone)]
 pub struct LowPass;
 
+#[derive(Clone)]
+pub struct BandPass {
+    first_sample: Option<f32>,
+}
+
+#[derive(Clone)]
+pub struct BandStop;
+
 pub trait FilterType {
     const NEW: Self;
+    /// The filter subtracts the first sample from the input, which avoids the response to the
+    /// initial step.
+    const REMOVES_OFFSET: bool = false;
 
     fn clear(&mut self) {}
     fn precondition(&mut self, sample: f32) -> f32;
@@ -34,6 +51,7 @@ pub trait FilterType {
 
 impl FilterType for HighPass {
     const NEW: Self = Self { first_sample: None };
+    const REMOVES_OFFSET: bool = true;
 
     fn clear(&mut self) {
         self.first_sample = None;
@@ -53,6 +71,28 @@ impl FilterType for LowPass {
     }
 }
 
+impl FilterType for BandPass {
+    const NEW: Self = Self { first_sample: None };
+    const REMOVES_OFFSET: bool = true;
+
+    fn clear(&mut self) {
+        self.first_sample = None;
+    }
+
+    fn precondition(&mut self, sample: f32) -> f32 {
+        let first_sample = self.first_sample.get_or_insert(sample);
+        sample - *first_sample
+    }
+}
+
+impl FilterType for BandStop {
+    const NEW: Self = Self;
+
+    fn precondition(&mut self, sample: f32) -> f32 {
+        sample
+    }
+}
+
 #[derive(Clone)]
 pub struct Iir<'a, T, const N: usize> {
     previous_inputs: SlidingWindow<N>,
@@ -100,11 +140,12 @@ impl<'a, T, const N: usize> IirFilter for Iir<'a, T, N> {
     }
 }
 
-impl<T, const N: usize> Filter for Iir<'_, T, N>
+impl<T, const N: usize> Iir<'_, T, N>
 where
     T: FilterType,
 {
-    fn update(&mut self, sample: f32) -> Option<f32> {
+    #[inline(always)]
+    fn filter_sample(&mut self, sample: f32) -> f32 {
         let sample = self.filter_kind.precondition(sample);
 
         let mut y_out = sample * self.num_coeffs[0];
@@ -123,7 +164,23 @@ where
         self.previous_inputs.push(sample);
         self.previous_outputs.push(y_out);
 
-        Some(y_out)
+        y_out
+    }
+}
+
+impl<T, const N: usize> Filter for Iir<'_, T, N>
+where
+    T: FilterType,
+{
+    fn update(&mut self, sample: f32) -> Option<f32> {
+        Some(self.filter_sample(sample))
+    }
+
+    fn process_in_place(&mut self, samples: &mut [f32]) -> usize {
+        for sample in samples.iter_mut() {
+            *sample = self.filter_sample(*sample);
+        }
+        samples.len()
     }
 
     fn clear(&mut self) {
@@ -133,9 +190,443 @@ where
     }
 }
 
+/// Errors reported by the runtime filter designer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DesignError {
+    /// A frequency is not between 0 and half the sample rate.
+    InvalidFrequency,
+    /// The filter order is 0, or the filter needs more sections than available.
+    InvalidOrder,
+    /// The quality factor is not positive.
+    InvalidQ,
+}
+
+/// Sine and cosine of `x` in `0..=PI/2`, using their Taylor series.
+///
+/// Cutoff frequencies are often tiny compared to the sample rate, and `micromath`'s
+/// approximations are not precise enough to design filters for them.
+fn sin_cos(x: f32) -> (f32, f32) {
+    let mut sin = 0.0;
+    let mut cos = 0.0;
+
+    // The terms are x^n / n!, with alternating signs.
+    let mut term = 1.0;
+    for n in (1..16).step_by(2) {
+        cos += term;
+        term *= x / n as f32;
+        sin += term;
+        term *= -x / (n + 1) as f32;
+    }
+
+    (sin, cos)
+}
+
+/// Coefficients of a second order section, normalized so that `a0` is 1.
+///
+/// The constructors use the bilinear transform, with the frequency response prewarped at the
+/// given frequency.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Biquad {
+    pub b: [f32; 3],
+    pub a: [f32; 2],
+}
+
+impl Biquad {
+    pub const PASS_THROUGH: Self = Self {
+        b: [1.0, 0.0, 0.0],
+        a: [0.0, 0.0],
+    };
+
+    fn validate(fs: f32, f0: f32, q: f32) -> Result<(), DesignError> {
+        // Written this way to reject NaNs, too.
+        let valid_frequency = f0 > 0.0 && f0 < fs / 2.0;
+        let valid_q = q > 0.0;
+
+        if !valid_frequency {
+            return Err(DesignError::InvalidFrequency);
+        }
+        if !valid_q {
+            return Err(DesignError::InvalidQ);
+        }
+        Ok(())
+    }
+
+    /// Returns sin and cos of the normalized angular frequency, and `1 - cos`, `1 + cos`
+    /// computed without cancellation.
+    fn angle(fs: f32, f0: f32) -> (f32, f32, f32, f32) {
+        let (sin, cos) = sin_cos(core::f32::consts::PI * f0 / fs);
+        (
+            2.0 * sin * cos,
+            cos * cos - sin * sin,
+            2.0 * sin * sin,
+            2.0 * cos * cos,
+        )
+    }
+
+    fn normalize(b: [f32; 3], a: [f32; 3]) -> Self {
+        Self {
+            b: b.map(|b| b / a[0]),
+            a: [a[1] / a[0], a[2] / a[0]],
+        }
+    }
+
+    /// Low-pass filter with `f0` cutoff frequency and quality factor `q`.
+    pub fn low_pass(fs: f32, f0: f32, q: f32) -> Result<Self, DesignError> {
+        Self::validate(fs, f0, q)?;
+        let (sin, cos, one_minus_cos, _) = Self::angle(fs, f0);
+        let alpha = sin / (2.0 * q);
+
+        Ok(Self::normalize(
+            [one_minus_cos / 2.0, one_minus_cos, one_minus_cos / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        ))
+    }
+
+    /// High-pass filter with `f0` cutoff frequency and quality factor `q`.
+    pub fn high_pass(fs: f32, f0: f32, q: f32) -> Result<Self, DesignError> {
+        Self::validate(fs, f0, q)?;
+        let (sin, cos, _, one_plus_cos) = Self::angle(fs, f0);
+        let alpha = sin / (2.0 * q);
+
+        Ok(Self::normalize(
+            [one_plus_cos / 2.0, -one_plus_cos, one_plus_cos / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        ))
+    }
+
+    /// Band-pass filter with unity gain at the `f0` center frequency. The bandwidth is `f0 / q`.
+    pub fn band_pass(fs: f32, f0: f32, q: f32) -> Result<Self, DesignError> {
+        Self::validate(fs, f0, q)?;
+        let (sin, cos, _, _) = Self::angle(fs, f0);
+        let alpha = sin / (2.0 * q);
+
+        Ok(Self::normalize(
+            [alpha, 0.0, -alpha],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        ))
+    }
+
+    /// Notch filter that removes `f0`. The width of the notch is `f0 / q`.
+    pub fn notch(fs: f32, f0: f32, q: f32) -> Result<Self, DesignError> {
+        Self::validate(fs, f0, q)?;
+        let (sin, cos, _, _) = Self::angle(fs, f0);
+        let alpha = sin / (2.0 * q);
+
+        Ok(Self::normalize(
+            [1.0, -2.0 * cos, 1.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        ))
+    }
+
+    /// First order low-pass filter, stored as a second order section.
+    fn first_order_low_pass(fs: f32, f0: f32) -> Self {
+        let (sin, cos) = sin_cos(core::f32::consts::PI * f0 / fs);
+        Self::normalize([sin, sin, 0.0], [sin + cos, sin - cos, 0.0])
+    }
+
+    /// First order high-pass filter, stored as a second order section.
+    fn first_order_high_pass(fs: f32, f0: f32) -> Self {
+        let (sin, cos) = sin_cos(core::f32::consts::PI * f0 / fs);
+        Self::normalize([cos, -cos, 0.0], [sin + cos, sin - cos, 0.0])
+    }
+
+    /// Designs a Butterworth filter of the given order, split into second order sections. The
+    /// response is -3 dB at `f0`.
+    fn butterworth<const S: usize>(
+        fs: f32,
+        f0: f32,
+        order: usize,
+        section: fn(f32, f32, f32) -> Result<Self, DesignError>,
+        first_order: fn(f32, f32) -> Self,
+    ) -> Result<[Self; S], DesignError> {
+        if order == 0 || order.div_ceil(2) > S {
+            return Err(DesignError::InvalidOrder);
+        }
+
+        let mut sections = [Self::PASS_THROUGH; S];
+        for (k, coeffs) in sections.iter_mut().enumerate().take(order / 2) {
+            // The analog prototype's poles come in conjugate pairs, at this angle from the
+            // negative real axis.
+            let angle = core::f32::consts::PI * (order - 1 - 2 * k) as f32 / (2 * order) as f32;
+            let (_, cos) = sin_cos(angle);
+            *coeffs = section(fs, f0, 1.0 / (2.0 * cos))?;
+        }
+        if order % 2 == 1 {
+            Self::validate(fs, f0, 1.0)?;
+            sections[order / 2] = first_order(fs, f0);
+        }
+
+        Ok(sections)
+    }
+
+    fn transfer_coeff_at(&self, w: f32) -> Complex<f32> {
+        let w = w * TAU;
+        let e_j_theta = |k: usize| Complex::from_polar(1.0, -(k as f32) * w);
+
+        let num = self.b[0] + self.b[1] * e_j_theta(1) + self.b[2] * e_j_theta(2);
+        let den = 1.0 + self.a[0] * e_j_theta(1) + self.a[1] * e_j_theta(2);
+
+        num / den
+    }
+}
+
+#[derive(Clone, Default)]
+struct BiquadState {
+    inputs: [f32; 2],
+    outputs: [f32; 2],
+}
+
+/// A cascade of `S` second order sections, with coefficients that can be computed at runtime.
+/// Higher order filters implemented this way are less sensitive to rounding errors than a
+/// single [`Iir`].
+#[derive(Clone)]
+pub struct BiquadCascade<T, const S: usize> {
+    sections: [Biquad; S],
+    states: [BiquadState; S],
+    filter_kind: T,
+}
+
+impl<T, const S: usize> BiquadCascade<T, S>
+where
+    T: FilterType,
+{
+    pub fn new(sections: [Biquad; S]) -> Self {
+        Self {
+            sections,
+            states: core::array::from_fn(|_| BiquadState::default()),
+            filter_kind: T::NEW,
+        }
+    }
+
+    pub fn sections(&self) -> &[Biquad; S] {
+        &self.sections
+    }
+
+    /// A band-pass filter made of `S` identical sections.
+    pub fn band_pass(fs: f32, f0: f32, q: f32) -> Result<Self, DesignError> {
+        Biquad::band_pass(fs, f0, q).map(|section| Self::new([section; S]))
+    }
+
+    /// A notch filter made of `S` identical sections.
+    pub fn notch(fs: f32, f0: f32, q: f32) -> Result<Self, DesignError> {
+        Biquad::notch(fs, f0, q).map(|section| Self::new([section; S]))
+    }
+}
+
+impl<const S: usize> BiquadCascade<LowPass, S> {
+    /// Butterworth low-pass filter with `f0` half power frequency. `order` can be at most `2 * S`.
+    pub fn butterworth(fs: f32, f0: f32, order: usize) -> Result<Self, DesignError> {
+        Biquad::butterworth(
+            fs,
+            f0,
+            order,
+            Biquad::low_pass,
+            Biquad::first_order_low_pass,
+        )
+        .map(Self::new)
+    }
+}
+
+impl<const S: usize> BiquadCascade<HighPass, S> {
+    /// Butterworth high-pass filter with `f0` half power frequency. `order` can be at most
+    /// `2 * S`.
+    pub fn butterworth(fs: f32, f0: f32, order: usize) -> Result<Self, DesignError> {
+        Biquad::butterworth(
+            fs,
+            f0,
+            order,
+            Biquad::high_pass,
+            Biquad::first_order_high_pass,
+        )
+        .map(Self::new)
+    }
+}
+
+impl<T, const S: usize> IirFilter for BiquadCascade<T, S> {
+    fn transfer_coeff_at(&self, w: f32) -> Complex<f32> {
+        self.sections
+            .iter()
+            .map(|section| section.transfer_coeff_at(w))
+            .fold(Complex::new(1.0, 0.0), |acc, h| acc * h)
+    }
+}
+
+impl<T, const S: usize> Filter for BiquadCascade<T, S>
+where
+    T: FilterType,
+{
+    fn update(&mut self, sample: f32) -> Option<f32> {
+        let mut sample = self.filter_kind.precondition(sample);
+
+        for (section, state) in self.sections.iter().zip(self.states.iter_mut()) {
+            let [b0, b1, b2] = section.b;
+            let [a1, a2] = section.a;
+
+            let y = b0 * sample + b1 * state.inputs[0] + b2 * state.inputs[1]
+                - a1 * state.outputs[0]
+                - a2 * state.outputs[1];
+
+            state.inputs = [sample, state.inputs[0]];
+            state.outputs = [y, state.outputs[0]];
+            sample = y;
+        }
+
+        Some(sample)
+    }
+
+    fn process_in_place(&mut self, samples: &mut [f32]) -> usize {
+        for sample in samples.iter_mut() {
+            *sample = self.filter_kind.precondition(*sample);
+        }
+
+        // Run the sections one after the other, so their state can stay in registers.
+        for (section, state) in self.sections.iter().zip(self.states.iter_mut()) {
+            let [b0, b1, b2] = section.b;
+            let [a1, a2] = section.a;
+            let [mut x1, mut x2] = state.inputs;
+            let [mut y1, mut y2] = state.outputs;
+
+            for sample in samples.iter_mut() {
+                let y = b0 * *sample + b1 * x1 + b2 * x2 - a1 * y1 - a2 * y2;
+
+                (x1, x2) = (*sample, x1);
+                (y1, y2) = (y, y1);
+                *sample = y;
+            }
+
+            state.inputs = [x1, x2];
+            state.outputs = [y1, y2];
+        }
+
+        samples.len()
+    }
+
+    fn clear(&mut self) {
+        self.states = core::array::from_fn(|_| BiquadState::default());
+        self.filter_kind.clear();
+    }
+}
+
+/// Fixed-point version of [`Iir`]. Coefficients are stored as Q2.30 numbers, so they must be in
+/// the `-2.0..2.0` range, which usually limits the filter to second order. The rounding error of
+/// each output is carried over to the next one, which keeps filters with very low cutoff
+/// frequencies accurate.
+#[derive(Clone)]
+pub struct IirQ31<T, const N: usize> {
+    b0: i32,
+    /// Coefficients of the previous inputs, most recent first.
+    b: [i32; N],
+    /// Coefficients of the previous outputs, most recent first.
+    a: [i32; N],
+    inputs: [i32; N],
+    outputs: [i32; N],
+    residue: i64,
+    first_sample: Option<i32>,
+    filter_kind: PhantomData<T>,
+}
+
+impl<T, const N: usize> IirQ31<T, N>
+where
+    T: FilterType,
+{
+    fn with_coeffs(b0: i32, b: [i32; N], a: [i32; N]) -> Self {
+        Self {
+            b0,
+            b,
+            a,
+            inputs: [0; N],
+            outputs: [0; N],
+            residue: 0,
+            first_sample: None,
+            filter_kind: PhantomData,
+        }
+    }
+
+    /// Converts the coefficients of a floating point filter.
+    pub fn from_iir(filter: &Iir<'_, T, N>) -> Self {
+        let mut b = [0; N];
+        let mut a = [0; N];
+
+        let num = filter.num_coeffs.iter().skip(1);
+        for (coeff, &value) in b.iter_mut().zip(num) {
+            *coeff = to_q2_30(value);
+        }
+        for (coeff, &value) in a.iter_mut().zip(filter.denom_coeffs.iter().rev()) {
+            *coeff = to_q2_30(value);
+        }
+
+        Self::with_coeffs(to_q2_30(filter.num_coeffs[0]), b, a)
+    }
+}
+
+impl<T> IirQ31<T, 2>
+where
+    T: FilterType,
+{
+    /// Converts the coefficients of a second order section.
+    pub fn from_biquad(section: &Biquad) -> Self {
+        let [b0, b1, b2] = section.b.map(to_q2_30);
+        let [a1, a2] = section.a.map(to_q2_30);
+
+        Self::with_coeffs(b0, [b1, b2], [a1, a2])
+    }
+}
+
+impl<T, const N: usize> FixedFilter for IirQ31<T, N>
+where
+    T: FilterType,
+{
+    fn update(&mut self, sample: i32) -> Option<i32> {
+        let sample = if T::REMOVES_OFFSET {
+            sample.wrapping_sub(*self.first_sample.get_or_insert(sample))
+        } else {
+            sample
+        };
+
+        let mut acc = self.residue + self.b0 as i64 * sample as i64;
+        for k in 0..N {
+            acc += self.b[k] as i64 * self.inputs[k] as i64;
+            acc -= self.a[k] as i64 * self.outputs[k] as i64;
+        }
+
+        let output = acc >> 30;
+        let saturated = output.clamp(i32::MIN as i64, i32::MAX as i64);
+        self.residue = if output == saturated {
+            acc & ((1 << 30) - 1)
+        } else {
+            0
+        };
+
+        let output = saturated as i32;
+        if N > 0 {
+            self.inputs.rotate_right(1);
+            self.inputs[0] = sample;
+            self.outputs.rotate_right(1);
+            self.outputs[0] = output;
+        }
+
+        Some(output)
+    }
+
+    fn clear(&mut self) {
+        self.inputs = [0; N];
+        self.outputs = [0; N];
+        self.residue = 0;
+        self.first_sample = None;
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::{ComplExt, Filter, HighPass, Iir, IirFilter, LowPass};
+    use super::{
+        sin_cos, BandPass, BandStop, Biquad, BiquadCascade, ComplExt, DesignError, Filter,
+        HighPass, Iir, IirFilter, IirQ31, LowPass,
+    };
+    use crate::{
+        filter::{fixed::FixedPoint, test::assert_block_matches_update},
+        synthetic::{test::assert_golden, SignalConfig, SyntheticEcg},
+    };
 
     #[track_caller]
     fn assert_float_equals(value: f32, expectation: f32, tolerance: f32) {
@@ -348,6 +839,201 @@ mod test {
         );
     }
 
+    #[test]
+    fn transfer_coeff_band_pass() {
+        #[rustfmt::skip]
+        let filter = macros::designfilt!(
+            "bandpassiir",
+            "FilterOrder", 4,
+            "HalfPowerFrequency1", 5,
+            "HalfPowerFrequency2", 15,
+            "SampleRate", 100
+        );
+
+        assert_float_equals(filter.transfer_coeff_at(0.0).norm(), 0.0, 0.01);
+        assert_float_equals(filter.transfer_coeff_at(0.05).norm(), 0.5_f32.sqrt(), 0.01);
+        assert_float_equals(filter.transfer_coeff_at(0.15).norm(), 0.5_f32.sqrt(), 0.01);
+        assert!(filter.transfer_coeff_at(0.4).norm() < 0.05);
+
+        let peak = (50..150)
+            .map(|f| filter.transfer_coeff_at(f as f32 / 1000.0).norm())
+            .fold(0.0, f32::max);
+        assert_float_equals(peak, 1.0, 0.01);
+    }
+
+    #[test]
+    fn transfer_coeff_band_stop() {
+        #[rustfmt::skip]
+        let filter = macros::designfilt!(
+            "bandstopiir",
+            "FilterOrder", 2,
+            "HalfPowerFrequency1", 45,
+            "HalfPowerFrequency2", 55,
+            "SampleRate", 1000
+        );
+
+        assert_float_equals(filter.transfer_coeff_at(0.0).norm(), 1.0, 0.01);
+        assert_float_equals(filter.transfer_coeff_at(0.045).norm(), 0.5_f32.sqrt(), 0.01);
+        assert_float_equals(filter.transfer_coeff_at(0.055).norm(), 0.5_f32.sqrt(), 0.01);
+        assert!(filter.transfer_coeff_at(0.04975).norm() < 0.01);
+        assert_float_equals(filter.transfer_coeff_at(0.25).norm(), 1.0, 0.01);
+    }
+
+    #[test]
+    fn transfer_coeff_notch() {
+        #[rustfmt::skip]
+        let filter = macros::designfilt!(
+            "notchiir",
+            "CenterFrequency", 50,
+            "Bandwidth", 5,
+            "SampleRate", 1000
+        );
+
+        assert_float_equals(filter.transfer_coeff_at(0.0).norm(), 1.0, 0.01);
+        assert_float_equals(filter.transfer_coeff_at(0.05).norm(), 0.0, 0.01);
+        assert_float_equals(filter.transfer_coeff_at(0.25).norm(), 1.0, 0.01);
+    }
+
+    #[test]
+    fn band_pass_removes_offset() {
+        #[rustfmt::skip]
+        let filter: Iir<BandPass, 2> = macros::designfilt!(
+            "bandpassiir",
+            "FilterOrder", 2,
+            "HalfPowerFrequency1", 1,
+            "HalfPowerFrequency2", 2,
+            "SampleRate", 10
+        );
+
+        test_filter(filter, &[5.0; 4], &[0.0; 4], 0.0001);
+    }
+
+    #[test]
+    fn band_stop_passes_offset() {
+        #[rustfmt::skip]
+        let filter: Iir<BandStop, 2> = macros::designfilt!(
+            "notchiir",
+            "CenterFrequency", 2,
+            "Bandwidth", 0.5,
+            "SampleRate", 10
+        );
+
+        let mut filter = filter;
+        let last = (0..200).filter_map(|_| filter.update(5.0)).last();
+        assert_float_equals(last.unwrap(), 5.0, 0.001);
+    }
+
+    #[test]
+    fn sin_cos_is_precise() {
+        for x in [0.0, 1.0e-4, 0.1, 0.5, 1.0, core::f32::consts::FRAC_PI_2] {
+            let (sin, cos) = sin_cos(x);
+            assert_float_equals(sin, x.sin(), 1.0e-6);
+            assert_float_equals(cos, x.cos(), 1.0e-6);
+        }
+    }
+
+    #[test]
+    fn designed_lowpass_matches_precomputed() {
+        test_filter(
+            BiquadCascade::<LowPass, 1>::butterworth(10.0, 1.0, 1).unwrap(),
+            &[0., 1., 0., 0., 0., 0., 0.],
+            &[0.0000, 0.2452, 0.3702, 0.1886, 0.0961, 0.0490, 0.0250],
+            0.0001,
+        );
+        test_filter(
+            BiquadCascade::<LowPass, 1>::butterworth(10.0, 1.0, 2).unwrap(),
+            &[0., 1., 1., 1., 1., 1., 1.],
+            &[0.0000, 0.0675, 0.2795, 0.5614, 0.7961, 0.9480, 1.0248],
+            0.0001,
+        );
+    }
+
+    #[test]
+    fn designed_highpass_matches_precomputed() {
+        test_filter(
+            BiquadCascade::<HighPass, 1>::butterworth(10.0, 1.0, 1).unwrap(),
+            &[0., 1., 1., 1., 1., 1., 1.],
+            &[0.0000, 0.7548, 0.3846, 0.1959, 0.0998, 0.0509, 0.0259],
+            0.0001,
+        );
+        test_filter(
+            BiquadCascade::<HighPass, 1>::butterworth(10.0, 1.0, 2).unwrap(),
+            &[0., 1., 0., 0., 0., 0., 0., 0.],
+            &[0.0000, 0.6389, -0.5476, -0.2507, -0.0605, 0.0343, 0.0642],
+            0.0001,
+        );
+    }
+
+    #[test]
+    fn higher_order_butterworth() {
+        let filter = BiquadCascade::<LowPass, 2>::butterworth(1000.0, 40.0, 4).unwrap();
+
+        assert_float_equals(filter.transfer_coeff_at(0.0).norm(), 1.0, 0.001);
+        assert_float_equals(filter.transfer_coeff_at(0.04).norm(), 0.5_f32.sqrt(), 0.001);
+        // Butterworth filters roll off at 20 dB/decade per order
+        assert!(filter.transfer_coeff_at(0.4).norm() < 1.0e-4);
+
+        let filter = BiquadCascade::<HighPass, 3>::butterworth(1000.0, 40.0, 3).unwrap();
+
+        assert_float_equals(filter.transfer_coeff_at(0.0).norm(), 0.0, 0.001);
+        assert_float_equals(filter.transfer_coeff_at(0.04).norm(), 0.5_f32.sqrt(), 0.001);
+        assert_float_equals(filter.transfer_coeff_at(0.4).norm(), 1.0, 0.001);
+        // The unused section passes the signal through
+        assert_eq!(filter.sections()[2], Biquad::PASS_THROUGH);
+    }
+
+    #[test]
+    fn low_cutoff_frequency() {
+        // Peak amplitude of a sine wave after the filter settled
+        fn gain(frequency: f32) -> f32 {
+            let mut filter = BiquadCascade::<HighPass, 1>::butterworth(1000.0, 0.75, 2).unwrap();
+            (0..10_000)
+                .filter_map(|n| {
+                    let t = n as f32 / 1000.0;
+                    filter.update((core::f32::consts::TAU * frequency * t).sin())
+                })
+                .skip(6_000)
+                .fold(0.0, f32::max)
+        }
+
+        assert_float_equals(gain(0.75), 0.5_f32.sqrt(), 0.005);
+        assert_float_equals(gain(10.0), 1.0, 0.005);
+    }
+
+    #[test]
+    fn band_pass_and_notch() {
+        let band_pass = BiquadCascade::<HighPass, 1>::band_pass(1000.0, 50.0, 5.0).unwrap();
+        assert_float_equals(band_pass.transfer_coeff_at(0.05).norm(), 1.0, 0.001);
+        assert_float_equals(band_pass.transfer_coeff_at(0.0).norm(), 0.0, 0.001);
+        assert!(band_pass.transfer_coeff_at(0.2).norm() < 0.1);
+
+        let notch = BiquadCascade::<LowPass, 1>::notch(1000.0, 50.0, 5.0).unwrap();
+        assert_float_equals(notch.transfer_coeff_at(0.05).norm(), 0.0, 0.001);
+        assert_float_equals(notch.transfer_coeff_at(0.0).norm(), 1.0, 0.001);
+        assert_float_equals(notch.transfer_coeff_at(0.2).norm(), 1.0, 0.01);
+    }
+
+    #[test]
+    fn invalid_designs_are_rejected() {
+        assert_eq!(
+            BiquadCascade::<LowPass, 1>::butterworth(1000.0, 500.0, 2).err(),
+            Some(DesignError::InvalidFrequency)
+        );
+        assert_eq!(
+            BiquadCascade::<LowPass, 1>::butterworth(1000.0, f32::NAN, 1).err(),
+            Some(DesignError::InvalidFrequency)
+        );
+        assert_eq!(
+            BiquadCascade::<HighPass, 2>::butterworth(1000.0, 1.0, 5).err(),
+            Some(DesignError::InvalidOrder)
+        );
+        assert_eq!(
+            BiquadCascade::<HighPass, 2>::butterworth(1000.0, 1.0, 0).err(),
+            Some(DesignError::InvalidOrder)
+        );
+        assert_eq!(Biquad::notch(1000.0, 50.0, 0.0), Err(DesignError::InvalidQ));
+    }
+
     #[track_caller]
     fn test_filter(mut filter: impl Filter, input: &[f32], expectation: &[f32], epsilon: f32) {
         let mut output = vec![];
@@ -372,4 +1058,155 @@ mod test {
             );
         }
     }
+
+    /// An ECG-like signal with a large offset and baseline wander, in volts.
+    fn ecg_like(n: usize) -> f32 {
+        let t = n as f32 / 1000.0;
+        let phase = (t * 1.2).fract();
+        let qrs = 1.0e-3 * (1.0 - (phase - 0.5).abs() / 0.02).max(0.0);
+        let wander = 0.5e-3 * (core::f32::consts::TAU * 0.3 * t).sin();
+        0.3 + wander + qrs
+    }
+
+    #[test]
+    fn block_processing_matches_update() {
+        let signal = (0..2000).map(ecg_like).collect::<Vec<_>>();
+
+        #[rustfmt::skip]
+        let filter = macros::designfilt!(
+            "notchiir",
+            "CenterFrequency", 50,
+            "Bandwidth", 5,
+            "SampleRate", 1000
+        );
+        assert_block_matches_update(filter, &signal);
+
+        let cascade = BiquadCascade::<LowPass, 2>::butterworth(1000.0, 40.0, 4).unwrap();
+        assert_block_matches_update(cascade, &signal);
+    }
+
+    /// Largest difference between two filters' outputs after the first second.
+    fn max_difference(mut a: impl Filter, mut b: impl Filter) -> f32 {
+        (0..5000)
+            .filter_map(|n| {
+                let sample = ecg_like(n);
+                let a = a.update(sample)?;
+                let b = b.update(sample)?;
+                (n >= 1000).then(|| (a - b).abs())
+            })
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn fixed_point_high_pass_matches_float() {
+        let filter = BiquadCascade::<HighPass, 1>::butterworth(1000.0, 0.75, 2).unwrap();
+        let fixed = IirQ31::<HighPass, 2>::from_biquad(&filter.sections()[0]);
+
+        let error = max_difference(filter, FixedPoint::new(fixed));
+        assert!(error < 1.0e-6, "{error}");
+    }
+
+    #[test]
+    fn fixed_point_low_pass_matches_float() {
+        let filter = BiquadCascade::<LowPass, 1>::butterworth(1000.0, 40.0, 2).unwrap();
+        let fixed = IirQ31::<LowPass, 2>::from_biquad(&filter.sections()[0]);
+
+        let error = max_difference(filter, FixedPoint::new(fixed));
+        assert!(error < 1.0e-6, "{error}");
+    }
+
+    #[test]
+    fn fixed_point_conversion_from_iir() {
+        #[rustfmt::skip]
+        let filter: Iir<'static, BandStop, 2> = macros::designfilt!(
+            "notchiir",
+            "CenterFrequency", 50,
+            "Bandwidth", 5,
+            "SampleRate", 1000
+        );
+        let fixed = IirQ31::from_iir(&filter);
+
+        // Without offset removal, the rounding errors of the floating point filter on the large
+        // offset dominate.
+        let error = max_difference(filter, FixedPoint::new(fixed));
+        assert!(error < 5.0e-6, "{error}");
+    }
+
+    /// Filters the same synthetic ECG with and without a disturbance, and returns both outputs.
+    /// The filters are linear, so the difference is the filtered disturbance.
+    fn filter_synthetic(
+        mut filter: impl Filter + Clone,
+        config: SignalConfig,
+        disturbance: SignalConfig,
+    ) -> (Vec<f32>, Vec<f32>) {
+        let mut reference = filter.clone();
+        let clean = SyntheticEcg::new(1000.0, config);
+        let disturbed = SyntheticEcg::new(1000.0, disturbance);
+
+        clean
+            .zip(disturbed)
+            .take(10_000)
+            .filter_map(|(clean, disturbed)| {
+                Some((reference.update(clean)?, filter.update(disturbed)?))
+            })
+            .unzip()
+    }
+
+    #[test]
+    fn high_pass_removes_baseline_wander() {
+        let filter = BiquadCascade::<HighPass, 1>::butterworth(1000.0, 0.75, 2).unwrap();
+        let config = SignalConfig {
+            offset: 0.3,
+            ..SignalConfig::default()
+        };
+        let wander = SignalConfig {
+            baseline_wander: 1.0e-3,
+            wander_frequency: 0.15,
+            ..config.clone()
+        };
+
+        let (clean, outputs) = filter_synthetic(filter, config, wander);
+
+        // The filter attenuates 0.15 Hz by about 28 dB.
+        let error = clean[5000..]
+            .iter()
+            .zip(&outputs[5000..])
+            .map(|(clean, output)| (clean - output).abs())
+            .fold(0.0, f32::max);
+        assert!(error < 50.0e-6, "{error}");
+
+        #[rustfmt::skip]
+        let golden = [
+            42.501656, -168.0431, -97.99871, 78.52555, 811.7372, 40.585293,
+            -171.28128, -93.66069, 87.65339, 787.3318, 61.58588, -152.36931,
+        ];
+        assert_golden(outputs[8000..].iter().copied().step_by(167), &golden);
+    }
+
+    #[test]
+    fn low_pass_removes_noise() {
+        let filter = BiquadCascade::<LowPass, 1>::butterworth(1000.0, 20.0, 2).unwrap();
+        let noisy = SignalConfig {
+            noise: 50.0e-6,
+            ..SignalConfig::default()
+        };
+
+        let (clean, outputs) = filter_synthetic(filter, SignalConfig::default(), noisy);
+
+        // White noise is spread evenly up to 500 Hz, the filter keeps about 20 Hz of it.
+        let noise = clean
+            .iter()
+            .zip(&outputs)
+            .map(|(clean, output)| (clean - output).powi(2))
+            .sum::<f32>();
+        let rms = (noise / clean.len() as f32).sqrt();
+        assert!(rms < 12.0e-6, "{rms}");
+
+        #[rustfmt::skip]
+        let golden = [
+            122.055626, 90.2529, 10.450567, 109.4282, 739.8509, 150.75528,
+            54.93804, -2.4711287, 129.40686, 803.45776, 143.4083, 76.88923,
+        ];
+        assert_golden(outputs[8000..].iter().copied().step_by(167), &golden);
+    }
 }
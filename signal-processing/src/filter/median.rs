@@ -1,15 +1,32 @@
+//! Running median
+//!
+//! The samples of the window are kept both in arrival order and in sorted order. When a new
+//! sample replaces the oldest one, it is moved to its place in the sorted array, which takes time
+//! proportional to the distance between the two values, and at most linear in the window size.
+
+use core::cmp::Ordering;
+
 use crate::sliding::SlidingWindow;
 
 use super::Filter;
 
-#[derive(Default, Clone)]
+#[derive(Clone)]
 pub struct MedianFilter<const N: usize> {
     buffer: SlidingWindow<N>,
+    sorted: [f32; N],
+}
+
+impl<const N: usize> Default for MedianFilter<N> {
+    #[inline(always)]
+    fn default() -> Self {
+        Self::DEFAULT
+    }
 }
 
 impl<const N: usize> MedianFilter<N> {
     pub const DEFAULT: Self = Self {
         buffer: SlidingWindow::new(),
+        sorted: [0.0; N],
     };
 
     #[inline(always)]
@@ -17,15 +34,27 @@ impl<const N: usize> MedianFilter<N> {
         Self::DEFAULT
     }
 
-    fn nth(data: &mut [f32; N], n: usize) -> f32 {
-        for i in 0..(n + 1) {
-            for j in i + 1..data.len() {
-                if data[j] < data[i] {
-                    data.swap(i, j);
-                }
-            }
+    fn insert(&mut self, sample: f32) {
+        let len = self.buffer.len();
+        let idx = self.sorted[..len].partition_point(|x| x.total_cmp(&sample).is_le());
+
+        self.sorted.copy_within(idx..len, idx + 1);
+        self.sorted[idx] = sample;
+    }
+
+    fn replace(&mut self, old: f32, sample: f32) {
+        let mut idx = self.sorted.partition_point(|x| x.total_cmp(&old).is_lt());
+        debug_assert!(self.sorted[idx].total_cmp(&old).is_eq());
+
+        self.sorted[idx] = sample;
+        while idx + 1 < N && self.sorted[idx + 1].total_cmp(&sample) == Ordering::Less {
+            self.sorted.swap(idx, idx + 1);
+            idx += 1;
+        }
+        while idx > 0 && self.sorted[idx - 1].total_cmp(&sample) == Ordering::Greater {
+            self.sorted.swap(idx - 1, idx);
+            idx -= 1;
         }
-        data[n]
     }
 }
 
@@ -35,19 +64,16 @@ impl<const N: usize> Filter for MedianFilter<N> {
     }
 
     fn update(&mut self, sample: f32) -> Option<f32> {
-        self.buffer.push(sample);
-
         if self.buffer.is_full() {
-            let mut iter = self.buffer.iter_unordered();
-
-            let mut copy: [f32; N] = [0.0; N];
-            copy.fill_with(|| unwrap!(iter.next()));
-            debug_assert!(iter.next().is_none());
-
-            Some(Self::nth(&mut copy, N / 2))
+            let old = unwrap!(self.buffer.push(sample));
+            self.replace(old, sample);
         } else {
-            None
+            // Insert before pushing, the sorted part's length is the number of stored samples.
+            self.insert(sample);
+            self.buffer.push(sample);
         }
+
+        self.buffer.is_full().then(|| self.sorted[N / 2])
     }
 }
 
@@ -67,4 +93,38 @@ mod test {
         assert_eq!(2.0, filter.update(2.0).unwrap());
         assert_eq!(3.0, filter.update(5.0).unwrap());
     }
+
+    #[test]
+    fn large_window_matches_sorting() {
+        // Deterministic pseudo-random samples, with repeated values
+        let samples = (0..2000u32)
+            .map(|n| (n.wrapping_mul(2_654_435_761) >> 24) as f32 % 37.0)
+            .collect::<Vec<_>>();
+
+        let mut filter: MedianFilter<201> = MedianFilter::new();
+        for (n, &sample) in samples.iter().enumerate() {
+            let output = filter.update(sample);
+
+            if n < 200 {
+                assert_eq!(output, None);
+            } else {
+                let mut window = samples[n - 200..=n].to_vec();
+                window.sort_by(f32::total_cmp);
+                assert_eq!(output, Some(window[100]), "sample {n}");
+            }
+        }
+    }
+
+    #[test]
+    fn clear_restarts_window() {
+        let mut filter: MedianFilter<3> = MedianFilter::new();
+        filter.update(10.0);
+        filter.update(10.0);
+        assert_eq!(filter.update(10.0), Some(10.0));
+
+        filter.clear();
+        assert_eq!(filter.update(1.0), None);
+        assert_eq!(filter.update(3.0), None);
+        assert_eq!(filter.update(2.0), Some(2.0));
+    }
 }
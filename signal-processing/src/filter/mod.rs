@@ -1,11 +1,14 @@
 use object_chain::{Chain, ChainElement, Link};
 
+pub mod baseline;
 pub mod comb;
 pub mod downsample;
 
 #[cfg(feature = "dyn_filter")]
 pub mod dyn_iir;
+pub mod filtfilt;
 pub mod fir;
+pub mod fixed;
 pub mod iir;
 pub mod median;
 pub mod pli;
@@ -13,6 +16,33 @@ pub mod pli;
 pub trait Filter {
     fn update(&mut self, sample: f32) -> Option<f32>;
     fn clear(&mut self);
+
+    /// Filters a block of samples and writes the outputs to the start of `output`. Returns the
+    /// number of outputs, which may be less than the number of inputs while the filter is warming
+    /// up, or if the filter decimates the signal.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `output` is shorter than `input`.
+    fn process_block(&mut self, input: &[f32], output: &mut [f32]) -> usize {
+        let output = &mut output[..input.len()];
+        output.copy_from_slice(input);
+        self.process_in_place(output)
+    }
+
+    /// Like [`Filter::process_block`], but the outputs overwrite the inputs.
+    ///
+    /// Filters that can process samples faster in bulk should override this method.
+    fn process_in_place(&mut self, samples: &mut [f32]) -> usize {
+        let mut count = 0;
+        for idx in 0..samples.len() {
+            if let Some(output) = self.update(samples[idx]) {
+                samples[count] = output;
+                count += 1;
+            }
+        }
+        count
+    }
 }
 
 impl<F> Filter for Chain<F>
@@ -26,6 +56,10 @@ where
     fn clear(&mut self) {
         self.object.clear()
     }
+
+    fn process_in_place(&mut self, samples: &mut [f32]) -> usize {
+        self.object.process_in_place(samples)
+    }
 }
 
 impl<F, P> Filter for Link<F, P>
@@ -42,4 +76,55 @@ where
         self.parent.clear();
         self.object.clear();
     }
+
+    fn process_in_place(&mut self, samples: &mut [f32]) -> usize {
+        let count = self.parent.process_in_place(samples);
+        self.object.process_in_place(&mut samples[..count])
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use object_chain::Chain;
+
+    use super::{
+        downsample::DownSampler,
+        iir::{BiquadCascade, HighPass},
+        Filter,
+    };
+
+    /// Checks that processing `signal` in blocks of different sizes produces the same outputs as
+    /// processing it sample by sample.
+    pub(crate) fn assert_block_matches_update(filter: impl Filter + Clone, signal: &[f32]) {
+        let mut single = filter.clone();
+        let expected = signal
+            .iter()
+            .filter_map(|&sample| single.update(sample))
+            .collect::<Vec<_>>();
+
+        for block_size in [1, 7, 32, signal.len()] {
+            let mut filter = filter.clone();
+            let mut outputs = Vec::new();
+            let mut buffer = vec![0.0; block_size];
+            for block in signal.chunks(block_size) {
+                let count = filter.process_block(block, &mut buffer);
+                outputs.extend_from_slice(&buffer[..count]);
+            }
+
+            assert_eq!(outputs, expected, "block size: {block_size}");
+        }
+    }
+
+    #[test]
+    fn chain_processes_blocks() {
+        let filter = Chain::new(BiquadCascade::<HighPass, 1>::butterworth(1000.0, 1.0, 2).unwrap())
+            .append(DownSampler::new())
+            .append(DownSampler::new());
+
+        let signal = (0..1000)
+            .map(|n| (n as f32 * 0.05).sin() + 0.2)
+            .collect::<Vec<_>>();
+
+        assert_block_matches_update(filter, &signal);
+    }
 }
@@ -5,13 +5,18 @@
 //! Implementation loosely based on matlab code found in <https://github.com/s-gv/rnicu/blob/master/ecg/adaptive_filter/pll_martens_errorfilt_supp.m>
 
 use crate::filter::{
-    iir::{HighPass, Iir, IirFilter},
+    fixed::FixedFilter,
+    iir::{BiquadCascade, DesignError, HighPass, Iir, IirFilter, IirQ31},
     Filter,
 };
 
 #[allow(unused_imports)]
 use crate::compat::*;
 
+fn error_filter_gain(sig_filter: &impl IirFilter, frequency: f32) -> f32 {
+    2.0 / sig_filter.transfer_coeff_at(frequency).norm()
+}
+
 #[derive(Clone)]
 struct FilterCore<F> {
     // constants
@@ -44,7 +49,7 @@ where
 
         Self {
             frequency,
-            gamma: 2.0 / sig_filter.transfer_coeff_at(frequency).norm(),
+            gamma: error_filter_gain(&sig_filter, frequency),
 
             phase_filter: sig_filter.clone(),
             amplitude_filter: sig_filter,
@@ -117,6 +122,7 @@ where
 
 pub mod adaptation_blocking {
     use crate::{
+        buffer::Buffer,
         filter::{comb::CombFilter, Filter},
         moving::{
             sum::MovingSum,
@@ -134,6 +140,13 @@ pub mod adaptation_blocking {
         fn clear(&mut self);
     }
 
+    /// Adaptation blocking for [`PowerLineFilterQ31`](super::PowerLineFilterQ31).
+    pub trait FixedAdaptationBlocking {
+        fn new() -> Self;
+        fn update(&mut self, sample: i32) -> Option<(i32, bool)>;
+        fn clear(&mut self);
+    }
+
     #[derive(Clone)]
     pub struct NoAdaptationBlocking;
 
@@ -158,6 +171,16 @@ pub mod adaptation_blocking {
         fn clear(&mut self) {}
     }
 
+    impl FixedAdaptationBlocking for NoAdaptationBlocking {
+        fn new() -> Self {
+            Self
+        }
+        fn update(&mut self, sample: i32) -> Option<(i32, bool)> {
+            Some((sample, false))
+        }
+        fn clear(&mut self) {}
+    }
+
     impl<V, const L: usize, const C: usize> AdaptationBlockingTrait for AdaptationBlocking<V, L, C>
     where
         V: MovingSum + Default,
@@ -189,6 +212,68 @@ pub mod adaptation_blocking {
             self.variance.clear();
         }
     }
+
+    /// Fixed-point version of [`AdaptationBlocking`], with an [`EstimatedSum`] of `W` samples.
+    /// Compares squares instead of taking a square root.
+    ///
+    /// [`EstimatedSum`]: crate::moving::sum::EstimatedSum
+    #[derive(Clone)]
+    pub struct AdaptationBlockingQ31<const W: usize, const L: usize, const C: usize> {
+        delay: Buffer<i32, L, false>,
+        comb: Buffer<i32, C, false>,
+        /// `W` times the estimated mean square of the comb filtered signal.
+        power: i64,
+        samples: usize,
+        delay_cnt: usize,
+    }
+
+    impl<const W: usize, const L: usize, const C: usize> AdaptationBlockingQ31<W, L, C> {
+        /// Larger steps block adaptation anyway, limiting them keeps the power from overflowing.
+        const MAX_STEP: i32 = 1 << 25;
+    }
+
+    impl<const W: usize, const L: usize, const C: usize> FixedAdaptationBlocking
+        for AdaptationBlockingQ31<W, L, C>
+    {
+        fn new() -> Self {
+            Self {
+                delay: Buffer::new(),
+                comb: Buffer::new(),
+                power: 0,
+                samples: 0,
+                delay_cnt: 0,
+            }
+        }
+
+        fn update(&mut self, sample: i32) -> Option<(i32, bool)> {
+            let delayed_sample = self.delay.push(sample);
+            let comb_filtered = sample.wrapping_sub(self.comb.push(sample)?);
+            let step = comb_filtered.clamp(-Self::MAX_STEP, Self::MAX_STEP) as i64;
+
+            if self.samples < W {
+                self.power += step * step;
+                self.samples += 1;
+                return None;
+            }
+            self.power += step * step - self.power / W as i64;
+
+            self.delay_cnt = if W as i64 * step * step > 2 * self.power {
+                2 * L
+            } else {
+                self.delay_cnt.saturating_sub(1)
+            };
+
+            delayed_sample.map(|delayed_sample| (delayed_sample, self.delay_cnt > 0))
+        }
+
+        fn clear(&mut self) {
+            self.delay.clear();
+            self.comb.clear();
+            self.power = 0;
+            self.samples = 0;
+            self.delay_cnt = 0;
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -254,6 +339,24 @@ where
     }
 }
 
+impl<ADB, const N_FS: usize> PowerLineFilter<ADB, BiquadCascade<HighPass, 1>, N_FS>
+where
+    ADB: adaptation_blocking::AdaptationBlockingTrait,
+{
+    /// Creates a filter for an arbitrary sample rate, designing the internal filters at runtime.
+    pub fn new(fs: f32, frequencies: [f32; N_FS]) -> Result<Self, DesignError> {
+        let filter = BiquadCascade::<HighPass, 1>::butterworth(fs, 50.0, 2)?;
+
+        Ok(Self {
+            consts: Constants::new(fs),
+            cores: frequencies.map(|f| FilterCore::new(fs, f, filter.clone())),
+            adaptation_blocking: ADB::new(fs),
+            error_filter: filter,
+            sample_idx: 0,
+        })
+    }
+}
+
 #[cfg(feature = "dyn_filter")]
 use crate::filter::dyn_iir::DynIir;
 
@@ -309,3 +412,415 @@ where
         Some(error)
     }
 }
+
+/// Sine of a full turn in 256 steps, as Q15 numbers. The last entry repeats the first one, so
+/// interpolation doesn't have to wrap around.
+const SINE_TABLE: [i16; 257] = sine_table();
+
+const fn sine_table() -> [i16; 257] {
+    let mut table = [0; 257];
+    let mut i = 0;
+    while i < table.len() {
+        let mut x = core::f64::consts::TAU * i as f64 / 256.0;
+        if x > core::f64::consts::PI {
+            x -= core::f64::consts::TAU;
+        }
+
+        // Taylor series, converges quickly in -PI..=PI
+        let mut term = x;
+        let mut sin = x;
+        let mut n = 1;
+        while n < 12 {
+            term *= -x * x / ((2 * n) * (2 * n + 1)) as f64;
+            sin += term;
+            n += 1;
+        }
+
+        let scaled = sin * 32767.0;
+        table[i] = if scaled < 0.0 {
+            scaled - 0.5
+        } else {
+            scaled + 0.5
+        } as i16;
+        i += 1;
+    }
+    table
+}
+
+/// Sine of `phase`, where a full turn is 2^32, as a Q30 number.
+fn sin_q30(phase: u32) -> i32 {
+    let idx = (phase >> 24) as usize;
+    let fraction = ((phase >> 8) & 0xFFFF) as i32;
+
+    let a = SINE_TABLE[idx] as i32;
+    let b = SINE_TABLE[idx + 1] as i32;
+
+    (a << 15) + (((b - a) * fraction) >> 1)
+}
+
+/// Fixed-point version of [`FilterCore`].
+///
+/// Phases are measured in turns, a full turn being 2^32. The oscillator's phase is accumulated
+/// instead of being computed from the sample index, so it stays precise in long recordings.
+/// Instead of scaling the quadrature oscillator by the inverse of the estimated amplitude before
+/// filtering it, the filtered error is divided by the amplitude.
+#[derive(Clone)]
+struct FilterCoreQ31 {
+    // constants
+    step: u32,
+    /// Adaptation gains including the error filter attenuation, see [`FilterCoreQ31::new`].
+    k_a: i32,
+    k_phi: i32,
+    k_dw: i32,
+
+    phase_filter: IirQ31<HighPass, 2>,
+    amplitude_filter: IirQ31<HighPass, 2>,
+
+    phase: u32,
+
+    // estimated signal parameters
+    /// Amplitude in LSBs, with 16 fractional bits.
+    theta_a: i64,
+    theta_phi: i64,
+    theta_dw: i64,
+    /// The amplitude the phase error is normalized with, in LSBs.
+    amplitude: i32,
+
+    // signatures, Q30
+    y_mod_a: i32,
+    y_mod_phi: i32,
+}
+
+impl FilterCoreQ31 {
+    /// The amplitude the phase error is normalized with before an estimate exists: 1 V.
+    const INITIAL_AMPLITUDE: i32 = 1 << 28;
+
+    fn new(
+        fs: f32,
+        frequency: f32,
+        sig_filter: &impl IirFilter,
+        fixed: IirQ31<HighPass, 2>,
+    ) -> Self {
+        let consts = Constants::new(fs);
+        let gamma = error_filter_gain(sig_filter, 2.0 * core::f32::consts::PI * frequency / fs);
+
+        // Phase corrections are computed in radians, but applied in turns.
+        let turn = 1.0 / (2.0 * core::f32::consts::PI);
+        let gain = |k: f32, shift: u32| (k * (1u64 << shift) as f32).round() as i32;
+
+        Self {
+            step: (frequency / fs * (1u64 << 32) as f32) as u32,
+            // 32 fractional bits, minus 16 fractional bits of theta_a
+            k_a: gain(consts.k_a * gamma, 32),
+            // 2 bits convert Q30 to turns, 24 are fractional bits
+            k_phi: gain(Constants::K_PHI * gamma * turn, 26),
+            k_dw: gain(Constants::K_DW * gamma * turn, 26),
+
+            phase_filter: fixed.clone(),
+            amplitude_filter: fixed,
+
+            phase: 0,
+
+            theta_a: 0,
+            theta_phi: 0,
+            theta_dw: 0,
+            amplitude: Self::INITIAL_AMPLITUDE,
+
+            y_mod_a: 0,
+            y_mod_phi: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.phase_filter.clear();
+        self.amplitude_filter.clear();
+
+        self.phase = 0;
+        self.theta_a = 0;
+        self.theta_phi = 0;
+        self.theta_dw = 0;
+        self.amplitude = Self::INITIAL_AMPLITUDE;
+        self.y_mod_a = 0;
+        self.y_mod_phi = 0;
+    }
+
+    fn estimate(&mut self) -> i32 {
+        let t = self.phase.wrapping_add(self.theta_phi as u32);
+        self.phase = self.phase.wrapping_add(self.step);
+
+        let osc_i = sin_q30(t);
+        let osc_q = sin_q30(t.wrapping_add(1 << 30));
+
+        // preserve defaults initially
+        self.y_mod_a = self.amplitude_filter.update(osc_i).unwrap_or(self.y_mod_a);
+        self.y_mod_phi = self.phase_filter.update(osc_q).unwrap_or(self.y_mod_phi);
+
+        // always update the estimated phase based on the
+        // frequency deviation, even when adaptation is blocked
+        self.theta_phi += self.theta_dw;
+
+        (((self.theta_a >> 16) * osc_i as i64) >> 30) as i32
+    }
+
+    fn adapt(&mut self, theta_dw_update_threshold: i64, ew: i32) {
+        // Q27, saturates at 16
+        let ew_normalized =
+            (((ew as i64) << 27) / self.amplitude as i64).clamp(i32::MIN as i64, i32::MAX as i64);
+        // Q30
+        let eta_phi = (ew_normalized * self.y_mod_phi as i64) >> 27;
+        let eta_a = (ew as i64 * self.y_mod_a as i64) >> 30;
+
+        let thetaa_est_new = self.theta_a + ((eta_a * self.k_a as i64) >> 16);
+        let thetadw_est_new = self.theta_dw + ((eta_phi * self.k_dw as i64) >> 24);
+        // not a bug: theta_dw added to theta_phi in estimate
+        let thetaphi_est_new = self.theta_phi + ((eta_phi * self.k_phi as i64) >> 24);
+
+        if thetaa_est_new > 0 {
+            self.theta_a = thetaa_est_new;
+            self.amplitude = ((thetaa_est_new >> 16) as i32).max(1);
+        }
+        if thetaphi_est_new.abs() < theta_dw_update_threshold {
+            self.theta_dw = thetadw_est_new;
+        }
+        self.theta_phi = thetaphi_est_new;
+    }
+}
+
+/// Fixed-point version of [`PowerLineFilter`], see [`fixed`](super::fixed).
+#[derive(Clone)]
+pub struct PowerLineFilterQ31<ADB, const N_FS: usize>
+where
+    ADB: adaptation_blocking::FixedAdaptationBlocking,
+{
+    /// In turns, a full turn being 2^32.
+    theta_dw_update_threshold: i64,
+    cores: [FilterCoreQ31; N_FS],
+    adaptation_blocking: ADB,
+    error_filter: IirQ31<HighPass, 2>,
+}
+
+impl<ADB, const N_FS: usize> PowerLineFilterQ31<ADB, N_FS>
+where
+    ADB: adaptation_blocking::FixedAdaptationBlocking,
+{
+    fn with_filter(
+        fs: f32,
+        frequencies: [f32; N_FS],
+        filter: &impl IirFilter,
+        fixed: IirQ31<HighPass, 2>,
+    ) -> Self {
+        let threshold =
+            Constants::new(fs).theta_dw_update_threshold / (2.0 * core::f32::consts::PI);
+
+        Self {
+            theta_dw_update_threshold: (threshold * (1u64 << 32) as f32) as i64,
+            cores: frequencies.map(|f| FilterCoreQ31::new(fs, f, filter, fixed.clone())),
+            adaptation_blocking: ADB::new(),
+            error_filter: fixed,
+        }
+    }
+
+    /// Uses the same filters as [`PowerLineFilter::new_1ksps`].
+    pub fn new_1ksps(frequencies: [f32; N_FS]) -> Self {
+        #[rustfmt::skip]
+        const FILTER: Iir<HighPass, 2> = macros::designfilt!(
+            "highpassiir",
+            "FilterOrder", 2,
+            "HalfPowerFrequency", 50,
+            "SampleRate", 1000
+        );
+
+        Self::with_filter(1000.0, frequencies, &FILTER, IirQ31::from_iir(&FILTER))
+    }
+
+    /// Creates a filter for an arbitrary sample rate, like [`PowerLineFilter::new`].
+    pub fn new(fs: f32, frequencies: [f32; N_FS]) -> Result<Self, DesignError> {
+        let filter = BiquadCascade::<HighPass, 1>::butterworth(fs, 50.0, 2)?;
+        let fixed = IirQ31::from_biquad(&filter.sections()[0]);
+
+        Ok(Self::with_filter(fs, frequencies, &filter, fixed))
+    }
+}
+
+impl<ADB, const N_FS: usize> FixedFilter for PowerLineFilterQ31<ADB, N_FS>
+where
+    ADB: adaptation_blocking::FixedAdaptationBlocking,
+{
+    fn clear(&mut self) {
+        self.cores.iter_mut().for_each(FilterCoreQ31::clear);
+        self.error_filter.clear();
+        self.adaptation_blocking.clear();
+    }
+
+    fn update(&mut self, sample: i32) -> Option<i32> {
+        let (delayed_sample, adapt_blocked) = self.adaptation_blocking.update(sample)?;
+
+        let x_est = self
+            .cores
+            .iter_mut()
+            .map(FilterCoreQ31::estimate)
+            .fold(0i32, i32::wrapping_add);
+
+        let error = delayed_sample.wrapping_sub(x_est);
+        let filtered_error = self.error_filter.update(error)?;
+
+        if !adapt_blocked {
+            let threshold = self.theta_dw_update_threshold;
+            self.cores
+                .iter_mut()
+                .for_each(|core| core.adapt(threshold, filtered_error));
+        }
+
+        Some(error)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        adaptation_blocking::{AdaptationBlocking, AdaptationBlockingQ31, NoAdaptationBlocking},
+        sin_q30, PowerLineFilter, PowerLineFilterQ31,
+    };
+    use crate::{
+        filter::{fixed::FixedPoint, Filter},
+        mains::MainsFrequency,
+        moving::sum::EstimatedSum,
+        synthetic::{test::assert_golden, SignalConfig, SyntheticEcg},
+    };
+
+    /// An ECG-like signal with an offset, in volts.
+    fn ecg(n: usize) -> f32 {
+        let phase = (n as f32 / 1000.0 * 1.2).fract();
+        0.1 + 1.0e-3 * (1.0 - (phase - 0.5).abs() / 0.02).max(0.0)
+    }
+
+    /// The ECG-like signal with power line interference.
+    fn signal(n: usize) -> f32 {
+        let t = n as f32 / 1000.0;
+        ecg(n) + 0.2e-3 * (core::f32::consts::TAU * 50.0 * t + 0.3).sin()
+    }
+
+    /// Returns the largest difference between the outputs, and the largest remaining
+    /// interference in the output of the fixed-point filter, after the filters have settled.
+    fn compare(mut float: impl Filter, mut fixed: impl Filter, delay: usize) -> (f32, f32) {
+        let mut difference = 0.0f32;
+        let mut error = 0.0f32;
+        for n in 0..10_000 {
+            let sample = signal(n);
+            let (Some(a), Some(b)) = (float.update(sample), fixed.update(sample)) else {
+                continue;
+            };
+
+            if n >= 8_000 {
+                difference = difference.max((a - b).abs());
+                error = error.max((b - ecg(n - delay)).abs());
+            }
+        }
+        (difference, error)
+    }
+
+    #[test]
+    fn sine_table() {
+        for i in 0..1024u32 {
+            let phase = i << 22;
+            let expected = (phase as f64 / (1u64 << 32) as f64 * core::f64::consts::TAU).sin();
+            let sin = sin_q30(phase) as f64 / (1 << 30) as f64;
+            assert!((sin - expected).abs() < 2e-4, "{i}: {sin} != {expected}");
+        }
+    }
+
+    #[test]
+    fn fixed_point_matches_float() {
+        let float = PowerLineFilter::<NoAdaptationBlocking, _, 1>::new(1000.0, [50.0]).unwrap();
+        let fixed = PowerLineFilterQ31::<NoAdaptationBlocking, 1>::new(1000.0, [50.0]).unwrap();
+
+        // Without adaptation blocking, the QRS complexes disturb the estimates, which amplifies
+        // rounding differences.
+        let (difference, _) = compare(float, FixedPoint::new(fixed), 0);
+        assert!(difference < 5.0e-6, "{difference}");
+    }
+
+    #[test]
+    fn fixed_point_with_adaptation_blocking_matches_float() {
+        type Float = PowerLineFilter<
+            AdaptationBlocking<EstimatedSum<1200>, 4, 19>,
+            super::BiquadCascade<super::HighPass, 1>,
+            3,
+        >;
+        type Fixed = PowerLineFilterQ31<AdaptationBlockingQ31<1200, 4, 19>, 3>;
+
+        let harmonics = [50.0, 100.0, 150.0];
+        let float = Float::new(1000.0, harmonics).unwrap();
+        let fixed = Fixed::new(1000.0, harmonics).unwrap();
+
+        let (difference, error) = compare(float, FixedPoint::new(fixed), 4);
+        assert!(difference < 2.0e-6, "{difference}");
+        assert!(error < 2.0e-6, "{error}");
+    }
+
+    /// The float filter configured like the measurement's at 1000 sps. `C` is about one mains
+    /// period.
+    type EcgPowerLineFilter<const C: usize> = PowerLineFilter<
+        AdaptationBlocking<EstimatedSum<1200>, 4, C>,
+        super::BiquadCascade<super::HighPass, 1>,
+        3,
+    >;
+
+    /// Filters a synthetic ECG with interference. Returns the outputs after the filter has
+    /// settled, and their largest difference from the clean ECG.
+    fn filter_synthetic(mut filter: impl Filter, mains: MainsFrequency) -> (Vec<f32>, f32) {
+        let config = SignalConfig {
+            mains,
+            mains_amplitude: 0.3e-3,
+            mains_harmonics: 0.25,
+            offset: 0.1,
+            ..SignalConfig::default()
+        };
+        let mut ecg = SyntheticEcg::new(1000.0, config);
+
+        let mut clean = Vec::new();
+        let mut outputs = Vec::new();
+        let mut error = 0.0f32;
+        for n in 0..10_000 {
+            let sample = ecg.sample();
+            clean.push(ecg.clean());
+
+            let Some(output) = filter.update(sample) else {
+                continue;
+            };
+            if n >= 8_000 {
+                error = error.max((output - 0.1 - clean[n - 4]).abs());
+                outputs.push(output - 0.1);
+            }
+        }
+
+        (outputs, error)
+    }
+
+    #[test]
+    fn removes_interference_from_synthetic_ecg() {
+        let harmonics = MainsFrequency::Hz50.harmonics();
+        let filter = EcgPowerLineFilter::<19>::new(1000.0, harmonics).unwrap();
+        let (_, error) = filter_synthetic(filter, MainsFrequency::Hz50);
+        assert!(error < 20.0e-6, "{error}");
+
+        let harmonics = MainsFrequency::Hz60.harmonics();
+        let filter = EcgPowerLineFilter::<17>::new(1000.0, harmonics).unwrap();
+        let (_, error) = filter_synthetic(filter, MainsFrequency::Hz60);
+        assert!(error < 20.0e-6, "{error}");
+    }
+
+    #[test]
+    fn synthetic_ecg_golden_output() {
+        let harmonics = MainsFrequency::Hz50.harmonics();
+        let filter = EcgPowerLineFilter::<19>::new(1000.0, harmonics).unwrap();
+
+        let (outputs, _) = filter_synthetic(filter, MainsFrequency::Hz50);
+        #[rustfmt::skip]
+        let golden = [
+            161.618, 63.39699, -0.022351742, 135.39195, 977.2778, 167.10162,
+            60.126186, 0.059604645, 138.04436, 992.22363, 172.45859, 57.07145,
+        ];
+        assert_golden(outputs.into_iter().step_by(167), &golden);
+    }
+}
@@ -0,0 +1,47 @@
+//! Fallible heap allocation
+//!
+//! The firmware's heap is small, so the processing chain reports a failed allocation to the
+//! caller instead of aborting.
+
+use core::alloc::Layout;
+
+use alloc::{boxed::Box, collections::VecDeque, vec::Vec};
+
+/// Allocates memory for a `T` and initializes it with `init`, or returns `None` if there is not
+/// enough memory. The value is created after the allocation so that it can be built in place
+/// instead of on the stack.
+#[inline(always)]
+pub(crate) fn try_box<T>(init: impl FnOnce() -> T) -> Option<Box<T>> {
+    let layout = Layout::new::<T>();
+    if layout.size() == 0 {
+        // Zero-sized values don't allocate.
+        return Some(Box::new(init()));
+    }
+
+    let ptr = unsafe { alloc::alloc::alloc(layout) }.cast::<T>();
+    if ptr.is_null() {
+        return None;
+    }
+
+    // The memory comes from the global allocator and has the layout of `T`, as `Box` expects.
+    unsafe {
+        ptr.write(init());
+        Some(Box::from_raw(ptr))
+    }
+}
+
+/// Returns a vector of `len` copies of `value`, or `None` if there is not enough memory.
+pub(crate) fn try_vec<T: Clone>(value: T, len: usize) -> Option<Vec<T>> {
+    let mut vec = Vec::new();
+    vec.try_reserve_exact(len).ok()?;
+    vec.resize(len, value);
+    Some(vec)
+}
+
+/// Returns an empty queue that can hold `capacity` elements without allocating, or `None` if
+/// there is not enough memory.
+pub(crate) fn try_queue<T>(capacity: usize) -> Option<VecDeque<T>> {
+    let mut queue = VecDeque::new();
+    queue.try_reserve_exact(capacity).ok()?;
+    Some(queue)
+}
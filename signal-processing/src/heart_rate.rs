@@ -1,4 +1,22 @@
-use core::num::{NonZeroU32, NonZeroU8};
+//! Heart rate from QRS detections.
+//!
+//! [`QrsDetector`] reports every QRS complex it finds. When the user moves, electrode motion
+//! produces spikes that look like QRS complexes. The detector's adaptive thresholds may then rise
+//! high enough to miss real beats afterwards. [`HeartRateCalculator`] checks each detection before
+//! using it:
+//!
+//! - Detections within the refractory period after a beat are rejected.
+//! - Once the rhythm is learned, detections that would make the RR interval much shorter than the
+//!   average are rejected.
+//! - Detections whose QRS slope differs a lot from the average beat's are rejected.
+//!
+//! If no beat is detected for much longer than the average RR interval, the calculator searches
+//! back for the wave most similar to the average QRS complex where the missed beat should have been.
+//! It accepts the wave as a beat if its slope is close enough to the average. If more than a third
+//! of the detections are rejected for a while, the rhythm is learned again, so a real change in
+//! heart rate or signal amplitude isn't rejected forever.
+
+use core::{num::NonZeroU8, ops::RangeInclusive};
 
 use crate::{
     filter::{median::MedianFilter, Filter},
@@ -12,18 +30,138 @@ pub use qrs_detector::{
 #[allow(unused_imports)]
 use crate::compat::*;
 
+/// Detections closer than this to the previous beat are rejected, in seconds. Corresponds to
+/// 300 BPM.
+const REFRACTORY_PERIOD: f32 = 0.2;
+
+/// Detections that would make the RR interval shorter than this fraction of the average are
+/// rejected.
+const MIN_RR_RATIO: f32 = 0.6;
+
+/// Searchback starts when there was no beat for this multiple of the average RR interval.
+const SEARCHBACK_RR_RATIO: f32 = 1.66;
+
+/// The accepted range of a detection's QRS slope, relative to the average.
+const SLOPE_RATIO: RangeInclusive<f32> = 0.4..=2.5;
+
+/// Searchback accepts slopes steeper than this fraction of the average, up to the end of
+/// [`SLOPE_RATIO`].
+const SEARCHBACK_SLOPE_RATIO: f32 = 0.3;
+
+/// The rhythm is learned again when [`HeartRateCalculator::rejections`] reaches this.
+const MAX_REJECTIONS: u8 = 8;
+
+/// The weight of the newest beat in the RR interval and slope averages.
+const AVERAGE_WEIGHT: f32 = 0.125;
+
+/// The weight of the newest detection in [`HeartRateCalculator::confidence`].
+const CONFIDENCE_WEIGHT: f32 = 0.25;
+
+/// The number of blocks [`SlopeHistory`] keeps.
+const SLOPE_BLOCKS: usize = 32;
+
+/// A detected heartbeat.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Beat {
+    /// The index of the R peak's sample, counted from the first sample passed to
+    /// [`HeartRateCalculator::update`] after [`HeartRateCalculator::clear`].
+    pub index: u32,
+    /// The number of samples the detection lags behind the R peak, i.e. the R peak was `delay`
+    /// samples before the sample that completed the detection.
+    pub delay: u32,
+    /// Samples since the previous R peak, unless this is the first beat detected after a pause.
+    pub rr_interval: Option<u32>,
+    /// The QRS detector missed the beat, and it was found by searching back. The index points to
+    /// the steepest slope of the QRS complex instead of the R peak.
+    pub searchback: bool,
+}
+
+/// The steepest slopes of the recent signal, kept as the maximum of each 20 ms block.
+#[derive(Clone)]
+struct SlopeHistory {
+    block_len: u32,
+    blocks: [f32; SLOPE_BLOCKS],
+}
+
+impl SlopeHistory {
+    fn new(fs: SamplingFrequency) -> Self {
+        Self {
+            block_len: fs.s_to_samples(0.02).max(1) as u32,
+            blocks: [0.0; SLOPE_BLOCKS],
+        }
+    }
+
+    fn clear(&mut self) {
+        self.blocks = [0.0; SLOPE_BLOCKS];
+    }
+
+    fn push(&mut self, idx: u32, slope: f32) {
+        let block = &mut self.blocks[(idx / self.block_len) as usize % SLOPE_BLOCKS];
+        if idx.is_multiple_of(self.block_len) {
+            *block = slope;
+        } else {
+            *block = block.max(slope);
+        }
+    }
+
+    /// Returns the steepest slope within a block of `idx`, or `None` if `idx` is too old.
+    fn around(&self, idx: u32, now: u32) -> Option<f32> {
+        let block = idx / self.block_len;
+        let current = now / self.block_len;
+        if current - block >= SLOPE_BLOCKS as u32 - 1 {
+            return None;
+        }
+
+        let slope = (block.saturating_sub(1)..=(block + 1).min(current))
+            .map(|block| self.blocks[block as usize % SLOPE_BLOCKS])
+            .fold(0.0, f32::max);
+        Some(slope)
+    }
+}
+
+/// Moves a running average towards a new value.
+fn average(average: f32, value: f32) -> f32 {
+    average + AVERAGE_WEIGHT * (value - average)
+}
+
 pub struct HeartRateCalculator<FMW, FB> {
     fs: SamplingFrequency,
     max_age: usize,
     max_init: usize,
+    refractory_period: u32,
 
+    /// The median of the last RR intervals.
     median: MedianFilter<3>,
     qrs_detector: QrsDetector<FMW, FB>,
     differentiator: SlidingWindow<2>,
-    prev_detection: Option<NonZeroU32>,
+    slopes: SlopeHistory,
+    /// The last two inputs of the QRS detector, to find the steepest points of the waves.
+    prev_slope: f32,
+    prev_prev_slope: f32,
     current_hr: Option<NonZeroU8>,
     is_beat: bool,
     age: usize,
+
+    /// The detector index of the last accepted beat.
+    prev_beat: Option<u32>,
+    /// The average RR interval in samples, starting from the median of the first intervals.
+    rr_average: Option<f32>,
+    /// The average steepest slope of the QRS complexes.
+    slope_average: Option<f32>,
+    /// The wave most similar to the average QRS complex where a missed beat may be, and its
+    /// detector index.
+    candidate: Option<(u32, f32)>,
+    /// Grows by 2 with every rejected detection and shrinks by 1 with every beat, so it reaches
+    /// [`MAX_REJECTIONS`] if more than a third of the detections are rejected for a while.
+    rejections: u8,
+    confidence: f32,
+
+    /// Samples passed to `update` since `clear`.
+    samples: u32,
+    /// The input index of the QRS detector's first sample.
+    detector_start: u32,
+    beat: Option<Beat>,
 }
 
 impl HeartRateCalculator<(), ()> {
@@ -36,6 +174,7 @@ impl HeartRateCalculator<(), ()> {
         HeartRateCalculator::new_from_qrs(fs, QrsDetector::new(fs))
     }
 
+    /// The QRS detector allocates its buffers with `qrs_detector`'s infallible constructor.
     #[cfg(feature = "alloc")]
     #[inline]
     pub fn new_alloc(
@@ -60,55 +199,216 @@ where
             fs,
             max_age,
             max_init,
+            refractory_period: fs.s_to_samples(REFRACTORY_PERIOD) as u32,
 
             median: MedianFilter::new(),
             qrs_detector,
             differentiator: SlidingWindow::new(),
-            prev_detection: None,
+            slopes: SlopeHistory::new(fs),
+            prev_slope: 0.0,
+            prev_prev_slope: 0.0,
             current_hr: None,
             is_beat: false,
             age: max_init,
+
+            prev_beat: None,
+            rr_average: None,
+            slope_average: None,
+            candidate: None,
+            rejections: 0,
+            confidence: 0.0,
+
+            samples: 0,
+            detector_start: 0,
+            beat: None,
         }
     }
 
     pub fn clear(&mut self) {
+        self.restart();
+        self.samples = 0;
+        self.detector_start = 0;
+    }
+
+    /// Restarts detection, but keeps counting samples.
+    fn restart(&mut self) {
         self.median.clear();
         self.qrs_detector.clear();
         self.differentiator.clear();
-        self.prev_detection = None;
+        self.slopes.clear();
+        self.prev_slope = 0.0;
+        self.prev_prev_slope = 0.0;
         self.current_hr = None;
         self.is_beat = false;
         self.age = self.max_init;
+        self.forget_rhythm();
+        self.confidence = 0.0;
+        self.beat = None;
+    }
+
+    fn forget_rhythm(&mut self) {
+        self.prev_beat = None;
+        self.rr_average = None;
+        self.slope_average = None;
+        self.candidate = None;
+        self.rejections = 0;
     }
 
     pub fn update(&mut self, sample: f32) -> Option<f32> {
+        self.samples += 1;
+        self.beat = None;
+        self.is_beat = false;
+
         let Some(old_sample) = self.differentiator.push(sample) else {
+            // The detector's first sample will be the next one.
+            self.detector_start = self.samples;
             return None;
         };
 
         let complex_lead = (sample - old_sample).abs();
 
-        if let Some(idx) = self.qrs_detector.update(complex_lead) {
-            if let Some(prev_idx) = self.prev_detection {
-                let raw = self.fs.s_to_samples(60.0) as f32 / (idx - prev_idx.get()) as f32;
-                let hr = self.median.update(raw).unwrap_or(raw);
+        // The detector's index of this sample.
+        let now = self.samples - 1 - self.detector_start;
+        self.slopes.push(now, complex_lead);
+        if self.prev_slope > self.prev_prev_slope && self.prev_slope >= complex_lead {
+            // The previous sample is the steepest point of a wave.
+            self.track_candidate(now - 1, self.prev_slope);
+        }
+        self.prev_prev_slope = self.prev_slope;
+        self.prev_slope = complex_lead;
 
-                self.current_hr = NonZeroU8::new(hr as u8);
+        if let Some(idx) = self.qrs_detector.update(complex_lead) {
+            let slope = self.slopes.around(idx, now);
+            if self.is_plausible(idx, slope) {
+                self.accept(idx, slope, false);
+            } else {
+                self.reject();
             }
+        } else if let Some((idx, slope)) = self.search_back(now) {
+            self.accept(idx, Some(slope), true);
+        }
 
-            self.is_beat = true;
-            self.prev_detection = NonZeroU32::new(idx);
+        if self.is_beat {
             self.age = self.max_age;
         } else if self.age > 0 {
-            self.is_beat = false;
             self.age -= 1;
         } else {
-            self.clear();
+            self.restart();
         }
 
         Some(complex_lead)
     }
 
+    /// Remembers the wave most similar to the average QRS complex in the part of the RR interval
+    /// where a missed beat may be.
+    fn track_candidate(&mut self, idx: u32, slope: f32) {
+        let (Some(prev), Some(rr_average), Some(slope_average)) =
+            (self.prev_beat, self.rr_average, self.slope_average)
+        else {
+            return;
+        };
+
+        let elapsed = idx.saturating_sub(prev) as f32;
+        if elapsed < MIN_RR_RATIO * rr_average || elapsed >= SEARCHBACK_RR_RATIO * rr_average {
+            return;
+        }
+
+        let difference = |slope: f32| (slope / slope_average).max(slope_average / slope);
+        if self
+            .candidate
+            .is_none_or(|(_, best)| difference(slope) < difference(best))
+        {
+            self.candidate = Some((idx, slope));
+        }
+    }
+
+    /// Returns the index and slope of a missed beat, once there should have been one.
+    fn search_back(&mut self, now: u32) -> Option<(u32, f32)> {
+        let prev = self.prev_beat?;
+        let rr_average = self.rr_average?;
+        let slope_average = self.slope_average?;
+
+        if ((now - prev) as f32) < SEARCHBACK_RR_RATIO * rr_average {
+            return None;
+        }
+
+        // Search only once, there are no new candidates after the search window.
+        let (idx, slope) = self.candidate.take()?;
+        let ratio = slope / slope_average;
+        (SEARCHBACK_SLOPE_RATIO..=*SLOPE_RATIO.end())
+            .contains(&ratio)
+            .then_some((idx, slope))
+    }
+
+    fn is_plausible(&self, idx: u32, slope: Option<f32>) -> bool {
+        if let Some(prev) = self.prev_beat {
+            // Searchback may have found the beat the detector reports late.
+            let rr_interval = idx.saturating_sub(prev);
+            if rr_interval < self.refractory_period {
+                return false;
+            }
+
+            if let Some(rr_average) = self.rr_average {
+                if (rr_interval as f32) < MIN_RR_RATIO * rr_average {
+                    return false;
+                }
+            }
+        }
+
+        match (slope, self.slope_average) {
+            (Some(slope), Some(average)) => SLOPE_RATIO.contains(&(slope / average)),
+            // Either nothing is learned yet, or the detection is too old to tell.
+            _ => true,
+        }
+    }
+
+    fn accept(&mut self, idx: u32, slope: Option<f32>, searchback: bool) {
+        let rr_interval = self.prev_beat.map(|prev| idx - prev);
+        if let Some(rr_interval) = rr_interval {
+            let rr = rr_interval as f32;
+            let median = self.median.update(rr);
+            let hr = self.fs.s_to_samples(60.0) as f32 / median.unwrap_or(rr);
+
+            self.current_hr = NonZeroU8::new(hr as u8);
+            // A single interval may span a missed beat, only trust the rhythm after a few.
+            if let Some(median) = median {
+                self.rr_average = Some(self.rr_average.map_or(median, |avg| average(avg, rr)));
+            }
+        }
+        if let Some(slope) = slope {
+            self.slope_average = Some(self.slope_average.map_or(slope, |avg| average(avg, slope)));
+        }
+
+        // `idx` counts the detector's samples since it was cleared.
+        let index = self.detector_start + idx;
+        self.beat = Some(Beat {
+            index,
+            delay: (self.samples - 1).saturating_sub(index),
+            rr_interval,
+            searchback,
+        });
+
+        self.is_beat = true;
+        self.prev_beat = Some(idx);
+        self.candidate = None;
+        self.rejections = self.rejections.saturating_sub(1);
+        self.update_confidence(if searchback { 0.5 } else { 1.0 });
+    }
+
+    fn reject(&mut self) {
+        self.update_confidence(0.0);
+
+        self.rejections += 2;
+        if self.rejections >= MAX_REJECTIONS {
+            // The signal has changed, learn it again.
+            self.forget_rhythm();
+        }
+    }
+
+    fn update_confidence(&mut self, score: f32) {
+        self.confidence += CONFIDENCE_WEIGHT * (score - self.confidence);
+    }
+
     #[inline]
     pub fn thresholds(&self) -> Thresholds {
         self.qrs_detector.thresholds()
@@ -119,8 +419,260 @@ where
         self.current_hr
     }
 
+    /// How much [`Self::current_hr`] can be trusted, between 0 and 1. Every detection moves the
+    /// confidence towards 1 if it was a plausible beat, or towards 0 if it was rejected. Beats
+    /// found by searching back count as half plausible.
+    #[inline]
+    pub fn confidence(&self) -> f32 {
+        self.confidence
+    }
+
     #[inline]
     pub fn is_beat(&self) -> bool {
         self.is_beat
     }
+
+    /// Returns the beat detected by the last call to [`Self::update`].
+    #[inline]
+    pub fn beat(&self) -> Option<Beat> {
+        self.beat
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod test {
+    use super::*;
+    use crate::{
+        ecg::{ecg_filter, heart_rate_noise_filter, BaselineFilter, SampleRate},
+        synthetic::{SignalConfig, SyntheticEcg},
+    };
+
+    const RATE: SampleRate = SampleRate::Sps1000;
+
+    struct Signal {
+        samples: Vec<f32>,
+        /// The noise-free ECG.
+        clean: Vec<f32>,
+        /// The R peaks.
+        peaks: Vec<u32>,
+    }
+
+    fn generate(config: SignalConfig, seconds: u32) -> Signal {
+        let mut ecg = SyntheticEcg::new(RATE.fs(), config);
+
+        let mut signal = Signal {
+            samples: Vec::new(),
+            clean: Vec::new(),
+            peaks: Vec::new(),
+        };
+        for n in 0..seconds * RATE.hz() as u32 {
+            signal.samples.push(ecg.sample());
+            signal.clean.push(ecg.clean());
+            if ecg.is_r_peak() {
+                signal.peaks.push(n);
+            }
+        }
+
+        signal
+    }
+
+    struct Detection {
+        /// The detected beats, with indices converted to input sample indices.
+        beats: Vec<Beat>,
+        /// The heart rate and the confidence at the end of every second.
+        heart_rate: Vec<(Option<NonZeroU8>, f32)>,
+    }
+
+    impl Detection {
+        /// Returns the R peak each beat belongs to, if any, ignoring the peaks outside `window`.
+        fn matched_peaks(&self, signal: &Signal, window: core::ops::Range<u32>) -> Vec<u32> {
+            self.beats
+                .iter()
+                .filter_map(|beat| {
+                    signal
+                        .peaks
+                        .iter()
+                        .copied()
+                        .find(|peak| peak.abs_diff(beat.index) < 150)
+                })
+                .filter(|peak| window.contains(peak))
+                .collect()
+        }
+
+        /// Returns the heart rates measured after `seconds`.
+        fn heart_rates_after(&self, seconds: usize) -> impl Iterator<Item = f32> + '_ {
+            self.heart_rate[seconds..]
+                .iter()
+                .map(|(hr, _)| hr.map_or(0.0, |hr| hr.get() as f32))
+        }
+    }
+
+    /// Runs a signal through the measurement's processing chain.
+    fn detect(signal: &[f32]) -> Detection {
+        let mut filter = ecg_filter(BaselineFilter::Weak, Default::default(), RATE).unwrap();
+        let mut noise_filter = heart_rate_noise_filter(RATE);
+        let mut calculator = HeartRateCalculator::new::<300, 50>(RATE.fs());
+
+        let mut detection = Detection {
+            beats: Vec::new(),
+            heart_rate: Vec::new(),
+        };
+        // Samples held back by the filters while they warm up.
+        let mut skipped = 0;
+        for (n, &sample) in signal.iter().enumerate() {
+            if n % RATE.hz() as usize == 0 {
+                let heart_rate = (calculator.current_hr(), calculator.confidence());
+                detection.heart_rate.push(heart_rate);
+            }
+
+            let Some(sample) = filter.update(sample).and_then(|s| noise_filter.update(s)) else {
+                skipped += 1;
+                continue;
+            };
+
+            calculator.update(sample);
+            if let Some(beat) = calculator.beat() {
+                detection.beats.push(Beat {
+                    index: beat.index + skipped,
+                    ..beat
+                });
+            }
+        }
+
+        detection
+    }
+
+    /// A disturbed signal, as recorded with dry electrodes.
+    fn noisy(heart_rate: f32) -> SignalConfig {
+        SignalConfig {
+            heart_rate,
+            noise: 20.0e-6,
+            baseline_wander: 0.3e-3,
+            mains_amplitude: 0.2e-3,
+            mains_harmonics: 0.25,
+            offset: 0.1,
+            ..SignalConfig::default()
+        }
+    }
+
+    #[test]
+    fn measures_heart_rate() {
+        for heart_rate in [45.0, 72.0, 120.0, 180.0] {
+            let signal = generate(noisy(heart_rate), 20);
+            let detection = detect(&signal.samples);
+
+            for measured in detection.heart_rates_after(10) {
+                assert!(
+                    (measured - heart_rate).abs() <= 2.0,
+                    "{heart_rate} BPM: measured {measured}"
+                );
+            }
+
+            let (_, confidence) = detection.heart_rate.last().unwrap();
+            assert!(*confidence > 0.95, "{confidence}");
+        }
+    }
+
+    #[test]
+    fn detects_every_beat() {
+        let config = SignalConfig {
+            rr_variability: 0.05,
+            ..noisy(72.0)
+        };
+        let signal = generate(config, 30);
+        let detection = detect(&signal.samples);
+
+        // Skip the beats while the detector learns the signal, and the ones it may not have
+        // finished detecting at the end.
+        let window = 5_000..29_000;
+        let expected = signal
+            .peaks
+            .iter()
+            .copied()
+            .filter(|peak| window.contains(peak))
+            .collect::<Vec<_>>();
+        assert_eq!(detection.matched_peaks(&signal, window), expected);
+
+        // The filters delay every beat by about the same amount, even though the RR intervals
+        // vary.
+        let offsets = detection
+            .beats
+            .iter()
+            .filter(|beat| beat.index >= 5_000)
+            .map(|beat| {
+                let peak = signal
+                    .peaks
+                    .iter()
+                    .min_by_key(|peak| peak.abs_diff(beat.index));
+                beat.index as i32 - *peak.unwrap() as i32
+            })
+            .collect::<Vec<_>>();
+        let min = offsets.iter().min().unwrap();
+        let max = offsets.iter().max().unwrap();
+        assert!(max - min <= 20, "{offsets:?}");
+    }
+
+    #[test]
+    fn rejects_motion_artifacts() {
+        let config = SignalConfig {
+            artifact_rate: 0.3,
+            artifact_amplitude: 5.0e-3,
+            ..noisy(72.0)
+        };
+        let signal = generate(config, 60);
+        let detection = detect(&signal.samples);
+
+        let false_beats = detection.beats.len() - detection.matched_peaks(&signal, 0..60_000).len();
+        assert!(false_beats <= 2, "{false_beats} false beats");
+
+        // Artifacts may briefly throw off the heart rate, but not with high confidence.
+        let confident = detection.heart_rate[10..]
+            .iter()
+            .filter(|(_, confidence)| *confidence >= 0.5)
+            .map(|(hr, _)| hr.map_or(0.0, |hr| hr.get() as f32))
+            .collect::<Vec<_>>();
+        let inaccurate = confident
+            .iter()
+            .filter(|&&hr| (hr - 72.0).abs() > 3.0)
+            .count();
+        assert!(inaccurate <= 2, "{confident:?}");
+        assert!(confident.len() >= 30, "{confident:?}");
+
+        // Rejected detections lower the confidence.
+        let min_confidence = detection.heart_rate[10..]
+            .iter()
+            .map(|(_, confidence)| *confidence)
+            .fold(1.0, f32::min);
+        assert!(min_confidence < 0.9, "{min_confidence}");
+    }
+
+    #[test]
+    fn searches_back_for_missed_beats() {
+        let mut signal = generate(noisy(72.0), 30);
+
+        // Every 4th beat is too weak for the QRS detector.
+        let half_rr = 60 * RATE.hz() as u32 / 72 / 2;
+        for &peak in signal.peaks.iter().step_by(4) {
+            for n in peak.saturating_sub(half_rr)..(peak + half_rr).min(30_000) {
+                let n = n as usize;
+                signal.samples[n] -= 0.65 * signal.clean[n];
+            }
+        }
+
+        let detection = detect(&signal.samples);
+
+        let window = 8_000..29_000;
+        let expected = signal
+            .peaks
+            .iter()
+            .copied()
+            .filter(|peak| window.contains(peak))
+            .collect::<Vec<_>>();
+        assert_eq!(detection.matched_peaks(&signal, window), expected);
+        assert!(detection.beats.iter().any(|beat| beat.searchback));
+
+        for measured in detection.heart_rates_after(10) {
+            assert!((measured - 72.0).abs() <= 2.0, "{measured}");
+        }
+    }
 }
@@ -0,0 +1,216 @@
+//! Heart rate variability
+//!
+//! Computes time-domain HRV metrics from a stream of RR intervals. Only intervals between two
+//! normal beats (NN intervals) are used: intervals outside the physiological range are rejected,
+//! as are intervals that differ too much from the median of the surrounding intervals. A premature
+//! beat produces a short interval followed by a long, compensatory one, and both are rejected this
+//! way. Successive differences are only computed between intervals that follow each other
+//! directly.
+
+use crate::filter::{median::MedianFilter, Filter};
+
+#[allow(unused_imports)]
+use crate::compat::*;
+
+/// Time-domain HRV metrics.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HrvMetrics {
+    /// Average NN interval, in milliseconds.
+    pub mean_nn_ms: f32,
+    /// Standard deviation of NN intervals, in milliseconds.
+    pub sdnn_ms: f32,
+    /// Root mean square of successive NN interval differences, in milliseconds.
+    pub rmssd_ms: f32,
+    /// Percentage of successive NN intervals that differ by more than 50 ms.
+    pub pnn50: f32,
+    /// The number of NN intervals the metrics were computed from.
+    pub normal_intervals: usize,
+    /// The number of rejected intervals.
+    pub rejected_intervals: usize,
+}
+
+impl HrvMetrics {
+    /// Average heart rate of normal beats, in beats per minute.
+    pub fn heart_rate(&self) -> f32 {
+        60_000.0 / self.mean_nn_ms
+    }
+}
+
+pub struct HrvCalculator {
+    ms_per_sample: f32,
+    median: MedianFilter<5>,
+    previous_nn: Option<f32>,
+
+    // Welford's running mean and sum of squared deviations
+    count: usize,
+    mean: f32,
+    m2: f32,
+
+    successive: usize,
+    successive_squares: f32,
+    nn50: usize,
+
+    rejected: usize,
+}
+
+impl HrvCalculator {
+    /// The shortest and longest accepted interval, in milliseconds (200 - 30 BPM).
+    const RANGE_MS: (f32, f32) = (300.0, 2000.0);
+    /// Maximum relative difference from the median of the surrounding intervals.
+    const MAX_DEVIATION: f32 = 0.2;
+
+    /// `fs` is the sample rate RR intervals are measured in.
+    pub fn new(fs: f32) -> Self {
+        Self {
+            ms_per_sample: 1000.0 / fs,
+            median: MedianFilter::new(),
+            previous_nn: None,
+
+            count: 0,
+            mean: 0.0,
+            m2: 0.0,
+
+            successive: 0,
+            successive_squares: 0.0,
+            nn50: 0,
+
+            rejected: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.median.clear();
+        self.previous_nn = None;
+        self.count = 0;
+        self.mean = 0.0;
+        self.m2 = 0.0;
+        self.successive = 0;
+        self.successive_squares = 0.0;
+        self.nn50 = 0;
+        self.rejected = 0;
+    }
+
+    /// Processes an RR interval, in samples. Returns whether it was accepted as an NN interval.
+    ///
+    /// Intervals are rejected until enough of them are seen to judge whether they are normal.
+    pub fn update(&mut self, rr_interval: u32) -> bool {
+        let interval = rr_interval as f32 * self.ms_per_sample;
+
+        let in_range = (Self::RANGE_MS.0..=Self::RANGE_MS.1).contains(&interval);
+        // Out of range intervals are usually detection gaps, don't let them affect the median.
+        let median = if in_range {
+            self.median.update(interval)
+        } else {
+            None
+        };
+
+        let is_normal =
+            median.is_some_and(|median| (interval - median).abs() <= median * Self::MAX_DEVIATION);
+
+        if !is_normal {
+            self.rejected += 1;
+            self.previous_nn = None;
+            return false;
+        }
+
+        self.count += 1;
+        let delta = interval - self.mean;
+        self.mean += delta / self.count as f32;
+        self.m2 += delta * (interval - self.mean);
+
+        if let Some(previous) = self.previous_nn.replace(interval) {
+            let difference = interval - previous;
+            self.successive += 1;
+            self.successive_squares += difference * difference;
+            if difference.abs() > 50.0 {
+                self.nn50 += 1;
+            }
+        }
+
+        true
+    }
+
+    /// Returns the metrics, if enough intervals were accepted to compute them.
+    pub fn metrics(&self) -> Option<HrvMetrics> {
+        if self.count < 2 || self.successive == 0 {
+            return None;
+        }
+
+        Some(HrvMetrics {
+            mean_nn_ms: self.mean,
+            sdnn_ms: (self.m2 / (self.count - 1) as f32).sqrt(),
+            rmssd_ms: (self.successive_squares / self.successive as f32).sqrt(),
+            pnn50: 100.0 * self.nn50 as f32 / self.successive as f32,
+            normal_intervals: self.count,
+            rejected_intervals: self.rejected,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn metrics_of(intervals: &[u32]) -> Option<HrvMetrics> {
+        let mut calculator = HrvCalculator::new(1000.0);
+        for &interval in intervals {
+            calculator.update(interval);
+        }
+        calculator.metrics()
+    }
+
+    #[test]
+    fn constant_rhythm_has_no_variability() {
+        let metrics = metrics_of(&[800; 20]).unwrap();
+
+        assert_eq!(metrics.mean_nn_ms, 800.0);
+        assert_eq!(metrics.sdnn_ms, 0.0);
+        assert_eq!(metrics.rmssd_ms, 0.0);
+        assert_eq!(metrics.pnn50, 0.0);
+        assert_eq!(metrics.heart_rate(), 75.0);
+        // The median needs 5 intervals before it can judge them.
+        assert_eq!(metrics.normal_intervals, 16);
+    }
+
+    #[test]
+    fn alternating_rhythm() {
+        let intervals = [760, 840].repeat(10);
+        let metrics = metrics_of(&intervals).unwrap();
+
+        assert_eq!(metrics.mean_nn_ms, 800.0);
+        assert_eq!(metrics.rmssd_ms, 80.0);
+        assert_eq!(metrics.pnn50, 100.0);
+        assert!((metrics.sdnn_ms - 41.3).abs() < 0.1, "{}", metrics.sdnn_ms);
+    }
+
+    #[test]
+    fn premature_beats_are_rejected() {
+        let mut intervals = [800; 20];
+        // Short coupling interval followed by a compensatory pause
+        intervals[10] = 500;
+        intervals[11] = 1100;
+
+        let metrics = metrics_of(&intervals).unwrap();
+
+        assert_eq!(metrics.rejected_intervals, 4 + 2);
+        assert_eq!(metrics.rmssd_ms, 0.0);
+        assert_eq!(metrics.mean_nn_ms, 800.0);
+    }
+
+    #[test]
+    fn detection_gaps_are_rejected() {
+        let mut intervals = [800; 20];
+        intervals[10] = 5000;
+
+        let metrics = metrics_of(&intervals).unwrap();
+
+        assert_eq!(metrics.rejected_intervals, 4 + 1);
+        assert_eq!(metrics.mean_nn_ms, 800.0);
+    }
+
+    #[test]
+    fn too_few_intervals() {
+        assert_eq!(metrics_of(&[800; 5]), None);
+    }
+}
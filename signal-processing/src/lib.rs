@@ -9,11 +9,24 @@ extern crate logger;
 pub mod battery;
 pub mod buffer;
 pub mod compressing_buffer;
+#[cfg(feature = "alloc")]
+pub mod ecg;
+pub mod ensemble;
 pub mod filter;
+#[cfg(feature = "alloc")]
+mod heap;
 pub mod heart_rate;
+pub mod hrv;
 pub mod lerp;
+pub mod mains;
+pub mod measurement;
 pub mod moving;
+pub mod quality;
+pub mod respiration;
+pub mod rhythm;
 pub mod sliding;
+#[cfg(any(test, feature = "std"))]
+pub mod synthetic;
 
 pub use macros::designfilt;
 
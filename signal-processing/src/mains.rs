@@ -0,0 +1,268 @@
+//! Power line frequency
+//!
+//! [`MainsFrequencyDetector`] decides whether a signal is disturbed by 50 Hz or 60 Hz power line
+//! interference. It measures the power of the fundamental and its 2nd and 3rd harmonic with the
+//! Goertzel algorithm. The analysed window is a whole number of periods of both frequencies, so
+//! the DC offset and the other frequency don't leak into the measurement.
+
+#[allow(unused_imports)]
+use crate::compat::*;
+
+/// Power line frequency.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MainsFrequency {
+    #[default]
+    Hz50,
+    Hz60,
+}
+
+impl MainsFrequency {
+    /// The fundamental frequency in Hz.
+    pub fn hz(self) -> u8 {
+        match self {
+            MainsFrequency::Hz50 => 50,
+            MainsFrequency::Hz60 => 60,
+        }
+    }
+
+    pub fn from_hz(hz: u8) -> Option<Self> {
+        [Self::Hz50, Self::Hz60]
+            .into_iter()
+            .find(|mains| mains.hz() == hz)
+    }
+
+    /// The fundamental frequency and its 2nd and 3rd harmonic, in Hz.
+    pub fn harmonics(self) -> [f32; 3] {
+        let fundamental = self.hz() as f32;
+        [fundamental, 2.0 * fundamental, 3.0 * fundamental]
+    }
+}
+
+#[derive(Clone)]
+struct Goertzel {
+    coeff: f32,
+    s1: f32,
+    s2: f32,
+}
+
+impl Goertzel {
+    fn new(fs: f32, frequency: f32) -> Self {
+        Self {
+            coeff: 2.0 * (core::f32::consts::TAU * frequency / fs).cos(),
+            s1: 0.0,
+            s2: 0.0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.s1 = 0.0;
+        self.s2 = 0.0;
+    }
+
+    fn update(&mut self, sample: f32) {
+        let s = sample + self.coeff * self.s1 - self.s2;
+        self.s2 = self.s1;
+        self.s1 = s;
+    }
+
+    fn power(&self) -> f32 {
+        self.s1 * self.s1 + self.s2 * self.s2 - self.coeff * self.s1 * self.s2
+    }
+}
+
+pub struct MainsFrequencyDetector {
+    window: usize,
+    count: usize,
+    offset: Option<f32>,
+    hz50: [Goertzel; 3],
+    hz60: [Goertzel; 3],
+    /// The number of harmonics below the Nyquist frequency, the rest alias onto other
+    /// frequencies.
+    harmonics: usize,
+}
+
+impl MainsFrequencyDetector {
+    /// One frequency is detected if its power is this many times the other's.
+    const MIN_RATIO: f32 = 4.0;
+    /// The smallest interference amplitude, in volts, that is considered. QRS complexes have
+    /// some energy at these frequencies, too.
+    const MIN_AMPLITUDE: f32 = 5.0e-6;
+
+    /// Creates a detector that analyses the first `window_s` seconds of a signal, in volts,
+    /// sampled at `fs`. The window is rounded down to a multiple of 100 ms, the common period of
+    /// 50 and 60 Hz.
+    pub fn new(fs: f32, window_s: f32) -> Self {
+        let periods = ((window_s * 10.0) as usize).max(1);
+        let goertzel = |mains: MainsFrequency| mains.harmonics().map(|f| Goertzel::new(fs, f));
+        let harmonics = MainsFrequency::Hz60
+            .harmonics()
+            .iter()
+            .filter(|&&f| f < fs / 2.0)
+            .count();
+
+        Self {
+            window: periods * (fs / 10.0) as usize,
+            count: 0,
+            offset: None,
+            hz50: goertzel(MainsFrequency::Hz50),
+            hz60: goertzel(MainsFrequency::Hz60),
+            harmonics,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.count = 0;
+        self.offset = None;
+        self.hz50.iter_mut().for_each(Goertzel::clear);
+        self.hz60.iter_mut().for_each(Goertzel::clear);
+    }
+
+    /// Processes a sample. Returns `true` once the whole window has been analysed, further
+    /// samples are ignored.
+    pub fn update(&mut self, sample: f32) -> bool {
+        if self.is_done() {
+            return true;
+        }
+
+        // Removing the offset keeps the filter states small, which preserves precision.
+        let sample = sample - *self.offset.get_or_insert(sample);
+        for goertzel in self.hz50.iter_mut().chain(self.hz60.iter_mut()) {
+            goertzel.update(sample);
+        }
+
+        self.count += 1;
+        self.is_done()
+    }
+
+    #[inline]
+    pub fn is_done(&self) -> bool {
+        self.count >= self.window
+    }
+
+    /// Returns the dominant power line frequency, or `None` if the window is not complete yet or
+    /// neither frequency stands out.
+    pub fn frequency(&self) -> Option<MainsFrequency> {
+        if !self.is_done() {
+            return None;
+        }
+
+        let power = |goertzels: &[Goertzel; 3]| {
+            goertzels[..self.harmonics]
+                .iter()
+                .map(Goertzel::power)
+                .sum::<f32>()
+        };
+        let hz50 = power(&self.hz50);
+        let hz60 = power(&self.hz60);
+
+        // A sine wave of amplitude A has a power of (A * N / 2)^2 in an N long window.
+        let min_power = (Self::MIN_AMPLITUDE * self.window as f32 / 2.0).powi(2);
+        if hz50.max(hz60) < min_power {
+            return None;
+        }
+
+        if hz50 > hz60 * Self::MIN_RATIO {
+            Some(MainsFrequency::Hz50)
+        } else if hz60 > hz50 * Self::MIN_RATIO {
+            Some(MainsFrequency::Hz60)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FS: f32 = 1000.0;
+
+    /// A crude ECG with a DC offset: 1 mV, 40 ms wide triangular QRS complexes at 72 BPM.
+    fn ecg(t: f32) -> f32 {
+        let phase = (t * 1.2).fract();
+        0.2 + 1.0e-3 * (1.0 - (phase - 0.5).abs() / 0.02).max(0.0)
+    }
+
+    fn sine(t: f32, frequency: f32, amplitude: f32) -> f32 {
+        amplitude * (core::f32::consts::TAU * frequency * t).sin()
+    }
+
+    fn detect(signal: impl Fn(f32) -> f32) -> Option<MainsFrequency> {
+        let mut detector = MainsFrequencyDetector::new(FS, 1.0);
+        let mut n = 0;
+        while !detector.update(signal(n as f32 / FS)) {
+            n += 1;
+        }
+        assert_eq!(n + 1, 1000);
+        detector.frequency()
+    }
+
+    #[test]
+    fn detects_50_hz() {
+        let mains = detect(|t| ecg(t) + sine(t, 50.0, 0.1e-3));
+        assert_eq!(mains, Some(MainsFrequency::Hz50));
+    }
+
+    #[test]
+    fn detects_60_hz() {
+        let mains = detect(|t| ecg(t) + sine(t, 60.0, 0.1e-3));
+        assert_eq!(mains, Some(MainsFrequency::Hz60));
+    }
+
+    #[test]
+    fn detects_harmonics() {
+        // Fundamental filtered out, only the 3rd harmonic remains
+        let mains = detect(|t| ecg(t) + sine(t, 180.0, 0.05e-3));
+        assert_eq!(mains, Some(MainsFrequency::Hz60));
+    }
+
+    #[test]
+    fn tolerates_frequency_deviation() {
+        let mains = detect(|t| ecg(t) + sine(t, 49.8, 0.1e-3));
+        assert_eq!(mains, Some(MainsFrequency::Hz50));
+    }
+
+    #[test]
+    fn clean_signal_is_undecided() {
+        assert_eq!(detect(ecg), None);
+    }
+
+    #[test]
+    fn mixed_interference_is_undecided() {
+        let mains = detect(|t| ecg(t) + sine(t, 50.0, 0.1e-3) + sine(t, 60.0, 0.1e-3));
+        assert_eq!(mains, None);
+    }
+
+    #[test]
+    fn detects_at_low_sample_rate() {
+        // The 3rd harmonics are above the Nyquist frequency and alias onto the 2nd harmonic of
+        // the other frequency.
+        let fs = 250.0;
+        for (frequency, expected) in [(50.0, MainsFrequency::Hz50), (60.0, MainsFrequency::Hz60)] {
+            let mut detector = MainsFrequencyDetector::new(fs, 1.0);
+            let mut n = 0;
+            while !detector.update(ecg(n as f32 / fs) + sine(n as f32 / fs, frequency, 0.1e-3)) {
+                n += 1;
+            }
+            assert_eq!(n + 1, 250);
+            assert_eq!(detector.frequency(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn no_result_before_window_is_complete() {
+        let mut detector = MainsFrequencyDetector::new(FS, 1.0);
+        for n in 0..999 {
+            assert!(!detector.update(sine(n as f32 / FS, 50.0, 1.0)));
+        }
+        assert_eq!(detector.frequency(), None);
+    }
+
+    #[test]
+    fn frequency_conversion() {
+        assert_eq!(MainsFrequency::from_hz(60), Some(MainsFrequency::Hz60));
+        assert_eq!(MainsFrequency::from_hz(55), None);
+        assert_eq!(MainsFrequency::Hz50.harmonics(), [50.0, 100.0, 150.0]);
+    }
+}
@@ -0,0 +1,1609 @@
+//! Measurement container format
+//!
+//! A stored or uploaded measurement starts with a format version. Version 0 files contain nothing
+//! but the sample stream produced by [`EkgFormat`](crate::compressing_buffer::EkgFormat), so the
+//! reader has to know how the recording was made.
+//!
+//! Starting with version 1, the version is followed by a header that describes the recording, and
+//! then by the sample stream. The header is a little endian `u32` byte count, followed by a
+//! sequence of records. Each record is a 1-byte tag, a little endian `u16` length and `length`
+//! bytes of data. Readers skip records they don't recognize, which means new fields can be added
+//! without bumping the format version.
+//!
+//! Version 2 files may contain more than one channel. The recorded channels are listed in the
+//! header, and the sample stream is a sequence of frames. Each frame holds one sample of every
+//! recorded channel, in ascending channel order, and every channel is coded independently.
+//! Version 1 files always contain a single, channel 1 stream.
+//!
+//! The header's sample encoding selects how samples are coded. Encoding 0 is
+//! [`EkgFormat`](crate::compressing_buffer::EkgFormat). Encoding 1 is
+//! [`RiceFormat`](crate::compressing_buffer::RiceFormat), which stores samples in blocks: the
+//! stream holds a block of every channel in turn, each covering the same frames.
+
+use core::str;
+
+use embedded_io::Write;
+
+#[allow(unused_imports)]
+use crate::compat::*;
+
+/// The current measurement container format version.
+pub const FORMAT_VERSION: u8 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FormatError {
+    /// The data ended before the header could be read.
+    UnexpectedEof,
+    /// The file was written by a newer firmware.
+    UnsupportedVersion(u8),
+    /// A header record contains an invalid value.
+    InvalidRecord(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WriteError<E> {
+    Io(E),
+    /// A record holds more data than its length field can describe. Nothing was written.
+    RecordTooLong(Tag),
+}
+
+/// Header record tags. Values must never be reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum Tag {
+    SampleRate = 1,
+    Gain = 2,
+    ReferenceVoltage = 3,
+    AdcDeviceId = 4,
+    HighPassCutoff = 5,
+    PowerLineFrequency = 6,
+    FirmwareVersion = 7,
+    StartTime = 8,
+    SampleEncoding = 9,
+    Channels = 10,
+    RespirationChannel = 11,
+    LeadOff = 12,
+    Quality = 13,
+    RPeaks = 14,
+    Rhythm = 15,
+    MedianBaseline = 16,
+    Keyframes = 17,
+    Intervals = 18,
+}
+
+impl Tag {
+    pub fn from_u8(tag: u8) -> Option<Self> {
+        let tag = match tag {
+            1 => Self::SampleRate,
+            2 => Self::Gain,
+            3 => Self::ReferenceVoltage,
+            4 => Self::AdcDeviceId,
+            5 => Self::HighPassCutoff,
+            6 => Self::PowerLineFrequency,
+            7 => Self::FirmwareVersion,
+            8 => Self::StartTime,
+            9 => Self::SampleEncoding,
+            10 => Self::Channels,
+            11 => Self::RespirationChannel,
+            12 => Self::LeadOff,
+            13 => Self::Quality,
+            14 => Self::RPeaks,
+            15 => Self::Rhythm,
+            16 => Self::MedianBaseline,
+            17 => Self::Keyframes,
+            18 => Self::Intervals,
+            _ => return None,
+        };
+
+        Some(tag)
+    }
+}
+
+/// The set of ADC channels stored in a recording. Bit `n` represents channel `n + 1`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChannelMask(u8);
+
+impl ChannelMask {
+    pub const CH1: Self = Self(0b01);
+    pub const CH2: Self = Self(0b10);
+    pub const ALL: Self = Self(0b11);
+
+    /// Returns `None` if the mask is empty or contains channels the device doesn't have.
+    pub const fn from_bits(bits: u8) -> Option<Self> {
+        if bits == 0 || bits & !Self::ALL.0 != 0 {
+            None
+        } else {
+            Some(Self(bits))
+        }
+    }
+
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Returns the number of channels, i.e. the number of samples in a frame.
+    pub const fn count(self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    /// Returns whether the 1-based `channel` is in the set.
+    pub const fn contains(self, channel: u8) -> bool {
+        channel >= 1 && channel <= 8 && self.0 & (1 << (channel - 1)) != 0
+    }
+
+    /// Iterates over the 1-based channel numbers, in the order they appear in a frame.
+    pub fn channels(self) -> impl Iterator<Item = u8> {
+        (1..=8).filter(move |&channel| self.contains(channel))
+    }
+}
+
+/// A range of frames, `start..start + length`. Frame indices are relative to the first frame of
+/// the sample stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FrameRange {
+    pub start: u32,
+    pub length: u32,
+}
+
+impl FrameRange {
+    pub const ENCODED_LEN: usize = 8;
+
+    pub fn end(&self) -> u32 {
+        self.start + self.length
+    }
+
+    pub fn to_le_bytes(self) -> [u8; Self::ENCODED_LEN] {
+        let mut bytes = [0; Self::ENCODED_LEN];
+        bytes[..4].copy_from_slice(&self.start.to_le_bytes());
+        bytes[4..].copy_from_slice(&self.length.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_le_bytes(bytes: [u8; Self::ENCODED_LEN]) -> Self {
+        let [s0, s1, s2, s3, l0, l1, l2, l3] = bytes;
+        Self {
+            start: u32::from_le_bytes([s0, s1, s2, s3]),
+            length: u32::from_le_bytes([l0, l1, l2, l3]),
+        }
+    }
+}
+
+/// An encoded list of [`FrameRange`]s, as stored in the header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FrameRanges<'a>(&'a [u8]);
+
+impl<'a> FrameRanges<'a> {
+    pub const EMPTY: Self = Self(&[]);
+
+    /// Returns `None` if `bytes` is not a whole number of encoded ranges, or a range ends past
+    /// the last representable frame.
+    pub fn from_bytes(bytes: &'a [u8]) -> Option<Self> {
+        let ranges = Self(bytes);
+        let whole_ranges = bytes
+            .chunks_exact(FrameRange::ENCODED_LEN)
+            .remainder()
+            .is_empty();
+        let valid_ranges = ranges
+            .iter()
+            .all(|range| range.start.checked_add(range.length).is_some());
+
+        (whole_ranges && valid_ranges).then_some(ranges)
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len() / FrameRange::ENCODED_LEN
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = FrameRange> + 'a {
+        self.0
+            .chunks_exact(FrameRange::ENCODED_LEN)
+            .map(|chunk| FrameRange::from_le_bytes(unwrap!(chunk.try_into().ok())))
+    }
+}
+
+/// Collects [`FrameRange`]s while recording, from a per-frame condition.
+#[cfg(feature = "alloc")]
+pub struct FrameRangeRecorder {
+    bytes: alloc::vec::Vec<u8>,
+    open: Option<u32>,
+    max_ranges: usize,
+}
+
+#[cfg(feature = "alloc")]
+impl FrameRangeRecorder {
+    /// Once `max_ranges` ranges are recorded, the last one is extended instead of adding new ones.
+    pub const fn new(max_ranges: usize) -> Self {
+        Self {
+            bytes: alloc::vec::Vec::new(),
+            open: None,
+            max_ranges,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+        self.open = None;
+    }
+
+    /// Records whether the condition holds for `frame`. Frames must be passed in order.
+    pub fn update(&mut self, frame: u32, active: bool) {
+        match (self.open, active) {
+            (None, true) => self.open = Some(frame),
+            (Some(start), false) => {
+                self.close(FrameRange {
+                    start,
+                    length: frame - start,
+                });
+                self.open = None;
+            }
+            _ => {}
+        }
+    }
+
+    fn close(&mut self, range: FrameRange) {
+        if self.bytes.len() / FrameRange::ENCODED_LEN < self.max_ranges {
+            self.bytes.extend_from_slice(&range.to_le_bytes());
+            return;
+        }
+
+        // Out of space: extend the last range to also cover this one.
+        let last_start = self.bytes.len() - FrameRange::ENCODED_LEN;
+        let last_bytes = &mut self.bytes[last_start..];
+        let mut last = FrameRange::from_le_bytes(unwrap!(last_bytes.as_ref().try_into().ok()));
+        last.length = range.end() - last.start;
+        last_bytes.copy_from_slice(&last.to_le_bytes());
+    }
+
+    /// Closes an open range at `end`, and makes the ranges relative to `first_frame`. Ranges
+    /// before `first_frame` are dropped, ranges that start before it are shortened.
+    pub fn finish(&mut self, end: u32, first_frame: u32) {
+        if let Some(start) = self.open.take() {
+            self.close(FrameRange {
+                start,
+                length: end - start,
+            });
+        }
+
+        let mut kept = 0;
+        for idx in 0..self.bytes.len() / FrameRange::ENCODED_LEN {
+            let chunk = idx * FrameRange::ENCODED_LEN..(idx + 1) * FrameRange::ENCODED_LEN;
+            let range = FrameRange::from_le_bytes(unwrap!(self.bytes[chunk].try_into().ok()));
+            if range.end() <= first_frame {
+                continue;
+            }
+
+            let start = range.start.max(first_frame);
+            let range = FrameRange {
+                start: start - first_frame,
+                length: range.end() - start,
+            };
+            let target = kept * FrameRange::ENCODED_LEN..(kept + 1) * FrameRange::ENCODED_LEN;
+            self.bytes[target].copy_from_slice(&range.to_le_bytes());
+            kept += 1;
+        }
+        self.bytes.truncate(kept * FrameRange::ENCODED_LEN);
+    }
+
+    /// The recorded ranges. Ranges are only complete after [`Self::finish`].
+    pub fn ranges(&self) -> FrameRanges<'_> {
+        FrameRanges(&self.bytes)
+    }
+}
+
+/// Signal quality scores of consecutive, equally long windows of frames, as stored in the header.
+///
+/// Encoded as the little endian `u32` start frame of the first window, the `u32` window length in
+/// frames, and one score per window, from 0 (unusable) to 100.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct QualityScores<'a>(&'a [u8]);
+
+impl<'a> QualityScores<'a> {
+    pub const EMPTY: Self = Self(&[]);
+
+    const PREFIX_LEN: usize = 8;
+
+    /// Returns `None` if `bytes` doesn't start with a valid window description, or the windows
+    /// end past the last representable frame.
+    pub fn from_bytes(bytes: &'a [u8]) -> Option<Self> {
+        let scores = Self(bytes);
+        if bytes.len() < Self::PREFIX_LEN || scores.window() == 0 {
+            return None;
+        }
+
+        let end = scores.start() as u64 + scores.scores().len() as u64 * scores.window() as u64;
+        (end <= u32::MAX as u64).then_some(scores)
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.0
+    }
+
+    fn prefix_field(&self, offset: usize) -> u32 {
+        match self.0.get(offset..offset + 4) {
+            Some(bytes) => u32::from_le_bytes(unwrap!(bytes.try_into().ok())),
+            None => 0,
+        }
+    }
+
+    /// The first frame of the first window.
+    pub fn start(&self) -> u32 {
+        self.prefix_field(0)
+    }
+
+    /// The number of frames in a window.
+    pub fn window(&self) -> u32 {
+        self.prefix_field(4)
+    }
+
+    pub fn scores(&self) -> &'a [u8] {
+        self.0.get(Self::PREFIX_LEN..).unwrap_or(&[])
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores().is_empty()
+    }
+
+    /// Iterates over the windows and their scores.
+    pub fn iter(&self) -> impl Iterator<Item = (FrameRange, u8)> + 'a {
+        let start = self.start();
+        let window = self.window();
+        self.scores().iter().enumerate().map(move |(idx, &score)| {
+            let range = FrameRange {
+                start: start + idx as u32 * window,
+                length: window,
+            };
+            (range, score)
+        })
+    }
+}
+
+/// Collects per-window [`QualityScores`] while recording.
+#[cfg(feature = "alloc")]
+pub struct QualityRecorder {
+    bytes: alloc::vec::Vec<u8>,
+    window: u32,
+    first_window: u32,
+    max_scores: usize,
+}
+
+#[cfg(feature = "alloc")]
+impl QualityRecorder {
+    /// Records scores of `window` frames long windows, the first one starting at frame 0. Once
+    /// `max_scores` scores are recorded, the oldest ones are dropped.
+    pub const fn new(window: u32, max_scores: usize) -> Self {
+        Self {
+            bytes: alloc::vec::Vec::new(),
+            window,
+            first_window: 0,
+            max_scores,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+        self.first_window = 0;
+    }
+
+    /// Records the score of the next window.
+    pub fn push(&mut self, score: u8) {
+        if self.bytes.is_empty() {
+            self.bytes.resize(QualityScores::PREFIX_LEN, 0);
+        }
+        if self.bytes.len() - QualityScores::PREFIX_LEN >= self.max_scores {
+            self.bytes.remove(QualityScores::PREFIX_LEN);
+            self.first_window += 1;
+        }
+        self.bytes.push(score);
+    }
+
+    /// Makes the windows relative to `first_frame`. Windows that start before `first_frame` are
+    /// dropped.
+    pub fn finish(&mut self, first_frame: u32) {
+        if self.bytes.is_empty() {
+            return;
+        }
+
+        let recorded = self.bytes.len() - QualityScores::PREFIX_LEN;
+        let skipped = (first_frame
+            .div_ceil(self.window)
+            .saturating_sub(self.first_window) as usize)
+            .min(recorded);
+        self.bytes
+            .drain(QualityScores::PREFIX_LEN..QualityScores::PREFIX_LEN + skipped);
+        self.first_window += skipped as u32;
+
+        if skipped == recorded {
+            self.bytes.clear();
+            return;
+        }
+
+        let start = self.first_window * self.window - first_frame;
+        self.bytes[..4].copy_from_slice(&start.to_le_bytes());
+        self.bytes[4..QualityScores::PREFIX_LEN].copy_from_slice(&self.window.to_le_bytes());
+    }
+
+    /// The recorded scores. Scores are only complete after [`Self::finish`].
+    pub fn scores(&self) -> QualityScores<'_> {
+        QualityScores(&self.bytes)
+    }
+}
+
+/// The frame indices of detected R peaks, in ascending order, as stored in the header. Each index
+/// is a little endian `u32`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RPeaks<'a>(&'a [u8]);
+
+impl<'a> RPeaks<'a> {
+    pub const EMPTY: Self = Self(&[]);
+
+    const ENCODED_LEN: usize = 4;
+
+    /// Returns `None` if `bytes` is not a whole number of encoded indices, or the indices are not
+    /// in ascending order.
+    pub fn from_bytes(bytes: &'a [u8]) -> Option<Self> {
+        let peaks = Self(bytes);
+        let whole_indices = bytes.chunks_exact(Self::ENCODED_LEN).remainder().is_empty();
+        let ascending = peaks
+            .iter()
+            .zip(peaks.iter().skip(1))
+            .all(|(previous, next)| previous < next);
+
+        (whole_indices && ascending).then_some(peaks)
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len() / Self::ENCODED_LEN
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u32> + 'a {
+        self.0
+            .chunks_exact(Self::ENCODED_LEN)
+            .map(|chunk| u32::from_le_bytes(unwrap!(chunk.try_into().ok())))
+    }
+
+    /// Iterates over the number of frames between consecutive R peaks.
+    ///
+    /// Beats are not detected while the signal is lost, so intervals much longer than their
+    /// neighbours may span undetected beats.
+    pub fn rr_intervals(&self) -> impl Iterator<Item = u32> + 'a {
+        let mut previous = None;
+        self.iter().filter_map(move |index| {
+            let interval = previous.map(|previous| index - previous);
+            previous = Some(index);
+            interval
+        })
+    }
+}
+
+/// Collects [`RPeaks`] while recording.
+#[cfg(feature = "alloc")]
+pub struct RPeakRecorder {
+    bytes: alloc::vec::Vec<u8>,
+    max_peaks: usize,
+}
+
+#[cfg(feature = "alloc")]
+impl RPeakRecorder {
+    /// Once `max_peaks` peaks are recorded, the oldest ones are dropped.
+    pub const fn new(max_peaks: usize) -> Self {
+        Self {
+            bytes: alloc::vec::Vec::new(),
+            max_peaks,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+    }
+
+    /// Records an R peak. Peaks must be passed in order.
+    pub fn push(&mut self, frame: u32) {
+        if self.bytes.len() / RPeaks::ENCODED_LEN >= self.max_peaks {
+            self.bytes.drain(..RPeaks::ENCODED_LEN);
+        }
+        self.bytes.extend_from_slice(&frame.to_le_bytes());
+    }
+
+    /// Makes the indices relative to `first_frame`. Peaks before `first_frame` are dropped.
+    pub fn finish(&mut self, first_frame: u32) {
+        let mut kept = 0;
+        for idx in 0..self.bytes.len() / RPeaks::ENCODED_LEN {
+            let chunk = idx * RPeaks::ENCODED_LEN..(idx + 1) * RPeaks::ENCODED_LEN;
+            let frame = u32::from_le_bytes(unwrap!(self.bytes[chunk].try_into().ok()));
+            let Some(frame) = frame.checked_sub(first_frame) else {
+                continue;
+            };
+
+            let target = kept * RPeaks::ENCODED_LEN..(kept + 1) * RPeaks::ENCODED_LEN;
+            self.bytes[target].copy_from_slice(&frame.to_le_bytes());
+            kept += 1;
+        }
+        self.bytes.truncate(kept * RPeaks::ENCODED_LEN);
+    }
+
+    /// The recorded peaks. Indices are only relative to the sample stream after
+    /// [`Self::finish`].
+    pub fn peaks(&self) -> RPeaks<'_> {
+        RPeaks(&self.bytes)
+    }
+}
+
+/// A frame where decoding the sample stream can start, because a block of every channel starts
+/// there. Frame indices and byte offsets are relative to the start of the sample stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Keyframe {
+    pub frame: u32,
+    pub offset: u32,
+}
+
+impl Keyframe {
+    pub const ENCODED_LEN: usize = 8;
+
+    pub fn to_le_bytes(self) -> [u8; Self::ENCODED_LEN] {
+        let mut bytes = [0; Self::ENCODED_LEN];
+        bytes[..4].copy_from_slice(&self.frame.to_le_bytes());
+        bytes[4..].copy_from_slice(&self.offset.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_le_bytes(bytes: [u8; Self::ENCODED_LEN]) -> Self {
+        let [f0, f1, f2, f3, o0, o1, o2, o3] = bytes;
+        Self {
+            frame: u32::from_le_bytes([f0, f1, f2, f3]),
+            offset: u32::from_le_bytes([o0, o1, o2, o3]),
+        }
+    }
+}
+
+/// An encoded list of [`Keyframe`]s in ascending order, as stored in the header.
+///
+/// Only sample encodings that code blocks independently, like
+/// [`RiceFormat`](crate::compressing_buffer::RiceFormat), have keyframes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Keyframes<'a>(&'a [u8]);
+
+impl<'a> Keyframes<'a> {
+    pub const EMPTY: Self = Self(&[]);
+
+    /// Returns `None` if `bytes` is not a whole number of encoded keyframes, or if the frames are
+    /// not strictly ascending. [`Keyframes::seek`] relies on the order.
+    pub fn from_bytes(bytes: &'a [u8]) -> Option<Self> {
+        let whole_keyframes = bytes
+            .chunks_exact(Keyframe::ENCODED_LEN)
+            .remainder()
+            .is_empty();
+
+        let keyframes = Self(bytes);
+        let ascending = keyframes
+            .iter()
+            .zip(keyframes.iter().skip(1))
+            .all(|(previous, next)| previous.frame < next.frame);
+
+        (whole_keyframes && ascending).then_some(keyframes)
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len() / Keyframe::ENCODED_LEN
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Keyframe> + 'a {
+        self.0
+            .chunks_exact(Keyframe::ENCODED_LEN)
+            .map(|chunk| Keyframe::from_le_bytes(unwrap!(chunk.try_into().ok())))
+    }
+
+    /// Returns the last keyframe at or before `frame`. Decoding has to start there to reach
+    /// `frame`.
+    pub fn seek(&self, frame: u32) -> Option<Keyframe> {
+        self.iter()
+            .take_while(|keyframe| keyframe.frame <= frame)
+            .last()
+    }
+}
+
+/// Collects [`Keyframes`] from the block boundaries of the sample stream.
+#[cfg(feature = "alloc")]
+pub struct KeyframeRecorder {
+    bytes: alloc::vec::Vec<u8>,
+    interval: u32,
+}
+
+#[cfg(feature = "alloc")]
+impl KeyframeRecorder {
+    /// Keyframes are recorded at most every `interval` frames, which limits the size of the index.
+    pub const fn new(interval: u32) -> Self {
+        Self {
+            bytes: alloc::vec::Vec::new(),
+            interval,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+    }
+
+    /// Records a block boundary. Boundaries must be passed in order.
+    pub fn push(&mut self, keyframe: Keyframe) {
+        let last = self
+            .bytes
+            .len()
+            .checked_sub(Keyframe::ENCODED_LEN)
+            .map(|start| Keyframe::from_le_bytes(unwrap!(self.bytes[start..].try_into().ok())));
+        if last.is_some_and(|last| keyframe.frame - last.frame < self.interval) {
+            return;
+        }
+
+        self.bytes.extend_from_slice(&keyframe.to_le_bytes());
+    }
+
+    pub fn keyframes(&self) -> Keyframes<'_> {
+        Keyframes(&self.bytes)
+    }
+}
+
+/// The result of irregular rhythm screening, as stored in the header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RhythmSummary {
+    /// The number of RR interval windows evaluated.
+    pub windows: u16,
+    /// The number of windows that were found irregular.
+    pub irregular_windows: u16,
+}
+
+impl RhythmSummary {
+    pub const ENCODED_LEN: usize = 4;
+
+    /// Returns whether most of the evaluated windows were irregular.
+    pub fn is_irregular(&self) -> bool {
+        self.windows > 0 && 2 * self.irregular_windows as u32 > self.windows as u32
+    }
+
+    pub fn to_le_bytes(self) -> [u8; Self::ENCODED_LEN] {
+        let [w0, w1] = self.windows.to_le_bytes();
+        let [i0, i1] = self.irregular_windows.to_le_bytes();
+        [w0, w1, i0, i1]
+    }
+
+    pub fn from_le_bytes(bytes: [u8; Self::ENCODED_LEN]) -> Self {
+        let [w0, w1, i0, i1] = bytes;
+        Self {
+            windows: u16::from_le_bytes([w0, w1]),
+            irregular_windows: u16::from_le_bytes([i0, i1]),
+        }
+    }
+}
+
+/// Intervals measured on the median beat of a recording, as stored in the header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct IntervalSummary {
+    /// The number of beats averaged.
+    pub beats: u16,
+    /// The median RR interval of the averaged beats, in milliseconds.
+    pub rr_ms: u16,
+    /// The PR interval in milliseconds, if a P wave was found.
+    pub pr_ms: Option<u16>,
+    /// The QRS duration in milliseconds.
+    pub qrs_ms: u16,
+    /// The QT interval in milliseconds, if a T wave was found.
+    pub qt_ms: Option<u16>,
+    /// The ST deviation in microvolts, if the baseline was removed without distorting it.
+    pub st_uv: Option<i16>,
+}
+
+impl IntervalSummary {
+    pub const ENCODED_LEN: usize = 12;
+
+    /// Marks an interval that was not measured.
+    const NO_INTERVAL: u16 = 0;
+    /// Marks an ST deviation that was not measured.
+    const NO_LEVEL: i16 = i16::MIN;
+
+    /// Returns the QT interval corrected for the heart rate with Bazett's formula, in
+    /// milliseconds.
+    pub fn qtc_ms(&self) -> Option<u16> {
+        let qt_ms = self.qt_ms?;
+        if self.rr_ms == 0 {
+            return None;
+        }
+
+        let rr = self.rr_ms as f32 / 1000.0;
+        Some((qt_ms as f32 / rr.sqrt()) as u16)
+    }
+
+    pub fn to_le_bytes(self) -> [u8; Self::ENCODED_LEN] {
+        let fields = [
+            self.beats,
+            self.rr_ms,
+            self.pr_ms.unwrap_or(Self::NO_INTERVAL),
+            self.qrs_ms,
+            self.qt_ms.unwrap_or(Self::NO_INTERVAL),
+            self.st_uv.unwrap_or(Self::NO_LEVEL) as u16,
+        ];
+
+        let mut bytes = [0; Self::ENCODED_LEN];
+        for (chunk, field) in bytes.chunks_exact_mut(2).zip(fields) {
+            chunk.copy_from_slice(&field.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_le_bytes(bytes: [u8; Self::ENCODED_LEN]) -> Self {
+        let field = |idx: usize| u16::from_le_bytes([bytes[2 * idx], bytes[2 * idx + 1]]);
+        let interval = |idx: usize| Some(field(idx)).filter(|&ms| ms != Self::NO_INTERVAL);
+
+        Self {
+            beats: field(0),
+            rr_ms: field(1),
+            pr_ms: interval(2),
+            qrs_ms: field(3),
+            qt_ms: interval(4),
+            st_uv: Some(field(5) as i16).filter(|&uv| uv != Self::NO_LEVEL),
+        }
+    }
+}
+
+/// Describes how a recording was made.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MeasurementHeader<'a> {
+    /// Samples per second.
+    pub sample_rate: u16,
+    /// PGA gain of the recorded channel.
+    pub gain: u8,
+    /// ADC reference voltage in millivolts.
+    pub reference_mv: u16,
+    /// The raw value of the ADC's ID register, if known.
+    pub adc_device_id: Option<u8>,
+    /// Cutoff frequency of the high-pass filter applied for display, in Hz. 0 means no filter.
+    pub high_pass_cutoff: f32,
+    /// Baseline wander was removed by median filtering for display, instead of a high-pass
+    /// filter.
+    pub median_baseline: bool,
+    /// Mains frequency the power-line filter was tuned to, in Hz.
+    pub power_line_frequency: u8,
+    /// Version string of the firmware that made the recording.
+    pub firmware_version: &'a str,
+    /// Measurement start time, in seconds since the Unix epoch.
+    pub start_time: Option<u64>,
+    /// Version of the sample stream encoding.
+    pub sample_encoding: u8,
+    /// The channels stored in the sample stream.
+    pub channels: ChannelMask,
+    /// The channel that carries the demodulated respiration signal instead of an ECG lead.
+    pub respiration_channel: Option<u8>,
+    /// Frames recorded while an electrode had no contact.
+    pub lead_off: FrameRanges<'a>,
+    /// Signal quality of the recording over time.
+    pub quality: QualityScores<'a>,
+    /// R peaks detected during the recording.
+    pub r_peaks: RPeaks<'a>,
+    /// Irregular rhythm screening result, if the recording was screened.
+    pub rhythm: Option<RhythmSummary>,
+    /// Frames where decoding the sample stream can start.
+    pub keyframes: Keyframes<'a>,
+    /// Intervals measured on the median beat, if enough beats were recorded.
+    pub intervals: Option<IntervalSummary>,
+}
+
+impl MeasurementHeader<'static> {
+    /// The parameters implied by version 0 files.
+    pub const LEGACY: Self = Self {
+        sample_rate: 1000,
+        gain: 1,
+        reference_mv: 2420,
+        adc_device_id: None,
+        high_pass_cutoff: 0.0,
+        median_baseline: false,
+        power_line_frequency: 50,
+        firmware_version: "",
+        start_time: None,
+        sample_encoding: 0,
+        channels: ChannelMask::CH1,
+        respiration_channel: None,
+        lead_off: FrameRanges::EMPTY,
+        quality: QualityScores::EMPTY,
+        r_peaks: RPeaks::EMPTY,
+        rhythm: None,
+        keyframes: Keyframes::EMPTY,
+        intervals: None,
+    };
+}
+
+impl<'a> MeasurementHeader<'a> {
+    const LENGTH_BYTES: usize = 4;
+    const RECORD_OVERHEAD: usize = 3;
+
+    /// The longest record data the `u16` length field can describe.
+    pub const MAX_RECORD_LEN: usize = u16::MAX as usize;
+
+    /// Returns the number of bytes [`Self::write`] will produce.
+    pub fn encoded_len(&self) -> usize {
+        Self::LENGTH_BYTES + self.records_len()
+    }
+
+    fn records_len(&self) -> usize {
+        let mut len = 0;
+        self.for_each_record(|_, data| len += Self::RECORD_OVERHEAD + data.len());
+        len
+    }
+
+    fn for_each_record(&self, mut f: impl FnMut(Tag, &[u8])) {
+        f(Tag::SampleRate, &self.sample_rate.to_le_bytes());
+        f(Tag::Gain, &[self.gain]);
+        f(Tag::ReferenceVoltage, &self.reference_mv.to_le_bytes());
+        if let Some(id) = self.adc_device_id {
+            f(Tag::AdcDeviceId, &[id]);
+        }
+        f(Tag::HighPassCutoff, &self.high_pass_cutoff.to_le_bytes());
+        if self.median_baseline {
+            f(Tag::MedianBaseline, &[]);
+        }
+        f(Tag::PowerLineFrequency, &[self.power_line_frequency]);
+        if !self.firmware_version.is_empty() {
+            f(Tag::FirmwareVersion, self.firmware_version.as_bytes());
+        }
+        if let Some(start_time) = self.start_time {
+            f(Tag::StartTime, &start_time.to_le_bytes());
+        }
+        f(Tag::SampleEncoding, &[self.sample_encoding]);
+        f(Tag::Channels, &[self.channels.bits()]);
+        if let Some(channel) = self.respiration_channel {
+            f(Tag::RespirationChannel, &[channel]);
+        }
+        if !self.lead_off.is_empty() {
+            f(Tag::LeadOff, self.lead_off.as_bytes());
+        }
+        if !self.quality.is_empty() {
+            f(Tag::Quality, self.quality.as_bytes());
+        }
+        if !self.r_peaks.is_empty() {
+            f(Tag::RPeaks, self.r_peaks.as_bytes());
+        }
+        if let Some(rhythm) = self.rhythm {
+            f(Tag::Rhythm, &rhythm.to_le_bytes());
+        }
+        if !self.keyframes.is_empty() {
+            f(Tag::Keyframes, self.keyframes.as_bytes());
+        }
+        if let Some(intervals) = self.intervals {
+            f(Tag::Intervals, &intervals.to_le_bytes());
+        }
+    }
+
+    /// Writes the header. The format version is not included.
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<usize, WriteError<W::Error>> {
+        let mut too_long = None;
+        self.for_each_record(|tag, data| {
+            if data.len() > Self::MAX_RECORD_LEN {
+                too_long.get_or_insert(tag);
+            }
+        });
+        if let Some(tag) = too_long {
+            return Err(WriteError::RecordTooLong(tag));
+        }
+
+        let records_len = self.records_len();
+        writer
+            .write_all(&(records_len as u32).to_le_bytes())
+            .map_err(WriteError::Io)?;
+
+        let mut result = Ok(());
+        self.for_each_record(|tag, data| {
+            if result.is_ok() {
+                result = write_record(writer, tag as u8, data);
+            }
+        });
+        result.map_err(WriteError::Io)?;
+
+        Ok(Self::LENGTH_BYTES + records_len)
+    }
+
+    /// Parses the header of a measurement that was written using the given format version.
+    ///
+    /// `data` must start right after the format version. Returns the header and the number of
+    /// bytes it occupies. The sample stream starts after these bytes.
+    pub fn parse(version: u8, data: &'a [u8]) -> Result<(Self, usize), FormatError> {
+        match version {
+            0 => Ok((MeasurementHeader::LEGACY, 0)),
+            1..=FORMAT_VERSION => {
+                let records = header_records(data)?;
+                let mut header = MeasurementHeader::LEGACY;
+
+                for (tag, value) in Records::new(records) {
+                    header.apply_record(tag, value)?;
+                }
+
+                // Version 1 streams are never interleaved.
+                if version == 1 {
+                    header.channels = ChannelMask::CH1;
+                }
+
+                Ok((header, Self::LENGTH_BYTES + records.len()))
+            }
+            _ => Err(FormatError::UnsupportedVersion(version)),
+        }
+    }
+
+    fn apply_record(&mut self, tag: u8, value: &'a [u8]) -> Result<(), FormatError> {
+        let Some(known_tag) = Tag::from_u8(tag) else {
+            // Unknown record, written by a newer firmware.
+            return Ok(());
+        };
+
+        let invalid = FormatError::InvalidRecord(tag);
+        match known_tag {
+            Tag::SampleRate => self.sample_rate = u16::from_le_bytes(array(value).ok_or(invalid)?),
+            Tag::Gain => self.gain = u8::from_le_bytes(array(value).ok_or(invalid)?),
+            Tag::ReferenceVoltage => {
+                self.reference_mv = u16::from_le_bytes(array(value).ok_or(invalid)?)
+            }
+            Tag::AdcDeviceId => {
+                self.adc_device_id = Some(u8::from_le_bytes(array(value).ok_or(invalid)?))
+            }
+            Tag::HighPassCutoff => {
+                self.high_pass_cutoff = f32::from_le_bytes(array(value).ok_or(invalid)?)
+            }
+            Tag::MedianBaseline => {
+                if !value.is_empty() {
+                    return Err(invalid);
+                }
+                self.median_baseline = true;
+            }
+            Tag::PowerLineFrequency => {
+                self.power_line_frequency = u8::from_le_bytes(array(value).ok_or(invalid)?)
+            }
+            Tag::FirmwareVersion => {
+                self.firmware_version = str::from_utf8(value).map_err(|_| invalid)?
+            }
+            Tag::StartTime => {
+                self.start_time = Some(u64::from_le_bytes(array(value).ok_or(invalid)?))
+            }
+            Tag::SampleEncoding => {
+                self.sample_encoding = u8::from_le_bytes(array(value).ok_or(invalid)?)
+            }
+            Tag::Channels => {
+                let bits = u8::from_le_bytes(array(value).ok_or(invalid)?);
+                self.channels = ChannelMask::from_bits(bits).ok_or(invalid)?;
+            }
+            Tag::RespirationChannel => {
+                let channel = u8::from_le_bytes(array(value).ok_or(invalid)?);
+                if !(1..=8).contains(&channel) {
+                    return Err(invalid);
+                }
+                self.respiration_channel = Some(channel);
+            }
+            Tag::LeadOff => self.lead_off = FrameRanges::from_bytes(value).ok_or(invalid)?,
+            Tag::Quality => self.quality = QualityScores::from_bytes(value).ok_or(invalid)?,
+            Tag::RPeaks => self.r_peaks = RPeaks::from_bytes(value).ok_or(invalid)?,
+            Tag::Rhythm => {
+                let rhythm = RhythmSummary::from_le_bytes(array(value).ok_or(invalid)?);
+                if rhythm.irregular_windows > rhythm.windows {
+                    return Err(invalid);
+                }
+                self.rhythm = Some(rhythm);
+            }
+            Tag::Keyframes => self.keyframes = Keyframes::from_bytes(value).ok_or(invalid)?,
+            Tag::Intervals => {
+                let intervals = IntervalSummary::from_le_bytes(array(value).ok_or(invalid)?);
+                if intervals.beats == 0 || intervals.qrs_ms == 0 {
+                    return Err(invalid);
+                }
+                self.intervals = Some(intervals);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn array<const N: usize>(value: &[u8]) -> Option<[u8; N]> {
+    value.try_into().ok()
+}
+
+fn write_record<W: Write>(writer: &mut W, tag: u8, data: &[u8]) -> Result<(), W::Error> {
+    // `MeasurementHeader::write` rejects longer records before writing anything.
+    let length = unwrap!(u16::try_from(data.len()).ok());
+
+    writer.write_all(&[tag])?;
+    writer.write_all(&length.to_le_bytes())?;
+    writer.write_all(data)
+}
+
+/// Returns the record bytes of a version 1 or later header.
+fn header_records(data: &[u8]) -> Result<&[u8], FormatError> {
+    let Some((length, rest)) = data.split_first_chunk::<4>() else {
+        return Err(FormatError::UnexpectedEof);
+    };
+
+    let length = u32::from_le_bytes(*length) as usize;
+    rest.get(..length).ok_or(FormatError::UnexpectedEof)
+}
+
+/// Iterates over the `(tag, data)` pairs of header records.
+///
+/// Iteration stops at the first truncated record.
+#[derive(Clone)]
+pub struct Records<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Records<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = (u8, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (&[tag, len_lo, len_hi], rest) = self.data.split_first_chunk::<3>()?;
+
+        let length = u16::from_le_bytes([len_lo, len_hi]) as usize;
+        let Some(value) = rest.get(..length) else {
+            self.data = &[];
+            return None;
+        };
+
+        self.data = &rest[length..];
+
+        Some((tag, value))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn header() -> MeasurementHeader<'static> {
+        MeasurementHeader {
+            sample_rate: 1000,
+            gain: 1,
+            reference_mv: 2420,
+            adc_device_id: Some(0x73),
+            high_pass_cutoff: 0.0,
+            median_baseline: true,
+            power_line_frequency: 60,
+            firmware_version: "0.1.0-abcdef",
+            start_time: Some(1_700_000_000),
+            sample_encoding: 0,
+            channels: ChannelMask::ALL,
+            respiration_channel: Some(1),
+            lead_off: FrameRanges::from_bytes(&[1, 0, 0, 0, 2, 0, 0, 0]).unwrap(),
+            quality: QualityScores::from_bytes(&[0, 0, 0, 0, 232, 3, 0, 0, 90, 45]).unwrap(),
+            r_peaks: RPeaks::from_bytes(&[10, 0, 0, 0, 20, 3, 0, 0]).unwrap(),
+            rhythm: Some(RhythmSummary {
+                windows: 40,
+                irregular_windows: 3,
+            }),
+            keyframes: Keyframes::from_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 99, 2, 0, 0])
+                .unwrap(),
+            intervals: Some(IntervalSummary {
+                beats: 8,
+                rr_ms: 810,
+                pr_ms: Some(152),
+                qrs_ms: 94,
+                qt_ms: Some(388),
+                st_uv: Some(-45),
+            }),
+        }
+    }
+
+    fn header_with_records(records: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut bytes = vec![0; 4];
+        for (tag, data) in records {
+            bytes.push(*tag);
+            bytes.extend_from_slice(&u16::try_from(data.len()).unwrap().to_le_bytes());
+            bytes.extend_from_slice(data);
+        }
+
+        let length = (bytes.len() - 4) as u32;
+        bytes[..4].copy_from_slice(&length.to_le_bytes());
+
+        bytes
+    }
+
+    fn encode(header: &MeasurementHeader) -> Vec<u8> {
+        let mut buffer = [0; 256];
+        let len = header.write(&mut &mut buffer[..]).unwrap();
+        assert_eq!(len, header.encoded_len());
+
+        buffer[..len].to_vec()
+    }
+
+    #[test]
+    fn header_roundtrip() {
+        let header = header();
+        let mut bytes = encode(&header);
+        let header_len = bytes.len();
+
+        bytes.extend_from_slice(&[1, 2, 3]);
+
+        let (parsed, len) = MeasurementHeader::parse(FORMAT_VERSION, &bytes).unwrap();
+
+        assert_eq!(parsed, header);
+        assert_eq!(len, header_len);
+        assert_eq!(&bytes[len..], [1, 2, 3]);
+    }
+
+    #[test]
+    fn oversized_records_are_rejected() {
+        let peaks = (0..=MeasurementHeader::MAX_RECORD_LEN as u32 / 4)
+            .flat_map(u32::to_le_bytes)
+            .collect::<Vec<_>>();
+        let header = MeasurementHeader {
+            r_peaks: RPeaks::from_bytes(&peaks).unwrap(),
+            ..header()
+        };
+        let mut buffer = [0xFF; 16];
+
+        let result = header.write(&mut &mut buffer[..]);
+
+        assert_eq!(result, Err(WriteError::RecordTooLong(Tag::RPeaks)));
+        assert_eq!(buffer, [0xFF; 16]);
+    }
+
+    #[test]
+    fn version_0_has_no_header() {
+        let bytes = [1, 2, 3];
+
+        let (parsed, len) = MeasurementHeader::parse(0, &bytes).unwrap();
+
+        assert_eq!(parsed, MeasurementHeader::LEGACY);
+        assert_eq!(len, 0);
+    }
+
+    #[test]
+    fn optional_fields_are_omitted() {
+        let header = MeasurementHeader {
+            adc_device_id: None,
+            median_baseline: false,
+            start_time: None,
+            respiration_channel: None,
+            lead_off: FrameRanges::EMPTY,
+            quality: QualityScores::EMPTY,
+            r_peaks: RPeaks::EMPTY,
+            rhythm: None,
+            keyframes: Keyframes::EMPTY,
+            intervals: None,
+            ..header()
+        };
+        let bytes = encode(&header);
+
+        let (parsed, _) = MeasurementHeader::parse(FORMAT_VERSION, &bytes).unwrap();
+
+        assert_eq!(parsed, header);
+        assert!(Records::new(&bytes[4..]).all(|(tag, _)| tag != Tag::StartTime as u8));
+    }
+
+    #[test]
+    fn unknown_records_are_skipped() {
+        let bytes = header_with_records(&[
+            (0xF0, &[1, 2, 3, 4, 5]),
+            (Tag::SampleRate as u8, &500u16.to_le_bytes()),
+        ]);
+
+        let (parsed, len) = MeasurementHeader::parse(FORMAT_VERSION, &bytes).unwrap();
+
+        assert_eq!(parsed.sample_rate, 500);
+        assert_eq!(len, bytes.len());
+    }
+
+    #[test]
+    fn truncated_header_is_an_error() {
+        let bytes = encode(&header());
+
+        assert_eq!(
+            MeasurementHeader::parse(FORMAT_VERSION, &bytes[..bytes.len() - 1]),
+            Err(FormatError::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn invalid_record_is_an_error() {
+        let bytes = header_with_records(&[(Tag::Gain as u8, &[1, 2])]);
+
+        assert_eq!(
+            MeasurementHeader::parse(FORMAT_VERSION, &bytes),
+            Err(FormatError::InvalidRecord(Tag::Gain as u8))
+        );
+    }
+
+    #[test]
+    fn version_1_is_single_channel() {
+        let bytes = header_with_records(&[
+            (Tag::SampleRate as u8, &500u16.to_le_bytes()),
+            (Tag::Channels as u8, &[ChannelMask::ALL.bits()]),
+        ]);
+
+        let (parsed, _) = MeasurementHeader::parse(1, &bytes).unwrap();
+
+        assert_eq!(parsed.sample_rate, 500);
+        assert_eq!(parsed.channels, ChannelMask::CH1);
+    }
+
+    #[test]
+    fn empty_channel_mask_is_an_error() {
+        let bytes = header_with_records(&[(Tag::Channels as u8, &[0])]);
+
+        assert_eq!(
+            MeasurementHeader::parse(FORMAT_VERSION, &bytes),
+            Err(FormatError::InvalidRecord(Tag::Channels as u8))
+        );
+    }
+
+    #[test]
+    fn channel_mask() {
+        assert_eq!(ChannelMask::CH1.count(), 1);
+        assert_eq!(ChannelMask::ALL.count(), 2);
+        assert!(ChannelMask::CH2.contains(2));
+        assert!(!ChannelMask::CH2.contains(1));
+        assert!(ChannelMask::ALL.channels().eq([1, 2]));
+        assert_eq!(ChannelMask::from_bits(0b100), None);
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn frame_ranges_roundtrip() {
+        let mut recorder = FrameRangeRecorder::new(8);
+        for frame in 0..100 {
+            recorder.update(frame, (10..20).contains(&frame) || frame >= 90);
+        }
+        recorder.finish(100, 0);
+        let ranges = recorder.ranges();
+
+        assert!(ranges.iter().eq([
+            FrameRange {
+                start: 10,
+                length: 10
+            },
+            FrameRange {
+                start: 90,
+                length: 10
+            },
+        ]));
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn frame_ranges_are_relative_to_first_kept_frame() {
+        let mut recorder = FrameRangeRecorder::new(8);
+        for frame in 0..100 {
+            recorder.update(
+                frame,
+                (10..20).contains(&frame) || (40..60).contains(&frame),
+            );
+        }
+        recorder.finish(100, 50);
+        let ranges = recorder.ranges();
+
+        assert!(ranges.iter().eq([FrameRange {
+            start: 0,
+            length: 10
+        }]));
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn frame_ranges_are_merged_when_full() {
+        let mut recorder = FrameRangeRecorder::new(1);
+        for frame in 0..100 {
+            recorder.update(frame, frame % 10 == 0);
+        }
+        recorder.finish(100, 0);
+        let ranges = recorder.ranges();
+
+        assert!(ranges.iter().eq([FrameRange {
+            start: 0,
+            length: 91
+        }]));
+    }
+
+    #[test]
+    fn truncated_frame_range_is_an_error() {
+        let bytes = header_with_records(&[(Tag::LeadOff as u8, &[1, 2, 3])]);
+
+        assert_eq!(
+            MeasurementHeader::parse(FORMAT_VERSION, &bytes),
+            Err(FormatError::InvalidRecord(Tag::LeadOff as u8))
+        );
+    }
+
+    #[test]
+    fn overflowing_frame_range_is_an_error() {
+        let range = FrameRange {
+            start: u32::MAX - 1,
+            length: 2,
+        };
+        let bytes = header_with_records(&[(Tag::LeadOff as u8, &range.to_le_bytes())]);
+
+        assert_eq!(
+            MeasurementHeader::parse(FORMAT_VERSION, &bytes),
+            Err(FormatError::InvalidRecord(Tag::LeadOff as u8))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn quality_scores_start_at_first_whole_window() {
+        let mut recorder = QualityRecorder::new(1000, 8);
+        for score in 0..5 {
+            recorder.push(score);
+        }
+        recorder.finish(1500);
+        let scores = recorder.scores();
+
+        assert_eq!(scores.start(), 500);
+        assert_eq!(scores.window(), 1000);
+        assert_eq!(scores.scores(), [2, 3, 4]);
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn oldest_quality_scores_are_dropped_when_full() {
+        let mut recorder = QualityRecorder::new(1000, 2);
+        for score in 0..5 {
+            recorder.push(score);
+        }
+        recorder.finish(0);
+        let scores = recorder.scores();
+
+        assert!(scores.iter().eq([
+            (
+                FrameRange {
+                    start: 3000,
+                    length: 1000
+                },
+                3
+            ),
+            (
+                FrameRange {
+                    start: 4000,
+                    length: 1000
+                },
+                4
+            ),
+        ]));
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn r_peaks_are_relative_to_first_kept_frame() {
+        let mut recorder = RPeakRecorder::new(3);
+        for frame in [100, 900, 1700, 2600] {
+            recorder.push(frame);
+        }
+        recorder.finish(1000);
+        let peaks = recorder.peaks();
+
+        assert!(peaks.iter().eq([700, 1600]));
+        assert!(peaks.rr_intervals().eq([900]));
+    }
+
+    #[test]
+    fn truncated_r_peak_is_an_error() {
+        let bytes = header_with_records(&[(Tag::RPeaks as u8, &[1, 2, 3])]);
+
+        assert_eq!(
+            MeasurementHeader::parse(FORMAT_VERSION, &bytes),
+            Err(FormatError::InvalidRecord(Tag::RPeaks as u8))
+        );
+    }
+
+    #[test]
+    fn unordered_r_peaks_are_an_error() {
+        let bytes = header_with_records(&[(Tag::RPeaks as u8, &[20, 0, 0, 0, 10, 0, 0, 0])]);
+
+        assert_eq!(
+            MeasurementHeader::parse(FORMAT_VERSION, &bytes),
+            Err(FormatError::InvalidRecord(Tag::RPeaks as u8))
+        );
+    }
+
+    #[test]
+    fn rhythm_summary_must_be_consistent() {
+        let rhythm = RhythmSummary {
+            windows: 1,
+            irregular_windows: 2,
+        };
+        let bytes = header_with_records(&[(Tag::Rhythm as u8, &rhythm.to_le_bytes())]);
+
+        assert_eq!(
+            MeasurementHeader::parse(FORMAT_VERSION, &bytes),
+            Err(FormatError::InvalidRecord(Tag::Rhythm as u8))
+        );
+    }
+
+    #[test]
+    fn unmeasured_intervals_roundtrip() {
+        let intervals = IntervalSummary {
+            beats: 5,
+            rr_ms: 1000,
+            pr_ms: None,
+            qrs_ms: 110,
+            qt_ms: None,
+            st_uv: None,
+        };
+
+        assert_eq!(
+            IntervalSummary::from_le_bytes(intervals.to_le_bytes()),
+            intervals
+        );
+        assert_eq!(intervals.qtc_ms(), None);
+    }
+
+    #[test]
+    fn qt_interval_is_corrected_for_heart_rate() {
+        let intervals = IntervalSummary {
+            beats: 5,
+            rr_ms: 640,
+            pr_ms: None,
+            qrs_ms: 90,
+            qt_ms: Some(360),
+            st_uv: Some(0),
+        };
+
+        assert_eq!(intervals.qtc_ms(), Some(450));
+    }
+
+    #[test]
+    fn interval_summary_must_have_beats() {
+        let intervals = IntervalSummary {
+            beats: 0,
+            rr_ms: 800,
+            pr_ms: None,
+            qrs_ms: 90,
+            qt_ms: None,
+            st_uv: None,
+        };
+        let bytes = header_with_records(&[(Tag::Intervals as u8, &intervals.to_le_bytes())]);
+
+        assert_eq!(
+            MeasurementHeader::parse(FORMAT_VERSION, &bytes),
+            Err(FormatError::InvalidRecord(Tag::Intervals as u8))
+        );
+    }
+
+    #[test]
+    fn median_baseline_flag_has_no_value() {
+        let bytes = header_with_records(&[(Tag::MedianBaseline as u8, &[1])]);
+
+        assert_eq!(
+            MeasurementHeader::parse(FORMAT_VERSION, &bytes),
+            Err(FormatError::InvalidRecord(Tag::MedianBaseline as u8))
+        );
+    }
+
+    #[test]
+    fn zero_quality_window_is_an_error() {
+        let bytes = header_with_records(&[(Tag::Quality as u8, &[0; 9])]);
+
+        assert_eq!(
+            MeasurementHeader::parse(FORMAT_VERSION, &bytes),
+            Err(FormatError::InvalidRecord(Tag::Quality as u8))
+        );
+    }
+
+    #[test]
+    fn overflowing_quality_windows_are_an_error() {
+        let mut data = (u32::MAX - 1000).to_le_bytes().to_vec();
+        data.extend_from_slice(&600u32.to_le_bytes());
+        data.extend_from_slice(&[90, 80]);
+        let bytes = header_with_records(&[(Tag::Quality as u8, &data)]);
+
+        assert_eq!(
+            MeasurementHeader::parse(FORMAT_VERSION, &bytes),
+            Err(FormatError::InvalidRecord(Tag::Quality as u8))
+        );
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        assert_eq!(
+            MeasurementHeader::parse(FORMAT_VERSION + 1, &[]),
+            Err(FormatError::UnsupportedVersion(FORMAT_VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn keyframe_recorder_limits_density() {
+        let mut recorder = KeyframeRecorder::new(1000);
+        for block in 0..20 {
+            recorder.push(Keyframe {
+                frame: block * 128,
+                offset: block * 100,
+            });
+        }
+
+        let frames = recorder
+            .keyframes()
+            .iter()
+            .map(|keyframe| keyframe.frame)
+            .collect::<Vec<_>>();
+        assert_eq!(frames, [0, 1024, 2048]);
+    }
+
+    #[test]
+    fn seeking_finds_preceding_keyframe() {
+        let mut recorder = KeyframeRecorder::new(0);
+        recorder.push(Keyframe {
+            frame: 0,
+            offset: 0,
+        });
+        recorder.push(Keyframe {
+            frame: 128,
+            offset: 90,
+        });
+        let keyframes = recorder.keyframes();
+
+        assert_eq!(keyframes.seek(0).map(|k| k.offset), Some(0));
+        assert_eq!(keyframes.seek(127).map(|k| k.offset), Some(0));
+        assert_eq!(keyframes.seek(128).map(|k| k.offset), Some(90));
+        assert_eq!(keyframes.seek(5000).map(|k| k.offset), Some(90));
+        assert_eq!(Keyframes::EMPTY.seek(5000), None);
+    }
+
+    #[test]
+    fn partial_keyframe_is_an_error() {
+        let bytes = header_with_records(&[(Tag::Keyframes as u8, &[0; 12])]);
+
+        assert_eq!(
+            MeasurementHeader::parse(FORMAT_VERSION, &bytes),
+            Err(FormatError::InvalidRecord(Tag::Keyframes as u8))
+        );
+    }
+
+    #[test]
+    fn keyframes_must_be_ascending() {
+        let keyframe = |frame: u32, offset: u32| Keyframe { frame, offset }.to_le_bytes();
+        let ascending = [keyframe(0, 0), keyframe(1024, 611)].concat();
+        let repeated = [keyframe(0, 0), keyframe(1024, 611), keyframe(1024, 900)].concat();
+        let descending = [keyframe(0, 0), keyframe(2048, 900), keyframe(1024, 611)].concat();
+
+        assert!(Keyframes::from_bytes(&ascending).is_some());
+        assert_eq!(Keyframes::from_bytes(&repeated), None);
+        assert_eq!(Keyframes::from_bytes(&descending), None);
+
+        let bytes = header_with_records(&[(Tag::Keyframes as u8, &descending)]);
+        assert_eq!(
+            MeasurementHeader::parse(FORMAT_VERSION, &bytes),
+            Err(FormatError::InvalidRecord(Tag::Keyframes as u8))
+        );
+    }
+}
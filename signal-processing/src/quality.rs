@@ -0,0 +1,320 @@
+//! Signal quality estimation
+//!
+//! Rates consecutive windows of an ECG recording on a 0 - 100 scale, so that segments disturbed
+//! by motion, poor electrode contact or saturation can be identified. Every window is rated on
+//! five properties, and the score is the product of the individual ratings:
+//!
+//! - clipping: the fraction of samples near the ADC's full scale,
+//! - baseline wander: the peak-to-peak range of the raw signal's low frequency component,
+//! - amplitude: the peak-to-peak range of the filtered signal,
+//! - high-frequency noise: the RMS of the filtered signal's second difference, relative to the
+//!   signal amplitude,
+//! - QRS consistency: whether beats are detected, and whether consecutive RR intervals agree.
+
+#[allow(unused_imports)]
+use crate::compat::*;
+
+/// A rating that is 1 at or below `good` and falls linearly to 0 at `bad`.
+fn rating(value: f32, good: f32, bad: f32) -> f32 {
+    1.0 - ((value - good) / (bad - good)).clamp(0.0, 1.0)
+}
+
+pub struct SignalQualityEstimator {
+    window: usize,
+    clipping_level: f32,
+    count: usize,
+    clipped: usize,
+
+    baseline: Option<f32>,
+    baseline_alpha: f32,
+    baseline_min: f32,
+    baseline_max: f32,
+
+    signal_min: f32,
+    signal_max: f32,
+    previous: Option<(f32, f32)>,
+    noise_power: f32,
+
+    since_beat: usize,
+    max_beat_gap: usize,
+    last_interval: Option<usize>,
+    beat_seen: bool,
+    intervals: usize,
+    consistent_intervals: usize,
+
+    current: Option<u8>,
+}
+
+impl SignalQualityEstimator {
+    /// Samples at or above this fraction of the full scale are considered clipped.
+    const CLIPPING_LEVEL: f32 = 0.99;
+    /// A window is unusable if this fraction of its samples are clipped.
+    const MAX_CLIPPED: f32 = 0.1;
+
+    /// Baseline wander range in volts, from acceptable to unusable.
+    const WANDER: (f32, f32) = (1.0e-3, 5.0e-3);
+    /// Signal amplitude in volts, from no signal to acceptable.
+    const AMPLITUDE: (f32, f32) = (0.05e-3, 0.2e-3);
+    /// Noise to amplitude ratio, from acceptable to unusable.
+    const NOISE: (f32, f32) = (0.02, 0.1);
+    /// Maximum relative difference between consistent RR intervals.
+    const RR_TOLERANCE: f32 = 0.25;
+
+    /// Creates an estimator that produces a score every `window_s` seconds of signal, sampled at
+    /// `fs`. `full_scale` is the largest voltage the ADC can measure.
+    pub fn new(fs: f32, window_s: f32, full_scale: f32) -> Self {
+        let s_to_samples = |s: f32| (s * fs) as usize;
+
+        Self {
+            window: s_to_samples(window_s).max(1),
+            clipping_level: full_scale * Self::CLIPPING_LEVEL,
+            count: 0,
+            clipped: 0,
+
+            baseline: None,
+            // Cuts off at about 0.5 Hz
+            baseline_alpha: 1.0 / (0.3 * fs + 1.0),
+            baseline_min: f32::MAX,
+            baseline_max: f32::MIN,
+
+            signal_min: f32::MAX,
+            signal_max: f32::MIN,
+            previous: None,
+            noise_power: 0.0,
+
+            since_beat: 0,
+            max_beat_gap: s_to_samples(3.0),
+            last_interval: None,
+            beat_seen: false,
+            intervals: 0,
+            consistent_intervals: 0,
+
+            current: None,
+        }
+    }
+
+    /// The number of samples a score is computed from.
+    #[inline]
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    pub fn clear(&mut self) {
+        self.clear_window();
+
+        self.baseline = None;
+        self.previous = None;
+        self.since_beat = 0;
+        self.last_interval = None;
+        self.beat_seen = false;
+        self.current = None;
+    }
+
+    fn clear_window(&mut self) {
+        self.count = 0;
+        self.clipped = 0;
+        self.baseline_min = f32::MAX;
+        self.baseline_max = f32::MIN;
+        self.signal_min = f32::MAX;
+        self.signal_max = f32::MIN;
+        self.noise_power = 0.0;
+        self.intervals = 0;
+        self.consistent_intervals = 0;
+    }
+
+    /// Processes a sample. `raw` is the unfiltered ADC voltage, `filtered` is the same sample
+    /// after baseline and power line filtering, and `is_beat` is set when a QRS complex was
+    /// detected at this sample.
+    ///
+    /// Returns the score of the window when it is complete.
+    pub fn update(&mut self, raw: f32, filtered: f32, is_beat: bool) -> Option<u8> {
+        if raw.abs() >= self.clipping_level {
+            self.clipped += 1;
+        }
+
+        let baseline = self.baseline.get_or_insert(raw);
+        *baseline += (raw - *baseline) * self.baseline_alpha;
+        self.baseline_min = self.baseline_min.min(*baseline);
+        self.baseline_max = self.baseline_max.max(*baseline);
+
+        self.signal_min = self.signal_min.min(filtered);
+        self.signal_max = self.signal_max.max(filtered);
+        if let Some((older, old)) = self.previous {
+            let second_difference = filtered - 2.0 * old + older;
+            self.noise_power += second_difference * second_difference;
+        }
+        self.previous = Some((self.previous.map_or(filtered, |(_, old)| old), filtered));
+
+        self.since_beat += 1;
+        if is_beat {
+            self.update_rhythm();
+        }
+
+        self.count += 1;
+        if self.count < self.window {
+            return None;
+        }
+
+        let score = (self.score() * 100.0) as u8;
+        self.clear_window();
+        self.current = Some(score);
+
+        Some(score)
+    }
+
+    fn update_rhythm(&mut self) {
+        if self.beat_seen {
+            let interval = self.since_beat;
+            if let Some(last) = self.last_interval {
+                let difference = interval.abs_diff(last) as f32;
+                if difference <= last as f32 * Self::RR_TOLERANCE {
+                    self.consistent_intervals += 1;
+                }
+                self.intervals += 1;
+            }
+            self.last_interval = Some(interval);
+        }
+
+        self.beat_seen = true;
+        self.since_beat = 0;
+    }
+
+    fn score(&self) -> f32 {
+        let clipping = rating(
+            self.clipped as f32 / self.count as f32,
+            0.0,
+            Self::MAX_CLIPPED,
+        );
+
+        let wander = self.baseline_max - self.baseline_min;
+        let wander = rating(wander, Self::WANDER.0, Self::WANDER.1);
+
+        let amplitude = self.signal_max - self.signal_min;
+        let signal = 1.0 - rating(amplitude, Self::AMPLITUDE.0, Self::AMPLITUDE.1);
+
+        let noise = (self.noise_power / self.count as f32).sqrt();
+        let noise = rating(
+            noise / amplitude.max(Self::AMPLITUDE.0),
+            Self::NOISE.0,
+            Self::NOISE.1,
+        );
+
+        let rhythm = if self.since_beat > self.max_beat_gap {
+            0.0
+        } else if self.intervals == 0 {
+            1.0
+        } else {
+            // An irregular rhythm may be real, so it only reduces the score.
+            0.5 + 0.5 * self.consistent_intervals as f32 / self.intervals as f32
+        };
+
+        (clipping * wander * signal * noise * rhythm).clamp(0.0, 1.0)
+    }
+
+    /// The score of the last complete window.
+    #[inline]
+    pub fn current(&self) -> Option<u8> {
+        self.current
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FS: f32 = 1000.0;
+    const FULL_SCALE: f32 = 2.42;
+
+    /// A crude ECG: 1 mV, 40 ms wide triangular QRS complexes at 72 BPM.
+    fn ecg(t: f32) -> f32 {
+        let phase = (t * 1.2).fract();
+        1.0e-3 * (1.0 - (phase - 0.5).abs() / 0.02).max(0.0)
+    }
+
+    /// Deterministic, roughly uniform noise in -1..1.
+    fn noise(n: usize) -> f32 {
+        let x = (n as u32).wrapping_mul(2_654_435_761).rotate_left(13) ^ 0x5bd1_e995;
+        x.wrapping_mul(1_597_334_677) as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+
+    fn beat_at(n: usize) -> bool {
+        // QRS peaks are at t = (k + 0.5) / 1.2 seconds.
+        let samples = n as f32 / FS * 1.2 - 0.5;
+        samples >= 0.0 && (samples - samples.round()).abs() * FS / 1.2 < 0.5
+    }
+
+    /// Feeds 10 seconds of signal and returns the last score.
+    fn score_with_beats(
+        raw: impl Fn(usize, f32) -> f32,
+        filtered: impl Fn(usize, f32) -> f32,
+        beat: impl Fn(usize) -> bool,
+    ) -> u8 {
+        let mut estimator = SignalQualityEstimator::new(FS, 1.0, FULL_SCALE);
+        let mut score = None;
+        for n in 0..10_000 {
+            let t = n as f32 / FS;
+            score = estimator
+                .update(raw(n, t), filtered(n, t), beat(n))
+                .or(score);
+        }
+        score.unwrap()
+    }
+
+    #[test]
+    fn clean_signal_is_good() {
+        let score = score_with_beats(|_, t| 0.2 + ecg(t), |_, t| ecg(t), beat_at);
+        assert!(score >= 90, "{score}");
+    }
+
+    #[test]
+    fn noisy_signal_is_bad() {
+        let signal = |n, t| ecg(t) + 0.1e-3 * noise(n);
+        let score = score_with_beats(move |n, t| 0.2 + signal(n, t), signal, beat_at);
+        assert!(score <= 30, "{score}");
+    }
+
+    #[test]
+    fn baseline_wander_is_bad() {
+        let wander = |t: f32| 8.0e-3 * (core::f32::consts::TAU * 0.4 * t).sin();
+        let score = score_with_beats(|_, t| 0.2 + wander(t) + ecg(t), |_, t| ecg(t), beat_at);
+        assert!(score <= 30, "{score}");
+    }
+
+    #[test]
+    fn clipping_is_bad() {
+        let score = score_with_beats(|_, _| FULL_SCALE, |_, t| ecg(t), beat_at);
+        assert_eq!(score, 0);
+    }
+
+    #[test]
+    fn missing_beats_are_bad() {
+        let score = score_with_beats(|_, t| 0.2 + ecg(t), |_, t| ecg(t), |_| false);
+        assert_eq!(score, 0);
+    }
+
+    #[test]
+    fn flat_signal_is_bad() {
+        let score = score_with_beats(|_, _| 0.2, |_, _| 0.0, beat_at);
+        assert_eq!(score, 0);
+    }
+
+    #[test]
+    fn irregular_rhythm_is_penalized() {
+        // Every third beat is missed, which alternates between short and long intervals.
+        let score = score_with_beats(
+            |_, t| 0.2 + ecg(t),
+            |_, t| ecg(t),
+            |n| beat_at(n) && matches!((n as f32 / FS * 1.2) as usize % 3, 1 | 2),
+        );
+        assert!((30..=80).contains(&score), "{score}");
+    }
+
+    #[test]
+    fn scores_are_produced_every_window() {
+        let mut estimator = SignalQualityEstimator::new(FS, 1.0, FULL_SCALE);
+        let scores = (0..5_500)
+            .filter_map(|_| estimator.update(0.2, 0.0, false))
+            .count();
+        assert_eq!(scores, 5);
+    }
+}
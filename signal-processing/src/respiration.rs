@@ -0,0 +1,221 @@
+//! Respiration rate estimation
+//!
+//! Works on the demodulated thoracic impedance signal of an ADS1292R. The signal is decimated to
+//! [`RespirationRateCalculator::PROCESSING_RATE`], band-limited to roughly 0.04 - 1 Hz with two
+//! first-order filters, and breaths are detected as rising crossings of a hysteresis band that
+//! scales with the signal amplitude.
+
+use core::num::NonZeroU8;
+
+use crate::filter::{median::MedianFilter, Filter};
+
+#[allow(unused_imports)]
+use crate::compat::*;
+
+pub struct RespirationRateCalculator {
+    decimation: usize,
+    accumulator: f32,
+    accumulated: usize,
+
+    baseline: Option<f32>,
+    baseline_alpha: f32,
+    smoothed: f32,
+    smoothing_alpha: f32,
+    amplitude: f32,
+    amplitude_alpha: f32,
+
+    settling: usize,
+    max_settling: usize,
+    is_above: bool,
+    since_breath: Option<usize>,
+    min_interval: usize,
+    max_interval: usize,
+
+    median: MedianFilter<3>,
+    current_rate: Option<NonZeroU8>,
+}
+
+impl RespirationRateCalculator {
+    /// Internal sample rate, in samples per second.
+    pub const PROCESSING_RATE: f32 = 25.0;
+
+    /// Fraction of the average signal amplitude a breath must swing through.
+    const HYSTERESIS: f32 = 0.3;
+
+    pub fn new(fs: f32) -> Self {
+        let decimation = ((fs / Self::PROCESSING_RATE) as usize).max(1);
+        let fs = fs / decimation as f32;
+
+        let s_to_samples = |s: f32| (s * fs) as usize;
+        // First-order smoothing factor with the given time constant
+        let alpha = |tau: f32| 1.0 / (tau * fs + 1.0);
+
+        Self {
+            decimation,
+            accumulator: 0.0,
+            accumulated: 0,
+
+            baseline: None,
+            baseline_alpha: alpha(4.0),
+            smoothed: 0.0,
+            smoothing_alpha: alpha(0.16),
+            amplitude: 0.0,
+            amplitude_alpha: alpha(8.0),
+
+            settling: s_to_samples(5.0),
+            max_settling: s_to_samples(5.0),
+            is_above: false,
+            since_breath: None,
+            // 40 to 4 breaths per minute
+            min_interval: s_to_samples(1.5),
+            max_interval: s_to_samples(15.0),
+
+            median: MedianFilter::new(),
+            current_rate: None,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.accumulator = 0.0;
+        self.accumulated = 0;
+        self.baseline = None;
+        self.smoothed = 0.0;
+        self.amplitude = 0.0;
+        self.settling = self.max_settling;
+        self.is_above = false;
+        self.since_breath = None;
+        self.median.clear();
+        self.current_rate = None;
+    }
+
+    /// Processes a sample. Returns the band-limited respiration signal when a decimated sample is
+    /// produced.
+    pub fn update(&mut self, sample: f32) -> Option<f32> {
+        self.accumulator += sample;
+        self.accumulated += 1;
+        if self.accumulated < self.decimation {
+            return None;
+        }
+
+        let sample = self.accumulator / self.accumulated as f32;
+        self.accumulator = 0.0;
+        self.accumulated = 0;
+
+        let baseline = self.baseline.get_or_insert(sample);
+        *baseline += (sample - *baseline) * self.baseline_alpha;
+        let high_passed = sample - *baseline;
+
+        self.smoothed += (high_passed - self.smoothed) * self.smoothing_alpha;
+        let signal = self.smoothed;
+
+        self.amplitude += (signal.abs() - self.amplitude) * self.amplitude_alpha;
+
+        if self.settling > 0 {
+            self.settling -= 1;
+            return Some(signal);
+        }
+
+        self.detect_breath(signal);
+
+        Some(signal)
+    }
+
+    fn detect_breath(&mut self, signal: f32) {
+        let threshold = self.amplitude * Self::HYSTERESIS;
+
+        if let Some(since_breath) = self.since_breath.as_mut() {
+            *since_breath += 1;
+            if *since_breath > self.max_interval {
+                // Breathing stopped or the signal is lost.
+                self.since_breath = None;
+                self.median.clear();
+                self.current_rate = None;
+            }
+        }
+
+        if self.is_above {
+            self.is_above = signal >= -threshold;
+            return;
+        }
+        if signal <= threshold {
+            return;
+        }
+
+        self.is_above = true;
+        match self.since_breath {
+            Some(interval) if interval < self.min_interval => return,
+            Some(interval) => {
+                let raw = 60.0 * Self::PROCESSING_RATE / interval as f32;
+                let rate = self.median.update(raw).unwrap_or(raw);
+
+                self.current_rate = NonZeroU8::new(rate as u8);
+            }
+            None => {}
+        }
+        self.since_breath = Some(0);
+    }
+
+    /// Breaths per minute, if breathing has been detected.
+    #[inline]
+    pub fn current_rate(&self) -> Option<NonZeroU8> {
+        self.current_rate
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rate_of(signal: impl Fn(f32) -> f32, seconds: usize) -> Option<NonZeroU8> {
+        let mut calculator = RespirationRateCalculator::new(1000.0);
+        for i in 0..seconds * 1000 {
+            calculator.update(signal(i as f32 / 1000.0));
+        }
+        calculator.current_rate()
+    }
+
+    fn breathing(bpm: f32) -> impl Fn(f32) -> f32 {
+        move |t: f32| 0.25 + 0.002 * (core::f32::consts::TAU * bpm / 60.0 * t).sin()
+    }
+
+    #[test]
+    fn detects_slow_breathing() {
+        let rate = rate_of(breathing(12.0), 60).unwrap().get();
+        assert!((11..=12).contains(&rate), "{rate}");
+    }
+
+    #[test]
+    fn detects_fast_breathing() {
+        let rate = rate_of(breathing(30.0), 60).unwrap().get();
+        assert!((29..=30).contains(&rate), "{rate}");
+    }
+
+    #[test]
+    fn ignores_cardiac_component() {
+        // Heartbeat-synchronous impedance changes are much faster than breathing.
+        let signal =
+            |t: f32| breathing(15.0)(t) + 0.0005 * (core::f32::consts::TAU * 1.2 * t).sin();
+        let rate = rate_of(signal, 60).unwrap().get();
+        assert!((14..=15).contains(&rate), "{rate}");
+    }
+
+    #[test]
+    fn no_rate_without_breathing() {
+        assert_eq!(rate_of(|_| 0.25, 60), None);
+    }
+
+    #[test]
+    fn rate_is_cleared_when_breathing_stops() {
+        let mut calculator = RespirationRateCalculator::new(1000.0);
+        let signal = breathing(15.0);
+        for i in 0..30_000 {
+            calculator.update(signal(i as f32 / 1000.0));
+        }
+        assert!(calculator.current_rate().is_some());
+
+        for _ in 0..20_000 {
+            calculator.update(0.25);
+        }
+        assert_eq!(calculator.current_rate(), None);
+    }
+}
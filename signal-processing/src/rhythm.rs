@@ -0,0 +1,291 @@
+//! Irregular rhythm screening
+//!
+//! Flags RR interval sequences that look like atrial fibrillation, based on Dash et al., "Automatic
+//! Real Time Detection of Atrial Fibrillation" (2009). A window of RR intervals is irregular if
+//! the intervals vary a lot (normalized RMSSD), are spread evenly over their range (Shannon
+//! entropy) and follow each other in a random order (turning point ratio). The extreme intervals
+//! are left out of the first two statistics, so that a few ectopic beats or missed detections
+//! don't make a regular rhythm look irregular.
+//!
+//! This is a screening aid, its result is an advisory, not a diagnosis.
+
+use crate::sliding::SlidingWindow;
+
+#[allow(unused_imports)]
+use crate::compat::*;
+
+const HISTOGRAM_BINS: usize = 16;
+
+/// Statistics of a window of RR intervals.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WindowStatistics {
+    /// RMSSD divided by the mean interval.
+    pub normalized_rmssd: f32,
+    /// Shannon entropy of the interval histogram, normalized to 0 - 1.
+    pub shannon_entropy: f32,
+    /// The number of turning points divided by the number of inner intervals.
+    pub turning_point_ratio: f32,
+    /// Whether all three statistics indicate an irregular rhythm.
+    pub irregular: bool,
+}
+
+pub struct IrregularityDetector<const N: usize> {
+    ms_per_sample: f32,
+    window: SlidingWindow<N>,
+    windows: u32,
+    irregular_windows: u32,
+}
+
+impl<const N: usize> IrregularityDetector<N> {
+    /// The shortest and longest interval considered, in milliseconds. Other intervals are
+    /// detection errors or gaps.
+    const RANGE_MS: (f32, f32) = (250.0, 2500.0);
+    const NRMSSD_THRESHOLD: f32 = 0.1;
+    const ENTROPY_THRESHOLD: f32 = 0.7;
+    /// The number of intervals left out on both ends of the range.
+    const TRIMMED: usize = N / 16;
+
+    /// `fs` is the sample rate RR intervals are measured in. Windows are `N` intervals long.
+    pub fn new(fs: f32) -> Self {
+        Self {
+            ms_per_sample: 1000.0 / fs,
+            window: SlidingWindow::new(),
+            windows: 0,
+            irregular_windows: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.window.clear();
+        self.windows = 0;
+        self.irregular_windows = 0;
+    }
+
+    /// Processes an RR interval, in samples. Once `N` intervals are collected, returns the
+    /// statistics of the last `N` intervals.
+    pub fn update(&mut self, rr_interval: u32) -> Option<WindowStatistics> {
+        let interval = rr_interval as f32 * self.ms_per_sample;
+        if !(Self::RANGE_MS.0..=Self::RANGE_MS.1).contains(&interval) {
+            return None;
+        }
+
+        self.window.push(interval);
+        if !self.window.is_full() {
+            return None;
+        }
+
+        let statistics = self.statistics();
+
+        self.windows += 1;
+        if statistics.irregular {
+            self.irregular_windows += 1;
+        }
+
+        Some(statistics)
+    }
+
+    fn statistics(&self) -> WindowStatistics {
+        let mut intervals = [0.0; N];
+        for (dst, src) in intervals.iter_mut().zip(self.window.iter()) {
+            *dst = src;
+        }
+
+        let mut sorted = intervals;
+        sorted.sort_unstable_by(f32::total_cmp);
+        let low = sorted[Self::TRIMMED];
+        let high = sorted[N - 1 - Self::TRIMMED];
+        let trimmed = || {
+            intervals
+                .iter()
+                .copied()
+                .filter(|x| (low..=high).contains(x))
+        };
+
+        let normalized_rmssd = Self::normalized_rmssd(trimmed());
+        let shannon_entropy = Self::shannon_entropy(trimmed(), low, high);
+        let turning_points = Self::turning_points(&intervals);
+
+        // The expected number of turning points in a random sequence, and its standard deviation
+        let expected = (2 * N - 4) as f32 / 3.0;
+        let deviation = ((16 * N - 29) as f32 / 90.0).sqrt();
+        let is_random = (turning_points as f32 - expected).abs() <= 1.96 * deviation;
+
+        WindowStatistics {
+            normalized_rmssd,
+            shannon_entropy,
+            turning_point_ratio: turning_points as f32 / (N - 2) as f32,
+            irregular: normalized_rmssd > Self::NRMSSD_THRESHOLD
+                && shannon_entropy > Self::ENTROPY_THRESHOLD
+                && is_random,
+        }
+    }
+
+    fn normalized_rmssd(intervals: impl Iterator<Item = f32>) -> f32 {
+        let mut count = 0;
+        let mut sum = 0.0;
+        let mut squares = 0.0;
+        let mut previous = None;
+        for interval in intervals {
+            count += 1;
+            sum += interval;
+            if let Some(previous) = previous.replace(interval) {
+                let difference = interval - previous;
+                squares += difference * difference;
+            }
+        }
+
+        if count < 2 {
+            return 0.0;
+        }
+
+        let rmssd = (squares / (count - 1) as f32).sqrt();
+        rmssd / (sum / count as f32)
+    }
+
+    fn shannon_entropy(intervals: impl Iterator<Item = f32>, low: f32, high: f32) -> f32 {
+        if high <= low {
+            return 0.0;
+        }
+
+        let mut histogram = [0_u16; HISTOGRAM_BINS];
+        let mut count = 0;
+        for interval in intervals {
+            let bin = ((interval - low) / (high - low) * HISTOGRAM_BINS as f32) as usize;
+            histogram[bin.min(HISTOGRAM_BINS - 1)] += 1;
+            count += 1;
+        }
+
+        let entropy = histogram
+            .iter()
+            .filter(|&&n| n > 0)
+            .map(|&n| {
+                let p = n as f32 / count as f32;
+                -p * p.ln()
+            })
+            .sum::<f32>();
+
+        entropy / (HISTOGRAM_BINS as f32).ln()
+    }
+
+    fn turning_points(intervals: &[f32]) -> usize {
+        intervals
+            .windows(3)
+            .filter(|w| (w[1] > w[0] && w[1] > w[2]) || (w[1] < w[0] && w[1] < w[2]))
+            .count()
+    }
+
+    /// The number of windows evaluated so far.
+    #[inline]
+    pub fn windows(&self) -> u32 {
+        self.windows
+    }
+
+    /// The number of windows found irregular so far.
+    #[inline]
+    pub fn irregular_windows(&self) -> u32 {
+        self.irregular_windows
+    }
+
+    /// Returns whether most of the evaluated windows were irregular.
+    pub fn is_irregular(&self) -> bool {
+        self.windows > 0 && 2 * self.irregular_windows > self.windows
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Deterministic, roughly uniform noise in 0..1.
+    fn noise(n: usize) -> f32 {
+        // MurmurHash3 finalizer
+        let mut x = (n as u32).wrapping_mul(0x9e37_79b9);
+        x ^= x >> 16;
+        x = x.wrapping_mul(0x85eb_ca6b);
+        x ^= x >> 13;
+        x = x.wrapping_mul(0xc2b2_ae35);
+        x ^= x >> 16;
+        (x >> 8) as f32 / (1 << 24) as f32
+    }
+
+    fn detect<const N: usize>(intervals: impl Iterator<Item = f32>) -> IrregularityDetector<N> {
+        let mut detector = IrregularityDetector::<N>::new(1000.0);
+        for interval in intervals {
+            detector.update(interval as u32);
+        }
+        detector
+    }
+
+    /// 75 BPM with respiratory sinus arrhythmia
+    fn sinus_rhythm(n: usize) -> f32 {
+        800.0 + 30.0 * (core::f32::consts::TAU * n as f32 / 4.5).sin() + 10.0 * noise(n)
+    }
+
+    /// Random intervals between 400 and 1000 ms
+    fn atrial_fibrillation(n: usize) -> f32 {
+        400.0 + 600.0 * noise(n)
+    }
+
+    #[test]
+    fn sinus_rhythm_is_regular() {
+        let detector = detect::<32>((0..200).map(sinus_rhythm));
+
+        assert_eq!(detector.windows(), 200 - 31);
+        assert_eq!(detector.irregular_windows(), 0);
+        assert!(!detector.is_irregular());
+    }
+
+    #[test]
+    fn atrial_fibrillation_is_irregular() {
+        let detector = detect::<32>((0..200).map(atrial_fibrillation));
+
+        assert!(detector.is_irregular());
+    }
+
+    #[test]
+    fn longer_windows() {
+        assert!(!detect::<128>((0..300).map(sinus_rhythm)).is_irregular());
+        assert!(detect::<128>((0..300).map(atrial_fibrillation)).is_irregular());
+    }
+
+    #[test]
+    fn bigeminy_is_not_irregular() {
+        // Every other beat is premature: variable, but predictable
+        let detector = detect::<32>((0..200).map(|n| if n % 2 == 0 { 600.0 } else { 1000.0 }));
+
+        assert_eq!(detector.irregular_windows(), 0);
+    }
+
+    #[test]
+    fn few_ectopic_beats_are_tolerated() {
+        let detector = detect::<32>((0..200).map(|n| match n % 40 {
+            20 => 500.0,
+            21 => 1100.0,
+            _ => sinus_rhythm(n),
+        }));
+
+        assert_eq!(detector.irregular_windows(), 0);
+    }
+
+    #[test]
+    fn detection_gaps_are_ignored() {
+        let detector = detect::<32>((0..40).map(|n| if n == 10 { 5000.0 } else { 800.0 }));
+
+        assert_eq!(detector.windows(), 40 - 1 - 31);
+    }
+
+    #[test]
+    fn statistics() {
+        let mut detector = IrregularityDetector::<32>::new(1000.0);
+        let statistics = (0..32)
+            .filter_map(|n| detector.update(if n % 2 == 0 { 600 } else { 1000 }))
+            .last()
+            .unwrap();
+
+        assert_eq!(statistics.turning_point_ratio, 1.0);
+        assert!((statistics.shannon_entropy - 0.25).abs() < 1e-3);
+        assert!(statistics.normalized_rmssd > 0.4);
+        assert!(!statistics.irregular);
+    }
+}
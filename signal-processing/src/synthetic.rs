@@ -0,0 +1,591 @@
+//! Synthetic ECG signals for validating the signal processing without hardware.
+//!
+//! [`SyntheticEcg`] implements the dynamical model of McSharry et al., "A dynamical model for
+//! generating synthetic electrocardiogram signals" (IEEE Trans. Biomed. Eng. 50(3), 2003). A point
+//! travels around a unit circle once per beat. The P, Q, R, S and T waves are placed at fixed
+//! angles on the circle, and each of them pushes the point's height up or down as it passes,
+//! tracing a Gaussian. The height is the ECG.
+//!
+//! The ST segment can be raised or lowered to simulate ischemia. On top of the model, the generator
+//! adds the disturbances the device has to deal with: white noise, baseline wander, power line
+//! interference, motion artifacts and the electrode offset. The output is in volts, like the
+//! frontend's samples.
+//!
+//! The signals are deterministic for a given [`SignalConfig`], so filter outputs can be checked
+//! against recorded values.
+
+use crate::mains::MainsFrequency;
+
+#[allow(unused_imports)]
+use crate::compat::*;
+
+use core::f32::consts::{PI, TAU};
+
+/// One of the waves of a heartbeat.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Wave {
+    /// The position of the wave's peak on the limit cycle, in radians. The R peak is at 0.
+    pub angle: f32,
+    /// The height of the wave, relative to [`SignalConfig::amplitude`].
+    pub height: f32,
+    /// The standard deviation of the wave's Gaussian, in radians.
+    pub width: f32,
+}
+
+impl Wave {
+    const fn new(angle: f32, height: f32, width: f32) -> Self {
+        Self {
+            angle,
+            height,
+            width,
+        }
+    }
+}
+
+/// The P, Q, R, S and T waves of a normal sinus beat at 60 BPM, after McSharry et al.
+pub const PQRST: [Wave; 5] = [
+    Wave::new(-PI / 3.0, 0.15, 0.25),
+    Wave::new(-PI / 12.0, -0.15, 0.1),
+    Wave::new(0.0, 1.0, 0.1),
+    Wave::new(PI / 12.0, -0.25, 0.1),
+    Wave::new(PI / 2.0, 0.3, 0.4),
+];
+
+/// Parameters of a [`SyntheticEcg`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct SignalConfig {
+    /// The mean heart rate in beats per minute.
+    pub heart_rate: f32,
+    /// The standard deviation of the RR intervals, relative to their mean.
+    pub rr_variability: f32,
+    /// The height of the R wave in volts.
+    pub amplitude: f32,
+    /// The shape of a beat at 60 BPM. At other heart rates, the angles and widths are scaled so
+    /// that the QT interval follows Bazett's formula.
+    pub waves: [Wave; 5],
+    /// The level of the ST segment in volts. The segment rises to this level after the S wave
+    /// and returns to the baseline as the T wave ends.
+    pub st_deviation: f32,
+    /// The standard deviation of white noise in volts.
+    pub noise: f32,
+    /// The amplitude of sinusoidal baseline wander in volts.
+    pub baseline_wander: f32,
+    /// The frequency of the baseline wander in Hz, about the breathing rate.
+    pub wander_frequency: f32,
+    /// The power line frequency.
+    pub mains: MainsFrequency,
+    /// The amplitude of the power line interference's fundamental in volts.
+    pub mains_amplitude: f32,
+    /// The amplitude of the 2nd and 3rd harmonics, relative to the fundamental. Harmonics at or
+    /// above the Nyquist frequency are left out.
+    pub mains_harmonics: f32,
+    /// The average number of motion artifacts per second. An artifact is a single period of a
+    /// 10 Hz sine, which looks like a QRS complex to a detector.
+    pub artifact_rate: f32,
+    /// The largest amplitude of the motion artifacts in volts. Each artifact has a random
+    /// amplitude between half of this and this, and a random sign.
+    pub artifact_amplitude: f32,
+    /// The DC offset of the electrodes in volts.
+    pub offset: f32,
+    /// Seeds the noise and the RR interval variation.
+    pub seed: u32,
+}
+
+impl Default for SignalConfig {
+    /// A clean 72 BPM signal with a 1 mV R wave.
+    fn default() -> Self {
+        Self {
+            heart_rate: 72.0,
+            rr_variability: 0.0,
+            amplitude: 1.0e-3,
+            waves: PQRST,
+            st_deviation: 0.0,
+            noise: 0.0,
+            baseline_wander: 0.0,
+            wander_frequency: 0.25,
+            mains: MainsFrequency::Hz50,
+            mains_amplitude: 0.0,
+            mains_harmonics: 0.0,
+            artifact_rate: 0.0,
+            artifact_amplitude: 0.0,
+            offset: 0.0,
+            seed: 1,
+        }
+    }
+}
+
+/// Xorshift random number generator. Not suitable for anything but test signals.
+#[derive(Clone)]
+struct Random(u32);
+
+impl Random {
+    fn new(seed: u32) -> Self {
+        Self(seed.max(1))
+    }
+
+    /// Returns a uniformly distributed number in (0, 1].
+    fn uniform(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        ((self.0 >> 8) + 1) as f32 / (1 << 24) as f32
+    }
+
+    /// Returns a normally distributed number with zero mean and unit variance.
+    fn gaussian(&mut self) -> f32 {
+        let magnitude = (-2.0 * self.uniform().ln()).sqrt();
+        magnitude * (TAU * self.uniform()).cos()
+    }
+}
+
+/// A sine wave generator that keeps its phase wrapped.
+#[derive(Clone)]
+struct Oscillator {
+    phase: f32,
+    step: f32,
+}
+
+impl Oscillator {
+    fn new(fs: f32, frequency: f32, phase: f32) -> Self {
+        Self {
+            phase,
+            step: TAU * frequency / fs,
+        }
+    }
+
+    fn next(&mut self) -> f32 {
+        let output = self.phase.sin();
+        self.phase = (self.phase + self.step) % TAU;
+        output
+    }
+}
+
+/// The length of a motion artifact in seconds.
+const ARTIFACT_LENGTH: f32 = 0.1;
+
+/// A motion artifact in progress.
+#[derive(Clone)]
+struct Artifact {
+    amplitude: f32,
+    oscillator: Oscillator,
+    remaining: usize,
+}
+
+/// Rises smoothly from 0 to 1 as `x` goes from `start` to `end`.
+fn smoothstep(x: f32, start: f32, end: f32) -> f32 {
+    let x = ((x - start) / (end - start)).clamp(0.0, 1.0);
+    x * x * (3.0 - 2.0 * x)
+}
+
+/// A point of the McSharry model's trajectory in the plane of the limit cycle.
+#[derive(Clone, Copy)]
+struct State {
+    x: f32,
+    y: f32,
+}
+
+impl State {
+    fn angle(&self) -> f32 {
+        self.y.atan2(self.x)
+    }
+
+    fn add(self, derivative: State, dt: f32) -> State {
+        State {
+            x: self.x + derivative.x * dt,
+            y: self.y + derivative.y * dt,
+        }
+    }
+}
+
+/// Generates a synthetic ECG, one sample at a time.
+#[derive(Clone)]
+pub struct SyntheticEcg {
+    config: SignalConfig,
+    dt: f32,
+    /// The waves, scaled to the mean heart rate.
+    waves: [Wave; 5],
+    state: State,
+    /// Angular velocity for the current beat, in radians per second.
+    omega: f32,
+    random: Random,
+    wander: Oscillator,
+    mains: [Oscillator; 3],
+    mains_amplitudes: [f32; 3],
+    artifact: Option<Artifact>,
+    clean: f32,
+    r_peak: bool,
+}
+
+impl SyntheticEcg {
+    pub fn new(fs: f32, config: SignalConfig) -> Self {
+        let scale = (config.heart_rate / 60.0).sqrt();
+        let waves = config.waves.map(|wave| Wave {
+            angle: wave.angle * scale,
+            height: wave.height,
+            width: wave.width * scale,
+        });
+
+        let fundamental = config.mains.hz() as f32;
+        let mains = core::array::from_fn(|idx| {
+            Oscillator::new(fs, fundamental * (idx + 1) as f32, 0.3 * idx as f32)
+        });
+        let mains_amplitudes = core::array::from_fn(|idx| {
+            let frequency = fundamental * (idx + 1) as f32;
+            match idx {
+                _ if frequency >= fs / 2.0 => 0.0,
+                0 => config.mains_amplitude,
+                _ => config.mains_amplitude * config.mains_harmonics,
+            }
+        });
+
+        // Start in the diastole, before the P wave.
+        let start = -0.8 * PI;
+        let mut ecg = Self {
+            dt: 1.0 / fs,
+            waves,
+            state: State {
+                x: start.cos(),
+                y: start.sin(),
+            },
+            omega: 0.0,
+            random: Random::new(config.seed),
+            wander: Oscillator::new(fs, config.wander_frequency, 0.0),
+            mains,
+            mains_amplitudes,
+            artifact: None,
+            clean: 0.0,
+            r_peak: false,
+            config,
+        };
+        ecg.omega = ecg.next_omega();
+
+        ecg
+    }
+
+    /// The angular velocity of a new beat.
+    fn next_omega(&mut self) -> f32 {
+        let mean_rr = 60.0 / self.config.heart_rate;
+        let rr = mean_rr * (1.0 + self.config.rr_variability * self.random.gaussian());
+
+        TAU / rr.max(0.2 * mean_rr)
+    }
+
+    fn derivative(&self, state: State) -> State {
+        let alpha = 1.0 - (state.x * state.x + state.y * state.y).sqrt();
+
+        State {
+            x: alpha * state.x - self.omega * state.y,
+            y: alpha * state.y + self.omega * state.x,
+        }
+    }
+
+    /// The height of the trajectory at the given angle.
+    ///
+    /// The model's third equation moves the height by the derivative of the waves' Gaussians as
+    /// the point passes them. This is its closed-form solution, which keeps the baseline between
+    /// the beats at zero.
+    fn height(&self, angle: f32) -> f32 {
+        let mut height = 0.0;
+        for wave in &self.waves {
+            // Wrap the angle difference to [-π, π)
+            let delta = (angle - wave.angle + PI).rem_euclid(TAU) - PI;
+            height += wave.height * (-delta * delta / (2.0 * wave.width * wave.width)).exp();
+        }
+
+        height
+    }
+
+    /// The level of the ST segment at the given angle, relative to
+    /// [`SignalConfig::st_deviation`].
+    fn st_level(&self, angle: f32) -> f32 {
+        let [_, _, _, s, t] = self.waves;
+        let rise = smoothstep(angle, s.angle, s.angle + 2.0 * s.width);
+        let fall = smoothstep(angle, t.angle, t.angle + 2.0 * t.width);
+
+        rise * (1.0 - fall)
+    }
+
+    /// Advances the model by one sample period with the 4th order Runge-Kutta method.
+    fn step(&mut self) {
+        let dt = self.dt;
+        let k1 = self.derivative(self.state);
+        let k2 = self.derivative(self.state.add(k1, dt / 2.0));
+        let k3 = self.derivative(self.state.add(k2, dt / 2.0));
+        let k4 = self.derivative(self.state.add(k3, dt));
+
+        let prev_angle = self.state.angle();
+        self.state = State {
+            x: self.state.x + dt / 6.0 * (k1.x + 2.0 * k2.x + 2.0 * k3.x + k4.x),
+            y: self.state.y + dt / 6.0 * (k1.y + 2.0 * k2.y + 2.0 * k3.y + k4.y),
+        };
+        let angle = self.state.angle();
+
+        self.r_peak = prev_angle < 0.0 && angle >= 0.0;
+        if prev_angle > 0.0 && angle < 0.0 {
+            // Wrapped around at ±π, a new beat starts.
+            self.omega = self.next_omega();
+        }
+    }
+
+    /// Returns the next sample, in volts.
+    pub fn sample(&mut self) -> f32 {
+        self.step();
+        let angle = self.state.angle();
+        self.clean = self.config.amplitude * self.height(angle);
+        if self.config.st_deviation != 0.0 {
+            self.clean += self.config.st_deviation * self.st_level(angle);
+        }
+
+        let mut sample = self.config.offset + self.clean;
+        sample += self.config.baseline_wander * self.wander.next();
+        for (oscillator, amplitude) in self.mains.iter_mut().zip(self.mains_amplitudes) {
+            sample += amplitude * oscillator.next();
+        }
+        if self.config.noise > 0.0 {
+            sample += self.config.noise * self.random.gaussian();
+        }
+        if self.config.artifact_rate > 0.0 {
+            sample += self.artifact();
+        }
+
+        sample
+    }
+
+    /// Returns the next sample of the motion artifacts.
+    fn artifact(&mut self) -> f32 {
+        if self.artifact.is_none() && self.random.uniform() < self.config.artifact_rate * self.dt {
+            let fs = 1.0 / self.dt;
+            let sign = if self.random.uniform() < 0.5 {
+                -1.0
+            } else {
+                1.0
+            };
+            let scale = 0.5 + 0.5 * self.random.uniform();
+
+            self.artifact = Some(Artifact {
+                amplitude: sign * scale * self.config.artifact_amplitude,
+                oscillator: Oscillator::new(fs, 1.0 / ARTIFACT_LENGTH, 0.0),
+                remaining: (ARTIFACT_LENGTH * fs) as usize,
+            });
+        }
+
+        let Some(artifact) = self.artifact.as_mut() else {
+            return 0.0;
+        };
+
+        let output = artifact.amplitude * artifact.oscillator.next();
+        artifact.remaining -= 1;
+        if artifact.remaining == 0 {
+            self.artifact = None;
+        }
+
+        output
+    }
+
+    /// The ECG in the last sample, without offset, noise, baseline wander, interference and
+    /// artifacts.
+    #[inline]
+    pub fn clean(&self) -> f32 {
+        self.clean
+    }
+
+    /// Returns whether the last sample is the first one at or after an R peak.
+    #[inline]
+    pub fn is_r_peak(&self) -> bool {
+        self.r_peak
+    }
+
+    #[inline]
+    pub fn config(&self) -> &SignalConfig {
+        &self.config
+    }
+}
+
+impl Iterator for SyntheticEcg {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        Some(self.sample())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    /// Checks outputs against values recorded from an earlier version of the code, to catch
+    /// unintended changes in behavior. The values are in microvolts. When a change is intended,
+    /// replace the recorded values with the ones printed by the failing assertion.
+    #[track_caller]
+    pub(crate) fn assert_golden(outputs: impl IntoIterator<Item = f32>, golden: &[f32]) {
+        let outputs = outputs
+            .into_iter()
+            .map(|output| output * 1.0e6)
+            .collect::<Vec<_>>();
+
+        let matches = outputs.len() == golden.len()
+            && outputs
+                .iter()
+                .zip(golden)
+                .all(|(output, golden)| (output - golden).abs() < 0.1);
+        assert!(
+            matches,
+            "outputs differ from the recorded values: {outputs:?}"
+        );
+    }
+
+    /// Returns the indices of the R peaks and the clean signal.
+    fn generate(fs: f32, config: SignalConfig, seconds: f32) -> (Vec<usize>, Vec<f32>) {
+        let mut ecg = SyntheticEcg::new(fs, config);
+        let mut peaks = Vec::new();
+        let mut clean = Vec::new();
+        for n in 0..(fs * seconds) as usize {
+            ecg.sample();
+            if ecg.is_r_peak() {
+                peaks.push(n);
+            }
+            clean.push(ecg.clean());
+        }
+        (peaks, clean)
+    }
+
+    #[test]
+    fn beats_follow_heart_rate() {
+        for heart_rate in [40.0, 72.0, 150.0, 200.0] {
+            let config = SignalConfig {
+                heart_rate,
+                ..SignalConfig::default()
+            };
+            let (peaks, _) = generate(500.0, config, 30.0);
+
+            let rr = (peaks[peaks.len() - 1] - peaks[0]) as f32 / (peaks.len() - 1) as f32;
+            let measured = 60.0 * 500.0 / rr;
+            assert!(
+                (measured - heart_rate).abs() < 0.2,
+                "{heart_rate}: {measured}"
+            );
+        }
+    }
+
+    #[test]
+    fn r_wave_has_configured_amplitude() {
+        let (peaks, clean) = generate(1000.0, SignalConfig::default(), 10.0);
+
+        for &peak in &peaks[1..] {
+            let max = clean[peak - 20..peak + 20]
+                .iter()
+                .copied()
+                .fold(f32::MIN, f32::max);
+            assert!((max - 1.0e-3).abs() < 0.1e-3, "{max}");
+
+            // The R peak is at the sample where the beat's phase crosses 0.
+            assert!((clean[peak] - max).abs() < 0.01e-3);
+        }
+    }
+
+    #[test]
+    fn rr_intervals_vary() {
+        let config = SignalConfig {
+            rr_variability: 0.05,
+            ..SignalConfig::default()
+        };
+        let (peaks, _) = generate(1000.0, config, 60.0);
+
+        let rr = peaks.windows(2).map(|w| (w[1] - w[0]) as f32);
+        let (min, max) = rr.fold((f32::MAX, 0.0f32), |(min, max), rr| {
+            (min.min(rr), max.max(rr))
+        });
+        assert!(min < 800.0 && max > 870.0, "{min} {max}");
+    }
+
+    #[test]
+    fn st_segment_is_shifted() {
+        let config = SignalConfig {
+            heart_rate: 60.0,
+            ..SignalConfig::default()
+        };
+        let (peaks, normal) = generate(1000.0, config.clone(), 10.0);
+        let elevated = SignalConfig {
+            st_deviation: 0.2e-3,
+            ..config
+        };
+        let (_, elevated) = generate(1000.0, elevated, 10.0);
+
+        for &peak in &peaks[1..peaks.len() - 1] {
+            let shift = |offset: usize| elevated[peak + offset] - normal[peak + offset];
+            // The QRS complex and the diastole are left alone, the ST segment is shifted.
+            assert_eq!(shift(0), 0.0);
+            assert!((shift(150) - 0.2e-3).abs() < 1.0e-6, "{}", shift(150));
+            assert_eq!(shift(600), 0.0);
+        }
+    }
+
+    #[test]
+    fn disturbances_are_added() {
+        let config = SignalConfig {
+            noise: 10.0e-6,
+            baseline_wander: 0.5e-3,
+            mains_amplitude: 0.2e-3,
+            mains_harmonics: 0.25,
+            offset: 0.1,
+            ..SignalConfig::default()
+        };
+        let mut ecg = SyntheticEcg::new(1000.0, config.clone());
+
+        let mut sum = 0.0;
+        let mut sum2 = 0.0;
+        for _ in 0..20_000 {
+            let disturbance = ecg.sample() - ecg.clean() - config.offset;
+            sum += disturbance;
+            sum2 += disturbance * disturbance;
+        }
+
+        // Sinusoids with amplitude A have A²/2 power.
+        let power =
+            0.5e-3f32.powi(2) / 2.0 + 0.2e-3f32.powi(2) / 2.0 * (1.0 + 2.0 * 0.25f32.powi(2));
+        let expected = power + 10.0e-6f32.powi(2);
+        let measured = sum2 / 20_000.0;
+        assert!((sum / 20_000.0f32).abs() < 5.0e-6);
+        assert!(
+            (measured / expected - 1.0).abs() < 0.02,
+            "{measured} {expected}"
+        );
+    }
+
+    #[test]
+    fn artifacts_are_added() {
+        let config = SignalConfig {
+            artifact_rate: 0.5,
+            artifact_amplitude: 2.0e-3,
+            ..SignalConfig::default()
+        };
+        let mut ecg = SyntheticEcg::new(1000.0, config);
+
+        let mut artifacts = 0;
+        let mut active = false;
+        for _ in 0..100_000 {
+            let artifact = ecg.sample() - ecg.clean();
+            assert!(artifact.abs() <= 2.0e-3);
+
+            // Count the starts of the stretches the artifacts are added to.
+            if artifact != 0.0 && !active {
+                artifacts += 1;
+            }
+            active = artifact != 0.0;
+        }
+        assert!((35..=65).contains(&artifacts), "{artifacts}");
+    }
+
+    #[test]
+    fn is_deterministic() {
+        let config = SignalConfig {
+            noise: 10.0e-6,
+            rr_variability: 0.05,
+            ..SignalConfig::default()
+        };
+
+        let a = SyntheticEcg::new(500.0, config.clone()).take(5000);
+        let b = SyntheticEcg::new(500.0, config).take(5000);
+        assert!(a.eq(b));
+    }
+}
@@ -0,0 +1,340 @@
+//! A minimal SNTP (RFC 4330) client.
+//!
+//! The client sends a single request and computes the current time from the server's
+//! reply, compensating for half of the network round trip. Networking is abstracted
+//! by the [`Transport`] trait.
+
+#![cfg_attr(not(test), no_std)]
+#![allow(unknown_lints, async_fn_in_trait)]
+
+#[macro_use]
+extern crate logger;
+
+pub mod transport;
+
+pub use transport::Transport;
+
+/// The well-known NTP server port.
+pub const NTP_PORT: u16 = 123;
+
+const PACKET_SIZE: usize = 48;
+
+/// Seconds between the NTP epoch (1900) and the Unix epoch (1970).
+const UNIX_EPOCH_OFFSET: u64 = 2_208_988_800;
+
+const NTP_VERSION: u8 = 4;
+const MODE_CLIENT: u8 = 3;
+const MODE_SERVER: u8 = 4;
+const LEAP_ALARM: u8 = 3;
+
+/// A 32.32 fixed point NTP timestamp, in seconds since the NTP epoch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct NtpTimestamp(pub u64);
+
+impl NtpTimestamp {
+    pub fn from_unix_micros(micros: u64) -> Self {
+        let secs = micros / 1_000_000 + UNIX_EPOCH_OFFSET;
+        let fraction = ((micros % 1_000_000) << 32) / 1_000_000;
+
+        // Truncating the seconds wraps into the next NTP era, as intended.
+        Self((secs << 32) | fraction)
+    }
+
+    pub fn to_unix_micros(self) -> u64 {
+        let mut secs = self.0 >> 32;
+        // Timestamps with the MSB cleared belong to the era starting in 2036.
+        if secs & 0x8000_0000 == 0 {
+            secs += 1 << 32;
+        }
+        let fraction = self.0 & 0xFFFF_FFFF;
+
+        (secs - UNIX_EPOCH_OFFSET) * 1_000_000 + ((fraction * 1_000_000) >> 32)
+    }
+
+    fn read(bytes: &[u8]) -> Self {
+        let mut raw = [0; 8];
+        raw.copy_from_slice(&bytes[..8]);
+        Self(u64::from_be_bytes(raw))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    Transport(E),
+    /// The server sent a packet that is not a valid SNTP reply.
+    InvalidResponse,
+    /// The server is not synchronized or asked us to stop querying it.
+    Unsynchronized,
+}
+
+/// The result of a successful time query.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TimeSync {
+    /// The local clock's value when the reply was received.
+    pub local_time_us: u64,
+    /// Unix time at `local_time_us`.
+    pub unix_time_us: u64,
+    pub round_trip_us: u64,
+}
+
+impl TimeSync {
+    /// Returns the Unix time for a reading of the local clock.
+    pub fn unix_time_at(&self, local_time_us: u64) -> u64 {
+        (self.unix_time_us + local_time_us).saturating_sub(self.local_time_us)
+    }
+}
+
+enum ReplyError {
+    Malformed,
+    Unrelated,
+    Unsynchronized,
+}
+
+struct Reply {
+    receive: NtpTimestamp,
+    transmit: NtpTimestamp,
+}
+
+impl Reply {
+    fn parse(data: &[u8], origin: NtpTimestamp) -> Result<Self, ReplyError> {
+        if data.len() < PACKET_SIZE {
+            return Err(ReplyError::Malformed);
+        }
+
+        let leap = data[0] >> 6;
+        let version = (data[0] >> 3) & 0x07;
+        let mode = data[0] & 0x07;
+        let stratum = data[1];
+
+        if mode != MODE_SERVER || !(1..=NTP_VERSION).contains(&version) {
+            return Err(ReplyError::Malformed);
+        }
+
+        // The server copies our transmit timestamp into the originate field.
+        if NtpTimestamp::read(&data[24..]) != origin {
+            return Err(ReplyError::Unrelated);
+        }
+
+        // Stratum 0 is a Kiss-o'-Death packet.
+        if leap == LEAP_ALARM || stratum == 0 {
+            return Err(ReplyError::Unsynchronized);
+        }
+
+        let reply = Self {
+            receive: NtpTimestamp::read(&data[32..]),
+            transmit: NtpTimestamp::read(&data[40..]),
+        };
+
+        if reply.transmit.0 == 0 {
+            return Err(ReplyError::Malformed);
+        }
+
+        Ok(reply)
+    }
+}
+
+fn request_packet(transmit: NtpTimestamp) -> [u8; PACKET_SIZE] {
+    let mut packet = [0; PACKET_SIZE];
+    packet[0] = (NTP_VERSION << 3) | MODE_CLIENT;
+    packet[40..].copy_from_slice(&transmit.0.to_be_bytes());
+    packet
+}
+
+/// Queries the current time.
+///
+/// `clock` is a monotonic local clock in microseconds, used to measure the round trip.
+/// This function does not time out on its own; the caller is expected to wrap it in a timeout.
+pub async fn request_time<T: Transport>(
+    transport: &mut T,
+    mut clock: impl FnMut() -> u64,
+) -> Result<TimeSync, Error<T::Error>> {
+    let sent_at = clock();
+
+    // The transmit timestamp is only used to match the reply to our request, so we can use the
+    // local clock instead of the (unknown) wall clock time.
+    let origin = NtpTimestamp(sent_at);
+    transport
+        .send(&request_packet(origin))
+        .await
+        .map_err(Error::Transport)?;
+
+    // Leave some space for optional fields so that they don't cause truncation errors.
+    let mut buffer = [0; 2 * PACKET_SIZE];
+    loop {
+        let len = transport
+            .receive(&mut buffer)
+            .await
+            .map_err(Error::Transport)?;
+        let received_at = clock();
+
+        let reply = match Reply::parse(&buffer[..len], origin) {
+            Ok(reply) => reply,
+            Err(ReplyError::Unrelated) => {
+                debug!("Ignoring unrelated SNTP reply");
+                continue;
+            }
+            Err(ReplyError::Malformed) => return Err(Error::InvalidResponse),
+            Err(ReplyError::Unsynchronized) => return Err(Error::Unsynchronized),
+        };
+
+        let server_receive = reply.receive.to_unix_micros();
+        let server_transmit = reply.transmit.to_unix_micros();
+        let server_processing = server_transmit.saturating_sub(server_receive);
+        let round_trip_us = (received_at - sent_at).saturating_sub(server_processing);
+
+        return Ok(TimeSync {
+            local_time_us: received_at,
+            unix_time_us: server_transmit + round_trip_us / 2,
+            round_trip_us,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        cell::Cell,
+        net::UdpSocket,
+        thread::{self, JoinHandle},
+    };
+
+    use super::*;
+
+    // 2024-03-01 12:00:00.5 UTC
+    const SERVER_TIME_US: u64 = 1_709_294_400_500_000;
+
+    struct StdTransport(UdpSocket);
+
+    impl Transport for StdTransport {
+        type Error = std::io::Error;
+
+        async fn send(&mut self, packet: &[u8]) -> Result<(), Self::Error> {
+            self.0.send(packet).map(|_| ())
+        }
+
+        async fn receive(&mut self, buffer: &mut [u8]) -> Result<usize, Self::Error> {
+            self.0.recv(buffer)
+        }
+    }
+
+    fn reply(request: &[u8], stratum: u8, origin_offset: u64) -> [u8; PACKET_SIZE] {
+        let time = NtpTimestamp::from_unix_micros(SERVER_TIME_US).0;
+
+        let mut reply = [0; PACKET_SIZE];
+        reply[0] = (NTP_VERSION << 3) | MODE_SERVER;
+        reply[1] = stratum;
+        let origin = NtpTimestamp::read(&request[40..]).0 + origin_offset;
+        reply[24..32].copy_from_slice(&origin.to_be_bytes());
+        reply[32..40].copy_from_slice(&time.to_be_bytes());
+        reply[40..48].copy_from_slice(&time.to_be_bytes());
+        reply
+    }
+
+    /// Starts a local stand-in server that answers a single request with the given replies.
+    fn stand_in_server(
+        replies: impl FnOnce(&[u8]) -> Vec<[u8; PACKET_SIZE]> + Send + 'static,
+    ) -> (StdTransport, JoinHandle<()>) {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.connect(server.local_addr().unwrap()).unwrap();
+
+        let handle = thread::spawn(move || {
+            let mut request = [0; 128];
+            let (len, client) = server.recv_from(&mut request).unwrap();
+            assert_eq!(len, PACKET_SIZE);
+            assert_eq!(request[0], 0x23);
+
+            for reply in replies(&request[..len]) {
+                server.send_to(&reply, client).unwrap();
+            }
+        });
+
+        (StdTransport(client), handle)
+    }
+
+    fn query(transport: &mut StdTransport) -> Result<TimeSync, Error<std::io::Error>> {
+        // The local clock advances 40ms between sending and receiving.
+        let clock = Cell::new(1_000_000);
+        let clock = || clock.replace(clock.get() + 40_000);
+
+        embassy_futures::block_on(request_time(transport, clock))
+    }
+
+    #[test]
+    fn timestamp_roundtrip() {
+        for micros in [
+            0,
+            1_000_000,
+            SERVER_TIME_US,
+            2_085_978_496_000_000 + 123_456,
+        ] {
+            let timestamp = NtpTimestamp::from_unix_micros(micros);
+            let error = micros.abs_diff(timestamp.to_unix_micros());
+            assert!(error <= 1, "{micros}: {error}");
+        }
+    }
+
+    #[test]
+    fn timestamp_era_rollover() {
+        // 2036-02-07 06:28:16 UTC is the start of NTP era 1.
+        assert_eq!(NtpTimestamp(0).to_unix_micros(), 2_085_978_496_000_000);
+        assert_eq!(
+            NtpTimestamp::from_unix_micros(2_085_978_496_000_000),
+            NtpTimestamp(0)
+        );
+    }
+
+    #[test]
+    fn query_compensates_round_trip() {
+        let (mut transport, server) = stand_in_server(|request| vec![reply(request, 1, 0)]);
+
+        let sync = query(&mut transport).unwrap();
+        server.join().unwrap();
+
+        assert_eq!(sync.local_time_us, 1_040_000);
+        assert_eq!(sync.round_trip_us, 40_000);
+        assert!(sync.unix_time_us.abs_diff(SERVER_TIME_US + 20_000) <= 1);
+        let later = sync.unix_time_at(2_040_000);
+        assert!(later.abs_diff(SERVER_TIME_US + 1_020_000) <= 1);
+    }
+
+    #[test]
+    fn unrelated_replies_are_ignored() {
+        let (mut transport, server) =
+            stand_in_server(|request| vec![reply(request, 1, 1), reply(request, 2, 0)]);
+
+        let sync = query(&mut transport).unwrap();
+        server.join().unwrap();
+
+        // The unrelated reply was received 40ms after sending, the valid one 40ms later.
+        assert_eq!(sync.round_trip_us, 80_000);
+        assert!(sync.unix_time_us.abs_diff(SERVER_TIME_US + 40_000) <= 1);
+    }
+
+    #[test]
+    fn kiss_of_death_is_an_error() {
+        let (mut transport, server) = stand_in_server(|request| vec![reply(request, 0, 0)]);
+
+        let result = query(&mut transport);
+        server.join().unwrap();
+
+        assert!(matches!(result, Err(Error::Unsynchronized)));
+    }
+
+    #[test]
+    fn malformed_reply_is_an_error() {
+        let (mut transport, server) = stand_in_server(|request| {
+            let mut reply = reply(request, 1, 0);
+            reply[0] = (NTP_VERSION << 3) | MODE_CLIENT;
+            vec![reply]
+        });
+
+        let result = query(&mut transport);
+        server.join().unwrap();
+
+        assert!(matches!(result, Err(Error::InvalidResponse)));
+    }
+}
@@ -0,0 +1,69 @@
+use core::fmt::Debug;
+
+/// A datagram socket connected to a single time server.
+pub trait Transport {
+    #[cfg(feature = "defmt")]
+    type Error: Debug + defmt::Format;
+    #[cfg(not(feature = "defmt"))]
+    type Error: Debug;
+
+    async fn send(&mut self, packet: &[u8]) -> Result<(), Self::Error>;
+
+    /// Receives a single datagram from the server and returns its length.
+    async fn receive(&mut self, buffer: &mut [u8]) -> Result<usize, Self::Error>;
+}
+
+#[cfg(feature = "embassy")]
+pub mod embassy_net_compat {
+    use super::*;
+    use embassy_net::{
+        udp::{RecvError, SendError, UdpSocket},
+        IpEndpoint,
+    };
+
+    pub struct UdpTransport<'a> {
+        socket: UdpSocket<'a>,
+        server: IpEndpoint,
+    }
+
+    impl<'a> UdpTransport<'a> {
+        /// Creates a new transport. The socket must already be bound.
+        pub fn new(socket: UdpSocket<'a>, server: IpEndpoint) -> Self {
+            Self { socket, server }
+        }
+    }
+
+    #[derive(Debug)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub enum UdpError {
+        Send(SendError),
+        Receive(RecvError),
+    }
+
+    impl Transport for UdpTransport<'_> {
+        type Error = UdpError;
+
+        async fn send(&mut self, packet: &[u8]) -> Result<(), Self::Error> {
+            self.socket
+                .send_to(packet, self.server)
+                .await
+                .map_err(UdpError::Send)
+        }
+
+        async fn receive(&mut self, buffer: &mut [u8]) -> Result<usize, Self::Error> {
+            loop {
+                let (len, meta) = self
+                    .socket
+                    .recv_from(buffer)
+                    .await
+                    .map_err(UdpError::Receive)?;
+
+                if meta.endpoint == self.server {
+                    return Ok(len);
+                }
+
+                debug!("Ignoring datagram from {:?}", meta.endpoint);
+            }
+        }
+    }
+}
@@ -2,12 +2,16 @@ use config_site::data::network::WifiNetwork;
 use embedded_io_async::{Read, Write};
 use gui::widgets::battery_small::BatteryStyle;
 use norfs::storable::{LoadError, Loadable, Storable};
+use signal_processing::{ecg::SampleRate, mains::MainsFrequency};
 use ssd1306::prelude::Brightness;
 
 use crate::board::DEFAULT_BACKEND_URL;
 
 use super::{
-    types::{DisplayBrightness, FilterStrength, MeasurementAction},
+    types::{
+        DisplayBrightness, FilterStrength, LiveStream, MeasurementAction, PowerLineFrequency,
+        RecordedChannels, SamplingRate,
+    },
     CURRENT_VERSION,
 };
 
@@ -19,21 +23,25 @@ pub struct Config {
     pub filter_strength: FilterStrength,
     pub backend_url: heapless::String<64>,
     pub measurement_action: MeasurementAction,
+    pub recorded_channels: RecordedChannels,
+    pub power_line_frequency: PowerLineFrequency,
+    pub sampling_rate: SamplingRate,
+    pub live_stream: LiveStream,
 }
 
-impl From<super::v4::Config> for Config {
-    fn from(value: super::v4::Config) -> Self {
+impl From<super::v8::Config> for Config {
+    fn from(value: super::v8::Config) -> Self {
         Self {
             battery_display_style: value.battery_display_style,
             display_brightness: value.display_brightness,
             known_networks: value.known_networks,
             filter_strength: value.filter_strength,
             backend_url: value.backend_url,
-            measurement_action: if value.store_measurement {
-                MeasurementAction::Auto
-            } else {
-                MeasurementAction::Upload
-            },
+            measurement_action: value.measurement_action,
+            recorded_channels: value.recorded_channels,
+            power_line_frequency: value.power_line_frequency,
+            sampling_rate: value.sampling_rate,
+            live_stream: LiveStream::Off,
         }
     }
 }
@@ -48,6 +56,10 @@ impl Default for Config {
             filter_strength: FilterStrength::Weak,
             backend_url: heapless::String::try_from(DEFAULT_BACKEND_URL).unwrap(),
             measurement_action: MeasurementAction::Auto,
+            recorded_channels: RecordedChannels::Ch1,
+            power_line_frequency: PowerLineFrequency::Auto,
+            sampling_rate: SamplingRate::Sps1000,
+            live_stream: LiveStream::Off,
         }
     }
 }
@@ -70,6 +82,24 @@ impl Config {
     pub fn filter_strength(&self) -> FilterStrength {
         self.filter_strength
     }
+
+    /// The power line frequency to filter, or `None` if it should be detected.
+    pub fn mains_frequency(&self) -> Option<MainsFrequency> {
+        match self.power_line_frequency {
+            PowerLineFrequency::Auto => None,
+            PowerLineFrequency::Hz50 => Some(MainsFrequency::Hz50),
+            PowerLineFrequency::Hz60 => Some(MainsFrequency::Hz60),
+        }
+    }
+
+    pub fn sample_rate(&self) -> SampleRate {
+        match self.sampling_rate {
+            SamplingRate::Sps250 => SampleRate::Sps250,
+            SamplingRate::Sps500 => SampleRate::Sps500,
+            SamplingRate::Sps1000 => SampleRate::Sps1000,
+            SamplingRate::Sps2000 => SampleRate::Sps2000,
+        }
+    }
 }
 
 impl Loadable for Config {
@@ -81,6 +111,10 @@ impl Loadable for Config {
             filter_strength: FilterStrength::load(reader).await?,
             backend_url: heapless::String::load(reader).await?,
             measurement_action: MeasurementAction::load(reader).await?,
+            recorded_channels: RecordedChannels::load(reader).await?,
+            power_line_frequency: PowerLineFrequency::load(reader).await?,
+            sampling_rate: SamplingRate::load(reader).await?,
+            live_stream: LiveStream::load(reader).await?,
         };
 
         Ok(data)
@@ -97,6 +131,10 @@ impl Storable for Config {
         self.filter_strength.store(writer).await?;
         self.backend_url.store(writer).await?;
         self.measurement_action.store(writer).await?;
+        self.recorded_channels.store(writer).await?;
+        self.power_line_frequency.store(writer).await?;
+        self.sampling_rate.store(writer).await?;
+        self.live_stream.store(writer).await?;
 
         Ok(())
     }
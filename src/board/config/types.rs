@@ -37,6 +37,8 @@ pub enum FilterStrength {
     None = 0,
     Weak = 1,
     Strong = 2,
+    /// Median filter based baseline removal, which doesn't distort the ST segment.
+    Median = 3,
 }
 
 impl Loadable for FilterStrength {
@@ -45,6 +47,7 @@ impl Loadable for FilterStrength {
             0 => Self::None,
             1 => Self::Weak,
             2 => Self::Strong,
+            3 => Self::Median,
             _ => return Err(LoadError::InvalidValue),
         };
 
@@ -87,3 +90,174 @@ impl Storable for MeasurementAction {
         writer.write_all(&[*self as u8]).await
     }
 }
+
+/// The ADC channels saved in a measurement. Channel 1 is always recorded.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, SelectValue)]
+pub enum RecordedChannels {
+    Ch1 = 0,
+    Both = 1,
+    /// Respiration on channel 1 and ECG on channel 2. Falls back to `Both` on devices other than
+    /// the ADS1292R.
+    Respiration = 2,
+}
+
+impl Loadable for RecordedChannels {
+    async fn load<R: Read>(reader: &mut R) -> Result<Self, LoadError<R::Error>> {
+        let data = match u8::load(reader).await? {
+            0 => Self::Ch1,
+            1 => Self::Both,
+            2 => Self::Respiration,
+            _ => return Err(LoadError::InvalidValue),
+        };
+
+        Ok(data)
+    }
+}
+
+impl Storable for RecordedChannels {
+    async fn store<W: Write>(&self, writer: &mut W) -> Result<(), W::Error> {
+        writer.write_all(&[*self as u8]).await
+    }
+}
+
+/// The power line frequency the ECG is filtered for.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PowerLineFrequency {
+    /// Estimated from the first second of each measurement, 50 Hz if it is not clear.
+    Auto = 0,
+    Hz50 = 1,
+    Hz60 = 2,
+}
+
+impl embedded_menu::items::menu_item::SelectValue for PowerLineFrequency {
+    fn next(&mut self) {
+        *self = match self {
+            Self::Auto => Self::Hz50,
+            Self::Hz50 => Self::Hz60,
+            Self::Hz60 => Self::Auto,
+        };
+    }
+
+    fn marker(&self) -> &'static str {
+        match self {
+            Self::Auto => "Auto",
+            Self::Hz50 => "50 Hz",
+            Self::Hz60 => "60 Hz",
+        }
+    }
+}
+
+impl Loadable for PowerLineFrequency {
+    async fn load<R: Read>(reader: &mut R) -> Result<Self, LoadError<R::Error>> {
+        let data = match u8::load(reader).await? {
+            0 => Self::Auto,
+            1 => Self::Hz50,
+            2 => Self::Hz60,
+            _ => return Err(LoadError::InvalidValue),
+        };
+
+        Ok(data)
+    }
+}
+
+impl Storable for PowerLineFrequency {
+    async fn store<W: Write>(&self, writer: &mut W) -> Result<(), W::Error> {
+        writer.write_all(&[*self as u8]).await
+    }
+}
+
+/// The rate the ECG is sampled at. Lower rates save storage and power.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SamplingRate {
+    Sps250 = 0,
+    Sps500 = 1,
+    Sps1000 = 2,
+    Sps2000 = 3,
+}
+
+impl embedded_menu::items::menu_item::SelectValue for SamplingRate {
+    fn next(&mut self) {
+        *self = match self {
+            Self::Sps250 => Self::Sps500,
+            Self::Sps500 => Self::Sps1000,
+            Self::Sps1000 => Self::Sps2000,
+            Self::Sps2000 => Self::Sps250,
+        };
+    }
+
+    fn marker(&self) -> &'static str {
+        match self {
+            Self::Sps250 => "250 sps",
+            Self::Sps500 => "500 sps",
+            Self::Sps1000 => "1000 sps",
+            Self::Sps2000 => "2000 sps",
+        }
+    }
+}
+
+impl Loadable for SamplingRate {
+    async fn load<R: Read>(reader: &mut R) -> Result<Self, LoadError<R::Error>> {
+        let data = match u8::load(reader).await? {
+            0 => Self::Sps250,
+            1 => Self::Sps500,
+            2 => Self::Sps1000,
+            3 => Self::Sps2000,
+            _ => return Err(LoadError::InvalidValue),
+        };
+
+        Ok(data)
+    }
+}
+
+impl Storable for SamplingRate {
+    async fn store<W: Write>(&self, writer: &mut W) -> Result<(), W::Error> {
+        writer.write_all(&[*self as u8]).await
+    }
+}
+
+/// What is streamed to the local network during a measurement.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LiveStream {
+    Off = 0,
+    /// The recorded channels, as ADC codes.
+    Raw = 1,
+    /// The ECG lead, after the ECG filter.
+    Filtered = 2,
+}
+
+impl embedded_menu::items::menu_item::SelectValue for LiveStream {
+    fn next(&mut self) {
+        *self = match self {
+            Self::Off => Self::Raw,
+            Self::Raw => Self::Filtered,
+            Self::Filtered => Self::Off,
+        };
+    }
+
+    fn marker(&self) -> &'static str {
+        match self {
+            Self::Off => "Off",
+            Self::Raw => "Raw",
+            Self::Filtered => "Filtered",
+        }
+    }
+}
+
+impl Loadable for LiveStream {
+    async fn load<R: Read>(reader: &mut R) -> Result<Self, LoadError<R::Error>> {
+        let data = match u8::load(reader).await? {
+            0 => Self::Off,
+            1 => Self::Raw,
+            2 => Self::Filtered,
+            _ => return Err(LoadError::InvalidValue),
+        };
+
+        Ok(data)
+    }
+}
+
+impl Storable for LiveStream {
+    async fn store<W: Write>(&self, writer: &mut W) -> Result<(), W::Error> {
+        writer.write_all(&[*self as u8]).await
+    }
+}
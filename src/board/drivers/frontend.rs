@@ -5,6 +5,35 @@ use embedded_hal::digital::{InputPin, OutputPin};
 use embedded_hal_async::{digital::Wait, spi::SpiDevice as AsyncSpiDevice};
 use register_access::AsyncRegisterAccess;
 
+/// PGA gain of the ECG channels.
+pub const PGA_GAIN: Gain = Gain::X1;
+
+/// The ADC reference voltage.
+pub const REFERENCE_VOLTAGE: ReferenceVoltage = ReferenceVoltage::_2_42V;
+
+/// Returns the amplification of the PGA gain setting.
+pub const fn gain_multiplier(gain: Gain) -> u8 {
+    match gain {
+        Gain::X1 => 1,
+        Gain::X2 => 2,
+        Gain::X3 => 3,
+        Gain::X4 => 4,
+        Gain::X6 => 6,
+        Gain::X8 => 8,
+        Gain::X12 => 12,
+    }
+}
+
+/// Returns the voltage of an internal reference setting in millivolts, `None` if the reference is
+/// external.
+pub const fn reference_millivolts(reference: ReferenceVoltage) -> Option<u16> {
+    match reference {
+        ReferenceVoltage::External => None,
+        ReferenceVoltage::_2_42V => Some(2420),
+        ReferenceVoltage::_4_033V => Some(4033),
+    }
+}
+
 pub struct Frontend<S, DRDY, RESET, CLKEN, TOUCH> {
     adc: Ads129x<S>,
     drdy: DRDY,
@@ -12,6 +41,9 @@ pub struct Frontend<S, DRDY, RESET, CLKEN, TOUCH> {
     clken: CLKEN,
     touch: TOUCH,
     device_id: Option<DeviceId>,
+    ch2_enabled: bool,
+    respiration_requested: bool,
+    data_rate: DataRate,
 }
 
 impl<S, DRDY, RESET, CLKEN, TOUCH> Frontend<S, DRDY, RESET, CLKEN, TOUCH> {
@@ -23,6 +55,9 @@ impl<S, DRDY, RESET, CLKEN, TOUCH> Frontend<S, DRDY, RESET, CLKEN, TOUCH> {
             clken,
             touch,
             device_id: None,
+            ch2_enabled: false,
+            respiration_requested: false,
+            data_rate: DataRate::_1ksps,
         }
     }
 
@@ -33,6 +68,34 @@ impl<S, DRDY, RESET, CLKEN, TOUCH> Frontend<S, DRDY, RESET, CLKEN, TOUCH> {
     pub fn device_id(&self) -> Option<DeviceId> {
         self.device_id
     }
+
+    /// Enables sampling the second channel. Takes effect the next time the frontend is enabled.
+    pub fn set_ch2_enabled(&mut self, enabled: bool) {
+        self.ch2_enabled = enabled;
+    }
+
+    /// Sets the sample rate. Takes effect the next time the frontend is enabled.
+    pub fn set_data_rate(&mut self, data_rate: DataRate) {
+        self.data_rate = data_rate;
+    }
+
+    /// Requests respiration measurement. Takes effect the next time the frontend is enabled, if
+    /// the device turns out to be an ADS1292R.
+    ///
+    /// The ADS1292R modulates the IN1 electrodes and demodulates the respiration signal on
+    /// channel 1, so the ECG has to be read from channel 2, which is enabled automatically.
+    pub fn set_respiration_enabled(&mut self, enabled: bool) {
+        self.respiration_requested = enabled;
+    }
+
+    /// Returns whether respiration is measured on channel 1.
+    pub fn respiration_enabled(&self) -> bool {
+        self.respiration_requested && self.device_id == Some(DeviceId::ADS1292R)
+    }
+
+    fn ch2_active(&self) -> bool {
+        self.ch2_enabled || self.respiration_enabled()
+    }
 }
 
 impl<S, DRDY, RESET, CLKEN, TOUCH> Frontend<S, DRDY, RESET, CLKEN, TOUCH>
@@ -44,17 +107,23 @@ where
 {
     #[rustfmt::skip]
     fn config(&self) -> ConfigRegisters {
+        let ch2_lead_off = if self.ch2_active() {
+            Input::Connected
+        } else {
+            Input::NotConnected
+        };
+
         ConfigRegisters {
             config1: Config1::new(|r| {
                 r
-                .data_rate().write(DataRate::_1ksps)
+                .data_rate().write(self.data_rate)
                 .sampling().write(Sampling::Continuous)
             }),
 
             config2: Config2::new(|r| {
                 r
                 .pdb_loff_comp().write(Buffer::Enabled)
-                .ref_voltage().write(ReferenceVoltage::_2_42V)
+                .ref_voltage().write(REFERENCE_VOLTAGE)
                 .clock_pin().write(ClockPin::Disabled)
                 .test_signal().write(TestSignal::Disabled)
             }),
@@ -69,15 +138,22 @@ where
             ch1set: Ch1Set::new(|r| {
                 r
                 .enabled().write(Channel::Enabled)
-                .gain().write(Gain::X1)
+                .gain().write(PGA_GAIN)
                 .mux().write(Ch1Mux::Normal)
             }),
 
             ch2set: Ch2Set::new(|r| {
-                r
-                .enabled().write(Channel::PowerDown)
-                .gain().write(Gain::X1)
-                .mux().write(Ch2Mux::Shorted)
+                if self.ch2_active() {
+                    r
+                    .enabled().write(Channel::Enabled)
+                    .gain().write(PGA_GAIN)
+                    .mux().write(Ch2Mux::Normal)
+                } else {
+                    r
+                    .enabled().write(Channel::PowerDown)
+                    .gain().write(PGA_GAIN)
+                    .mux().write(Ch2Mux::Shorted)
+                }
             }),
 
             rldsens: RldSens::new(|r| {
@@ -95,16 +171,33 @@ where
                 r
                 .flip2().write(CurrentDirection::Normal)
                 .flip1().write(CurrentDirection::Normal)
-                .loff2n().write(Input::NotConnected)
-                .loff2p().write(Input::NotConnected)
+                .loff2n().write(ch2_lead_off)
+                .loff2p().write(ch2_lead_off)
                 .loff1n().write(Input::Connected)
                 .loff1p().write(Input::Connected)
             }),
 
             loffstat: LoffStat::new(|r| r.clk_div().write(ClockDivider::External512kHz)),
 
-            resp1: Resp1::default(),
-            resp2: Resp2::new(|r| r.rld_reference().write(RldReference::MidSupply)),
+            resp1: if self.respiration_enabled() {
+                Resp1::new(|r| {
+                    r
+                    .demod_en().write(Respiration::Enabled)
+                    .mod_en().write(Respiration::Enabled)
+                    .phase().write(Phase::_112deg)
+                    .clock().write(RespirationClock::Internal)
+                })
+            } else {
+                Resp1::default()
+            },
+            resp2: Resp2::new(|r| {
+                let r = if self.respiration_enabled() {
+                    r.frequency().write(RespirationFrequency::_32kHz)
+                } else {
+                    r
+                };
+                r.rld_reference().write(RldReference::MidSupply)
+            }),
 
             gpio: Gpio::new(|r| {
                 r
@@ -167,6 +260,14 @@ where
     pub fn spi_mut(&mut self) -> &mut S {
         self.frontend.spi_mut()
     }
+
+    pub fn device_id(&self) -> Option<DeviceId> {
+        self.frontend.device_id()
+    }
+
+    pub fn respiration_enabled(&self) -> bool {
+        self.frontend.respiration_enabled()
+    }
 }
 
 impl<S, DRDY, RESET, CLKEN, TOUCH> PoweredFrontend<S, DRDY, RESET, CLKEN, TOUCH>
@@ -245,7 +346,11 @@ where
     {
         unwrap!(self.frontend.drdy.wait_for_falling_edge().await.ok());
 
-        let sample = self.frontend.adc.read_data_1ch_async().await?;
+        let sample = if self.frontend.ch2_active() {
+            self.frontend.adc.read_data_2ch_async().await?
+        } else {
+            self.frontend.adc.read_data_1ch_async().await?
+        };
         self.touched = sample.ch1_negative_lead_connected();
 
         Ok(sample)
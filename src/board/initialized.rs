@@ -176,7 +176,6 @@ impl InnerContext {
         Some(sta)
     }
 
-    #[allow(unused)]
     pub async fn enable_wifi_ap(&mut self) -> Option<Ap> {
         if !self.can_enable_wifi() {
             self.wifi.stop_if().await;
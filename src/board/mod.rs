@@ -18,6 +18,7 @@ pub mod ota;
 pub mod startup;
 pub mod storage;
 pub mod utils;
+pub mod wall_clock;
 pub mod wifi;
 
 #[cfg(feature = "esp-println")]
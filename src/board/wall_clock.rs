@@ -0,0 +1,52 @@
+//! Wall-clock time.
+//!
+//! The time is synchronized over SNTP when the device connects to a network. The RTC keeps
+//! counting during deep sleep, so we store the time there before going to sleep and read it
+//! back after waking up.
+
+use core::cell::Cell;
+
+use critical_section::Mutex;
+use embassy_time::Instant;
+use esp_hal::rtc_cntl::Rtc;
+
+/// RTC times before 2024-01-01 mean the clock was never set and counts from power-on.
+const MIN_VALID_UNIX_TIME_US: u64 = 1_704_067_200 * 1_000_000;
+
+/// Unix time in microseconds at `Instant` zero, if known.
+static BOOT_TIME_US: Mutex<Cell<Option<u64>>> = Mutex::new(Cell::new(None));
+
+/// Sets the current time from the RTC, if the RTC has been set before.
+pub fn restore(rtc: &Rtc) {
+    let rtc_time_us = rtc.current_time_us();
+    if rtc_time_us < MIN_VALID_UNIX_TIME_US {
+        debug!("RTC time is not set");
+        return;
+    }
+
+    set(Instant::now(), rtc_time_us);
+}
+
+/// Stores the current time in the RTC, to keep it across deep sleep.
+pub fn persist(rtc: &Rtc) {
+    if let Some(unix_time_us) = unix_time_us_at(Instant::now()) {
+        rtc.set_current_time_us(unix_time_us);
+    }
+}
+
+/// Sets the wall-clock time `unix_time_us` corresponding to `instant`.
+pub fn set(instant: Instant, unix_time_us: u64) {
+    let boot_time_us = unix_time_us.saturating_sub(instant.as_micros());
+    critical_section::with(|cs| BOOT_TIME_US.borrow(cs).set(Some(boot_time_us)));
+}
+
+fn unix_time_us_at(instant: Instant) -> Option<u64> {
+    let boot_time_us = critical_section::with(|cs| BOOT_TIME_US.borrow(cs).get())?;
+
+    Some(boot_time_us + instant.as_micros())
+}
+
+/// Returns the Unix time in seconds at `instant`, if known.
+pub fn unix_time_at(instant: Instant) -> Option<u64> {
+    unix_time_us_at(instant).map(|us| us / 1_000_000)
+}
@@ -28,11 +28,14 @@ macro_rules! mk_static {
     }};
 }
 
-const STACK_SOCKET_COUNT: usize = 3;
+/// DHCP, DNS and time synchronization, plus a live stream and a connection of the current app
+/// state.
+const STACK_SOCKET_COUNT: usize = 4;
 
 pub mod ap;
 pub mod ap_sta;
 pub mod sta;
+pub mod stream;
 
 pub struct WifiDriver {
     rng: Rng,
@@ -181,7 +184,6 @@ impl WifiDriver {
         }
     }
 
-    #[allow(unused)]
     pub async fn configure_ap(&mut self, ap_config: Config) -> Ap {
         // Prepare, stop STA if running
         if !matches!(self.state, WifiDriverState::Ap(_, _)) {
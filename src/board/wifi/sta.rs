@@ -1,7 +1,7 @@
 use core::{alloc::AllocError, ptr::addr_of, sync::atomic::Ordering};
 
 use crate::{
-    board::initialized::Context,
+    board::{initialized::Context, wall_clock},
     task_control::{TaskControlToken, TaskController},
     Shared,
 };
@@ -9,14 +9,18 @@ use alloc::{boxed::Box, rc::Rc, vec::Vec};
 use config_site::data::network::WifiNetwork;
 use embassy_executor::Spawner;
 use embassy_futures::select::{select, Either};
-use embassy_net::{dns::DnsSocket, Stack};
+use embassy_net::{
+    dns::{DnsQueryType, DnsSocket},
+    udp::{PacketMetadata, UdpSocket},
+    IpEndpoint, Stack,
+};
 use embassy_sync::{
     blocking_mutex::raw::NoopRawMutex,
     channel::Channel,
     mutex::{Mutex, MutexGuard},
     signal::Signal,
 };
-use embassy_time::{with_timeout, Duration};
+use embassy_time::{with_timeout, Duration, Instant};
 use enumset::EnumSet;
 use esp_hal::rng::Rng;
 use esp_wifi::wifi::{
@@ -26,6 +30,7 @@ use gui::widgets::wifi_client::WifiClientState;
 use heapless::String;
 use macros as cardio;
 use reqwless::client::{HttpClient, TlsConfig, TlsVerify};
+use sntp::transport::embassy_net_compat::UdpTransport;
 
 pub(super) const SCAN_RESULTS: usize = 20;
 
@@ -109,6 +114,10 @@ impl Sta {
         self.state.read().into()
     }
 
+    pub fn stack(&self) -> Stack<'static> {
+        self.sta_stack.clone()
+    }
+
     pub async fn visible_networks(
         &self,
     ) -> MutexGuard<'_, NoopRawMutex, heapless::Vec<AccessPointInfo, SCAN_RESULTS>> {
@@ -341,6 +350,7 @@ enum StaControllerState {
     ScanAndConnect,
     Connect(u8),    // select network, start connection
     AutoConnecting, // waiting for IP
+    SyncTime,       // query SNTP server
     AutoConnected,  // wait for disconnection
 }
 
@@ -350,6 +360,9 @@ const CONTINUE: Duration = Duration::from_millis(0);
 const CONNECT_RETRY_PERIOD: Duration = Duration::from_millis(100);
 const CONNECT_RETRY_COUNT: u8 = 5;
 
+const NTP_SERVER: &str = "pool.ntp.org";
+const NTP_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub enum StaCommand {
     ScanOnce,
 }
@@ -522,9 +535,57 @@ impl StaController {
         }
     }
 
+    async fn sync_time(&self) {
+        let addresses = match self.stack.dns_query(NTP_SERVER, DnsQueryType::A).await {
+            Ok(addresses) => addresses,
+            Err(e) => {
+                warn!("Failed to resolve {}: {:?}", NTP_SERVER, e);
+                return;
+            }
+        };
+        let Some(&address) = addresses.first() else {
+            warn!("No address for {}", NTP_SERVER);
+            return;
+        };
+
+        let mut rx_meta = [PacketMetadata::EMPTY; 1];
+        let mut rx_buffer = [0; 128];
+        let mut tx_meta = [PacketMetadata::EMPTY; 1];
+        let mut tx_buffer = [0; 128];
+        let mut socket = UdpSocket::new(
+            self.stack,
+            &mut rx_meta,
+            &mut rx_buffer,
+            &mut tx_meta,
+            &mut tx_buffer,
+        );
+        if let Err(e) = socket.bind(0) {
+            warn!("Failed to bind SNTP socket: {:?}", e);
+            return;
+        }
+
+        let mut transport = UdpTransport::new(socket, IpEndpoint::new(address, sntp::NTP_PORT));
+        let clock = || Instant::now().as_micros();
+
+        match with_timeout(NTP_TIMEOUT, sntp::request_time(&mut transport, clock)).await {
+            Ok(Ok(sync)) => {
+                info!(
+                    "Time synchronized: {}s (round trip {}us)",
+                    sync.unix_time_us / 1_000_000,
+                    sync.round_trip_us
+                );
+                wall_clock::set(Instant::from_micros(sync.local_time_us), sync.unix_time_us);
+            }
+            Ok(Err(e)) => warn!("Failed to synchronize time: {:?}", e),
+            Err(_) => warn!("Time synchronization timed out"),
+        }
+    }
+
     pub fn events(&self) -> EnumSet<WifiEvent> {
         match self.controller_state {
-            StaControllerState::AutoConnecting | StaControllerState::AutoConnected => {
+            StaControllerState::AutoConnecting
+            | StaControllerState::SyncTime
+            | StaControllerState::AutoConnected => {
                 enumset::enum_set! { WifiEvent::StaStop | WifiEvent::StaDisconnected }
             }
             StaControllerState::Idle
@@ -541,7 +602,9 @@ impl StaController {
         }
 
         match self.controller_state {
-            StaControllerState::AutoConnecting | StaControllerState::AutoConnected => {
+            StaControllerState::AutoConnecting
+            | StaControllerState::SyncTime
+            | StaControllerState::AutoConnected => {
                 if events.contains(WifiEvent::StaDisconnected) {
                     self.state.update(InternalConnectionState::Disconnected);
                     self.controller_state = StaControllerState::ScanAndConnect;
@@ -615,6 +678,12 @@ impl StaController {
 
                 info!("Got IP: {}", config.address);
                 self.state.update(InternalConnectionState::Connected);
+                self.controller_state = StaControllerState::SyncTime;
+                CONTINUE
+            }
+
+            StaControllerState::SyncTime => {
+                self.sync_time().await;
                 self.controller_state = StaControllerState::AutoConnected;
                 CONTINUE
             }
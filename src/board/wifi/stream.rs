@@ -0,0 +1,167 @@
+use alloc::rc::Rc;
+use embassy_executor::Spawner;
+use embassy_futures::select::{select, Either};
+use embassy_net::{
+    udp::{PacketMetadata, UdpSocket},
+    IpAddress, IpEndpoint, Ipv4Address, Stack,
+};
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel};
+use embassy_time::{Duration, Instant};
+use macros as cardio;
+use protocol::stream::{
+    is_subscription, MAX_PACKET_LEN, STREAM_PORT, SUBSCRIPTION_LEN, SUBSCRIPTION_TIMEOUT_S,
+};
+
+use crate::task_control::{TaskControlToken, TaskController};
+
+/// The number of packets that may wait to be sent.
+const QUEUE_LEN: usize = 4;
+
+type Packet = heapless::Vec<u8, MAX_PACKET_LEN>;
+type PacketQueue = Channel<NoopRawMutex, Packet, QUEUE_LEN>;
+
+/// Where the stream packets go.
+#[derive(Clone, Copy, PartialEq)]
+enum Destination {
+    /// Every device on the network. Only used on the device's own access point.
+    Broadcast,
+    /// The receiver that sent the last subscription, and when it did.
+    Subscriber(IpEndpoint, Instant),
+    /// No receiver asked for the stream yet.
+    Unsubscribed,
+}
+
+impl Destination {
+    fn endpoint(&self) -> Option<IpEndpoint> {
+        match *self {
+            Destination::Broadcast => Some(IpEndpoint::new(
+                IpAddress::Ipv4(Ipv4Address::BROADCAST),
+                STREAM_PORT,
+            )),
+            Destination::Subscriber(endpoint, since) => {
+                (since.elapsed() < Duration::from_secs(SUBSCRIPTION_TIMEOUT_S)).then_some(endpoint)
+            }
+            Destination::Unsubscribed => None,
+        }
+    }
+}
+
+/// Sends live stream packets to the local network. On the device's own access point, the packets
+/// are broadcast. Otherwise, they are sent to the receiver that subscribed last.
+pub struct StreamSender {
+    queue: Rc<PacketQueue>,
+    task_control: TaskController<()>,
+}
+
+impl StreamSender {
+    /// Starts sending packets. `broadcast` should only be set on the device's own access point.
+    pub async fn start(stack: Stack<'static>, broadcast: bool) -> Self {
+        let queue = Rc::new(PacketQueue::new());
+        let task_control = TaskController::new();
+
+        let destination = if broadcast {
+            Destination::Broadcast
+        } else {
+            Destination::Unsubscribed
+        };
+
+        let spawner = Spawner::for_current_executor().await;
+        spawner.must_spawn(stream_task(
+            stack,
+            queue.clone(),
+            destination,
+            task_control.token(),
+        ));
+
+        Self {
+            queue,
+            task_control,
+        }
+    }
+
+    /// Queues a packet. Packets are dropped if the network can't keep up with the samples.
+    pub fn send(&self, packet: &[u8]) {
+        let Ok(packet) = Packet::from_slice(packet) else {
+            return;
+        };
+
+        if self.queue.try_send(packet).is_err() {
+            debug!("Stream packet dropped");
+        }
+    }
+
+    pub async fn stop(self) {
+        let _ = self.task_control.stop().await;
+    }
+}
+
+#[cardio::task]
+async fn stream_task(
+    stack: Stack<'static>,
+    queue: Rc<PacketQueue>,
+    mut destination: Destination,
+    mut task_control: TaskControlToken<()>,
+) {
+    task_control
+        .run_cancellable(|_| async {
+            let mut rx_meta = [PacketMetadata::EMPTY; 1];
+            let mut rx_buffer = [0; 16];
+            let mut tx_meta = [PacketMetadata::EMPTY; QUEUE_LEN];
+            let mut tx_buffer = [0; QUEUE_LEN * MAX_PACKET_LEN];
+            let mut socket = UdpSocket::new(
+                stack,
+                &mut rx_meta,
+                &mut rx_buffer,
+                &mut tx_meta,
+                &mut tx_buffer,
+            );
+            if let Err(e) = socket.bind(STREAM_PORT) {
+                warn!("Failed to bind stream socket: {:?}", e);
+                return;
+            }
+
+            if destination == Destination::Broadcast {
+                info!("Broadcasting to port {}", STREAM_PORT);
+            } else {
+                info!("Waiting for a stream subscription on port {}", STREAM_PORT);
+            }
+
+            // Packets can't be sent until the network is up, only report the first failure.
+            let mut reported = false;
+            let mut subscription = [0; SUBSCRIPTION_LEN];
+            loop {
+                let event = select(queue.receive(), socket.recv_from(&mut subscription)).await;
+                match event {
+                    Either::First(packet) => {
+                        // Packets are dropped while nobody listens.
+                        let Some(remote) = destination.endpoint() else {
+                            continue;
+                        };
+                        match socket.send_to(&packet, remote).await {
+                            Ok(()) => reported = false,
+                            Err(e) if !reported => {
+                                warn!("Failed to send stream packet: {:?}", e);
+                                reported = true;
+                            }
+                            Err(_) => {}
+                        }
+                    }
+                    Either::Second(Ok((len, meta))) => {
+                        if destination == Destination::Broadcast
+                            || !is_subscription(&subscription[..len])
+                        {
+                            continue;
+                        }
+
+                        if destination.endpoint() != Some(meta.endpoint) {
+                            info!("Streaming to {}", meta.endpoint);
+                        }
+                        destination = Destination::Subscriber(meta.endpoint, Instant::now());
+                    }
+                    // Datagrams that don't fit are not subscriptions.
+                    Either::Second(Err(_)) => {}
+                }
+            }
+        })
+        .await;
+}
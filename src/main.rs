@@ -32,13 +32,14 @@ use crate::{
         initialized::{Context, InnerContext},
         startup::StartupResources,
         storage::FileSystem,
+        wall_clock,
     },
     states::{
         charging::charging,
         display_serial::display_serial,
         firmware_update::firmware_update,
         init::initialize,
-        measure::{measure, ECG_BUFFER_SIZE},
+        measure::{measure, MeasurementMetadata, ECG_BUFFER_SIZE},
         menu::{display_menu_screen, AppMenu},
         throughput::throughput,
         upload_or_store_measurement::{upload_or_store_measurement, upload_stored_measurements},
@@ -109,7 +110,7 @@ pub enum AppState {
     Throughput,
     Shutdown,
     UploadStored(AppMenu),
-    UploadOrStore(Box<CompressingBuffer<ECG_BUFFER_SIZE>>),
+    UploadOrStore(Box<CompressingBuffer<ECG_BUFFER_SIZE>>, MeasurementMetadata),
 }
 
 async fn load_config<M: StorageMedium>(storage: Option<&mut Storage<M>>) -> &'static mut Config
@@ -194,6 +195,7 @@ async fn main(_spawner: Spawner) {
     esp_alloc::heap_allocator!(size: (48 + 96) * 1024);
 
     let resources = StartupResources::initialize().await;
+    wall_clock::restore(&resources.rtc);
 
     static INTERRUPT_EXECUTOR: StaticCell<InterruptExecutor<1>> = StaticCell::new();
     let interrupt_executor =
@@ -252,8 +254,8 @@ async fn main(_spawner: Spawner) {
             AppState::UploadStored(next_state) => {
                 upload_stored_measurements(&mut board, AppState::Menu(next_state)).await
             }
-            AppState::UploadOrStore(buffer) => {
-                upload_or_store_measurement(&mut board, buffer, AppState::Shutdown).await
+            AppState::UploadOrStore(buffer, metadata) => {
+                upload_or_store_measurement(&mut board, buffer, metadata, AppState::Shutdown).await
             }
             AppState::Shutdown => break,
         };
@@ -303,6 +305,7 @@ fn enter_sleep(mut rtc: esp_hal::rtc_cntl::Rtc, is_charging: bool) {
     ];
     let wakeup_source = sleep::RtcioWakeupSource::new(&mut wakeup_pins);
 
+    wall_clock::persist(&rtc);
     rtc.sleep_deep(&[&wakeup_source]);
 }
 
@@ -329,5 +332,6 @@ fn enter_sleep(mut rtc: esp_hal::rtc_cntl::Rtc, is_charging: bool) {
 
     let wakeup_source = sleep::Ext1WakeupSource::new(&mut wakeup_pins);
 
+    wall_clock::persist(&rtc);
     rtc.sleep_deep(&[&wakeup_source]);
 }
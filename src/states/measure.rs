@@ -1,7 +1,10 @@
 use crate::{
     board::{
-        config::types::FilterStrength,
-        initialized::{Context, InnerContext},
+        config::types::{FilterStrength, LiveStream, RecordedChannels},
+        drivers::frontend::{gain_multiplier, reference_millivolts, PGA_GAIN, REFERENCE_VOLTAGE},
+        initialized::{Context, InnerContext, StaMode},
+        wall_clock,
+        wifi::stream::StreamSender,
         AdcSpi, EcgFrontend, PoweredEcgFrontend,
     },
     states::{menu::AppMenu, to_progress, INIT_MENU_THRESHOLD, INIT_TIME, MIN_FRAME_TIME},
@@ -9,7 +12,7 @@ use crate::{
     timeout::Timeout,
     AppState,
 };
-use ads129x::{Error, Sample};
+use ads129x::{descriptors::DataRate, AdsData, Error, Sample};
 use alloc::{boxed::Box, sync::Arc};
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
 use embassy_time::{Duration, Instant, Ticker};
@@ -18,22 +21,48 @@ use embedded_hal::spi::ErrorType;
 use esp_hal::time::Rate;
 use gui::screens::{init::StartupScreen, measure::EcgScreen};
 use macros as cardio;
-use object_chain::{chain, Chain, ChainElement, Link};
+use protocol::stream::{PacketHeader, Packetizer, SampleKind};
 use signal_processing::{
-    compressing_buffer::CompressingBuffer,
+    compressing_buffer::{CompressingBuffer, RiceFormat},
+    ecg::{
+        ecg_filter, heart_rate_noise_filter, BaselineFilter, EcgFilter, MainsFilter, SampleRate,
+        ECG_FILTER_DELAY,
+    },
+    ensemble::EnsembleAverager,
     filter::{
-        iir::{precomputed::ALL_PASS, HighPass, Iir, LowPass},
-        pli::{adaptation_blocking::AdaptationBlocking, PowerLineFilter},
+        iir::{BiquadCascade, LowPass},
         Filter,
     },
     heart_rate::HeartRateCalculator,
-    moving::sum::EstimatedSum,
+    mains::{MainsFrequency, MainsFrequencyDetector},
+    measurement::{
+        ChannelMask, FrameRangeRecorder, FrameRanges, IntervalSummary, KeyframeRecorder, Keyframes,
+        MeasurementHeader, QualityRecorder, QualityScores, RPeakRecorder, RPeaks, RhythmSummary,
+    },
+    quality::SignalQualityEstimator,
+    respiration::RespirationRateCalculator,
+    rhythm::IrregularityDetector,
 };
 
-#[cfg(not(feature = "downsampler-light"))]
-use signal_processing::filter::downsample::DownSampler;
+#[cfg(all(not(feature = "downsampler-light"), not(feature = "fixed-point")))]
+use signal_processing::filter::downsample::{DownSampler, DownSamplerCascade};
+
+#[cfg(all(not(feature = "downsampler-light"), feature = "fixed-point"))]
+use signal_processing::filter::{
+    downsample::{DownSamplerCascade, DownSamplerQ15},
+    fixed::FixedPoint,
+};
+
+/// Samples are filtered in blocks of up to this many.
+const BLOCK_SIZE: usize = 32;
+
+/// The capacity of the sample queue, 32ms worth of samples at the highest sample rate.
+const QUEUE_CAPACITY: usize = 64;
 
-type MessageQueue = Channel<CriticalSectionRawMutex, Sample, 32>;
+/// The heart rate is only displayed while the calculator is at least this confident in it.
+const MIN_HR_CONFIDENCE: f32 = 0.5;
+
+type MessageQueue = Channel<CriticalSectionRawMutex, AdsData, QUEUE_CAPACITY>;
 
 unsafe impl Send for PoweredEcgFrontend {}
 
@@ -42,15 +71,10 @@ struct EcgTaskParams {
     sender: Arc<MessageQueue>,
 }
 
-// PLI filtering algo is probably overkill for displaying, but it's fancy
-pub type EcgFilter = chain! {
-    PowerLineFilter<AdaptationBlocking<EstimatedSum<1200>, 4, 19>, Iir<'static, HighPass, 2>, 1>,
-    Iir<'static, HighPass, 2>
-};
-
 #[cfg(feature = "downsampler-light")]
 pub struct DownsamplerLight {
-    filter: Iir<'static, LowPass, 2>,
+    filter: BiquadCascade<LowPass, 1>,
+    decimation: u8,
     counter: u8,
 }
 
@@ -64,7 +88,7 @@ impl Filter for DownsamplerLight {
     fn update(&mut self, sample: f32) -> Option<f32> {
         let filtered = self.filter.update(sample)?;
         if self.counter == 0 {
-            self.counter = 7;
+            self.counter = self.decimation - 1;
             Some(filtered)
         } else {
             self.counter -= 1;
@@ -73,104 +97,396 @@ impl Filter for DownsamplerLight {
     }
 }
 
-// Downsample by 8 to display around 1 second
+/// The most halvings needed to display around 1 second, at 2000 sps.
 #[cfg(not(feature = "downsampler-light"))]
-pub type DownsamplerChain = chain! {
-    DownSampler,
-    DownSampler,
-    DownSampler
-};
+const MAX_DOWNSAMPLER_STAGES: usize = 4;
 
-#[cfg(not(feature = "downsampler-light"))]
-type EcgDownsampler = DownsamplerChain;
+#[cfg(all(not(feature = "downsampler-light"), not(feature = "fixed-point")))]
+type EcgDownsampler = DownSamplerCascade<DownSampler, MAX_DOWNSAMPLER_STAGES>;
 
-#[cfg(not(feature = "downsampler-light"))]
-fn create_downsampler() -> DownsamplerChain {
-    Chain::new(DownSampler::new())
-        .append(DownSampler::new())
-        .append(DownSampler::new())
+#[cfg(all(not(feature = "downsampler-light"), feature = "fixed-point"))]
+type EcgDownsampler = FixedPoint<DownSamplerCascade<DownSamplerQ15, MAX_DOWNSAMPLER_STAGES>>;
+
+// Downsample to display around 1 second
+#[cfg(all(not(feature = "downsampler-light"), not(feature = "fixed-point")))]
+fn create_downsampler(rate: SampleRate) -> EcgDownsampler {
+    DownSamplerCascade::new(DownSampler::new(), rate.display_decimation_stages())
+}
+
+#[cfg(all(not(feature = "downsampler-light"), feature = "fixed-point"))]
+fn create_downsampler(rate: SampleRate) -> EcgDownsampler {
+    FixedPoint::new(DownSamplerCascade::new(
+        DownSamplerQ15::new(),
+        rate.display_decimation_stages(),
+    ))
 }
 
 #[cfg(feature = "downsampler-light")]
 type EcgDownsampler = DownsamplerLight;
 
 #[cfg(feature = "downsampler-light")]
-fn create_downsampler() -> DownsamplerLight {
+fn create_downsampler(rate: SampleRate) -> DownsamplerLight {
+    let decimation = 1 << rate.display_decimation_stages();
+
     DownsamplerLight {
-        #[rustfmt::skip]
-        filter: macros::designfilt!(
-            "lowpassiir",
-            "FilterOrder", 2,
-            "HalfPowerFrequency", 35,
-            "SampleRate", 1000
-        ),
-        counter: 7,
+        filter: unwrap!(BiquadCascade::<LowPass, 1>::butterworth(rate.fs(), 35.0, 2)),
+        decimation,
+        counter: decimation - 1,
     }
 }
 
 pub const ECG_BUFFER_SIZE: usize = 90_000;
 
+/// How long the contact indicator stays on after the last lead-off sample, in milliseconds.
+/// Lead-off intervals are extended by the same amount, as the signal needs time to settle.
+const LEAD_OFF_HOLD_MS: u32 = 500;
+
+/// Samples are dropped for this long after starting, for the input to settle.
+const SETTLING_TIME_MS: u32 = 1500;
+
+/// Limits the memory used to store lead-off intervals.
+const MAX_LEAD_OFF_INTERVALS: usize = 256;
+
+/// About 17 minutes, longer than the ECG buffer can hold.
+const MAX_QUALITY_SCORES: usize = 1024;
+
+/// About 30 minutes at 60 BPM, longer than the ECG buffer can hold.
+const MAX_R_PEAKS: usize = 2048;
+
+/// The sample stream is indexed for seeking about this often.
+const KEYFRAME_INTERVAL_S: u32 = 1;
+
+/// The number of RR intervals irregular rhythm screening evaluates at once.
+const RHYTHM_WINDOW: usize = 32;
+
+/// The number of recent beats the median beat is computed from.
+const AVERAGED_BEATS: usize = 8;
+
+/// Intervals are only measured if at least this many beats could be averaged.
+const MIN_AVERAGED_BEATS: usize = 5;
+
+/// Live stream packets carry this much of the signal.
+const STREAM_PACKET_MS: u32 = 40;
+
+/// With automatic power line frequency selection, this much of the signal is analysed before
+/// filtering starts.
+const MAINS_DETECTION_WINDOW_S: f32 = 1.0;
+
+/// The PGA gain of the recorded channels, as stored in the measurement header.
+const HEADER_GAIN: u8 = gain_multiplier(PGA_GAIN);
+
+/// The ADC reference voltage in millivolts, as stored in the measurement header.
+const HEADER_REFERENCE_MV: u16 = match reference_millivolts(REFERENCE_VOLTAGE) {
+    Some(millivolts) => millivolts,
+    None => panic!("The reference voltage of an external reference is unknown"),
+};
+
+/// The largest voltage the ADC can measure at the configured gain.
+const ADC_FULL_SCALE: f32 = Sample::VOLTS_PER_LSB * (1 << 23) as f32;
+
+/// Everything that is stored about a measurement besides the samples.
+pub struct MeasurementMetadata {
+    header: MeasurementHeader<'static>,
+    lead_off: FrameRangeRecorder,
+    quality: QualityRecorder,
+    r_peaks: RPeakRecorder,
+    keyframes: KeyframeRecorder,
+}
+
+impl MeasurementMetadata {
+    pub fn header(&self) -> MeasurementHeader<'_> {
+        MeasurementHeader {
+            lead_off: self.lead_off.ranges(),
+            quality: self.quality.scores(),
+            r_peaks: self.r_peaks.peaks(),
+            keyframes: self.keyframes.keyframes(),
+            ..self.header
+        }
+    }
+}
+
+impl core::fmt::Debug for MeasurementMetadata {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("MeasurementMetadata")
+            .field(&self.header())
+            .finish()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for MeasurementMetadata {
+    fn format(&self, fmt: defmt::Formatter<'_>) {
+        defmt::write!(fmt, "MeasurementMetadata({})", self.header())
+    }
+}
+
+fn data_rate(rate: SampleRate) -> DataRate {
+    match rate {
+        SampleRate::Sps250 => DataRate::_250sps,
+        SampleRate::Sps500 => DataRate::_500sps,
+        SampleRate::Sps1000 => DataRate::_1ksps,
+        SampleRate::Sps2000 => DataRate::_2ksps,
+    }
+}
+
+fn screen_rhythm(r_peaks: RPeaks<'_>, sample_rate: u16) -> Option<RhythmSummary> {
+    let mut detector = IrregularityDetector::<RHYTHM_WINDOW>::new(sample_rate as f32);
+    for rr_interval in r_peaks.rr_intervals() {
+        detector.update(rr_interval);
+    }
+
+    let saturate = |windows: u32| windows.min(u16::MAX as u32) as u16;
+    (detector.windows() > 0).then(|| RhythmSummary {
+        windows: saturate(detector.windows()),
+        irregular_windows: saturate(detector.irregular_windows()),
+    })
+}
+
+/// Measures the intervals on the median of the last averaged beats. The ST deviation is only
+/// kept if the baseline was removed without a high-pass filter, which would distort it.
+fn measure_intervals(
+    averager: &EnsembleAverager,
+    baseline: BaselineFilter,
+) -> Option<IntervalSummary> {
+    let measurements = averager.median_beat(MIN_AVERAGED_BEATS)?.measure()?;
+
+    let interval = |ms: f32| ms.round() as u16;
+    let st_uv = (measurements.st_deviation_mv * 1000.0)
+        .round()
+        .clamp(-(i16::MAX as f32), i16::MAX as f32) as i16;
+    Some(IntervalSummary {
+        beats: measurements.beats.min(u16::MAX as usize) as u16,
+        rr_ms: interval(measurements.rr_ms),
+        pr_ms: measurements.pr_ms.map(interval),
+        qrs_ms: interval(measurements.qrs_ms),
+        qt_ms: measurements.qt_ms.map(interval),
+        st_uv: (baseline.cutoff() == 0.0).then_some(st_uv),
+    })
+}
+
+/// Sends samples of the measurement to the local network.
+struct LiveStreamer {
+    kind: SampleKind,
+    packetizer: Packetizer,
+    sender: StreamSender,
+}
+
+impl LiveStreamer {
+    fn push_raw(&mut self, frame: &[i32]) {
+        if self.kind != SampleKind::Raw {
+            return;
+        }
+        if let Some(packet) = self.packetizer.push_raw(frame) {
+            self.sender.send(packet);
+        }
+    }
+
+    fn push_filtered(&mut self, sample: f32) {
+        if self.kind != SampleKind::Filtered {
+            return;
+        }
+        if let Some(packet) = self.packetizer.push_filtered(&[sample]) {
+            self.sender.send(packet);
+        }
+    }
+}
+
+/// Connects to a known network, or starts an access point if there is none, to stream the
+/// measurement.
+async fn start_live_stream(context: &mut Context) -> Option<(SampleKind, StreamSender)> {
+    let kind = match context.config.live_stream {
+        LiveStream::Off => return None,
+        LiveStream::Raw => SampleKind::Raw,
+        LiveStream::Filtered => SampleKind::Filtered,
+    };
+
+    // Only the device's own access point is private enough to broadcast the samples to.
+    let own_network = context.config.known_networks.is_empty();
+    let stack = if own_network {
+        context.enable_wifi_ap().await?.stack()
+    } else {
+        context.enable_wifi_sta(StaMode::Enable).await?.stack()
+    };
+
+    Some((kind, StreamSender::start(stack, own_network).await))
+}
+
 // Two filter chains:
 // - PLI -> IIR HPF -> FIR Downsample -> display
 // - PLI -> IIR HPF -> FIR LPF in HR calculator -> HR calculator
 struct EcgObjects {
     pub filter: EcgFilter,
     pub downsampler: EcgDownsampler,
-    pub heart_rate_calculator: HeartRateCalculator<[f32; 300], [f32; 50]>,
-    pub hr_noise_filter: Iir<'static, LowPass, 2>,
+    pub heart_rate_calculator: HeartRateCalculator<Box<[f32]>, Box<[f32]>>,
+    pub hr_noise_filter: BiquadCascade<LowPass, 1>,
+    pub respiration_rate_calculator: RespirationRateCalculator,
+    pub quality_estimator: SignalQualityEstimator,
+    pub averager: EnsembleAverager,
+    baseline: BaselineFilter,
+    rate: SampleRate,
 }
 
 impl EcgObjects {
+    /// Returns `None` if there is not enough memory for the processing chain.
     #[inline(always)]
-    fn new(hpf: Iir<'static, HighPass, 2>) -> Self {
-        Self {
-            filter: Chain::new(PowerLineFilter::new_1ksps([50.0])).append(hpf),
-            downsampler: create_downsampler(),
-            heart_rate_calculator: HeartRateCalculator::new(1000.0),
-
-            #[rustfmt::skip]
-            hr_noise_filter: macros::designfilt!(
-                "lowpassiir",
-                "FilterOrder", 2,
-                "HalfPowerFrequency", 20,
-                "SampleRate", 1000
-            ),
+    fn new(baseline: BaselineFilter, mains: MainsFrequency, rate: SampleRate) -> Option<Box<Self>> {
+        let fs = rate.fs();
+
+        // The large filters are allocated first, the QRS detector's buffers are not fallible.
+        let filter = ecg_filter(baseline, mains, rate)?;
+        let averager = EnsembleAverager::new(fs, AVERAGED_BEATS)?;
+
+        Box::try_new(Self {
+            filter,
+            downsampler: create_downsampler(rate),
+            heart_rate_calculator: HeartRateCalculator::new_alloc(fs),
+            hr_noise_filter: heart_rate_noise_filter(rate),
+            respiration_rate_calculator: RespirationRateCalculator::new(fs),
+            quality_estimator: SignalQualityEstimator::new(fs, 1.0, ADC_FULL_SCALE),
+            averager,
+            baseline,
+            rate,
+        })
+        .ok()
+    }
+
+    /// Replaces the power line filter. The baseline filter keeps its state.
+    fn set_mains_frequency(&mut self, mains: MainsFrequency) {
+        self.filter.parent.object = MainsFilter::new(self.rate, mains);
+    }
+
+    /// Converts a duration to a number of samples.
+    fn samples(&self, ms: u32) -> u32 {
+        ms * self.rate.hz() as u32 / 1000
+    }
+
+    /// Filters a block of ECG samples and processes the results. `frames` counts the recorded
+    /// frames including the ones in `block`.
+    fn process_block(
+        &mut self,
+        block: &[f32],
+        frames: u32,
+        r_peaks: &mut RPeakRecorder,
+        quality: &mut QualityRecorder,
+        screen: &mut EcgScreen,
+        mut stream: Option<&mut LiveStreamer>,
+    ) {
+        let mut filtered = [0.0; BLOCK_SIZE];
+        let count = self.filter.process_block(block, &mut filtered);
+        let filtered = &filtered[..count];
+
+        let mut hr_input = [0.0; BLOCK_SIZE];
+        self.hr_noise_filter.process_block(filtered, &mut hr_input);
+
+        // Once warmed up, the filters produce one output for every input, so the outputs belong
+        // to the last samples of the block.
+        let raw_samples = &block[block.len() - count..];
+        let filter_delay = (ECG_FILTER_DELAY + self.baseline.delay(self.rate)) as u32;
+
+        for (idx, (&raw, &filtered)) in raw_samples.iter().zip(filtered).enumerate() {
+            self.averager.update(filtered);
+            self.heart_rate_calculator.update(hr_input[idx]);
+
+            if let Some(beat) = self.heart_rate_calculator.beat() {
+                self.averager.add_beat(&beat);
+
+                // Peaks detected right after the recording started may be older than the first
+                // frame.
+                let samples_after = (count - 1 - idx) as u32;
+                let delay = filter_delay + beat.delay + 1 + samples_after;
+                if let Some(frame) = frames.checked_sub(delay) {
+                    r_peaks.push(frame);
+                }
+            }
+
+            if let Some(stream) = stream.as_deref_mut() {
+                stream.push_filtered(filtered);
+            }
+
+            let is_beat = self.heart_rate_calculator.is_beat();
+            if let Some(score) = self.quality_estimator.update(raw, filtered, is_beat) {
+                quality.push(score);
+            }
+        }
+
+        let mut downsampled = [0.0; BLOCK_SIZE];
+        let count = self.downsampler.process_block(filtered, &mut downsampled);
+        for &sample in &downsampled[..count] {
+            screen.push(sample);
         }
     }
 }
 
 pub async fn measure(context: &mut Context) -> AppState {
-    let filter = match context.config.filter_strength() {
-        FilterStrength::None => ALL_PASS,
-        #[rustfmt::skip]
-        FilterStrength::Weak => macros::designfilt!(
-            "highpassiir",
-            "FilterOrder", 2,
-            "HalfPowerFrequency", 0.75,
-            "SampleRate", 1000
-        ),
-        #[rustfmt::skip]
-        FilterStrength::Strong => macros::designfilt!(
-            "highpassiir",
-            "FilterOrder", 2,
-            "HalfPowerFrequency", 1.5,
-            "SampleRate", 1000
-        ),
+    let baseline = match context.config.filter_strength() {
+        FilterStrength::None => BaselineFilter::None,
+        FilterStrength::Weak => BaselineFilter::Weak,
+        FilterStrength::Strong => BaselineFilter::Strong,
+        FilterStrength::Median => BaselineFilter::Median,
+    };
+    let rate = context.config.sample_rate();
+    let configured_mains = context.config.mains_frequency();
+    let mains = configured_mains.unwrap_or_default();
+    let mains_detector = configured_mains
+        .is_none()
+        .then(|| MainsFrequencyDetector::new(rate.fs(), MAINS_DETECTION_WINDOW_S));
+    let recorded_channels = context.config.recorded_channels;
+    let channels = match recorded_channels {
+        RecordedChannels::Ch1 => ChannelMask::CH1,
+        RecordedChannels::Both | RecordedChannels::Respiration => ChannelMask::ALL,
+    };
+
+    let header = MeasurementHeader {
+        sample_rate: rate.hz(),
+        gain: HEADER_GAIN,
+        reference_mv: HEADER_REFERENCE_MV,
+        adc_device_id: None,
+        high_pass_cutoff: baseline.cutoff(),
+        median_baseline: baseline == BaselineFilter::Median,
+        power_line_frequency: mains.hz(),
+        firmware_version: env!("FW_VERSION"),
+        start_time: None,
+        sample_encoding: RiceFormat::VERSION,
+        channels,
+        respiration_channel: None,
+        lead_off: FrameRanges::EMPTY,
+        quality: QualityScores::EMPTY,
+        r_peaks: RPeaks::EMPTY,
+        rhythm: None,
+        keyframes: Keyframes::EMPTY,
+        intervals: None,
     };
 
     // We allocate two different objects because the filters don't need to outlive this app state.
-    let ecg_buffer = Box::try_new(CompressingBuffer::EMPTY).ok();
-    let mut ecg = Box::new(EcgObjects::new(filter));
+    let mut ecg_buffer = Box::try_new(CompressingBuffer::EMPTY).ok();
+    let Some(mut ecg) = EcgObjects::new(baseline, mains, rate) else {
+        context.display_message("Out of memory").await;
+        return AppState::Menu(AppMenu::Main);
+    };
 
-    if ecg_buffer.is_none() {
-        warn!("Failed to allocate ECG buffer");
+    match ecg_buffer.as_deref_mut() {
+        Some(ecg_buffer) => ecg_buffer.set_channels(channels.count()),
+        None => warn!("Failed to allocate ECG buffer"),
     }
 
-    unsafe {
-        let frontend = core::ptr::read(&context.frontend);
+    let stream = start_live_stream(context).await;
 
-        let (next_state, frontend) =
-            measure_impl(&mut context.inner, frontend, &mut ecg, ecg_buffer).await;
+    unsafe {
+        let mut frontend = core::ptr::read(&context.frontend);
+        frontend.set_ch2_enabled(channels.contains(2));
+        frontend.set_data_rate(data_rate(rate));
+        frontend.set_respiration_enabled(recorded_channels == RecordedChannels::Respiration);
+
+        let (next_state, frontend) = measure_impl(
+            &mut context.inner,
+            frontend,
+            &mut ecg,
+            ecg_buffer,
+            header,
+            mains_detector,
+            stream,
+        )
+        .await;
 
         core::ptr::write(&mut context.frontend, frontend);
         next_state
@@ -182,6 +498,9 @@ async fn measure_impl(
     frontend: EcgFrontend,
     ecg: &mut EcgObjects,
     mut ecg_buffer: Option<Box<CompressingBuffer<ECG_BUFFER_SIZE>>>,
+    mut header: MeasurementHeader<'static>,
+    mut mains_detector: Option<MainsFrequencyDetector>,
+    stream: Option<(SampleKind, StreamSender)>,
 ) -> (AppState, EcgFrontend) {
     let mut frontend = match frontend.enable_async().await {
         Ok(frontend) => frontend,
@@ -210,6 +529,38 @@ async fn measure_impl(
         _ => {}
     }
 
+    header.adc_device_id = frontend.device_id().map(u8::from);
+
+    // With respiration enabled, channel 1 carries the respiration signal and the ECG is on
+    // channel 2.
+    let respiration = frontend.respiration_enabled();
+    header.respiration_channel = respiration.then_some(1);
+
+    // Raw streams carry the recorded channels, filtered streams only the ECG lead.
+    let mut stream = stream.map(|(kind, sender)| {
+        let channels = match kind {
+            SampleKind::Raw => header.channels,
+            SampleKind::Filtered if respiration => ChannelMask::CH2,
+            SampleKind::Filtered => ChannelMask::CH1,
+        };
+        let stream_header = PacketHeader {
+            kind,
+            channels,
+            gain: header.gain,
+            sample_rate: header.sample_rate,
+            reference_mv: header.reference_mv,
+            first_frame: 0,
+            frames: 0,
+        };
+        let frames_per_packet = ecg.samples(STREAM_PACKET_MS) as u16;
+
+        Box::new(LiveStreamer {
+            kind,
+            packetizer: Packetizer::new(stream_header, frames_per_packet),
+            sender,
+        })
+    });
+
     let queue = Arc::new(MessageQueue::new());
 
     let task_control = TaskController::from_resources(frontend);
@@ -222,39 +573,108 @@ async fn measure_impl(
         }));
 
     ecg.heart_rate_calculator.clear();
+    ecg.respiration_rate_calculator.clear();
+    ecg.quality_estimator.clear();
+    ecg.averager.clear();
 
     let mut screen = EcgScreen::new();
 
     let mut samples = 0; // Counter and 1s timer to debug perf issues
     let mut debug_print_timer = Timeout::new(Duration::from_secs(1));
 
+    let mut lead_off = FrameRangeRecorder::new(MAX_LEAD_OFF_INTERVALS);
+    let mut lead_off_hold = 0;
+    // Signal quality is scored for every second of the recording.
+    let mut quality = QualityRecorder::new(ecg.samples(1000), MAX_QUALITY_SCORES);
+    let mut r_peaks = RPeakRecorder::new(MAX_R_PEAKS);
+    let mut frames = 0;
+
     let mut ticker = Ticker::every(MIN_FRAME_TIME);
-    let mut drop_samples = 1500; // Slight delay for the input to settle
+    let mut drop_samples = ecg.samples(SETTLING_TIME_MS);
+    let lead_off_hold_samples = ecg.samples(LEAD_OFF_HOLD_MS);
     let mut entered = Instant::now();
     let exit_timer = Timeout::new_with_start(INIT_TIME, entered - INIT_MENU_THRESHOLD);
 
     while !task_control.has_exited() && !context.battery_monitor.is_low() {
         let display_full = screen.buffer_full();
-        while let Ok(sample) = queue.try_receive() {
+        let mut block = [0.0; BLOCK_SIZE];
+        let mut block_len = 0;
+        while let Ok(data) = queue.try_receive() {
             samples += 1;
 
             if drop_samples == 0 {
-                if let Some(ecg_buffer) = ecg_buffer.as_deref_mut() {
-                    ecg_buffer.push(sample.raw());
+                let contact = data.ch1_leads_connected()
+                    && (!header.channels.contains(2) || data.ch2_leads_connected());
+                if contact {
+                    lead_off_hold = lead_off_hold.saturating_sub(1);
+                } else {
+                    lead_off_hold = lead_off_hold_samples;
                 }
-                if let Some(filtered) = ecg.filter.update(sample.voltage()) {
-                    if let Some(filtered) = ecg.hr_noise_filter.update(filtered) {
-                        ecg.heart_rate_calculator.update(filtered);
+
+                if let Some(ecg_buffer) = ecg_buffer.as_deref_mut() {
+                    if header.channels.contains(2) {
+                        ecg_buffer.push_frame(&[data.ch1_sample().raw(), data.ch2_sample().raw()]);
+                    } else {
+                        ecg_buffer.push(data.ch1_sample().raw());
                     }
 
-                    if let Some(downsampled) = ecg.downsampler.update(filtered) {
-                        screen.push(downsampled);
+                    lead_off.update(frames, lead_off_hold > 0);
+                    frames += 1;
+                }
+
+                if let Some(stream) = stream.as_deref_mut() {
+                    let frame = [data.ch1_sample().raw(), data.ch2_sample().raw()];
+                    stream.push_raw(&frame[..header.channels.count()]);
+                }
+
+                let sample = if respiration {
+                    ecg.respiration_rate_calculator
+                        .update(data.ch1_sample().voltage());
+                    data.ch2_sample()
+                } else {
+                    data.ch1_sample()
+                };
+
+                // The filters need time to settle after starting, so they are only started once
+                // the power line frequency is known.
+                if let Some(detector) = mains_detector.as_mut() {
+                    if detector.update(sample.voltage()) {
+                        let mains = detector.frequency().unwrap_or_default();
+                        debug!("Power line frequency: {} Hz", mains.hz());
+                        ecg.set_mains_frequency(mains);
+                        header.power_line_frequency = mains.hz();
+                        mains_detector = None;
                     }
+                    continue;
+                }
+
+                block[block_len] = sample.voltage();
+                block_len += 1;
+                if block_len == BLOCK_SIZE {
+                    let block = &block[..block_len];
+                    ecg.process_block(
+                        block,
+                        frames,
+                        &mut r_peaks,
+                        &mut quality,
+                        &mut screen,
+                        stream.as_deref_mut(),
+                    );
+                    block_len = 0;
                 }
             } else {
                 drop_samples -= 1;
             }
         }
+        let block = &block[..block_len];
+        ecg.process_block(
+            block,
+            frames,
+            &mut r_peaks,
+            &mut quality,
+            &mut screen,
+            stream.as_deref_mut(),
+        );
 
         if !display_full {
             if screen.buffer_full() {
@@ -263,6 +683,13 @@ async fn measure_impl(
             if let Some(ecg_buffer) = ecg_buffer.as_deref_mut() {
                 ecg_buffer.clear();
             }
+            lead_off.clear();
+            frames = 0;
+            // Align quality windows to the start of the recording.
+            ecg.quality_estimator.clear();
+            quality.clear();
+            r_peaks.clear();
+            ecg.averager.clear();
         }
 
         if debug_print_timer.is_elapsed() {
@@ -284,7 +711,15 @@ async fn measure_impl(
                     }
                     .draw(display)
                 } else {
-                    screen.update_heart_rate(ecg.heart_rate_calculator.current_hr());
+                    let hr_calculator = &ecg.heart_rate_calculator;
+                    screen.update_heart_rate(
+                        hr_calculator
+                            .current_hr()
+                            .filter(|_| hr_calculator.confidence() >= MIN_HR_CONFIDENCE),
+                    );
+                    screen.update_contact(lead_off_hold == 0);
+                    screen.update_quality(ecg.quality_estimator.current());
+                    screen.update_respiration_rate(ecg.respiration_rate_calculator.current_rate());
                     screen.elapsed_secs = entered.elapsed().as_secs() as usize;
 
                     screen.draw(display)
@@ -296,6 +731,10 @@ async fn measure_impl(
     }
 
     let result = task_control.stop().await;
+    if let Some(stream) = stream {
+        stream.sender.stop().await;
+    }
+
     let next_state = match result {
         Ok(result) => {
             // task stopped itself
@@ -304,8 +743,35 @@ async fn measure_impl(
             }
             if result.is_ok() && !exit_timer.is_elapsed() {
                 AppState::Menu(AppMenu::Main)
-            } else if let Some(ecg_buffer) = ecg_buffer {
-                AppState::UploadOrStore(ecg_buffer)
+            } else if let Some(mut ecg_buffer) = ecg_buffer {
+                // Recording starts when the display buffer is first filled.
+                header.start_time = wall_clock::unix_time_at(entered);
+
+                // Encoding the last block may overwrite the oldest frames.
+                ecg_buffer.flush();
+
+                // Older frames may have been overwritten in the buffer.
+                let first_frame = frames - ecg_buffer.len() as u32;
+                lead_off.finish(frames, first_frame);
+                quality.finish(first_frame);
+                r_peaks.finish(first_frame);
+                header.rhythm = screen_rhythm(r_peaks.peaks(), header.sample_rate);
+                header.intervals = measure_intervals(&ecg.averager, ecg.baseline);
+
+                let mut keyframes =
+                    KeyframeRecorder::new(KEYFRAME_INTERVAL_S * header.sample_rate as u32);
+                for keyframe in ecg_buffer.keyframes() {
+                    keyframes.push(keyframe);
+                }
+
+                let metadata = MeasurementMetadata {
+                    header,
+                    lead_off,
+                    quality,
+                    r_peaks,
+                    keyframes,
+                };
+                AppState::UploadOrStore(ecg_buffer, metadata)
             } else {
                 AppState::Shutdown
             }
@@ -343,7 +809,7 @@ async fn read_ecg(
                     return Ok(());
                 }
 
-                if queue.try_send(sample.ch1_sample()).is_err() {
+                if queue.try_send(sample).is_err() {
                     warn!("Sample lost");
                 }
             }
@@ -1,6 +1,6 @@
 use crate::{
     board::{
-        config::types::{DisplayBrightness, FilterStrength},
+        config::types::{DisplayBrightness, FilterStrength, PowerLineFrequency},
         initialized::Context,
     },
     states::menu::{AppMenu, MenuScreen},
@@ -33,6 +33,7 @@ pub enum DisplayMenuEvents {
     ChangeBrigtness(DisplayBrightness),
     ChangeBatteryStyle(BatteryStyle),
     ChangeFilterStrength(FilterStrength),
+    ChangePowerLineFrequency(PowerLineFrequency),
     Back,
 }
 
@@ -43,11 +44,14 @@ type DisplayMenuBuilder = MenuBuilder<
     object_chain::Link<
         MenuItem<&'static str, DisplayMenuEvents, &'static str, true>,
         object_chain::Link<
-            MenuItem<&'static str, DisplayMenuEvents, FilterStrength, true>,
+            MenuItem<&'static str, DisplayMenuEvents, PowerLineFrequency, true>,
             object_chain::Link<
-                MenuItem<&'static str, DisplayMenuEvents, BatteryStyle, true>,
-                object_chain::Chain<
-                    MenuItem<&'static str, DisplayMenuEvents, DisplayBrightness, true>,
+                MenuItem<&'static str, DisplayMenuEvents, FilterStrength, true>,
+                object_chain::Link<
+                    MenuItem<&'static str, DisplayMenuEvents, BatteryStyle, true>,
+                    object_chain::Chain<
+                        MenuItem<&'static str, DisplayMenuEvents, DisplayBrightness, true>,
+                    >,
                 >,
             >,
         >,
@@ -75,6 +79,11 @@ fn display_menu_builder(context: &mut Context) -> DisplayMenuBuilder {
             context.config.filter_strength,
             DisplayMenuEvents::ChangeFilterStrength,
         )
+        .add_item(
+            "Mains",
+            context.config.power_line_frequency,
+            DisplayMenuEvents::ChangePowerLineFrequency,
+        )
         .add_item("Back", "<-", |_| DisplayMenuEvents::Back)
 }
 
@@ -103,6 +112,9 @@ impl MenuScreen for DisplayMenu {
             DisplayMenuEvents::ChangeFilterStrength(strength) => {
                 context.update_config(|config| config.filter_strength = strength);
             }
+            DisplayMenuEvents::ChangePowerLineFrequency(frequency) => {
+                context.update_config(|config| config.power_line_frequency = frequency);
+            }
             DisplayMenuEvents::Back => return Some(AppState::Menu(AppMenu::Main)),
         }
 
@@ -1,6 +1,9 @@
 use crate::{
     board::{
-        config::{types::MeasurementAction, Config},
+        config::{
+            types::{LiveStream, MeasurementAction, RecordedChannels, SamplingRate},
+            Config,
+        },
         initialized::Context,
         storage::FileSystem,
     },
@@ -21,6 +24,9 @@ use gui::{embedded_layout::object_chain, screens::create_menu};
 #[derive(Clone, Copy)]
 pub enum StorageMenuEvents {
     ChangeMeasurementAction(MeasurementAction),
+    ChangeRecordedChannels(RecordedChannels),
+    ChangeSamplingRate(SamplingRate),
+    ChangeLiveStream(LiveStream),
     Format,
     Upload,
     Nothing,
@@ -70,8 +76,22 @@ type StorageMenuBuilder = MenuBuilder<
                         MenuItem<&'static str, StorageMenuEvents, UsedStorage, true>,
                         StorageMenuEvents,
                     >,
-                    object_chain::Chain<
-                        MenuItem<&'static str, StorageMenuEvents, MeasurementAction, true>,
+                    object_chain::Link<
+                        MenuItem<&'static str, StorageMenuEvents, LiveStream, true>,
+                        object_chain::Link<
+                            MenuItem<&'static str, StorageMenuEvents, SamplingRate, true>,
+                            object_chain::Link<
+                                MenuItem<&'static str, StorageMenuEvents, RecordedChannels, true>,
+                                object_chain::Chain<
+                                    MenuItem<
+                                        &'static str,
+                                        StorageMenuEvents,
+                                        MeasurementAction,
+                                        true,
+                                    >,
+                                >,
+                            >,
+                        >,
                     >,
                 >,
             >,
@@ -124,6 +144,21 @@ async fn storage_menu_builder(context: &mut Context) -> StorageMenuBuilder {
             context.config.measurement_action,
             StorageMenuEvents::ChangeMeasurementAction,
         )
+        .add_item(
+            "Channels",
+            context.config.recorded_channels,
+            StorageMenuEvents::ChangeRecordedChannels,
+        )
+        .add_item(
+            "Sample rate",
+            context.config.sampling_rate,
+            StorageMenuEvents::ChangeSamplingRate,
+        )
+        .add_item(
+            "Live stream",
+            context.config.live_stream,
+            StorageMenuEvents::ChangeLiveStream,
+        )
         .add_menu_items(used_item)
         .add_menu_items(items)
         .add_item("Format storage", "->", |_| StorageMenuEvents::Format)
@@ -150,6 +185,21 @@ impl MenuScreen for StorageMenu {
 
                 context.update_config(|config| config.measurement_action = action);
             }
+            StorageMenuEvents::ChangeRecordedChannels(channels) => {
+                debug!("Settings changed");
+
+                context.update_config(|config| config.recorded_channels = channels);
+            }
+            StorageMenuEvents::ChangeSamplingRate(rate) => {
+                debug!("Settings changed");
+
+                context.update_config(|config| config.sampling_rate = rate);
+            }
+            StorageMenuEvents::ChangeLiveStream(stream) => {
+                debug!("Settings changed");
+
+                context.update_config(|config| config.live_stream = stream);
+            }
             StorageMenuEvents::Format => {
                 info!("Format requested");
                 context.display_message("Formatting storage...").await;
@@ -1,11 +1,12 @@
 use core::{
     mem::{self, MaybeUninit},
-    str,
+    ops::Range,
 };
 
 use alloc::{boxed::Box, vec::Vec};
-use embassy_time::{with_timeout, Duration};
-use embedded_graphics::pixelcolor::BinaryColor;
+use crc::{Crc, CRC_32_ISO_HDLC};
+use embassy_time::{with_timeout, Duration, Ticker, Timer};
+use embedded_graphics::{pixelcolor::BinaryColor, Drawable};
 use embedded_menu::{
     builder::MenuBuilder,
     collection::MenuItems,
@@ -14,17 +15,34 @@ use embedded_menu::{
     selection_indicator::{style::AnimatedTriangle, AnimatedPosition},
 };
 use embedded_nal_async::{Dns, TcpConnect};
-use gui::{embedded_layout::object_chain, screens::create_menu};
+use gui::{
+    embedded_layout::object_chain,
+    screens::{
+        create_menu,
+        summary::{IntervalSummaryScreen, MeasurementSummaryScreen},
+    },
+    widgets::wifi_client::WifiClientState,
+};
 use norfs::{
-    medium::StorageMedium, read_dir::DirEntry, writer::FileDataWriter, OnCollision, Storage,
-    StorageError,
+    medium::StorageMedium,
+    read_dir::DirEntry,
+    storable::{LoadError, Loadable, Storable},
+    writer::FileDataWriter,
+    OnCollision, Storage, StorageError,
+};
+use protocol::upload::{
+    parse_offset, slice_parts, SessionId, UploadSession, CHUNK_SIZE, MAX_SESSION_ID_LEN,
 };
 use reqwless::{
     client::HttpClient,
     request::{Method, RequestBody, RequestBuilder},
     response::Status,
 };
-use signal_processing::compressing_buffer::{CompressingBuffer, EkgFormat};
+use signal_processing::{
+    compressing_buffer::CompressingBuffer,
+    hrv::HrvCalculator,
+    measurement::{MeasurementHeader, FORMAT_VERSION},
+};
 use ufmt::uwrite;
 
 use crate::{
@@ -33,10 +51,15 @@ use crate::{
         initialized::{Context, InnerContext, StaMode},
     },
     human_readable::BinarySize,
-    states::menu::MenuScreen,
+    states::{measure::MeasurementMetadata, menu::MenuScreen, MIN_FRAME_TIME},
+    timeout::Timeout,
     uformat, AppState, SerialNumber,
 };
 
+/// How long each page of the measurement summary is shown, unless the user touches the
+/// electrodes.
+const SUMMARY_DURATION: Duration = Duration::from_secs(10);
+
 /// Whether to store the measurement or not. Used instead of a bool to reduce confusion.
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -54,16 +77,16 @@ pub async fn upload_stored_measurements(context: &mut Context, next_state: AppSt
 pub async fn upload_or_store_measurement<const SIZE: usize>(
     context: &mut Context,
     mut buffer: Box<CompressingBuffer<SIZE>>,
+    metadata: MeasurementMetadata,
     next_state: AppState,
 ) -> AppState {
+    let header = metadata.header();
     let sample_count = buffer.len();
     let samples = buffer.make_contiguous();
 
-    const SAMPLE_RATE: usize = 1000; // samples/sec
-
     debug!("Measurement length: {} samples", sample_count);
 
-    if sample_count < 20 * SAMPLE_RATE {
+    if sample_count < 20 * header.sample_rate as usize {
         if context.config.measurement_action != MeasurementAction::Discard {
             // We don't want to store too-short measurements.
             debug!("Measurement is too short to upload or store.");
@@ -74,6 +97,8 @@ pub async fn upload_or_store_measurement<const SIZE: usize>(
         return next_state;
     }
 
+    display_summary(context, &header, sample_count).await;
+
     let (can_upload, can_store) = match context.config.measurement_action {
         MeasurementAction::Ask => ask_for_measurement_action(context).await,
         MeasurementAction::Auto => (true, true),
@@ -82,8 +107,26 @@ pub async fn upload_or_store_measurement<const SIZE: usize>(
         MeasurementAction::Discard => (false, false),
     };
 
+    let start_time = header.start_time;
+    let header = match encode_header(&header) {
+        Ok(header) => header,
+        Err(message) => {
+            context.display_message(message).await;
+            return next_state;
+        }
+    };
+
+    let measurement = MeasurementRef {
+        version: FORMAT_VERSION,
+        start_time,
+        header: &header,
+        samples,
+    };
+
+    // A failed upload can continue from the stored measurement.
+    let mut session = None;
     let store_after_upload = if can_upload {
-        let upload_result = try_to_upload(context, samples).await;
+        let upload_result = try_to_upload(context, measurement, &mut session).await;
         debug!("Upload result: {:?}", upload_result);
         upload_result == StoreMeasurement::Store
     } else {
@@ -91,7 +134,7 @@ pub async fn upload_or_store_measurement<const SIZE: usize>(
     };
 
     if can_store && store_after_upload {
-        let store_result = try_store_measurement(context, samples).await;
+        let store_result = try_store_measurement(context, measurement, session.as_ref()).await;
 
         if let Err(e) = store_result {
             context.display_message("Could not store measurement").await;
@@ -112,6 +155,59 @@ pub async fn upload_or_store_measurement<const SIZE: usize>(
     next_state
 }
 
+async fn display_summary(context: &mut Context, header: &MeasurementHeader<'_>, frames: usize) {
+    let mut hrv = HrvCalculator::new(header.sample_rate as f32);
+    for rr_interval in header.r_peaks.rr_intervals() {
+        hrv.update(rr_interval);
+    }
+    let metrics = hrv.metrics();
+    debug!("HRV metrics: {:?}", metrics);
+
+    let screen = MeasurementSummaryScreen {
+        duration_secs: (frames / header.sample_rate as usize) as u32,
+        heart_rate: metrics.map(|metrics| metrics.heart_rate() as u8),
+        rmssd_ms: metrics.map(|metrics| metrics.rmssd_ms as u16),
+        irregular_rhythm: header.rhythm.is_some_and(|rhythm| rhythm.is_irregular()),
+    };
+    show_summary_page(context, &screen).await;
+
+    if let Some(intervals) = header.intervals {
+        debug!("Intervals: {:?}", intervals);
+        let screen = IntervalSummaryScreen {
+            pr_ms: intervals.pr_ms,
+            qrs_ms: intervals.qrs_ms,
+            qt_ms: intervals.qt_ms,
+            qtc_ms: intervals.qtc_ms(),
+            st_uv: intervals.st_uv,
+        };
+        show_summary_page(context, &screen).await;
+    }
+}
+
+async fn show_summary_page(
+    context: &mut Context,
+    screen: &impl Drawable<Color = BinaryColor, Output = ()>,
+) {
+    let mut ticker = Ticker::every(MIN_FRAME_TIME);
+    let timeout = Timeout::new(SUMMARY_DURATION);
+
+    // Only a new touch skips the summary, the electrodes may still be held when it appears.
+    let mut released = false;
+    while !timeout.is_elapsed() {
+        let touched = context.frontend.is_touched();
+        if released && touched {
+            break;
+        }
+        released |= !touched;
+
+        context
+            .with_status_bar(|display| screen.draw(display))
+            .await;
+
+        ticker.next().await;
+    }
+}
+
 async fn ask_for_measurement_action(context: &mut Context) -> (bool, bool) {
     let network_configured =
         !context.config.backend_url.is_empty() && !context.config.known_networks.is_empty();
@@ -210,7 +306,11 @@ impl MenuScreen for AskForMeasurementActionMenu {
     }
 }
 
-async fn try_to_upload(context: &mut Context, buffer: &[u8]) -> StoreMeasurement {
+async fn try_to_upload(
+    context: &mut Context,
+    measurement: MeasurementRef<'_>,
+    session: &mut Option<UploadSession>,
+) -> StoreMeasurement {
     if context.config.backend_url.is_empty() {
         debug!("No backend URL configured, not uploading.");
         return StoreMeasurement::Store;
@@ -237,14 +337,7 @@ async fn try_to_upload(context: &mut Context, buffer: &[u8]) -> StoreMeasurement
     };
     let mut client = client_resources.client();
 
-    match upload_measurement(
-        &mut client,
-        0,
-        MeasurementRef { version: 0, buffer },
-        &mut context.inner,
-    )
-    .await
-    {
+    match upload_measurement(&mut client, measurement, session, &mut context.inner).await {
         Ok(_) => {
             // Upload successful, do not store in file.
             context.display_message("Upload successful").await;
@@ -293,6 +386,9 @@ async fn upload_stored(context: &mut Context) {
     };
     let mut client = client_resources.client();
 
+    // Creating or deleting files could invalidate `dir`, so session files are updated at the end.
+    let mut session_updates = Vec::new();
+
     let mut success = true;
     loop {
         match dir.next(storage).await {
@@ -309,19 +405,43 @@ async fn upload_stored(context: &mut Context) {
                             continue;
                         };
 
-                        if let Err(e) =
-                            upload_measurement(&mut client, 0, buffer.as_ref(), &mut context.inner)
-                                .await
-                        {
+                        let session_file = session_filename(name);
+                        let mut session =
+                            load_session(storage, &session_file, buffer.as_ref()).await;
+
+                        let result = upload_measurement(
+                            &mut client,
+                            buffer.as_ref(),
+                            &mut session,
+                            &mut context.inner,
+                        )
+                        .await;
+
+                        if let Err(e) = result {
                             warn!("Failed to upload {}: {:?}", name, e);
                             success = false;
-                            break;
+
+                            if let Some(session) = session {
+                                let stored = StoredSession::new(session, buffer.as_ref());
+                                defer_session_update(
+                                    &mut session_updates,
+                                    session_file,
+                                    Some(stored),
+                                );
+                            }
+
+                            // Other measurements may still succeed, unless we lost the network.
+                            if sta.connection_state() != WifiClientState::Connected {
+                                break;
+                            }
+                            continue;
                         }
 
                         info!("Uploaded {}", name);
                         if let Err(e) = file.delete(storage).await {
                             warn!("Failed to delete file: {:?}", e);
                         }
+                        defer_session_update(&mut session_updates, session_file, None);
                     }
                     Ok(_) | Err(StorageError::InsufficientBuffer) => {
                         // not a measurement file, ignore
@@ -341,6 +461,13 @@ async fn upload_stored(context: &mut Context) {
         }
     }
 
+    for update in session_updates {
+        match update.session {
+            Some(session) => store_session(storage, &update.filename, &session).await,
+            None => delete_session(storage, &update.filename).await,
+        }
+    }
+
     let message = if success {
         "Upload successful"
     } else {
@@ -351,33 +478,91 @@ async fn upload_stored(context: &mut Context) {
     context.signal_sta_work_available(!success);
 }
 
+/// Returns the encoded header, or the message to display if it can't be encoded.
+fn encode_header(header: &MeasurementHeader) -> Result<Box<[u8]>, &'static str> {
+    let mut buffer = buffer_with_capacity(header.encoded_len(), 0).map_err(|_| "Out of memory")?;
+    if let Err(e) = header.write(&mut buffer.as_mut()) {
+        warn!("Failed to encode header: {:?}", e);
+        return Err("Could not encode measurement");
+    }
+
+    Ok(buffer)
+}
+
 struct Measurement {
-    version: u32,
+    version: u8,
+    start_time: Option<u64>,
+    /// The header followed by the samples.
     buffer: Box<[u8]>,
+    header_len: usize,
 }
 
 impl Measurement {
     fn as_ref(&self) -> MeasurementRef<'_> {
+        let (header, samples) = self.buffer.split_at(self.header_len);
         MeasurementRef {
             version: self.version,
-            buffer: &self.buffer,
+            start_time: self.start_time,
+            header,
+            samples,
         }
     }
 }
 
+#[derive(Clone, Copy)]
 struct MeasurementRef<'a> {
-    version: u32,
-    buffer: &'a [u8],
+    version: u8,
+    /// Unix time in seconds, if known.
+    start_time: Option<u64>,
+    /// Encoded header, empty for version 0 measurements.
+    header: &'a [u8],
+    samples: &'a [u8],
+}
+
+impl MeasurementRef<'_> {
+    fn data_len(&self) -> usize {
+        self.header.len() + self.samples.len()
+    }
+
+    /// The length of the uploaded data.
+    fn upload_len(&self) -> usize {
+        4 + self.data_len()
+    }
+
+    /// Identifies the measurement a stored upload session belongs to.
+    fn checksum(&self) -> u32 {
+        let crc = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+        let mut digest = crc.digest();
+        digest.update(&[self.version]);
+        digest.update(self.header);
+        digest.update(self.samples);
+        digest.finalize()
+    }
+}
+
+/// A part of the uploaded data.
+struct MeasurementChunk<'a> {
+    measurement: MeasurementRef<'a>,
+    range: Range<usize>,
 }
 
-impl RequestBody for MeasurementRef<'_> {
+impl RequestBody for MeasurementChunk<'_> {
     fn len(&self) -> Option<usize> {
-        Some(self.buffer.len() + 4)
+        Some(self.range.len())
     }
 
     async fn write<W: embedded_io_async::Write>(&self, writer: &mut W) -> Result<(), W::Error> {
-        writer.write_all(&self.version.to_le_bytes()).await?;
-        writer.write_all(self.buffer).await?;
+        // The upload format predates the measurement header and uses a 32-bit version field.
+        let version = (self.measurement.version as u32).to_le_bytes();
+        let parts = [
+            &version[..],
+            self.measurement.header,
+            self.measurement.samples,
+        ];
+
+        for part in slice_parts(&parts, self.range.clone()) {
+            writer.write_all(part).await?;
+        }
 
         Ok(())
     }
@@ -416,11 +601,24 @@ where
         return Err(());
     };
 
+    let (start_time, header_len) = match MeasurementHeader::parse(version, &buffer) {
+        Ok((header, header_len)) => {
+            debug!("Measurement header: {:?}", header);
+            (header.start_time, header_len)
+        }
+        Err(e) => {
+            warn!("Invalid measurement: {:?}", e);
+            return Err(());
+        }
+    };
+
     Ok((
         DirEntry::from_reader(reader),
         Measurement {
-            version: version as u32,
+            version,
+            start_time,
             buffer,
+            header_len,
         },
     ))
 }
@@ -442,10 +640,167 @@ fn buffer_with_capacity<T: Copy>(size: usize, init_val: T) -> Result<Box<[T]>, (
     Ok(buffer.into_boxed_slice())
 }
 
+/// The session of the stored measurement `meas.<idx>` is stored in `upl.<idx>`, so that the
+/// upload can continue after a reboot.
+fn session_filename(measurement: &str) -> heapless::String<64> {
+    let idx = measurement.strip_prefix("meas.").unwrap_or(measurement);
+
+    // File names are at most 64 bytes long, and the prefix gets shorter.
+    let mut filename = heapless::String::<64>::new();
+    unwrap!(uwrite!(&mut filename, "upl.{}", idx));
+    filename
+}
+
+struct StoredSession {
+    session: UploadSession,
+    /// The checksum of the measurement, so that a session is never continued with different data.
+    checksum: u32,
+}
+
+impl StoredSession {
+    fn new(session: UploadSession, measurement: MeasurementRef<'_>) -> Self {
+        Self {
+            session,
+            checksum: measurement.checksum(),
+        }
+    }
+}
+
+impl Loadable for StoredSession {
+    async fn load<R: embedded_io_async::Read>(reader: &mut R) -> Result<Self, LoadError<R::Error>> {
+        let total_len = u32::load(reader).await?;
+        let checksum = u32::load(reader).await?;
+
+        let id_len = u8::load(reader).await? as usize;
+        if id_len > MAX_SESSION_ID_LEN {
+            return Err(LoadError::InvalidValue);
+        }
+        let mut id = [0; MAX_SESSION_ID_LEN];
+        for byte in &mut id[..id_len] {
+            *byte = u8::load(reader).await?;
+        }
+        let id = SessionId::parse(&id[..id_len]).map_err(|_| LoadError::InvalidValue)?;
+
+        Ok(Self {
+            session: UploadSession::new(id, total_len),
+            checksum,
+        })
+    }
+}
+
+impl Storable for StoredSession {
+    async fn store<W: embedded_io_async::Write>(&self, writer: &mut W) -> Result<(), W::Error> {
+        let id = self.session.id().as_str();
+
+        self.session.total_len().store(writer).await?;
+        self.checksum.store(writer).await?;
+        (id.len() as u8).store(writer).await?;
+        writer.write_all(id.as_bytes()).await
+    }
+}
+
+async fn load_session<M>(
+    storage: &mut Storage<M>,
+    filename: &str,
+    measurement: MeasurementRef<'_>,
+) -> Option<UploadSession>
+where
+    M: StorageMedium,
+    [(); M::BLOCK_COUNT]:,
+{
+    // Most measurements don't have a session.
+    let mut reader = storage.read(filename).await.ok()?;
+    match reader.read_loadable::<StoredSession>(storage).await {
+        Ok(stored)
+            if stored.checksum != measurement.checksum()
+                || stored.session.total_len() != measurement.upload_len() as u32 =>
+        {
+            warn!("{} does not belong to the measurement", filename);
+            None
+        }
+        Ok(stored) => {
+            debug!("Resuming upload session {:?}", stored.session.id());
+            Some(stored.session)
+        }
+        Err(e) => {
+            warn!("Failed to read {}: {:?}", filename, e);
+            None
+        }
+    }
+}
+
+async fn store_session<M>(storage: &mut Storage<M>, filename: &str, session: &StoredSession)
+where
+    M: StorageMedium,
+    [(); M::BLOCK_COUNT]:,
+{
+    if let Err(e) = storage
+        .store_writer(filename, session, OnCollision::Overwrite)
+        .await
+    {
+        warn!("Failed to store {}: {:?}", filename, e);
+    }
+}
+
+async fn delete_session<M>(storage: &mut Storage<M>, filename: &str)
+where
+    M: StorageMedium,
+    [(); M::BLOCK_COUNT]:,
+{
+    // The file only exists if an earlier upload failed.
+    if storage.delete(filename).await.is_ok() {
+        debug!("Deleted {}", filename);
+    }
+}
+
+/// A session file change that waits until the directory is no longer read.
+struct SessionUpdate {
+    filename: heapless::String<64>,
+    /// The session to store, or `None` to delete the file.
+    session: Option<StoredSession>,
+}
+
+fn defer_session_update(
+    updates: &mut Vec<SessionUpdate>,
+    filename: heapless::String<64>,
+    session: Option<StoredSession>,
+) {
+    // A stale session file is ignored because of its checksum, a lost one only restarts the
+    // upload.
+    if updates.try_reserve(1).is_err() {
+        warn!("Out of memory, not updating {}", filename.as_str());
+        return;
+    }
+    updates.push(SessionUpdate { filename, session });
+}
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+/// The timeout of a single request, including sending a chunk.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How many requests may fail in a row before an upload is abandoned.
+const UPLOAD_ATTEMPTS: u32 = 5;
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+const NO_BODY: &[u8] = &[];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum RequestError {
+    /// The request failed and may be retried.
+    Failed,
+    /// The backend does not know the upload session.
+    UnknownSession,
+    /// The backend does not support upload sessions.
+    SessionsUnsupported,
+}
+
+/// Uploads the measurement in chunks. A session that failed to complete is left in `session`, so
+/// that a later call can continue it.
 async fn upload_measurement<T, DNS>(
     client: &mut HttpClient<'_, T, DNS>,
-    meas_timestamp: u64,
-    samples: MeasurementRef<'_>,
+    measurement: MeasurementRef<'_>,
+    session: &mut Option<UploadSession>,
     context: &mut InnerContext,
 ) -> Result<(), ()>
 where
@@ -455,13 +810,113 @@ where
     let uploading_msg = uformat!(
         32,
         "Uploading measurement: {}",
-        BinarySize(samples.buffer.len())
+        BinarySize(measurement.data_len())
     );
     context.display_message(uploading_msg.as_str()).await;
 
-    const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
-    const UPLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+    let upload_len = measurement.upload_len() as u32;
+    if session.is_some_and(|session| session.total_len() != upload_len) {
+        warn!("Upload session does not belong to the measurement");
+        *session = None;
+    }
+
+    let mut session_url = heapless::String::<128>::new();
+    if uwrite!(
+        &mut session_url,
+        "{}/upload_session/{}",
+        context.config.backend_url.as_str(),
+        SerialNumber
+    )
+    .is_err()
+    {
+        warn!("URL too long");
+        return Err(());
+    }
+
+    debug!("Uploading measurement to {}", session_url);
+
+    // We don't know how much of a resumed session the backend has stored.
+    let mut synchronized = false;
+    let mut failures = 0;
+    let mut stored = 0;
+    loop {
+        let result = match session.as_mut() {
+            None => match open_session(client, &session_url, measurement).await {
+                Ok(new_session) => {
+                    info!("Opened upload session {:?}", new_session.id());
+                    *session = Some(new_session);
+                    synchronized = true;
+                    Ok(())
+                }
+                Err(RequestError::SessionsUnsupported) => {
+                    warn!("Backend does not support upload sessions");
+                    return upload_in_one_request(client, measurement, context).await;
+                }
+                Err(e) => Err(e),
+            },
+            Some(current) if !synchronized => {
+                let result = query_offset(client, &session_url, current).await;
+                synchronized = result.is_ok();
+                result
+            }
+            Some(current) => {
+                let Some(range) = current.next_chunk(CHUNK_SIZE) else {
+                    return Ok(());
+                };
+
+                let result = send_chunk(client, &session_url, measurement, current, range).await;
+
+                let progress = uformat!(
+                    32,
+                    "Uploading measurement: {}%",
+                    current.acknowledged() as u64 * 100 / upload_len as u64
+                );
+                context.display_message(progress.as_str()).await;
+
+                result
+            }
+        };
+
+        // Only progress resets the retry counter, a backend that doesn't store our data can't
+        // keep us here.
+        let acknowledged = session.map_or(0, |session| session.acknowledged());
+        if acknowledged > stored {
+            stored = acknowledged;
+            failures = 0;
+        }
+
+        match result {
+            Ok(()) => continue,
+            Err(RequestError::Failed | RequestError::SessionsUnsupported) => {}
+            Err(RequestError::UnknownSession) => {
+                warn!("Upload session expired, starting over");
+                *session = None;
+                stored = 0;
+            }
+        }
+
+        failures += 1;
+        if failures >= UPLOAD_ATTEMPTS {
+            warn!("Giving up upload after {} failed requests", failures);
+            return Err(());
+        }
+
+        // The backend may have stored data that we haven't received an acknowledgement for.
+        synchronized = false;
+        Timer::after(RETRY_DELAY).await;
+    }
+}
 
+/// Uploads the measurement to a backend that predates upload sessions.
+async fn upload_in_one_request<T, DNS>(
+    client: &mut HttpClient<'_, T, DNS>,
+    measurement: MeasurementRef<'_>,
+    context: &mut InnerContext,
+) -> Result<(), ()>
+where
+    T: TcpConnect,
+    DNS: Dns,
+{
     let mut upload_url = heapless::String::<128>::new();
     if uwrite!(
         &mut upload_url,
@@ -475,57 +930,229 @@ where
         return Err(());
     }
 
-    let mut timestamp = heapless::String::<32>::new();
-    unwrap!(uwrite!(&mut timestamp, "{}", meas_timestamp));
-
     debug!("Uploading measurement to {}", upload_url);
 
+    let mut timestamp = heapless::String::<32>::new();
+    unwrap!(uwrite!(
+        &mut timestamp,
+        "{}",
+        measurement.start_time.unwrap_or(0)
+    ));
+
     let headers = [("X-Timestamp", timestamp.as_str())];
+    let body = MeasurementChunk {
+        measurement,
+        range: 0..measurement.upload_len(),
+    };
+
+    match upload_request(client, Method::POST, &upload_url, &headers, body).await {
+        Ok((Status::Ok | Status::Created, _)) => Ok(()),
+        _ => Err(()),
+    }
+}
+
+async fn open_session<T, DNS>(
+    client: &mut HttpClient<'_, T, DNS>,
+    url: &str,
+    measurement: MeasurementRef<'_>,
+) -> Result<UploadSession, RequestError>
+where
+    T: TcpConnect,
+    DNS: Dns,
+{
+    // 0 means the measurement time is unknown.
+    let mut timestamp = heapless::String::<32>::new();
+    unwrap!(uwrite!(
+        &mut timestamp,
+        "{}",
+        measurement.start_time.unwrap_or(0)
+    ));
+
+    let mut length = heapless::String::<16>::new();
+    unwrap!(uwrite!(&mut length, "{}", measurement.upload_len()));
+
+    let headers = [
+        ("X-Timestamp", timestamp.as_str()),
+        ("X-Upload-Length", length.as_str()),
+    ];
+
+    let (status, body) = upload_request(client, Method::POST, url, &headers, NO_BODY).await?;
+    match status {
+        Status::Ok | Status::Created => {}
+        Status::NotFound => return Err(RequestError::SessionsUnsupported),
+        _ => return Err(RequestError::Failed),
+    }
+
+    match SessionId::parse(&body) {
+        Ok(id) => Ok(UploadSession::new(id, measurement.upload_len() as u32)),
+        Err(e) => {
+            warn!("Invalid session id: {:?}", e);
+            Err(RequestError::Failed)
+        }
+    }
+}
+
+async fn query_offset<T, DNS>(
+    client: &mut HttpClient<'_, T, DNS>,
+    session_url: &str,
+    session: &mut UploadSession,
+) -> Result<(), RequestError>
+where
+    T: TcpConnect,
+    DNS: Dns,
+{
+    let url = session_resource(session_url, session)?;
+
+    let (status, body) = upload_request(client, Method::GET, &url, &[], NO_BODY).await?;
+    match status {
+        Status::Ok => acknowledge(session, &body),
+        Status::NotFound => Err(RequestError::UnknownSession),
+        _ => Err(RequestError::Failed),
+    }
+}
+
+async fn send_chunk<T, DNS>(
+    client: &mut HttpClient<'_, T, DNS>,
+    session_url: &str,
+    measurement: MeasurementRef<'_>,
+    session: &mut UploadSession,
+    range: Range<usize>,
+) -> Result<(), RequestError>
+where
+    T: TcpConnect,
+    DNS: Dns,
+{
+    let url = session_resource(session_url, session)?;
+
+    let mut offset = heapless::String::<16>::new();
+    unwrap!(uwrite!(&mut offset, "{}", range.start));
+
+    let headers = [("X-Upload-Offset", offset.as_str())];
+    let chunk = MeasurementChunk {
+        measurement,
+        range: range.clone(),
+    };
+
+    let (status, body) = upload_request(client, Method::PUT, &url, &headers, chunk).await?;
+    match status {
+        Status::Ok | Status::Created => {
+            acknowledge(session, &body)?;
+            if session.acknowledged() as usize <= range.start {
+                warn!("Backend did not store the chunk at {}", range.start);
+                return Err(RequestError::Failed);
+            }
+            Ok(())
+        }
+        Status::Conflict => {
+            acknowledge(session, &body)?;
+            warn!("Backend expects offset {}", session.acknowledged());
+            Err(RequestError::Failed)
+        }
+        Status::NotFound => Err(RequestError::UnknownSession),
+        _ => Err(RequestError::Failed),
+    }
+}
+
+fn session_resource(
+    session_url: &str,
+    session: &UploadSession,
+) -> Result<heapless::String<160>, RequestError> {
+    let mut url = heapless::String::<160>::new();
+    if uwrite!(&mut url, "{}/{}", session_url, session.id().as_str()).is_err() {
+        warn!("URL too long");
+        return Err(RequestError::Failed);
+    }
+    Ok(url)
+}
+
+fn acknowledge(session: &mut UploadSession, body: &[u8]) -> Result<(), RequestError> {
+    match parse_offset(body).and_then(|offset| session.acknowledge(offset)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            warn!("Invalid acknowledgement: {:?}", e);
+            Err(RequestError::Failed)
+        }
+    }
+}
+
+/// Sends a request of the upload protocol. Returns the response status and, for responses the
+/// protocol defines a body for, the body.
+async fn upload_request<T, DNS, B>(
+    client: &mut HttpClient<'_, T, DNS>,
+    method: Method,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: B,
+) -> Result<(Status, heapless::Vec<u8, 64>), RequestError>
+where
+    T: TcpConnect,
+    DNS: Dns,
+    B: RequestBody,
+{
+    let mut request = match with_timeout(CONNECT_TIMEOUT, client.request(method, url)).await {
+        Ok(Ok(request)) => request.headers(headers).body(body),
+        Ok(Err(e)) => {
+            warn!("HTTP connect error: {:?}", e);
+            return Err(RequestError::Failed);
+        }
+        _ => {
+            warn!("Connect timeout");
+            return Err(RequestError::Failed);
+        }
+    };
+
+    let mut rx_buffer = [0; 512];
+    let response = match with_timeout(REQUEST_TIMEOUT, request.send(&mut rx_buffer)).await {
+        Ok(Ok(response)) => response,
+        Ok(Err(e)) => {
+            warn!("HTTP request error: {:?}", e);
+            return Err(RequestError::Failed);
+        }
+        _ => {
+            warn!("Request timeout");
+            return Err(RequestError::Failed);
+        }
+    };
 
-    let mut request =
-        match with_timeout(CONNECT_TIMEOUT, client.request(Method::POST, &upload_url)).await {
-            Ok(Ok(request)) => request.headers(&headers).body(samples),
+    let status_code = response.status;
+    let status: Status = status_code.into();
+    let mut body = heapless::Vec::new();
+    if [Status::Ok, Status::Created, Status::Conflict].contains(&status) {
+        let data = match with_timeout(REQUEST_TIMEOUT, response.body().read_to_end()).await {
+            Ok(Ok(data)) => data,
             Ok(Err(e)) => {
-                warn!("HTTP connect error: {:?}", e);
-                return Err(());
+                warn!("HTTP read error: {:?}", e);
+                return Err(RequestError::Failed);
             }
             _ => {
-                warn!("Conect timeout");
-                return Err(());
+                warn!("Read timeout");
+                return Err(RequestError::Failed);
             }
         };
 
-    let mut rx_buffer = [0; 512];
-    match with_timeout(UPLOAD_TIMEOUT, request.send(&mut rx_buffer)).await {
-        Ok(Ok(response)) => {
-            if [Status::Ok, Status::Created].contains(&response.status.into()) {
-                return Ok(());
-            }
-
-            warn!("HTTP upload failed: {:?}", response.status);
-            for header in response.headers() {
-                if !header.0.is_empty() {
-                    debug!(
-                        "Header {}: {}",
-                        header.0,
-                        str::from_utf8(header.1).unwrap_or("not a string")
-                    );
-                }
-            }
+        if body.extend_from_slice(data).is_err() {
+            warn!("Response too long");
+            return Err(RequestError::Failed);
         }
-        Ok(Err(e)) => warn!("HTTP upload error: {:?}", e),
-        _ => warn!("Timeout"),
+    } else {
+        warn!("HTTP request failed: {:?}", status_code);
     }
-    Err(())
+
+    Ok((status, body))
 }
 
 async fn try_store_measurement(
     context: &mut Context,
-    measurement: &[u8],
+    measurement: MeasurementRef<'_>,
+    session: Option<&UploadSession>,
 ) -> Result<(), StorageError> {
     debug!("Trying to store measurement");
 
-    let saving_msg = uformat!(32, "Saving measurement: {}", BinarySize(measurement.len()));
+    let saving_msg = uformat!(
+        32,
+        "Saving measurement: {}",
+        BinarySize(measurement.data_len())
+    );
     context.display_message(&saving_msg).await;
     let Some(storage) = context.storage.as_mut() else {
         return Ok(());
@@ -546,6 +1173,16 @@ async fn try_store_measurement(
 
     info!("Measurement saved to {}", filename);
 
+    // A session file may be left over from an earlier measurement with the same index.
+    let session_file = session_filename(&filename);
+    match session {
+        Some(session) => {
+            let stored = StoredSession::new(*session, measurement);
+            store_session(storage, &session_file, &stored).await
+        }
+        None => delete_session(storage, &session_file).await,
+    }
+
     context.signal_sta_work_available(true);
 
     Ok(())
@@ -590,12 +1227,7 @@ where
     Ok(max_index.map(|idx| idx + 1).unwrap_or(0))
 }
 
-struct MeasurementWriter<'a>(&'a [u8]);
-
-impl<'a> MeasurementWriter<'a> {
-    // We're good with a straight u8 until 127 samples, then we can consider switching to varint.
-    const FORMAT_VERSION: u8 = EkgFormat::VERSION;
-}
+struct MeasurementWriter<'a>(MeasurementRef<'a>);
 
 impl FileDataWriter for MeasurementWriter<'_> {
     async fn write<M>(
@@ -612,15 +1244,14 @@ impl FileDataWriter for MeasurementWriter<'_> {
 
         let mut writer = writer.bind(storage);
 
-        writer
-            .write_all(&Self::FORMAT_VERSION.to_le_bytes())
-            .await?;
-        writer.write_all(self.0).await?;
+        writer.write_all(&self.0.version.to_le_bytes()).await?;
+        writer.write_all(self.0.header).await?;
+        writer.write_all(self.0.samples).await?;
 
         Ok(())
     }
 
     fn estimate_length(&self) -> usize {
-        Self::FORMAT_VERSION.to_le_bytes().len() + self.0.len()
+        self.0.version.to_le_bytes().len() + self.0.data_len()
     }
 }
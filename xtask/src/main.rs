@@ -201,9 +201,9 @@ fn extra_checks(config: BuildConfig) -> AnyResult<()> {
 }
 
 fn test() -> AnyResult<()> {
-    let packages = ["signal-processing"];
+    let packages = ["cardio-tool", "protocol", "signal-processing", "sntp"];
 
-    let mut args = vec!["test", "--features=dyn_filter"];
+    let mut args = vec!["test", "--features=signal-processing/dyn_filter"];
 
     for p in packages {
         args.push("-p");